@@ -8,6 +8,54 @@ struct Camera {
     mat4 proj_inverse;
 };
 
+struct Globals {
+    Camera camera;
+    vec4 color;
+    mat4 previous_view_proj;
+    uint history_valid;
+    uint samples_per_pixel;
+    // Diffuse bounces traced from raytrace.rchit, at most the pipeline recursion depth minus the
+    // primary ray
+    uint max_bounces;
+    // Mixed into the per-pixel random numbers, renders with the same seed are reproducible
+    uint seed;
+};
+
+struct PushConstants {
+    uint frame;
+    uint light_count;
+};
+
+struct InstanceData {
+    mat3 normal_matrix;
+    // Buffer device addresses, zero when the mesh doesn't have them
+    uvec2 positions;
+    uvec2 indices;
+    uvec2 colors;
+    uvec2 shading;
+};
+
+// Per-vertex shading attributes, interleaved
+struct ShadingVertex {
+    vec3 normal;
+    vec2 uv;
+};
+
+// An emissive instance, sampled for next-event estimation
+struct Light {
+    vec3 emission;
+    uint instance;
+    uint triangle_count;
+};
+
 struct PerRayData {
     vec3 hit_color;
+    // G-buffer of the hit, normal is zero and hit_t negative on a miss
+    vec3 albedo;
+    vec3 normal;
+    float hit_t;
+    // Bounces before this ray, 0 for the primary ray
+    uint depth;
+    // Random number state, handed back to the caller so bounces don't repeat its numbers
+    uint rng;
 };
@@ -0,0 +1,28 @@
+// PCG hash, see "Hash Functions for GPU Rendering" (Jarzynski and Olano, 2020)
+uint pcg_hash(uint value) {
+    uint state = value * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Seeds the generator from the pixel, the frame and the user seed, the same triple always gives
+// the same sequence
+uint rng_init(uvec2 pixel, uint frame, uint seed) {
+    return pcg_hash(pixel.x ^ pcg_hash(pixel.y ^ pcg_hash(frame ^ pcg_hash(seed))));
+}
+
+// Uniform float in [0, 1)
+float rng_next(inout uint state) {
+    state = pcg_hash(state);
+    return float(state >> 8u) / 16777216.0;
+}
+
+// Cosine-weighted direction in the hemisphere around the unit vector `normal`, its pdf cancels
+// the cosine term of a Lambertian surface
+vec3 sample_cosine_hemisphere(vec3 normal, inout uint state) {
+    float phi = 6.28318530718 * rng_next(state);
+    float r = sqrt(rng_next(state));
+    vec3 tangent = normalize(cross(normal, abs(normal.x) > 0.9 ? vec3(0, 1, 0) : vec3(1, 0, 0)));
+    vec3 bitangent = cross(normal, tangent);
+    return tangent * (r * cos(phi)) + bitangent * (r * sin(phi)) + normal * sqrt(1.0 - r * r);
+}
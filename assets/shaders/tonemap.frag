@@ -5,7 +5,39 @@ layout(location = 0) out vec4 output_color;
 
 layout(binding = 0, set = 0) uniform sampler2D initial_image;
 
+layout(push_constant) uniform PushConstants {
+    float exposure;
+    float white_point;
+    uint operator;
+} push_constants;
+
+const uint OPERATOR_REINHARD = 1;
+const uint OPERATOR_ACES = 2;
+
+// Extended Reinhard, maps white_point to 1.0
+vec3 reinhard(vec3 color, float white_point) {
+    return color * (1.0 + color / (white_point * white_point)) / (1.0 + color);
+}
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 color) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return (color * (a * color + b)) / (color * (c * color + d) + e);
+}
 
 void main() {
-    output_color = texture(initial_image, in_uv);
+    vec4 initial_color = texture(initial_image, in_uv);
+    vec3 color = initial_color.rgb * push_constants.exposure;
+
+    if (push_constants.operator == OPERATOR_REINHARD) {
+        color = reinhard(color, push_constants.white_point);
+    } else if (push_constants.operator == OPERATOR_ACES) {
+        color = aces(color) / aces(vec3(push_constants.white_point));
+    }
+
+    output_color = vec4(clamp(color, 0.0, 1.0), initial_color.a);
 }
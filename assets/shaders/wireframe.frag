@@ -0,0 +1,13 @@
+#version 460
+
+layout(location = 0) out vec4 outColor;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model_view_proj;
+    vec4 color;
+} pushConstants;
+
+void main() {
+    // the pipeline blends with premultiplied alpha
+    outColor = vec4(pushConstants.color.rgb * pushConstants.color.a, pushConstants.color.a);
+}
@@ -0,0 +1,13 @@
+#version 460
+
+layout(location = 0) in vec3 inPos;
+
+layout(push_constant) uniform PushConstants {
+    // view_proj * model of the instance being drawn
+    mat4 model_view_proj;
+    vec4 color;
+} pushConstants;
+
+void main() {
+    gl_Position = pushConstants.model_view_proj * vec4(inPos, 1.0);
+}
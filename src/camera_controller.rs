@@ -1,14 +1,79 @@
-use bevy::{input::mouse::MouseMotion, prelude::*};
+use crate::render::renderer::Renderer;
+use bevy::{
+    input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use thiserror::Error;
 
-#[derive(Debug)]
+/// How many pixels of a touchpad scroll count as one line of a mouse wheel.
+const PIXELS_PER_LINE: f32 = 16.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CameraMode {
+    /// Moves freely with the movement keys, dragging the mouse looks around.
+    Fly,
+    /// Circles `CameraController::focus`, dragging the mouse rotates around it and the wheel
+    /// changes the distance.
+    Orbit,
+}
+
+#[derive(Error, Debug)]
+pub enum CameraConfigError {
+    #[error("failed to read the camera config: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to parse the camera config: {0}")]
+    Parse(#[from] ron::Error),
+    #[error("{key:?} is bound to both {first} and {second}")]
+    DuplicateKey {
+        key: KeyCode,
+        first: &'static str,
+        second: &'static str,
+    },
+}
+
+/// The runtime state is skipped when (de)serializing, missing settings keep their defaults.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CameraController {
+    pub mode: CameraMode,
+    /// Point orbited around in [`CameraMode::Orbit`].
+    pub focus: Vec3,
+    pub orbit_radius: f32,
+    pub min_orbit_radius: f32,
     pub accel: f32,
     pub max_speed: f32,
+    /// Multiply `max_speed` while `key_sprint` or `key_crawl` is held.
+    pub sprint_factor: f32,
+    pub crawl_factor: f32,
     pub sensitivity: f32,
+    /// Distance moved along the view direction per line scrolled.
+    pub zoom_sensitivity: f32,
+    /// Caps the distance a single frame of scrolling moves the camera, so a fast scroll can't
+    /// jump through the geometry in front of it.
+    pub max_zoom_step: f32,
+    #[serde(skip)]
     pub velocity: Vec3,
     pub friction: f32,
+    /// Time in seconds the camera takes to catch up with the input, `None` moves it instantly.
+    /// The smoothing is critically damped, so it never overshoots.
+    pub smoothing: Option<f32>,
+    /// Where the input systems want the camera, the transform follows it. `None` until the first
+    /// update, when it starts from the current transform.
+    #[serde(skip)]
+    pub target: Option<Transform>,
+    /// Velocity of the smoothed translation towards the target.
+    #[serde(skip)]
+    pub smoothing_velocity: Vec3,
+    #[serde(skip)]
     pub pitch: f32,
+    #[serde(skip)]
     pub yaw: f32,
+    /// Lowest and highest pitch in degrees, kept short of 90 so the view never flips over.
+    pub min_pitch: f32,
+    pub max_pitch: f32,
 
     pub key_forward: KeyCode,
     pub key_backward: KeyCode,
@@ -16,6 +81,14 @@ pub struct CameraController {
     pub key_right: KeyCode,
     pub key_up: KeyCode,
     pub key_down: KeyCode,
+    pub key_sprint: KeyCode,
+    pub key_crawl: KeyCode,
+    /// Switches between [`CameraMode::Fly`] and [`CameraMode::Orbit`].
+    pub key_toggle_mode: KeyCode,
+    /// Locks and hides the cursor for free-look without holding the mouse button.
+    pub key_grab_cursor: KeyCode,
+    #[serde(skip)]
+    pub cursor_grabbed: bool,
 
     pub enabled: bool,
 }
@@ -23,31 +96,98 @@ pub struct CameraController {
 impl Default for CameraController {
     fn default() -> Self {
         Self {
+            mode: CameraMode::Fly,
+            focus: Vec3::ZERO,
+            orbit_radius: 1.0,
+            min_orbit_radius: 0.05,
             accel: 1.5,
             max_speed: 0.5,
+            sprint_factor: 4.0,
+            crawl_factor: 0.2,
             sensitivity: 6.0,
+            zoom_sensitivity: 0.1,
+            max_zoom_step: 0.5,
             velocity: Vec3::ZERO,
             friction: 1.0,
+            smoothing: None,
+            target: None,
+            smoothing_velocity: Vec3::ZERO,
             pitch: 0.0,
             yaw: 0.0,
+            min_pitch: -89.9,
+            max_pitch: 89.9,
             key_forward: KeyCode::W,
             key_backward: KeyCode::S,
             key_left: KeyCode::A,
             key_right: KeyCode::D,
             key_up: KeyCode::Q,
             key_down: KeyCode::E,
+            key_sprint: KeyCode::LShift,
+            key_crawl: KeyCode::LControl,
+            key_toggle_mode: KeyCode::O,
+            key_grab_cursor: KeyCode::G,
+            cursor_grabbed: false,
             enabled: true,
         }
     }
 }
 
+impl CameraController {
+    /// Loads the settings and key bindings from a RON file, rejecting configs that bind one key
+    /// to several actions.
+    pub fn from_config(path: impl AsRef<Path>) -> Result<Self, CameraConfigError> {
+        let config = std::fs::read_to_string(path)?;
+        let controller: CameraController = ron::de::from_str(&config)?;
+        controller.validate_bindings()?;
+        Ok(controller)
+    }
+
+    pub fn validate_bindings(&self) -> Result<(), CameraConfigError> {
+        let bindings = self.bindings();
+        for (i, &(first, key)) in bindings.iter().enumerate() {
+            if let Some(&(second, _)) = bindings[i + 1..].iter().find(|(_, other)| *other == key) {
+                return Err(CameraConfigError::DuplicateKey { key, first, second });
+            }
+        }
+        Ok(())
+    }
+
+    /// Flips between free-look with a grabbed cursor and looking while the left button is held,
+    /// returns whether the cursor is now grabbed.
+    pub fn toggle_cursor_grab(&mut self) -> bool {
+        self.cursor_grabbed = !self.cursor_grabbed;
+        self.cursor_grabbed
+    }
+
+    /// Whether mouse movement turns the camera, always while the cursor is grabbed.
+    fn is_looking(&self, left_button_held: bool) -> bool {
+        self.enabled && (self.cursor_grabbed || left_button_held)
+    }
+
+    fn bindings(&self) -> [(&'static str, KeyCode); 10] {
+        [
+            ("key_forward", self.key_forward),
+            ("key_backward", self.key_backward),
+            ("key_left", self.key_left),
+            ("key_right", self.key_right),
+            ("key_up", self.key_up),
+            ("key_down", self.key_down),
+            ("key_sprint", self.key_sprint),
+            ("key_crawl", self.key_crawl),
+            ("key_toggle_mode", self.key_toggle_mode),
+            ("key_grab_cursor", self.key_grab_cursor),
+        ]
+    }
+}
+
 fn camera_movement_system(
     time: Res<Time>,
     keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut CameraController, &mut Transform)>,
+    mut query: Query<(&mut CameraController, &Transform)>,
 ) {
-    for (mut options, mut transform) in query.iter_mut() {
-        let (axis_h, axis_v, axis_float) = if options.enabled {
+    for (mut options, transform) in query.iter_mut() {
+        let mut target = options.target.unwrap_or(*transform);
+        let (axis_h, axis_v, axis_float) = if options.enabled && options.mode == CameraMode::Fly {
             (
                 movement_axis(&keyboard_input, options.key_right, options.key_left),
                 movement_axis(&keyboard_input, options.key_backward, options.key_forward),
@@ -57,7 +197,7 @@ fn camera_movement_system(
             (0.0, 0.0, 0.0)
         };
 
-        let rotation = transform.rotation;
+        let rotation = target.rotation;
         let accel: Vec3 = (strafe_vector(&rotation) * axis_h)
             + (forward_walk_vector(&rotation) * axis_v)
             + (Vec3::Y * axis_float);
@@ -76,8 +216,9 @@ fn camera_movement_system(
         options.velocity += accel * time.delta_seconds();
 
         // clamp within max speed
-        if options.velocity.length() > options.max_speed {
-            options.velocity = options.velocity.normalize() * options.max_speed;
+        let max_speed = options.max_speed * speed_factor(&keyboard_input, &options);
+        if options.velocity.length() > max_speed {
+            options.velocity = options.velocity.normalize() * max_speed;
         }
 
         let delta_friction = friction * time.delta_seconds();
@@ -89,7 +230,8 @@ fn camera_movement_system(
                 options.velocity + delta_friction
             };
 
-        transform.translation += options.velocity;
+        target.translation += options.velocity;
+        options.target = Some(target);
     }
 }
 
@@ -97,12 +239,8 @@ fn mouse_motion_system(
     time: Res<Time>,
     mouse_input: Res<Input<MouseButton>>,
     mut mouse_motion_event_reader: EventReader<MouseMotion>,
-    mut query: Query<(&mut CameraController, &mut Transform)>,
+    mut query: Query<(&mut CameraController, &Transform)>,
 ) {
-    if !mouse_input.pressed(MouseButton::Left) {
-        return;
-    }
-
     let mut delta: Vec2 = Vec2::ZERO;
     for event in mouse_motion_event_reader.iter() {
         delta += event.delta;
@@ -111,21 +249,185 @@ fn mouse_motion_system(
         return;
     }
 
-    for (mut options, mut transform) in query.iter_mut() {
-        if !options.enabled {
+    for (mut options, transform) in query.iter_mut() {
+        if !options.is_looking(mouse_input.pressed(MouseButton::Left)) {
             continue;
         }
-        options.yaw -= delta.x * options.sensitivity * time.delta_seconds();
-        options.pitch += delta.y * options.sensitivity * time.delta_seconds();
+        let mut target = options.target.unwrap_or(*transform);
+        look(&mut options, &mut target, delta, time.delta_seconds());
+        options.target = Some(target);
+    }
+}
+
+/// Turns `target` by a mouse movement of `delta`, orbiting around the focus in
+/// [`CameraMode::Orbit`].
+fn look(options: &mut CameraController, target: &mut Transform, delta: Vec2, delta_seconds: f32) {
+    options.yaw -= delta.x * options.sensitivity * delta_seconds;
+    options.pitch += delta.y * options.sensitivity * delta_seconds;
+
+    options.pitch = options.pitch.clamp(options.min_pitch, options.max_pitch);
+
+    target.rotation = look_rotation(options.yaw, options.pitch);
+    if options.mode == CameraMode::Orbit {
+        target.translation = orbit_translation(options, &target.rotation);
+    }
+}
+
+fn camera_mode_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut query: Query<(&mut CameraController, &Transform)>,
+) {
+    for (mut options, transform) in query.iter_mut() {
+        if !options.enabled || !keyboard_input.just_pressed(options.key_toggle_mode) {
+            continue;
+        }
+
+        options.mode = match options.mode {
+            CameraMode::Fly => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Fly,
+        };
+        options.velocity = Vec3::ZERO;
+
+        if options.mode == CameraMode::Orbit {
+            // Keep the view direction and orbit at the current distance from the focus
+            let mut target = options.target.unwrap_or(*transform);
+            options.orbit_radius = target
+                .translation
+                .distance(options.focus)
+                .max(options.min_orbit_radius);
+            target.translation = orbit_translation(&options, &target.rotation);
+            options.target = Some(target);
+        }
+    }
+}
+
+fn cursor_grab_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut windows: ResMut<Windows>,
+    mut query: Query<&mut CameraController>,
+) {
+    for mut options in query.iter_mut() {
+        if !options.enabled || !keyboard_input.just_pressed(options.key_grab_cursor) {
+            continue;
+        }
+
+        let grabbed = options.toggle_cursor_grab();
+        if let Some(window) = windows.get_primary_mut() {
+            window.set_cursor_lock_mode(grabbed);
+            window.set_cursor_visibility(!grabbed);
+        }
+    }
+}
+
+/// Moves the transform to the target the input systems left in the controller, eased in when
+/// smoothing is enabled. The accumulated samples are thrown away whenever the camera moves.
+fn camera_follow_system(
+    time: Res<Time>,
+    renderer: Option<ResMut<Renderer>>,
+    mut query: Query<(&mut CameraController, &mut Transform)>,
+) {
+    let mut moved = false;
+    for (mut options, mut transform) in query.iter_mut() {
+        let target = match options.target {
+            Some(target) => target,
+            None => continue,
+        };
+        let previous = *transform;
+
+        match options.smoothing {
+            Some(smooth_time) if smooth_time > 0.0 => {
+                let delta_seconds = time.delta_seconds();
+                let mut velocity = options.smoothing_velocity;
+                transform.translation = smooth_damp(
+                    transform.translation,
+                    target.translation,
+                    &mut velocity,
+                    smooth_time,
+                    delta_seconds,
+                );
+                options.smoothing_velocity = velocity;
+                // Exponential decay with the time constant of the translation's spring
+                let t = 1.0 - (-2.0 * delta_seconds / smooth_time).exp();
+                transform.rotation = transform.rotation.slerp(target.rotation, t);
+            }
+            _ => {
+                transform.translation = target.translation;
+                transform.rotation = target.rotation;
+                options.smoothing_velocity = Vec3::ZERO;
+            }
+        }
+
+        moved |= transform.translation != previous.translation
+            || transform.rotation != previous.rotation;
+    }
+
+    if let (true, Some(mut renderer)) = (moved, renderer) {
+        renderer.reset_accumulation();
+    }
+}
+
+/// Critically damped spring from "Game Programming Gems 4", 1.10, frame rate independent.
+fn smooth_damp(
+    current: Vec3,
+    target: Vec3,
+    velocity: &mut Vec3,
+    smooth_time: f32,
+    delta_seconds: f32,
+) -> Vec3 {
+    let omega = 2.0 / smooth_time;
+    let x = omega * delta_seconds;
+    let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+    let change = current - target;
+    let temp = (*velocity + omega * change) * delta_seconds;
+    *velocity = (*velocity - omega * temp) * decay;
+    target + (change + temp) * decay
+}
+
+fn look_rotation(yaw: f32, pitch: f32) -> Quat {
+    Quat::from_axis_angle(Vec3::Y, yaw.to_radians())
+        * Quat::from_axis_angle(-Vec3::X, pitch.to_radians())
+}
+
+/// Where a camera with `rotation` sits to look at the focus from the orbit radius.
+fn orbit_translation(options: &CameraController, rotation: &Quat) -> Vec3 {
+    options.focus + forward_vector(rotation) * options.orbit_radius
+}
 
-        options.pitch = options.pitch.clamp(-89.0, 89.9);
-        // println!("pitch: {}, yaw: {}", options.pitch, options.yaw);
+fn mouse_wheel_system(
+    mut mouse_wheel_event_reader: EventReader<MouseWheel>,
+    mut query: Query<(&mut CameraController, &Transform)>,
+) {
+    let mut scroll = 0.0;
+    for event in mouse_wheel_event_reader.iter() {
+        scroll += match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        };
+    }
+    if scroll == 0.0 || scroll.is_nan() {
+        return;
+    }
 
-        let yaw_radians = options.yaw.to_radians();
-        let pitch_radians = options.pitch.to_radians();
+    for (mut options, transform) in query.iter_mut() {
+        if !options.enabled {
+            continue;
+        }
+        let mut target = options.target.unwrap_or(*transform);
+        zoom(&mut options, &mut target, scroll);
+        options.target = Some(target);
+    }
+}
 
-        transform.rotation = Quat::from_axis_angle(Vec3::Y, yaw_radians)
-            * Quat::from_axis_angle(-Vec3::X, pitch_radians);
+/// Moves `target` towards where it looks by `scroll` lines, or closer to the focus when orbiting.
+fn zoom(options: &mut CameraController, target: &mut Transform, scroll: f32) {
+    let step =
+        (scroll * options.zoom_sensitivity).clamp(-options.max_zoom_step, options.max_zoom_step);
+    match options.mode {
+        CameraMode::Fly => target.translation += dolly_vector(&target.rotation) * step,
+        CameraMode::Orbit => {
+            options.orbit_radius = (options.orbit_radius - step).max(options.min_orbit_radius);
+            target.translation = orbit_translation(options, &target.rotation);
+        }
     }
 }
 
@@ -133,6 +435,11 @@ fn forward_vector(rotation: &Quat) -> Vec3 {
     rotation.mul_vec3(Vec3::Z).normalize()
 }
 
+/// Direction the camera looks at, cameras look down their local -Z.
+fn dolly_vector(rotation: &Quat) -> Vec3 {
+    -forward_vector(rotation)
+}
+
 fn forward_walk_vector(rotation: &Quat) -> Vec3 {
     let f = forward_vector(rotation);
     Vec3::new(f.x, 0.0, f.z).normalize()
@@ -145,6 +452,17 @@ fn strafe_vector(rotation: &Quat) -> Vec3 {
         .normalize()
 }
 
+/// Scale of the max speed for the held modifier keys, sprinting wins if both are held.
+fn speed_factor(input: &Input<KeyCode>, options: &CameraController) -> f32 {
+    if input.pressed(options.key_sprint) {
+        options.sprint_factor
+    } else if input.pressed(options.key_crawl) {
+        options.crawl_factor
+    } else {
+        1.0
+    }
+}
+
 pub fn movement_axis(input: &Res<Input<KeyCode>>, plus: KeyCode, minus: KeyCode) -> f32 {
     let mut axis = 0.0;
     if input.pressed(plus) {
@@ -156,12 +474,151 @@ pub fn movement_axis(input: &Res<Input<KeyCode>>, plus: KeyCode, minus: KeyCode)
     axis
 }
 
+/// Labels used to move the camera after its target has been updated for the frame.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub enum CameraSystem {
+    /// Turns input into the controller's target.
+    Input,
+}
+
 #[derive(Default)]
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system(camera_movement_system.system())
-            .add_system(mouse_motion_system.system());
+        app.add_system(camera_movement_system.system().label(CameraSystem::Input))
+            .add_system(mouse_motion_system.system().label(CameraSystem::Input))
+            .add_system(mouse_wheel_system.system().label(CameraSystem::Input))
+            .add_system(camera_mode_system.system().label(CameraSystem::Input))
+            .add_system(cursor_grab_system.system().label(CameraSystem::Input))
+            .add_system(camera_follow_system.system().after(CameraSystem::Input));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrolling_up_dollies_forward() {
+        let mut options = CameraController::default();
+        let mut target = Transform::from_rotation(look_rotation(30.0, 10.0));
+
+        zoom(&mut options, &mut target, 2.0);
+
+        let forward = dolly_vector(&target.rotation);
+        let expected = forward * 2.0 * options.zoom_sensitivity;
+        assert!(target.translation.abs_diff_eq(expected, 1e-6));
+        assert!(target.translation.dot(forward) > 0.0);
+    }
+
+    #[test]
+    fn pitch_is_clamped_symmetrically() {
+        let mut options = CameraController::default();
+        let mut target = Transform::identity();
+
+        look(&mut options, &mut target, Vec2::new(0.0, 1.0e6), 1.0);
+        assert_eq!(options.pitch, 89.9);
+
+        look(&mut options, &mut target, Vec2::new(0.0, -1.0e6), 1.0);
+        assert_eq!(options.pitch, -89.9);
+        assert_eq!(options.min_pitch, -options.max_pitch);
+    }
+
+    #[test]
+    fn smoothing_approaches_the_target_over_several_steps() {
+        let target = Vec3::new(10.0, 0.0, 0.0);
+        let mut velocity = Vec3::ZERO;
+
+        let first = smooth_damp(Vec3::ZERO, target, &mut velocity, 0.3, 1.0 / 60.0);
+        assert!(first.x > 0.0 && first.x < target.x);
+
+        let mut current = first;
+        for _ in 0..600 {
+            let next = smooth_damp(current, target, &mut velocity, 0.3, 1.0 / 60.0);
+            // Critically damped, so it never overshoots
+            assert!(next.x >= current.x - 1e-4 && next.x <= target.x + 1e-4);
+            current = next;
+        }
+        assert!(current.abs_diff_eq(target, 1e-3));
+    }
+
+    #[test]
+    fn modifier_keys_scale_the_max_speed() {
+        let options = CameraController::default();
+        let mut input = Input::<KeyCode>::default();
+        assert_eq!(speed_factor(&input, &options), 1.0);
+
+        input.press(options.key_crawl);
+        assert_eq!(speed_factor(&input, &options), options.crawl_factor);
+
+        // Sprinting wins when both are held
+        input.press(options.key_sprint);
+        assert_eq!(speed_factor(&input, &options), options.sprint_factor);
+    }
+
+    #[test]
+    fn grabbing_the_cursor_looks_without_the_button() {
+        let mut options = CameraController::default();
+        assert!(!options.is_looking(false));
+        assert!(options.is_looking(true));
+
+        assert!(options.toggle_cursor_grab());
+        assert!(options.is_looking(false));
+
+        assert!(!options.toggle_cursor_grab());
+        assert!(!options.is_looking(false));
+
+        options.enabled = false;
+        assert!(!options.is_looking(true));
+    }
+
+    #[test]
+    fn keys_bound_to_two_actions_are_rejected() {
+        assert!(CameraController::default().validate_bindings().is_ok());
+
+        let options = CameraController {
+            key_crawl: KeyCode::W,
+            ..Default::default()
+        };
+        match options.validate_bindings() {
+            Err(CameraConfigError::DuplicateKey { key, first, second }) => {
+                assert_eq!(key, KeyCode::W);
+                assert_eq!(first, "key_forward");
+                assert_eq!(second, "key_crawl");
+            }
+            result => panic!("expected a duplicate key error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn config_overrides_only_the_listed_settings() {
+        let options: CameraController =
+            ron::de::from_str("(max_speed: 2.0, key_forward: Up)").unwrap();
+
+        assert_eq!(options.max_speed, 2.0);
+        assert_eq!(options.key_forward, KeyCode::Up);
+        assert_eq!(options.key_backward, KeyCode::S);
+    }
+
+    #[test]
+    fn orbit_drag_changes_yaw_at_a_fixed_distance() {
+        let mut options = CameraController {
+            mode: CameraMode::Orbit,
+            focus: Vec3::new(1.0, 2.0, 3.0),
+            orbit_radius: 4.0,
+            ..Default::default()
+        };
+        let mut target = Transform::identity();
+
+        look(&mut options, &mut target, Vec2::new(10.0, 0.0), 0.1);
+        let first = target.translation;
+        look(&mut options, &mut target, Vec2::new(10.0, 0.0), 0.1);
+
+        assert!((options.yaw + 2.0 * 10.0 * options.sensitivity * 0.1).abs() < 1e-4);
+        assert_eq!(options.pitch, 0.0);
+        assert!(!target.translation.abs_diff_eq(first, 1e-4));
+        assert!((first.distance(options.focus) - 4.0).abs() < 1e-4);
+        assert!((target.translation.distance(options.focus) - 4.0).abs() < 1e-4);
     }
 }
@@ -255,7 +255,13 @@ async fn load_gltf<'a, 'b>(
             .insert_bundle((Transform::identity(), GlobalTransform::identity()))
             .with_children(|parent| {
                 for node in scene.nodes() {
-                    let result = load_node(&node, parent, load_context, &buffer_data);
+                    let result = load_node(
+                        &node,
+                        parent,
+                        &GlobalTransform::identity(),
+                        load_context,
+                        &buffer_data,
+                    );
                     if result.is_err() {
                         err = Some(result);
                         return;
@@ -295,21 +301,29 @@ async fn load_gltf<'a, 'b>(
 fn load_material(material: &gltf::Material, load_context: &mut LoadContext) -> Handle<Material> {
     let material_label = material_label(&material);
 
-    load_context.set_labeled_asset(&material_label, LoadedAsset::new(Material::default()))
+    let material = Material {
+        emissive: Vec3::from(material.emissive_factor()),
+        ..Default::default()
+    };
+
+    load_context.set_labeled_asset(&material_label, LoadedAsset::new(material))
 }
 
+/// Spawns `gltf_node` and its children under `world_builder`. `parent_transform` is the world
+/// transform of the parent node, composed with each node's local transform so that mesh entities
+/// are placed correctly before the first transform propagation runs.
 fn load_node(
     gltf_node: &gltf::Node,
     world_builder: &mut WorldChildBuilder,
+    parent_transform: &GlobalTransform,
     load_context: &mut LoadContext,
     buffer_data: &[Vec<u8>],
 ) -> Result<(), GltfError> {
-    let transform = gltf_node.transform();
+    let transform =
+        Transform::from_matrix(Mat4::from_cols_array_2d(&gltf_node.transform().matrix()));
+    let global_transform = compose_transform(parent_transform, &transform);
     let mut gltf_error = None;
-    let mut node = world_builder.spawn_bundle((
-        Transform::from_matrix(Mat4::from_cols_array_2d(&transform.matrix())),
-        GlobalTransform::identity(),
-    ));
+    let mut node = world_builder.spawn_bundle((transform, global_transform));
 
     if let Some(name) = gltf_node.name() {
         node.insert(Name::new(name.to_string()));
@@ -384,16 +398,21 @@ fn load_node(
                 let material_asset_path =
                     AssetPath::new_ref(load_context.path(), Some(&material_label));
 
+                // Primitives sit at their node's origin, all the placement comes from the node
                 parent.spawn_bundle(MeshBundle {
                     mesh: load_context.get_handle(mesh_asset_path),
                     material: load_context.get_handle(material_asset_path),
+                    transform: Transform::identity(),
+                    global_transform,
                 });
             }
         }
 
         // append other nodes
         for child in gltf_node.children() {
-            if let Err(err) = load_node(&child, parent, load_context, buffer_data) {
+            if let Err(err) =
+                load_node(&child, parent, &global_transform, load_context, buffer_data)
+            {
                 gltf_error = Some(err);
                 return;
             }
@@ -406,6 +425,11 @@ fn load_node(
     }
 }
 
+/// World transform of a node with local `transform` under a parent at `parent`.
+fn compose_transform(parent: &GlobalTransform, transform: &Transform) -> GlobalTransform {
+    parent.mul_transform(*transform)
+}
+
 fn get_primitive_topology(mode: Mode) -> Result<PrimitiveTopology, GltfError> {
     match mode {
         Mode::Points => Ok(PrimitiveTopology::PointList),
@@ -567,3 +591,28 @@ impl<'a> DataUri<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn child_world_transform_is_the_product_of_the_hierarchy() {
+        let parent_local = Transform {
+            translation: Vec3::new(1.0, 2.0, 3.0),
+            rotation: Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+            scale: Vec3::splat(2.0),
+        };
+        let child_local = Transform::from_translation(Vec3::new(0.0, 0.0, 1.0));
+
+        let parent = compose_transform(&GlobalTransform::identity(), &parent_local);
+        let child = compose_transform(&parent, &child_local);
+
+        let expected = parent_local.compute_matrix() * child_local.compute_matrix();
+        assert!(child.compute_matrix().abs_diff_eq(expected, 1e-5));
+        // Rotating +z by a quarter turn around y points it at +x, doubled by the parent scale
+        assert!(child
+            .translation
+            .abs_diff_eq(Vec3::new(3.0, 2.0, 3.0), 1e-5));
+    }
+}
@@ -1,16 +1,32 @@
 use bevy::reflect::TypeUuid;
-use glam::Vec4;
+use glam::{Vec3, Vec4};
 
 #[derive(Debug, TypeUuid)]
 #[uuid = "dace545e-4bc6-4595-a79d-c224fc694975"]
 pub struct Material {
     pub base_color: Vec4,
+    /// Linear color emitted by the surface, scaled by `emissive_strength`.
+    pub emissive: Vec3,
+    pub emissive_strength: f32,
+}
+
+impl Material {
+    /// Radiance leaving the surface on its own, zero for materials that aren't lights.
+    pub fn emission(&self) -> Vec3 {
+        self.emissive * self.emissive_strength
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission() != Vec3::ZERO
+    }
 }
 
 impl Default for Material {
     fn default() -> Self {
         Material {
             base_color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+            emissive: Vec3::ZERO,
+            emissive_strength: 1.0,
         }
     }
 }
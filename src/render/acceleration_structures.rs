@@ -4,6 +4,7 @@ use crate::render::{
     util::ToErupt,
 };
 use erupt::vk;
+use glam::Mat4;
 
 #[derive(Clone)]
 pub struct AccelerationStructureInfo {
@@ -100,6 +101,16 @@ impl TransformMatrix {
     }
 }
 
+impl From<Mat4> for TransformMatrix {
+    /// Keeps the top three rows of `matrix`, Vulkan stores instance transforms row-major.
+    fn from(matrix: Mat4) -> Self {
+        let rows = matrix.transpose().to_cols_array_2d();
+        TransformMatrix {
+            matrix: [rows[0], rows[1], rows[2]],
+        }
+    }
+}
+
 impl Default for TransformMatrix {
     fn default() -> Self {
         Self::identity()
@@ -116,6 +127,14 @@ impl InstanceCustomIndexAndMask {
 
         InstanceCustomIndexAndMask(custom_index | ((mask as u32) << 24))
     }
+
+    pub fn custom_index(&self) -> u32 {
+        self.0 & 0x00ff_ffff
+    }
+
+    pub fn mask(&self) -> u8 {
+        (self.0 >> 24) as u8
+    }
 }
 
 impl Default for InstanceCustomIndexAndMask {
@@ -146,6 +165,14 @@ impl InstanceShaderBindingOffsetAndFlags {
             instance_shader_binding_offset | ((flags.bits() as u32) << 24),
         )
     }
+
+    pub fn instance_shader_binding_offset(&self) -> u32 {
+        self.0 & 0x00ff_ffff
+    }
+
+    pub fn flags(&self) -> vk::GeometryInstanceFlagsKHR {
+        vk::GeometryInstanceFlagsKHR::from_bits_truncate(self.0 >> 24)
+    }
 }
 
 impl Default for InstanceShaderBindingOffsetAndFlags {
@@ -176,4 +203,69 @@ impl AccelerationStructureInstance {
         self.transform = transform;
         self
     }
+
+    /// Sets the value the shaders read back as `gl_InstanceCustomIndexEXT`.
+    pub fn with_custom_index(mut self, custom_index: u32) -> Self {
+        self.custom_index_mask =
+            InstanceCustomIndexAndMask::new(custom_index, self.custom_index_mask.mask());
+        self
+    }
+
+    /// Selects the hit group record `instance_shader_binding_offset` records into the SBT hit
+    /// region, on top of the ray's own SBT offset and the geometry index.
+    pub fn with_shader_binding_offset(
+        mut self,
+        instance_shader_binding_offset: u32,
+        flags: vk::GeometryInstanceFlagsKHR,
+    ) -> Self {
+        self.shader_binding_offset_flags =
+            InstanceShaderBindingOffsetAndFlags::new(instance_shader_binding_offset, flags);
+        self
+    }
+
+    /// Sets the visibility mask, an instance with a mask of 0 is skipped by every ray.
+    pub fn with_mask(mut self, mask: u8) -> Self {
+        self.set_mask(mask);
+        self
+    }
+
+    /// Sets the visibility mask, an instance with a mask of 0 is skipped by every ray.
+    pub fn set_mask(&mut self, mask: u8) -> &mut Self {
+        self.custom_index_mask =
+            InstanceCustomIndexAndMask::new(self.custom_index_mask.custom_index(), mask);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU64;
+
+    fn instance() -> AccelerationStructureInstance {
+        AccelerationStructureInstance::new(DeviceAddress(NonZeroU64::new(0x1000).unwrap()))
+    }
+
+    #[test]
+    fn custom_index_and_mask_packing() {
+        let instance = instance().with_custom_index(7).with_mask(0x02);
+
+        assert_eq!(instance.custom_index_mask.0, 0x0200_0007);
+        assert_eq!(instance.custom_index_mask.custom_index(), 7);
+        assert_eq!(instance.custom_index_mask.mask(), 0x02);
+    }
+
+    #[test]
+    fn shader_binding_offset_and_flags_packing() {
+        let instance = instance()
+            .with_shader_binding_offset(3, vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE_KHR);
+
+        let packed = instance.shader_binding_offset_flags;
+        assert_eq!(packed.0, 0x0400_0003);
+        assert_eq!(packed.instance_shader_binding_offset(), 3);
+        assert_eq!(
+            packed.flags(),
+            vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE_KHR
+        );
+    }
 }
@@ -1,6 +1,8 @@
-use crate::render::{resources::Buffer, util::ToErupt};
+use crate::render::{device::Device, resources::Buffer, util::ToErupt};
+use crevice::std430::{AsStd430, Std430};
 use erupt::vk;
 use gpu_alloc::UsageFlags;
+use std::marker::PhantomData;
 use std::num::NonZeroU64;
 
 pub struct BufferInfo {
@@ -8,6 +10,8 @@ pub struct BufferInfo {
     pub size: u64,
     pub usage_flags: vk::BufferUsageFlags,
     pub allocation_flags: UsageFlags,
+    /// Attached to the buffer with `VK_EXT_debug_utils` in debug builds.
+    pub name: Option<&'static str>,
 }
 
 #[derive(Clone)]
@@ -27,6 +31,61 @@ impl BufferRegion {
             stride: None,
         }
     }
+
+    /// The `size` bytes starting `offset` bytes into this region, with the same stride. Panics if
+    /// they don't lie within the region.
+    pub fn sub(&self, offset: u64, size: u64) -> BufferRegion {
+        BufferRegion {
+            buffer: self.buffer.clone(),
+            offset: sub_region_offset(self.offset, self.size, offset, size),
+            size,
+            stride: self.stride,
+        }
+    }
+
+    pub fn device_address(&self) -> Option<DeviceAddress> {
+        self.buffer
+            .device_address()
+            .map(|address| address.advance(self.offset))
+    }
+}
+
+/// Host visible uniform buffer holding a single `T` with std430 layout.
+pub struct UniformBuffer<T: AsStd430> {
+    buffer: Buffer,
+    _marker: PhantomData<T>,
+}
+
+impl<T: AsStd430> UniformBuffer<T> {
+    pub fn new(device: &Device, value: &T, name: &'static str) -> Self {
+        let mut uniform_buffer = UniformBuffer {
+            buffer: device.create_buffer(BufferInfo {
+                align: 255,
+                size: Self::binding_size(),
+                usage_flags: vk::BufferUsageFlags::UNIFORM_BUFFER,
+                allocation_flags: UsageFlags::FAST_DEVICE_ACCESS | UsageFlags::HOST_ACCESS,
+                name: Some(name),
+            }),
+            _marker: PhantomData,
+        };
+        uniform_buffer.update(device, value);
+
+        uniform_buffer
+    }
+
+    /// Overwrites the whole buffer with `value`, the GPU must not be reading it.
+    pub fn update(&mut self, device: &Device, value: &T) {
+        device.write_buffer(&mut self.buffer, 0, value.as_std430().as_bytes());
+    }
+
+    /// Range of the uniform descriptor, the size of `T` with std430 layout.
+    pub fn binding_size() -> u64 {
+        std::mem::size_of::<T::Std430Type>() as u64
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -37,8 +96,13 @@ impl DeviceAddress {
         NonZeroU64::new(address).map(DeviceAddress).unwrap()
     }
 
-    pub fn offset(&mut self, offset: u64) -> DeviceAddress {
-        let value = self.0.get().checked_add(offset).unwrap();
+    /// Returns the address `offset` bytes past this one, panicking if it would overflow.
+    pub fn advance(&self, offset: u64) -> DeviceAddress {
+        let value = self
+            .0
+            .get()
+            .checked_add(offset)
+            .expect("Device address overflow");
         DeviceAddress(unsafe { NonZeroU64::new_unchecked(value) })
     }
 }
@@ -58,3 +122,60 @@ impl ToErupt<vk::DeviceOrHostAddressConstKHR> for DeviceAddress {
         }
     }
 }
+
+/// Offset in the buffer of the `size` bytes starting `offset` bytes into a region at
+/// `region_offset` spanning `region_size` bytes. Panics if they don't lie within the region.
+fn sub_region_offset(region_offset: u64, region_size: u64, offset: u64, size: u64) -> u64 {
+    let end = offset
+        .checked_add(size)
+        .expect("Buffer sub-region overflow");
+    assert!(
+        end <= region_size,
+        "sub-region {}..{} is out of bounds of a {} byte region",
+        offset,
+        end,
+        region_size
+    );
+
+    region_offset + offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_region_offset_is_absolute() {
+        assert_eq!(sub_region_offset(256, 128, 64, 64), 320);
+        assert_eq!(sub_region_offset(256, 128, 0, 0), 256);
+        assert_eq!(sub_region_offset(256, 128, 128, 0), 384);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn sub_region_past_the_end_panics() {
+        sub_region_offset(256, 128, 64, 65);
+    }
+
+    #[test]
+    fn advancing_an_address_adds_the_offset() {
+        assert_eq!(DeviceAddress::new(256).advance(64).0.get(), 320);
+        assert_eq!(DeviceAddress::new(u64::MAX).advance(0).0.get(), u64::MAX);
+        assert_eq!(
+            DeviceAddress::new(1).advance(u64::MAX - 1).0.get(),
+            u64::MAX
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Device address overflow")]
+    fn advancing_past_u64_max_panics() {
+        DeviceAddress::new(u64::MAX).advance(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "overflow")]
+    fn sub_region_overflow_panics() {
+        sub_region_offset(0, u64::MAX, 1, u64::MAX);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::render::{
+    buffer::{BufferInfo, BufferRegion},
+    device::Device,
+    resources::Buffer,
+    util::align_up,
+};
+use bytemuck::Pod;
+use erupt::vk;
+use std::ops::Range;
+
+/// Allocator handing out [`BufferRegion`]s from a few large buffers, so many small meshes don't
+/// each need their own allocation. Regions are returned with [`BufferArena::free`] once the GPU
+/// is done with them, and their space is reused by later allocations.
+pub struct BufferArena {
+    block_size: u64,
+    usage_flags: vk::BufferUsageFlags,
+    allocation_flags: gpu_alloc::UsageFlags,
+    blocks: Vec<(Buffer, FreeList)>,
+}
+
+impl BufferArena {
+    /// Every sub-allocation starts at a multiple of this many bytes.
+    pub const ALIGNMENT: u64 = 256;
+
+    pub fn new(
+        block_size: u64,
+        usage_flags: vk::BufferUsageFlags,
+        allocation_flags: gpu_alloc::UsageFlags,
+    ) -> Self {
+        BufferArena {
+            block_size,
+            usage_flags,
+            allocation_flags,
+            blocks: vec![],
+        }
+    }
+
+    pub fn allocate(&mut self, device: &Device, size: u64) -> BufferRegion {
+        let allocation = self
+            .blocks
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, (_, free_list))| {
+                free_list
+                    .allocate(size, Self::ALIGNMENT - 1)
+                    .map(|offset| (index, offset))
+            });
+
+        let (index, offset) = match allocation {
+            Some(allocation) => allocation,
+            None => {
+                let block_size = size.max(self.block_size);
+                let buffer = device.create_buffer(BufferInfo {
+                    align: Self::ALIGNMENT - 1,
+                    size: block_size,
+                    usage_flags: self.usage_flags,
+                    allocation_flags: self.allocation_flags,
+                    name: None,
+                });
+                let mut free_list = FreeList::new(block_size);
+                let offset = free_list.allocate(size, Self::ALIGNMENT - 1).unwrap();
+                self.blocks.push((buffer, free_list));
+                (self.blocks.len() - 1, offset)
+            }
+        };
+
+        BufferRegion {
+            buffer: self.blocks[index].0.clone(),
+            offset,
+            size,
+            stride: None,
+        }
+    }
+
+    pub fn allocate_with_data<T>(&mut self, device: &Device, data: &[T]) -> BufferRegion
+    where
+        T: Pod,
+    {
+        let mut region = self.allocate(device, std::mem::size_of_val(data) as u64);
+        device.write_buffer(&mut region.buffer, region.offset, data);
+        region
+    }
+
+    /// Makes the space of `region`, handed out by this arena, available again. The GPU has to be
+    /// done with it.
+    pub fn free(&mut self, region: &BufferRegion) {
+        let (_, free_list) = self
+            .blocks
+            .iter_mut()
+            .find(|(buffer, _)| buffer.handle() == region.buffer.handle())
+            .expect("region wasn't allocated from this arena");
+        free_list.free(region.offset..region.offset + region.size);
+    }
+
+    /// Bytes not handed out across all blocks.
+    pub fn free_space(&self) -> u64 {
+        self.blocks
+            .iter()
+            .map(|(_, free_list)| free_list.free_space())
+            .sum()
+    }
+
+    /// Makes the whole arena available again. The blocks are kept and reused, so every region
+    /// handed out before has to be dead by now.
+    pub fn reset(&mut self) {
+        for (buffer, free_list) in &mut self.blocks {
+            *free_list = FreeList::new(buffer.info().size);
+        }
+    }
+}
+
+/// The unallocated ranges of a block, sorted and never adjacent.
+#[derive(Debug)]
+struct FreeList {
+    ranges: Vec<Range<u64>>,
+}
+
+impl FreeList {
+    fn new(size: u64) -> Self {
+        FreeList {
+            ranges: vec![Range {
+                start: 0,
+                end: size,
+            }],
+        }
+    }
+
+    /// Takes `size` bytes from the first free range they fit in once aligned to `align_mask`.
+    /// Returns their offset, `None` if no range is large enough.
+    fn allocate(&mut self, size: u64, align_mask: u64) -> Option<u64> {
+        let (index, offset) = self.ranges.iter().enumerate().find_map(|(index, range)| {
+            let offset = align_up(align_mask, range.start)?;
+            let end = offset.checked_add(size)?;
+            if end <= range.end {
+                Some((index, offset))
+            } else {
+                None
+            }
+        })?;
+
+        // The padding before the aligned offset and the space after the allocation stay free
+        let range = self.ranges.remove(index);
+        let remaining = [range.start..offset, offset + size..range.end];
+        let remaining = remaining.iter().filter(|range| !range.is_empty()).cloned();
+        self.ranges.splice(index..index, remaining);
+
+        Some(offset)
+    }
+
+    /// Returns `range` to the list, merging it with the free ranges it touches.
+    fn free(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let index = self
+            .ranges
+            .iter()
+            .position(|free| free.start >= range.end)
+            .unwrap_or(self.ranges.len());
+        debug_assert!(
+            index == 0 || self.ranges[index - 1].end <= range.start,
+            "freeing {:?} twice",
+            range
+        );
+
+        let mut merged = range;
+        let mut first = index;
+        if index > 0 && self.ranges[index - 1].end == merged.start {
+            first -= 1;
+            merged.start = self.ranges[first].start;
+        }
+        let mut last = index;
+        if index < self.ranges.len() && self.ranges[index].start == merged.end {
+            merged.end = self.ranges[index].end;
+            last += 1;
+        }
+        self.ranges.splice(first..last, std::iter::once(merged));
+    }
+
+    fn free_space(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALIGN_MASK: u64 = BufferArena::ALIGNMENT - 1;
+
+    #[test]
+    fn sequential_allocations_are_aligned_and_disjoint() {
+        let mut free_list = FreeList::new(4096);
+        let sizes = [100, 300, 256];
+
+        let ranges = sizes
+            .iter()
+            .map(|&size| {
+                let offset = free_list.allocate(size, ALIGN_MASK).unwrap();
+                offset..offset + size
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(ranges, vec![0..100, 256..556, 768..1024]);
+        for range in &ranges {
+            assert_eq!(range.start % BufferArena::ALIGNMENT, 0);
+        }
+        for pair in ranges.windows(2) {
+            assert!(pair[0].end <= pair[1].start);
+        }
+        assert_eq!(free_list.free_space(), 4096 - 656);
+    }
+
+    #[test]
+    fn allocations_that_dont_fit_fail() {
+        let mut free_list = FreeList::new(512);
+
+        assert_eq!(free_list.allocate(200, ALIGN_MASK), Some(0));
+        // 200..512 is free but the aligned offset leaves only 256 bytes
+        assert_eq!(free_list.allocate(300, ALIGN_MASK), None);
+        assert_eq!(free_list.allocate(256, ALIGN_MASK), Some(256));
+    }
+
+    #[test]
+    fn freed_space_is_merged_and_reused() {
+        let mut free_list = FreeList::new(1024);
+        let first = free_list.allocate(256, ALIGN_MASK).unwrap();
+        let second = free_list.allocate(256, ALIGN_MASK).unwrap();
+        let third = free_list.allocate(256, ALIGN_MASK).unwrap();
+
+        free_list.free(first..first + 256);
+        free_list.free(third..third + 256);
+        assert_eq!(free_list.ranges, vec![0..256, 512..1024]);
+
+        // Freeing the middle one joins everything back into a single range
+        free_list.free(second..second + 256);
+        assert_eq!(free_list.ranges, vec![0..1024]);
+
+        // Loading and unloading the same mesh over and over doesn't run out of space
+        for _ in 0..100 {
+            let offset = free_list.allocate(1000, ALIGN_MASK).unwrap();
+            free_list.free(offset..offset + 1000);
+        }
+        assert_eq!(free_list.free_space(), 1024);
+    }
+}
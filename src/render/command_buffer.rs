@@ -6,17 +6,18 @@ use crate::render::{
     buffer::BufferRegion,
     device::Device,
     encoder::Command,
-    image::ImageMemoryBarrier,
+    image::{Image, ImageMemoryBarrier, ImageSubresourceLayers, ImageSubresourceRange},
     pipeline::ShaderBindingTable,
     render_pass::{ClearValue, DEFAULT_ATTACHMENT_COUNT},
     resources::{
-        Buffer, DescriptorSet, Framebuffer, GraphicsPipeline, PipelineLayout, RayTracingPipeline,
-        RenderPass,
+        Buffer, ComputePipeline, DescriptorSet, Framebuffer, GraphicsPipeline, PipelineLayout,
+        QueryPool, RayTracingPipeline, RenderPass,
     },
     util::ToErupt,
 };
 use erupt::{vk, DeviceLoader};
 use smallvec::SmallVec;
+use std::ffi::CString;
 use std::ops::Range;
 
 pub struct CommandBuffer {
@@ -37,6 +38,17 @@ impl CommandBuffer {
     }
 
     pub fn write(&mut self, device: &Device, commands: &[Command<'_>]) {
+        if cfg!(debug_assertions) {
+            for command in commands {
+                if let Command::BindDescriptorSets {
+                    descriptor_sets, ..
+                } = command
+                {
+                    device.validate_descriptor_sets(descriptor_sets);
+                }
+            }
+        }
+
         let device = device.handle();
         if !self.recording {
             unsafe {
@@ -65,6 +77,9 @@ impl CommandBuffer {
                 Command::BindRayTracingPipeline { pipeline } => {
                     self.bind_raytracing_pipeline(device, pipeline)
                 }
+                Command::BindComputePipeline { pipeline } => {
+                    self.bind_compute_pipeline(device, pipeline)
+                }
                 Command::BindDescriptorSets {
                     bind_point,
                     layout,
@@ -90,11 +105,61 @@ impl CommandBuffer {
                     vertex_offset,
                     ref instances,
                 } => self.draw_indexed(device, indices, vertex_offset, instances),
+                Command::DrawIndexedIndirect {
+                    buffer,
+                    offset,
+                    draw_count,
+                    stride,
+                } => self.draw_indexed_indirect(device, buffer, offset, draw_count, stride),
                 Command::UpdateBuffer {
                     buffer,
                     offset,
                     data,
                 } => self.update_buffer(device, buffer, offset, data),
+                Command::CopyBuffer {
+                    src,
+                    src_offset,
+                    dst,
+                    dst_offset,
+                    size,
+                } => self.copy_buffer(device, src, src_offset, dst, dst_offset, size),
+                Command::CopyImageToBuffer {
+                    image,
+                    layout,
+                    buffer,
+                    buffer_offset,
+                } => self.copy_image_to_buffer(device, image, layout, buffer, buffer_offset),
+                Command::CopyBufferToImage {
+                    buffer,
+                    image,
+                    layout,
+                    subresource,
+                    offset,
+                    extent,
+                } => self.copy_buffer_to_image(
+                    device,
+                    buffer,
+                    image,
+                    layout,
+                    subresource,
+                    offset,
+                    extent,
+                ),
+                Command::ClearColorImage {
+                    image,
+                    layout,
+                    color,
+                    ranges,
+                } => self.clear_color_image(device, image, layout, color, ranges),
+                Command::ResetQueryPool {
+                    query_pool,
+                    ref queries,
+                } => self.reset_query_pool(device, query_pool, queries),
+                Command::WriteTimestamp {
+                    stage,
+                    query_pool,
+                    query,
+                } => self.write_timestamp(device, stage, query_pool, query),
                 Command::BindVertexBuffers { first, buffers } => {
                     self.bind_vertex_buffers(device, first, buffers)
                 }
@@ -109,7 +174,12 @@ impl CommandBuffer {
                 Command::TraceRays {
                     shader_binding_table,
                     extent,
-                } => self.trace_rays(device, shader_binding_table, extent),
+                    depth,
+                } => self.trace_rays(device, shader_binding_table, extent, depth),
+                Command::Dispatch { group_count } => self.dispatch(device, group_count),
+                Command::DispatchIndirect { buffer, offset } => {
+                    self.dispatch_indirect(device, buffer, offset)
+                }
                 Command::PipelineBarrier {
                     src,
                     dst,
@@ -130,6 +200,10 @@ impl CommandBuffer {
                     offset,
                     data,
                 } => self.push_constants(device, layout, stages, offset, data),
+                Command::BeginDebugLabel { name, color } => {
+                    self.begin_debug_label(device, name, color)
+                }
+                Command::EndDebugLabel => self.end_debug_label(device),
             }
         }
 
@@ -205,6 +279,16 @@ impl CommandBuffer {
         }
     }
 
+    fn bind_compute_pipeline(&mut self, device: &DeviceLoader, pipeline: &ComputePipeline) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                self.handle,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.handle(),
+            )
+        }
+    }
+
     fn bind_descriptor_sets(
         &mut self,
         device: &DeviceLoader,
@@ -268,6 +352,25 @@ impl CommandBuffer {
         }
     }
 
+    fn draw_indexed_indirect(
+        &mut self,
+        device: &DeviceLoader,
+        buffer: &Buffer,
+        offset: u64,
+        draw_count: u32,
+        stride: u32,
+    ) {
+        unsafe {
+            device.cmd_draw_indexed_indirect(
+                self.handle,
+                buffer.handle(),
+                offset,
+                draw_count,
+                stride,
+            )
+        }
+    }
+
     fn pipeline_barrier(
         &mut self,
         device: &DeviceLoader,
@@ -290,48 +393,34 @@ impl CommandBuffer {
                 &image_barriers
                     .iter()
                     .map(|image_barrier| {
-                        vk::ImageMemoryBarrierBuilder::new()
-                            .image(image_barrier.image.handle())
-                            .src_access_mask(src_access_mask)
-                            .dst_access_mask(src_access_mask)
-                            .old_layout(
-                                image_barrier
-                                    .old_layout
-                                    .unwrap_or(vk::ImageLayout::UNDEFINED),
-                            )
-                            .new_layout(image_barrier.new_layout)
-                            .src_queue_family_index(
-                                image_barrier
-                                    .family_transfer
-                                    .as_ref()
-                                    .map(|range| range.start)
-                                    .unwrap_or(vk::QUEUE_FAMILY_IGNORED),
-                            )
-                            .dst_queue_family_index(
-                                image_barrier
-                                    .family_transfer
-                                    .as_ref()
-                                    .map(|range| range.end)
-                                    .unwrap_or(vk::QUEUE_FAMILY_IGNORED),
-                            )
-                            .subresource_range(image_barrier.subresource.to_erupt())
+                        image_memory_barrier(image_barrier, src_access_mask, dst_access_mask)
                     })
                     .collect::<Vec<_>>(),
             )
         }
     }
 
+    fn dispatch(&mut self, device: &DeviceLoader, group_count: [u32; 3]) {
+        let [x, y, z] = group_count;
+        unsafe { device.cmd_dispatch(self.handle, x, y, z) }
+    }
+
+    fn dispatch_indirect(&mut self, device: &DeviceLoader, buffer: &Buffer, offset: u64) {
+        unsafe { device.cmd_dispatch_indirect(self.handle, buffer.handle(), offset) }
+    }
+
     fn trace_rays(
         &mut self,
         device: &DeviceLoader,
         shader_binding_table: &ShaderBindingTable,
         extent: vk::Extent2D,
+        depth: u32,
     ) {
         let to_erupt = |buffer_region: &BufferRegion| {
-            let device_address = buffer_region.buffer.device_address().unwrap().0.get();
+            let device_address = buffer_region.device_address().unwrap();
 
             vk::StridedDeviceAddressRegionKHRBuilder::new()
-                .device_address(device_address + buffer_region.offset)
+                .device_address(device_address.0.get())
                 .stride(buffer_region.stride.unwrap())
                 .size(buffer_region.size)
                 .build()
@@ -357,7 +446,7 @@ impl CommandBuffer {
                     .map_or(vk::StridedDeviceAddressRegionKHR::default(), to_erupt),
                 extent.width,
                 extent.height,
-                1,
+                depth,
             );
         }
     }
@@ -504,6 +593,150 @@ impl CommandBuffer {
         }
     }
 
+    fn copy_buffer(
+        &mut self,
+        device: &DeviceLoader,
+        src: &Buffer,
+        src_offset: u64,
+        dst: &Buffer,
+        dst_offset: u64,
+        size: u64,
+    ) {
+        unsafe {
+            device.cmd_copy_buffer(
+                self.handle,
+                src.handle(),
+                dst.handle(),
+                &[vk::BufferCopyBuilder::new()
+                    .src_offset(src_offset)
+                    .dst_offset(dst_offset)
+                    .size(size)],
+            )
+        }
+    }
+
+    fn copy_image_to_buffer(
+        &mut self,
+        device: &DeviceLoader,
+        image: &Image,
+        layout: vk::ImageLayout,
+        buffer: &Buffer,
+        buffer_offset: u64,
+    ) {
+        let extent = image.info().extent;
+        let region = vk::BufferImageCopyBuilder::new()
+            .buffer_offset(buffer_offset)
+            // Zero row length and image height mean the rows are tightly packed
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            });
+
+        unsafe {
+            device.cmd_copy_image_to_buffer(
+                self.handle,
+                image.handle(),
+                layout,
+                buffer.handle(),
+                &[region],
+            )
+        }
+    }
+
+    fn copy_buffer_to_image(
+        &mut self,
+        device: &DeviceLoader,
+        buffer: &Buffer,
+        image: &Image,
+        layout: vk::ImageLayout,
+        subresource: &ImageSubresourceLayers,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+    ) {
+        let region = vk::BufferImageCopyBuilder::new()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: subresource.aspect,
+                mip_level: subresource.level,
+                base_array_layer: subresource.first_layer,
+                layer_count: subresource.layer_count,
+            })
+            .image_offset(offset)
+            .image_extent(extent);
+
+        unsafe {
+            device.cmd_copy_buffer_to_image(
+                self.handle,
+                buffer.handle(),
+                image.handle(),
+                layout,
+                &[region],
+            )
+        }
+    }
+
+    fn reset_query_pool(
+        &mut self,
+        device: &DeviceLoader,
+        query_pool: &QueryPool,
+        queries: &Range<u32>,
+    ) {
+        unsafe {
+            device.cmd_reset_query_pool(
+                self.handle,
+                query_pool.handle(),
+                queries.start,
+                queries.len() as u32,
+            )
+        }
+    }
+
+    fn write_timestamp(
+        &mut self,
+        device: &DeviceLoader,
+        stage: vk::PipelineStageFlagBits,
+        query_pool: &QueryPool,
+        query: u32,
+    ) {
+        unsafe { device.cmd_write_timestamp(self.handle, stage, query_pool.handle(), query) }
+    }
+
+    fn clear_color_image(
+        &mut self,
+        device: &DeviceLoader,
+        image: &Image,
+        layout: vk::ImageLayout,
+        color: [f32; 4],
+        ranges: &[ImageSubresourceRange],
+    ) {
+        let ranges = ranges
+            .iter()
+            .map(|range| range.to_erupt().into_builder())
+            .collect::<SmallVec<[_; 4]>>();
+
+        unsafe {
+            device.cmd_clear_color_image(
+                self.handle,
+                image.handle(),
+                layout,
+                &vk::ClearColorValue { float32: color },
+                &ranges[..],
+            )
+        }
+    }
+
     fn push_constants(
         &mut self,
         device: &DeviceLoader,
@@ -523,4 +756,169 @@ impl CommandBuffer {
             )
         }
     }
+
+    fn begin_debug_label(&mut self, device: &DeviceLoader, name: &str, color: [f32; 4]) {
+        // VK_EXT_debug_utils is only enabled in debug builds
+        if !cfg!(debug_assertions) {
+            return;
+        }
+
+        let name = CString::new(name).unwrap();
+        unsafe {
+            device.cmd_begin_debug_utils_label_ext(
+                self.handle,
+                &vk::DebugUtilsLabelEXTBuilder::new()
+                    .label_name(&name)
+                    .color(color),
+            )
+        }
+    }
+
+    fn end_debug_label(&mut self, device: &DeviceLoader) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+
+        unsafe { device.cmd_end_debug_utils_label_ext(self.handle) }
+    }
+}
+
+/// The Vulkan barrier for `barrier` inside a `pipeline_barrier` with the given access masks,
+/// which the image barrier's own [`ImageMemoryBarrier::access`] overrides.
+fn image_memory_barrier<'b>(
+    barrier: &ImageMemoryBarrier,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> vk::ImageMemoryBarrierBuilder<'b> {
+    vk::ImageMemoryBarrierBuilder::new()
+        .image(barrier.image.handle())
+        .src_access_mask(
+            barrier
+                .access
+                .as_ref()
+                .map(|range| range.start)
+                .unwrap_or(src_access_mask),
+        )
+        .dst_access_mask(
+            barrier
+                .access
+                .as_ref()
+                .map(|range| range.end)
+                .unwrap_or(dst_access_mask),
+        )
+        .old_layout(barrier.old_layout.unwrap_or(vk::ImageLayout::UNDEFINED))
+        .new_layout(barrier.new_layout)
+        .src_queue_family_index(
+            barrier
+                .family_transfer
+                .as_ref()
+                .map(|range| range.start)
+                .unwrap_or(vk::QUEUE_FAMILY_IGNORED),
+        )
+        .dst_queue_family_index(
+            barrier
+                .family_transfer
+                .as_ref()
+                .map(|range| range.end)
+                .unwrap_or(vk::QUEUE_FAMILY_IGNORED),
+        )
+        .subresource_range(barrier.subresource.to_erupt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::image::ImageInfo;
+
+    fn image() -> Image {
+        Image::new(
+            ImageInfo {
+                extent: vk::Extent2D {
+                    width: 4,
+                    height: 4,
+                },
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                mip_levels: 1,
+                array_layers: 1,
+                samples: vk::SampleCountFlagBits::_1,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                flags: vk::ImageCreateFlags::empty(),
+                name: None,
+            },
+            vk::Image::null(),
+            None,
+        )
+    }
+
+    #[test]
+    fn image_barriers_use_the_dst_mask_for_dst_access() {
+        let image = image();
+        let barrier = ImageMemoryBarrier::transition_whole(
+            &image,
+            vk::ImageLayout::GENERAL..vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        let recorded = image_memory_barrier(
+            &barrier,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        assert_eq!(recorded.src_access_mask, vk::AccessFlags::SHADER_WRITE);
+        assert_eq!(recorded.dst_access_mask, vk::AccessFlags::SHADER_READ);
+        assert_eq!(recorded.old_layout, vk::ImageLayout::GENERAL);
+        assert_eq!(
+            recorded.new_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+    }
+
+    #[test]
+    fn explicit_image_barrier_masks_override_the_global_ones() {
+        let image = image();
+        let barrier = ImageMemoryBarrier::transition_whole(
+            &image,
+            vk::ImageLayout::GENERAL..vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+        .with_access(vk::AccessFlags::TRANSFER_WRITE..vk::AccessFlags::INPUT_ATTACHMENT_READ);
+
+        let recorded = image_memory_barrier(
+            &barrier,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        assert_eq!(recorded.src_access_mask, vk::AccessFlags::TRANSFER_WRITE);
+        assert_eq!(
+            recorded.dst_access_mask,
+            vk::AccessFlags::INPUT_ATTACHMENT_READ
+        );
+    }
+
+    #[test]
+    fn queue_transfers_emit_the_family_indices() {
+        let image = image();
+        let transfer = ImageMemoryBarrier::queue_transfer(
+            &image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL..vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            1,
+            0,
+        );
+        let transition = ImageMemoryBarrier::transition_whole(
+            &image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL..vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        let masks = (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+        );
+        let recorded = image_memory_barrier(&transfer, masks.0, masks.1);
+        assert_eq!(recorded.src_queue_family_index, 1);
+        assert_eq!(recorded.dst_queue_family_index, 0);
+
+        let recorded = image_memory_barrier(&transition, masks.0, masks.1);
+        assert_eq!(recorded.src_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+        assert_eq!(recorded.dst_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+    }
 }
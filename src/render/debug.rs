@@ -45,6 +45,36 @@ impl DebugMessenger {
     }
 }
 
+/// A Vulkan handle that can be given a name with `vkSetDebugUtilsObjectNameEXT`.
+pub trait DebugObject: Copy {
+    const OBJECT_TYPE: vk::ObjectType;
+
+    fn object_handle(self) -> u64;
+}
+
+macro_rules! impl_debug_object {
+    ($($handle:ident => $object_type:ident,)*) => {
+        $(
+            impl DebugObject for vk::$handle {
+                const OBJECT_TYPE: vk::ObjectType = vk::ObjectType::$object_type;
+
+                fn object_handle(self) -> u64 {
+                    self.0
+                }
+            }
+        )*
+    };
+}
+
+impl_debug_object! {
+    Buffer => BUFFER,
+    Image => IMAGE,
+    ImageView => IMAGE_VIEW,
+    Sampler => SAMPLER,
+    Pipeline => PIPELINE,
+    AccelerationStructureKHR => ACCELERATION_STRUCTURE_KHR,
+}
+
 unsafe extern "system" fn debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagBitsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
@@ -33,6 +33,23 @@ pub enum Descriptors<'a> {
     AccelerationStructure(&'a [AccelerationStructure]),
 }
 
+impl Descriptors<'_> {
+    pub fn descriptor_type(&self) -> DescriptorType {
+        match self {
+            Self::Sampler(_) => DescriptorType::Sampler,
+            Self::CombinedImageSampler(_) => DescriptorType::CombinedImageSampler,
+            Self::SampledImage(_) => DescriptorType::SampledImage,
+            Self::StorageImage(_) => DescriptorType::StorageImage,
+            Self::UniformBuffer(_) => DescriptorType::UniformBuffer,
+            Self::StorageBuffer(_) => DescriptorType::StorageBuffer,
+            Self::UniformBufferDynamic(_) => DescriptorType::UniformBufferDynamic,
+            Self::StorageBufferDynamic(_) => DescriptorType::StorageBufferDynamic,
+            Self::InputAttachment(_) => DescriptorType::InputAttachment,
+            Self::AccelerationStructure(_) => DescriptorType::AccelerationStructure,
+        }
+    }
+}
+
 pub struct CopyDescriptorSet<'a> {
     pub src: &'a DescriptorSet,
     pub src_binding: u32,
@@ -182,3 +199,31 @@ impl Deref for DescriptorSizes {
         self.as_slice()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_writes_use_the_type_of_their_descriptors() {
+        let types = [
+            Descriptors::UniformBuffer(&[]),
+            Descriptors::StorageBuffer(&[]),
+            Descriptors::UniformBufferDynamic(&[]),
+            Descriptors::StorageBufferDynamic(&[]),
+        ]
+        .iter()
+        .map(|descriptors| descriptors.descriptor_type().to_erupt())
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            types,
+            [
+                vk::DescriptorType::UNIFORM_BUFFER,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+            ]
+        );
+    }
+}
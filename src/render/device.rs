@@ -1,37 +1,42 @@
-use crate::render::image::ImageSubresourceLayers;
 use crate::render::{
     acceleration_structures::{
         AccelerationStructureBuildSizesInfo, AccelerationStructureGeometryInfo,
         AccelerationStructureInfo, AccelerationStructureLevel,
     },
     buffer::{BufferInfo, BufferRegion, DeviceAddress},
+    debug::DebugObject,
     descriptor::{
         CopyDescriptorSet, DescriptorSetInfo, DescriptorSetLayoutInfo, DescriptorSizes,
         Descriptors, WriteDescriptorSet,
     },
     framebuffer::FramebufferInfo,
-    image::{Image, ImageInfo, ImageView, ImageViewInfo},
+    image::{
+        srgb_to_linear, Image, ImageInfo, ImageMemoryBarrier, ImageSubresourceLayers,
+        ImageSubresourceRange, ImageView, ImageViewInfo, SamplerInfo, TextureColorSpace,
+    },
     physical_device::PhysicalDevice,
     pipeline::{
-        GraphicsPipelineInfo, PipelineLayoutInfo, RayTracingPipelineInfo,
-        RayTracingShaderGroupInfo, ShaderBindingTable, ShaderBindingTableInfo,
+        ComputePipelineInfo, GraphicsPipelineInfo, PipelineError, PipelineLayoutInfo,
+        RayTracingPipelineInfo, RayTracingShaderGroupInfo, ShaderBindingTable,
+        ShaderBindingTableInfo,
     },
+    queue::Queue,
     render_pass::RenderPassInfo,
     resources::{
-        AccelerationStructure, Buffer, DescriptorSet, DescriptorSetLayout, Fence, Framebuffer,
-        GraphicsPipeline, PipelineLayout, RayTracingPipeline, RenderPass, Sampler, Semaphore,
-        ShaderModule,
+        AccelerationStructure, Buffer, ComputePipeline, DescriptorSet, DescriptorSetLayout, Fence,
+        Framebuffer, GraphicsPipeline, PipelineLayout, QueryPool, RayTracingPipeline, RenderPass,
+        Sampler, Semaphore, ShaderModule,
     },
-    shader::ShaderModuleInfo,
+    scratch_pool,
+    shader::{Shader, ShaderModuleInfo},
     surface::Surface,
     swapchain::Swapchain,
-    util::{align_up, ToErupt},
+    util::{align_down, align_up, ToErupt},
 };
-use bumpalo::Bump;
 use crevice::internal::bytemuck;
 use crevice::internal::bytemuck::Pod;
 use erupt::{vk, DeviceLoader, ExtendableFromConst, InstanceLoader};
-use gpu_alloc::{GpuAllocator, UsageFlags};
+use gpu_alloc::{GpuAllocator, MemoryPropertyFlags, UsageFlags};
 use gpu_alloc_erupt::EruptMemoryDevice;
 use parking_lot::Mutex;
 use slab::Slab;
@@ -39,6 +44,8 @@ use smallvec::SmallVec;
 use std::convert::TryFrom;
 use std::ffi::CString;
 use std::ops::Range;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 struct ImageUpload {
@@ -60,6 +67,7 @@ pub struct DeviceInner {
     swapchains: Mutex<Slab<vk::SwapchainKHR>>,
     semaphores: Mutex<Slab<vk::Semaphore>>,
     fences: Mutex<Slab<vk::Fence>>,
+    query_pools: Mutex<Slab<vk::QueryPool>>,
     framebuffers: Mutex<Slab<vk::Framebuffer>>,
     images: Mutex<Slab<vk::Image>>,
     image_views: Mutex<Slab<vk::ImageView>>,
@@ -73,6 +81,9 @@ pub struct DeviceInner {
     acceleration_structures: Mutex<Slab<vk::AccelerationStructureKHR>>,
 
     image_uploads: Mutex<Slab<ImageUpload>>,
+
+    /// Set by [`Device::cleanup`], after which nothing may be called on the device anymore.
+    destroyed: AtomicBool,
 }
 
 #[derive(Clone)]
@@ -102,6 +113,7 @@ impl Device {
                 swapchains: Mutex::new(Slab::with_capacity(1024)),
                 semaphores: Mutex::new(Slab::with_capacity(1024)),
                 fences: Mutex::new(Slab::with_capacity(1024)),
+                query_pools: Mutex::new(Slab::with_capacity(1024)),
                 framebuffers: Mutex::new(Slab::with_capacity(1024)),
                 images: Mutex::new(Slab::with_capacity(1024)),
                 image_views: Mutex::new(Slab::with_capacity(1024)),
@@ -114,6 +126,7 @@ impl Device {
                 shader_modules: Mutex::new(Slab::with_capacity(1024)),
                 acceleration_structures: Mutex::new(Slab::with_capacity(1024)),
                 image_uploads: Mutex::new(Slab::with_capacity(1024)),
+                destroyed: AtomicBool::new(false),
             }),
         }
     }
@@ -146,6 +159,12 @@ impl Device {
                 .iter()
                 .for_each(|(_, &fence)| device.destroy_fence(Some(fence), None));
 
+            self.inner
+                .query_pools
+                .lock()
+                .iter()
+                .for_each(|(_, &query_pool)| device.destroy_query_pool(Some(query_pool), None));
+
             self.inner
                 .framebuffers
                 .lock()
@@ -218,10 +237,45 @@ impl Device {
                 },
             );
 
-            self.instance().destroy_instance(None);
-
+            // The instance outlives the device and is destroyed by the renderer
             self.handle().destroy_device(None)
         }
+
+        self.inner.destroyed.store(true, Ordering::Release);
+    }
+
+    pub fn is_destroyed(&self) -> bool {
+        self.inner.destroyed.load(Ordering::Acquire)
+    }
+
+    pub fn physical_device(&self) -> &PhysicalDevice {
+        &self.inner.physical_device
+    }
+
+    /// Alignment mask for acceleration structure scratch buffers, which need both the usual 256
+    /// byte alignment and `minAccelerationStructureScratchOffsetAlignment`.
+    pub fn scratch_align_mask(&self) -> u64 {
+        let alignment = self
+            .physical_device()
+            .info()
+            .accel_properties
+            .min_acceleration_structure_scratch_offset_alignment as u64;
+
+        scratch_pool::align_mask(alignment)
+    }
+
+    /// Distance between consecutive `size` byte elements of a buffer bound as
+    /// `UNIFORM_BUFFER_DYNAMIC`, every dynamic offset has to be a multiple of
+    /// `minUniformBufferOffsetAlignment`.
+    pub fn dynamic_uniform_stride(&self, size: u64) -> u64 {
+        let alignment = self
+            .physical_device()
+            .info()
+            .device_properties
+            .limits
+            .min_uniform_buffer_offset_alignment;
+
+        align_up(alignment.max(1) - 1, size).unwrap()
     }
 
     pub fn instance(&self) -> &InstanceLoader {
@@ -287,6 +341,10 @@ impl Device {
             None
         };
 
+        if let Some(name) = info.name {
+            self.set_object_name(buffer, name);
+        }
+
         let buffer_index = self.inner.buffers.lock().insert(buffer);
         let allocation_flags = info.allocation_flags;
 
@@ -321,7 +379,12 @@ impl Device {
                 ptr.as_ptr(),
                 std::mem::size_of_val(data),
             );
+        }
+
+        // Has to happen while the memory is still mapped
+        self.flush_buffer(&mut buffer, 0, std::mem::size_of_val(data) as u64);
 
+        unsafe {
             buffer
                 .memory_block()
                 .unmap(EruptMemoryDevice::wrap(self.handle()));
@@ -329,6 +392,8 @@ impl Device {
         buffer
     }
 
+    /// Copies `data` to `offset` bytes into a host visible buffer. gpu-alloc flushes the write
+    /// itself when the memory isn't coherent.
     pub fn write_buffer<T>(&self, buffer: &mut Buffer, offset: u64, data: &[T])
     where
         T: Pod,
@@ -345,6 +410,77 @@ impl Device {
         }
     }
 
+    /// Copies `data.len()` bytes starting at `offset` out of a host visible buffer.
+    pub fn read_buffer_bytes(&self, buffer: &mut Buffer, offset: u64, data: &mut [u8]) {
+        unsafe {
+            buffer
+                .memory_block()
+                .read_bytes(EruptMemoryDevice::wrap(self.handle()), offset, data)
+                .unwrap();
+        }
+    }
+
+    /// Copies `count` values starting at `offset` bytes out of a host visible buffer, mostly useful
+    /// for debugging. Device local buffers are read with [`RenderContext::read_device_buffer`].
+    ///
+    /// [`RenderContext::read_device_buffer`]: crate::render::render_context::RenderContext::read_device_buffer
+    pub fn read_buffer<T: Pod>(&self, buffer: &Buffer, offset: u64, count: usize) -> Vec<T> {
+        let mut values = vec![T::zeroed(); count];
+        self.read_buffer_bytes(
+            &mut buffer.clone(),
+            offset,
+            bytemuck::cast_slice_mut(&mut values),
+        );
+        values
+    }
+
+    pub fn is_host_visible(&self, buffer: &Buffer) -> bool {
+        let props = unsafe { buffer.clone().memory_block().props() };
+        props.contains(MemoryPropertyFlags::HOST_VISIBLE)
+    }
+
+    /// Makes host writes to `size` bytes at `offset` of a mapped buffer visible to the device,
+    /// which only takes a flush when its memory isn't `HOST_COHERENT`.
+    pub fn flush_buffer(&self, buffer: &mut Buffer, offset: u64, size: u64) {
+        let block = unsafe { buffer.memory_block() };
+        let atom_size = self
+            .inner
+            .physical_device
+            .info()
+            .device_properties
+            .limits
+            .non_coherent_atom_size;
+        let range = match flush_range(
+            block.props(),
+            atom_size,
+            block.offset()..block.offset() + block.size(),
+            offset..offset + size,
+        ) {
+            Some(range) => range,
+            None => return,
+        };
+
+        unsafe {
+            self.handle()
+                .flush_mapped_memory_ranges(&[vk::MappedMemoryRangeBuilder::new()
+                    .memory(*block.memory())
+                    .offset(range.start)
+                    .size(range.end - range.start)])
+                .unwrap();
+        }
+    }
+
+    /// Maps the whole buffer and leaves it mapped, the pointer stays valid as long as the buffer.
+    pub fn map_buffer(&self, buffer: &mut Buffer) -> NonNull<u8> {
+        let size = buffer.info().size as usize;
+        unsafe {
+            buffer
+                .memory_block()
+                .map(EruptMemoryDevice::wrap(self.handle()), 0, size)
+                .expect("Mapping to buffer failed")
+        }
+    }
+
     pub fn create_image_with_data<T>(
         &self,
         mut info: ImageInfo,
@@ -354,41 +490,175 @@ impl Device {
     where
         T: Pod,
     {
+        self.create_image_with_levels(info, layout, &[bytemuck::cast_slice(data)])
+    }
+
+    /// Creates an image and queues the upload of `levels`, one slice per mip level starting with
+    /// the largest.
+    pub fn create_image_with_levels(
+        &self,
+        mut info: ImageInfo,
+        layout: vk::ImageLayout,
+        levels: &[&[u8]],
+    ) -> Image {
+        debug_assert!(levels.len() <= info.mip_levels as usize);
+
         info.usage |= vk::ImageUsageFlags::TRANSFER_DST;
-        let subresource =
-            ImageSubresourceLayers::new(vk::ImageAspectFlags::COLOR, 0, 0..info.array_layers);
         let image = self.create_image(info);
 
-        let staging_buffer = self.create_buffer_with_data(
-            BufferInfo {
-                align: 15,
-                size: std::mem::size_of_val(data) as u64,
-                usage_flags: vk::BufferUsageFlags::TRANSFER_SRC,
-                allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS
-                    | gpu_alloc::UsageFlags::TRANSIENT,
-            },
-            data,
-        );
+        let mut image_uploads = self.inner.image_uploads.lock();
+        for (level, data) in levels.iter().enumerate() {
+            let staging_buffer = self.create_buffer_with_data(
+                BufferInfo {
+                    align: 15,
+                    size: data.len() as u64,
+                    usage_flags: vk::BufferUsageFlags::TRANSFER_SRC,
+                    allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS
+                        | gpu_alloc::UsageFlags::TRANSIENT,
+                    name: Some("staging buffer"),
+                },
+                data,
+            );
 
-        self.inner.image_uploads.lock().insert(ImageUpload {
-            staging_buffer,
-            image: image.clone(),
-            access: vk::ImageAspectFlags::all(),
-            layout,
-            subresource,
-            offset: Default::default(),
-            extent: vk::Extent3D {
-                width: info.extent.width,
-                height: info.extent.height,
-                depth: 1,
-            },
-        });
+            image_uploads.insert(ImageUpload {
+                staging_buffer,
+                image: image.clone(),
+                access: vk::ImageAspectFlags::all(),
+                layout,
+                subresource: ImageSubresourceLayers::new(
+                    vk::ImageAspectFlags::COLOR,
+                    level as u32,
+                    0..info.array_layers,
+                ),
+                offset: Default::default(),
+                extent: vk::Extent3D {
+                    width: (info.extent.width >> level).max(1),
+                    height: (info.extent.height >> level).max(1),
+                    depth: 1,
+                },
+            });
+        }
 
         image
     }
 
-    pub fn flush_uploads(&self, bump: &Bump) {
-        let image_uploads = self.inner.image_uploads.lock();
+    /// The features the device supports for optimally tiled images of `format`.
+    pub fn format_features(&self, format: vk::Format) -> vk::FormatFeatureFlags {
+        unsafe {
+            self.instance()
+                .get_physical_device_format_properties(self.inner.physical_device.handle(), format)
+                .optimal_tiling_features
+        }
+    }
+
+    /// The features the device supports for buffers of `format`.
+    pub fn format_buffer_features(&self, format: vk::Format) -> vk::FormatFeatureFlags {
+        unsafe {
+            self.instance()
+                .get_physical_device_format_properties(self.inner.physical_device.handle(), format)
+                .buffer_features
+        }
+    }
+
+    /// Like [`Device::create_image_with_data`], but `data` is encoded in `color_space`. sRGB data
+    /// is stored in the matching `_SRGB` format so it is decoded when sampled, and float data,
+    /// which has no sRGB formats, is converted to linear before the upload.
+    pub fn create_image_with_color_space<T>(
+        &self,
+        mut info: ImageInfo,
+        color_space: TextureColorSpace,
+        layout: vk::ImageLayout,
+        data: &[T],
+    ) -> Image
+    where
+        T: Pod,
+    {
+        if let Some(format) = color_space.format(info.format) {
+            info.format = format;
+            return self.create_image_with_data(info, layout, data);
+        }
+
+        assert_eq!(
+            info.format,
+            vk::Format::R32G32B32A32_SFLOAT,
+            "no sRGB decode for {:?}",
+            info.format
+        );
+        let linear = bytemuck::cast_slice::<T, [f32; 4]>(data)
+            .iter()
+            .map(|&[r, g, b, a]| [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a])
+            .collect::<Vec<_>>();
+        self.create_image_with_data(info, layout, &linear)
+    }
+
+    /// Copies the data of every image created with data since the last call into the image and
+    /// moves it to its final layout. Work submitted to `queue` afterwards sees the uploaded images.
+    pub fn flush_uploads(&self, queue: &mut Queue) {
+        let uploads = self.inner.image_uploads.lock().drain().collect::<Vec<_>>();
+        if uploads.is_empty() {
+            return;
+        }
+
+        let level_range = |upload: &ImageUpload| {
+            let subresource = &upload.subresource;
+            ImageSubresourceRange::new(
+                subresource.aspect,
+                subresource.level..subresource.level + 1,
+                subresource.first_layer..subresource.first_layer + subresource.layer_count,
+            )
+        };
+        let to_transfer = uploads
+            .iter()
+            .map(|upload| ImageMemoryBarrier {
+                image: &upload.image,
+                old_layout: None,
+                new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                family_transfer: None,
+                subresource: level_range(upload),
+                access: None,
+            })
+            .collect::<Vec<_>>();
+        let to_final = uploads
+            .iter()
+            .map(|upload| ImageMemoryBarrier {
+                image: &upload.image,
+                old_layout: Some(vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+                new_layout: upload.layout,
+                family_transfer: None,
+                subresource: level_range(upload),
+                access: None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut encoder = queue.create_enconder();
+        encoder.pipeline_barrier(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::TRANSFER_WRITE,
+            &to_transfer,
+        );
+        for upload in &uploads {
+            encoder.copy_buffer_to_image(
+                &upload.staging_buffer,
+                &upload.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &upload.subresource,
+                upload.offset,
+                upload.extent,
+            );
+        }
+        encoder.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+            &to_final,
+        );
+
+        // The staging buffers are only released with the device, so nothing has to wait here
+        let command_buffer = encoder.finish(self);
+        queue.submit(command_buffer, &[], &[], None);
     }
 
     pub fn create_swapchain(&self, surface: &Surface) -> Swapchain {
@@ -407,6 +677,69 @@ impl Device {
         Semaphore::new(semaphore)
     }
 
+    /// Creates a semaphore whose payload is a 64-bit counter instead of a signaled flag.
+    pub fn create_timeline_semaphore(&self, initial_value: u64) -> Semaphore {
+        let mut type_info = vk::SemaphoreTypeCreateInfoBuilder::new()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+        let semaphore = unsafe {
+            self.handle()
+                .create_semaphore(
+                    &vk::SemaphoreCreateInfoBuilder::new().extend_from(&mut type_info),
+                    None,
+                )
+                .unwrap()
+        };
+
+        self.inner.semaphores.lock().insert(semaphore);
+
+        Semaphore::new(semaphore)
+    }
+
+    /// Waits until every timeline semaphore reaches its value, returning `false` if `timeout`
+    /// nanoseconds pass first.
+    pub fn wait_semaphores(&self, semaphores: &[(&Semaphore, u64)], timeout: u64) -> bool {
+        let (handles, values) = semaphores
+            .iter()
+            .map(|(semaphore, value)| (semaphore.handle(), *value))
+            .unzip::<_, _, SmallVec<[_; 8]>, SmallVec<[_; 8]>>();
+
+        let result = unsafe {
+            self.handle().wait_semaphores(
+                &vk::SemaphoreWaitInfoBuilder::new()
+                    .semaphores(&handles)
+                    .values(&values),
+                timeout,
+            )
+        };
+        match result.raw {
+            vk::Result::SUCCESS => true,
+            vk::Result::TIMEOUT => false,
+            err => panic!("Failed to wait for semaphores: {:?}", err),
+        }
+    }
+
+    /// Sets a timeline semaphore to `value` from the host.
+    pub fn signal_value(&self, semaphore: &Semaphore, value: u64) {
+        unsafe {
+            self.handle()
+                .signal_semaphore(
+                    &vk::SemaphoreSignalInfoBuilder::new()
+                        .semaphore(semaphore.handle())
+                        .value(value),
+                )
+                .unwrap();
+        }
+    }
+
+    pub fn semaphore_value(&self, semaphore: &Semaphore) -> u64 {
+        unsafe {
+            self.handle()
+                .get_semaphore_counter_value(semaphore.handle())
+                .unwrap()
+        }
+    }
+
     pub fn create_fence(&self) -> Fence {
         let fence = unsafe {
             self.handle()
@@ -418,6 +751,62 @@ impl Device {
         Fence::new(fence)
     }
 
+    /// A pool of `count` timestamp queries, `None` if the graphics and compute queues can't write
+    /// timestamps.
+    pub fn create_timestamp_query_pool(&self, count: u32) -> Option<QueryPool> {
+        let limits = &self.inner.physical_device.info().device_properties.limits;
+        if limits.timestamp_compute_and_graphics == vk::FALSE {
+            return None;
+        }
+
+        let query_pool = unsafe {
+            self.handle()
+                .create_query_pool(
+                    &vk::QueryPoolCreateInfoBuilder::new()
+                        .query_type(vk::QueryType::TIMESTAMP)
+                        .query_count(count),
+                    None,
+                )
+                .unwrap()
+        };
+        self.inner.query_pools.lock().insert(query_pool);
+
+        Some(QueryPool::new(query_pool, count))
+    }
+
+    /// Reads the timestamps of `queries`, in ticks of [`Device::timestamp_period`], waiting for
+    /// them to be available. All of them must have been submitted. Returns `None` if the results
+    /// aren't ready.
+    pub fn timestamps(&self, query_pool: &QueryPool, queries: Range<u32>) -> Option<Vec<u64>> {
+        let mut timestamps = vec![0u64; queries.len()];
+        let result = unsafe {
+            self.handle().get_query_pool_results(
+                query_pool.handle(),
+                queries.start,
+                queries.len() as u32,
+                timestamps.len() * std::mem::size_of::<u64>(),
+                timestamps.as_mut_ptr().cast(),
+                std::mem::size_of::<u64>() as u64,
+                Some(vk::QueryResultFlags::_64 | vk::QueryResultFlags::WAIT),
+            )
+        };
+        match result.raw {
+            vk::Result::SUCCESS => Some(timestamps),
+            vk::Result::NOT_READY => None,
+            err => panic!("Failed to get query pool results: {:?}", err),
+        }
+    }
+
+    /// Nanoseconds per timestamp tick.
+    pub fn timestamp_period(&self) -> f32 {
+        self.inner
+            .physical_device
+            .info()
+            .device_properties
+            .limits
+            .timestamp_period
+    }
+
     pub fn reset_fences(&self, fences: &[&Fence]) {
         let fences = fences
             .iter()
@@ -440,6 +829,31 @@ impl Device {
         }
     }
 
+    /// Returns `true` once `fence` is signaled, without blocking.
+    pub fn get_fence_status(&self, fence: &Fence) -> bool {
+        let result = unsafe { self.handle().get_fence_status(fence.handle()) };
+        match result.raw {
+            vk::Result::SUCCESS => true,
+            vk::Result::NOT_READY => false,
+            err => panic!("Failed to get fence status: {:?}", err),
+        }
+    }
+
+    /// Like [`Device::wait_fences`] but gives up after `timeout` nanoseconds, returning `false`
+    /// if the fences weren't signaled by then.
+    pub fn wait_fences_timeout(&self, fences: &[&Fence], wait_all: bool, timeout: u64) -> bool {
+        let fences = fences
+            .iter()
+            .map(|fence| fence.handle())
+            .collect::<SmallVec<[_; 16]>>();
+        let result = unsafe { self.handle().wait_for_fences(&fences, wait_all, timeout) };
+        match result.raw {
+            vk::Result::SUCCESS => true,
+            vk::Result::TIMEOUT => false,
+            err => panic!("Failed to wait for fences: {:?}", err),
+        }
+    }
+
     pub fn wait_idle(&self) {
         unsafe { self.handle().device_wait_idle().unwrap() }
     }
@@ -517,12 +931,122 @@ impl Device {
         DescriptorSet::new(info, handles[0], pool)
     }
 
+    /// Panics if any of `descriptor_sets` was not allocated by this device.
+    pub fn validate_descriptor_sets(&self, descriptor_sets: &[DescriptorSet]) {
+        for descriptor_set in descriptor_sets {
+            assert!(
+                is_alive(&self.inner.descriptor_pools, descriptor_set.pool()),
+                "Descriptor set {:?} was not allocated by this device",
+                descriptor_set.handle()
+            );
+        }
+    }
+
+    /// Catches descriptor writes referencing resources that were never created by this device or
+    /// that lack the usage their descriptor type needs, before they reach the driver.
+    fn validate_descriptor_writes(&self, writes: &[WriteDescriptorSet<'_>]) {
+        for write in writes {
+            self.validate_descriptor_sets(std::slice::from_ref(write.descriptor_set));
+
+            let validate_image_view = |image_view: &ImageView, usage: vk::ImageUsageFlags| {
+                assert!(
+                    is_alive(&self.inner.image_views, image_view.handle()),
+                    "Binding {} references image view {:?} which was not created by this device",
+                    write.binding,
+                    image_view.handle()
+                );
+                assert!(
+                    image_view.info().image.info().usage.contains(usage),
+                    "Binding {} needs an image with {:?} usage",
+                    write.binding,
+                    usage
+                );
+            };
+
+            let validate_buffers = |buffers: &[(Buffer, u64, u64)], usage: vk::BufferUsageFlags| {
+                for (buffer, offset, size) in buffers {
+                    assert!(
+                        is_alive(&self.inner.buffers, buffer.handle()),
+                        "Binding {} references buffer {:?} which was not created by this device",
+                        write.binding,
+                        buffer.handle()
+                    );
+                    assert!(
+                        buffer.info().usage_flags.contains(usage),
+                        "Binding {} needs a buffer with {:?} usage",
+                        write.binding,
+                        usage
+                    );
+                    assert!(
+                        offset + size <= buffer.info().size,
+                        "Binding {} range {}..{} is out of bounds of a {} byte buffer",
+                        write.binding,
+                        offset,
+                        offset + size,
+                        buffer.info().size
+                    );
+                }
+            };
+
+            match write.descriptors {
+                Descriptors::Sampler(samplers) => {
+                    for sampler in samplers {
+                        assert!(is_alive(&self.inner.samplers, sampler.handle()));
+                    }
+                }
+                Descriptors::CombinedImageSampler(slice) => {
+                    for (image_view, _, sampler) in slice {
+                        validate_image_view(image_view, vk::ImageUsageFlags::SAMPLED);
+                        assert!(is_alive(&self.inner.samplers, sampler.handle()));
+                    }
+                }
+                Descriptors::SampledImage(slice) => {
+                    for (image_view, _) in slice {
+                        validate_image_view(image_view, vk::ImageUsageFlags::SAMPLED);
+                    }
+                }
+                Descriptors::StorageImage(slice) => {
+                    for (image_view, _) in slice {
+                        validate_image_view(image_view, vk::ImageUsageFlags::STORAGE);
+                    }
+                }
+                Descriptors::InputAttachment(slice) => {
+                    for (image_view, _) in slice {
+                        validate_image_view(image_view, vk::ImageUsageFlags::INPUT_ATTACHMENT);
+                    }
+                }
+                Descriptors::UniformBuffer(slice) | Descriptors::UniformBufferDynamic(slice) => {
+                    validate_buffers(slice, vk::BufferUsageFlags::UNIFORM_BUFFER)
+                }
+                Descriptors::StorageBuffer(slice) | Descriptors::StorageBufferDynamic(slice) => {
+                    validate_buffers(slice, vk::BufferUsageFlags::STORAGE_BUFFER)
+                }
+                Descriptors::AccelerationStructure(slice) => {
+                    for acceleration_structure in slice {
+                        assert!(
+                            is_alive(
+                                &self.inner.acceleration_structures,
+                                acceleration_structure.handle()
+                            ),
+                            "Binding {} references acceleration structure {:?} which was not \
+                             created by this device",
+                            write.binding,
+                            acceleration_structure.handle()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     pub fn update_descriptor_sets<'a>(
         &self,
         writes: &[WriteDescriptorSet<'a>],
         copies: &[CopyDescriptorSet<'a>],
     ) {
-        debug_assert!(copies.is_empty());
+        if cfg!(debug_assertions) {
+            self.validate_descriptor_writes(writes);
+        }
 
         let mut ranges = SmallVec::<[_; 64]>::new();
         let mut images = SmallVec::<[_; 16]>::new();
@@ -604,14 +1128,12 @@ impl Device {
                     Descriptors::StorageImage(_) => write_builder
                         .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
                         .image_info(&images[ranges.next().unwrap()]),
-                    Descriptors::UniformBuffer(_) => write_builder
-                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                        .buffer_info(&buffers[ranges.next().unwrap()]),
-                    Descriptors::StorageBuffer(_) => write_builder
-                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    Descriptors::UniformBuffer(_)
+                    | Descriptors::StorageBuffer(_)
+                    | Descriptors::UniformBufferDynamic(_)
+                    | Descriptors::StorageBufferDynamic(_) => write_builder
+                        .descriptor_type(write.descriptors.descriptor_type().to_erupt())
                         .buffer_info(&buffers[ranges.next().unwrap()]),
-                    Descriptors::UniformBufferDynamic(_) => unimplemented!(),
-                    Descriptors::StorageBufferDynamic(_) => unimplemented!(),
                     Descriptors::InputAttachment(_) => unimplemented!(),
                     Descriptors::AccelerationStructure(_) => {
                         let range = ranges.next().unwrap();
@@ -630,7 +1152,12 @@ impl Device {
             })
             .collect::<SmallVec<[_; 16]>>();
 
-        unsafe { self.handle().update_descriptor_sets(&writes, &[]) }
+        let copies = copies
+            .iter()
+            .map(copy_descriptor_set)
+            .collect::<SmallVec<[_; 16]>>();
+
+        unsafe { self.handle().update_descriptor_sets(&writes, &copies) }
     }
 
     pub fn create_pipeline_layout(&self, info: PipelineLayoutInfo) -> PipelineLayout {
@@ -681,13 +1208,15 @@ impl Device {
     }
 
     pub fn create_render_pass(&self, info: RenderPassInfo) -> RenderPass {
+        self.validate_sample_counts(&info);
+
         let attachments = info
             .attachments
             .iter()
             .map(|attachment| {
                 vk::AttachmentDescriptionBuilder::new()
                     .format(attachment.format)
-                    .samples(vk::SampleCountFlagBits::_1)
+                    .samples(attachment.samples)
                     .load_op(attachment.load_op)
                     .store_op(attachment.store_op)
                     .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
@@ -718,6 +1247,13 @@ impl Device {
                             .collect::<SmallVec<[_; 16]>>(),
                     );
 
+                    let resolve_offset = subpass_attachments.len();
+                    subpass_attachments.extend(subpass.resolves.iter().map(|&resolve| {
+                        vk::AttachmentReferenceBuilder::new()
+                            .attachment(resolve as _)
+                            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    }));
+
                     let depth_offset = subpass_attachments.len();
                     if let Some(depth) = subpass.depth {
                         subpass_attachments.push(
@@ -726,7 +1262,7 @@ impl Device {
                                 .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
                         )
                     }
-                    (color_offset, depth_offset)
+                    (color_offset, resolve_offset, depth_offset)
                 })
                 .collect::<SmallVec<[_; 16]>>()
         };
@@ -735,10 +1271,15 @@ impl Device {
             .subpasses
             .iter()
             .zip(subpass_offsets)
-            .map(|(subpass, (color_offset, depth_offset))| {
-                let subpass_descriptor = vk::SubpassDescriptionBuilder::new()
+            .map(|(subpass, (color_offset, resolve_offset, depth_offset))| {
+                let mut subpass_descriptor = vk::SubpassDescriptionBuilder::new()
                     .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-                    .color_attachments(&subpass_attachments[color_offset..depth_offset]);
+                    .color_attachments(&subpass_attachments[color_offset..resolve_offset]);
+
+                if !subpass.resolves.is_empty() {
+                    subpass_descriptor = subpass_descriptor
+                        .resolve_attachments(&subpass_attachments[resolve_offset..depth_offset]);
+                }
 
                 if subpass.depth.is_some() {
                     subpass_descriptor.depth_stencil_attachment(&subpass_attachments[depth_offset])
@@ -748,9 +1289,28 @@ impl Device {
             })
             .collect::<Vec<_>>();
 
+        let subpass_index = |subpass: Option<usize>| match subpass {
+            Some(subpass) => subpass as u32,
+            None => vk::SUBPASS_EXTERNAL,
+        };
+        let dependencies = info
+            .dependencies
+            .iter()
+            .map(|dependency| {
+                vk::SubpassDependencyBuilder::new()
+                    .src_subpass(subpass_index(dependency.src))
+                    .dst_subpass(subpass_index(dependency.dst))
+                    .src_stage_mask(dependency.src_stages)
+                    .dst_stage_mask(dependency.dst_stages)
+                    .src_access_mask(dependency.src_access)
+                    .dst_access_mask(dependency.dst_access)
+            })
+            .collect::<SmallVec<[_; 16]>>();
+
         let render_pass_create_info = vk::RenderPassCreateInfoBuilder::new()
             .attachments(&attachments)
-            .subpasses(&subpasses);
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
 
         let render_pass = unsafe {
             self.handle()
@@ -763,7 +1323,10 @@ impl Device {
         RenderPass::new(info, render_pass)
     }
 
-    pub fn create_graphics_pipeline(&self, info: GraphicsPipelineInfo) -> GraphicsPipeline {
+    pub fn create_graphics_pipeline(
+        &self,
+        info: GraphicsPipelineInfo,
+    ) -> Result<GraphicsPipeline, PipelineError> {
         let mut shader_stages = Vec::with_capacity(2);
 
         let vertex_binding_descriptions = info
@@ -821,13 +1384,17 @@ impl Device {
             viewport_info = vk::PipelineViewportStateCreateInfoBuilder::new()
                 .viewport_count(1)
                 .scissor_count(1);
+            let depth_bias = rasterizer.depth_bias.unwrap_or_default();
             rasterization_info = vk::PipelineRasterizationStateCreateInfoBuilder::new()
                 .rasterizer_discard_enable(false)
                 .depth_clamp_enable(rasterizer.depth_clamp)
                 .polygon_mode(rasterizer.polygon_mode)
                 .cull_mode(rasterizer.cull_mode)
                 .front_face(rasterizer.front_face)
-                .depth_bias_enable(false)
+                .depth_bias_enable(rasterizer.depth_bias.is_some())
+                .depth_bias_constant_factor(depth_bias.constant_factor)
+                .depth_bias_clamp(depth_bias.clamp)
+                .depth_bias_slope_factor(depth_bias.slope_factor)
                 .line_width(1.0);
             let stencil_op = vk::StencilOpStateBuilder::new()
                 .fail_op(vk::StencilOp::KEEP)
@@ -855,7 +1422,7 @@ impl Device {
             color_blend_info = vk::PipelineColorBlendStateCreateInfoBuilder::new()
                 .attachments(&color_blend_attachments);
             multisample_info = vk::PipelineMultisampleStateCreateInfoBuilder::new()
-                .rasterization_samples(vk::SampleCountFlagBits::_1);
+                .rasterization_samples(rasterizer.samples);
 
             if let Some(fragment_shader) = &rasterizer.fragment_shader {
                 shader_stages.push(
@@ -889,16 +1456,58 @@ impl Device {
                 .subpass(info.subpass)
         };
 
-        let pipelines = unsafe {
+        let result = unsafe {
             self.handle()
                 .create_graphics_pipelines(None, &[pipeline_info], None)
-                .unwrap()
         };
 
-        let pipeline = pipelines[0];
+        // On failure the driver may still hand back a vec with null handles,
+        // so the individual pipeline has to be checked as well as the result
+        let pipeline = match result.value {
+            Some(pipelines) if result.raw == vk::Result::SUCCESS => pipelines[0],
+            _ => return Err(PipelineError::Creation(result.raw)),
+        };
+        if pipeline.is_null() {
+            return Err(PipelineError::NullHandle(result.raw));
+        }
+
+        self.set_object_name(pipeline, "graphics pipeline");
+        self.inner.pipelines.lock().insert(pipeline);
+
+        Ok(GraphicsPipeline::new(info, pipeline))
+    }
+
+    pub fn create_compute_pipeline(
+        &self,
+        info: ComputePipelineInfo,
+    ) -> Result<ComputePipeline, PipelineError> {
+        let shader_entry_name = CString::new("main").unwrap();
+        let stage = vk::PipelineShaderStageCreateInfoBuilder::new()
+            .stage(info.shader.stage)
+            .module(info.shader.module.handle())
+            .name(&shader_entry_name);
+
+        let pipeline_info = vk::ComputePipelineCreateInfoBuilder::new()
+            .stage(*stage)
+            .layout(info.layout.handle());
+
+        let result = unsafe {
+            self.handle()
+                .create_compute_pipelines(None, &[pipeline_info], None)
+        };
+
+        let pipeline = match result.value {
+            Some(pipelines) if result.raw == vk::Result::SUCCESS => pipelines[0],
+            _ => return Err(PipelineError::Creation(result.raw)),
+        };
+        if pipeline.is_null() {
+            return Err(PipelineError::NullHandle(result.raw));
+        }
+
+        self.set_object_name(pipeline, "compute pipeline");
         self.inner.pipelines.lock().insert(pipeline);
 
-        GraphicsPipeline::new(info, pipeline)
+        Ok(ComputePipeline::new(info, pipeline))
     }
 
     pub fn create_image(&self, info: ImageInfo) -> Image {
@@ -906,6 +1515,7 @@ impl Device {
             self.handle()
                 .create_image(
                     &vk::ImageCreateInfoBuilder::new()
+                        .flags(info.flags)
                         .image_type(vk::ImageType::_2D)
                         .format(info.format)
                         .extent(vk::Extent3D {
@@ -942,6 +1552,10 @@ impl Device {
                 .unwrap()
         };
 
+        if let Some(name) = info.name {
+            self.set_object_name(image, name);
+        }
+
         self.inner.images.lock().insert(image);
 
         unsafe {
@@ -980,24 +1594,24 @@ impl Device {
         ImageView::new(info, view)
     }
 
-    pub fn create_sampler(&self) -> Sampler {
+    pub fn create_sampler(&self, info: SamplerInfo) -> Sampler {
         let sampler = unsafe {
             self.handle()
                 .create_sampler(
                     &vk::SamplerCreateInfoBuilder::new()
-                        .mag_filter(vk::Filter::NEAREST)
-                        .min_filter(vk::Filter::NEAREST)
-                        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
-                        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-                        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-                        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+                        .mag_filter(info.filter)
+                        .min_filter(info.filter)
+                        .mipmap_mode(info.mipmap_mode)
+                        .address_mode_u(info.address_mode)
+                        .address_mode_v(info.address_mode)
+                        .address_mode_w(info.address_mode)
                         .mip_lod_bias(0.0)
                         .anisotropy_enable(false)
                         .compare_enable(false)
                         .compare_op(vk::CompareOp::NEVER)
                         .min_lod(0.0)
                         .max_lod(0.0)
-                        .border_color(vk::BorderColor::FLOAT_TRANSPARENT_BLACK)
+                        .border_color(info.border_color)
                         .unnormalized_coordinates(false),
                     None,
                 )
@@ -1037,7 +1651,29 @@ impl Device {
         Framebuffer::new(info, framebuffer)
     }
 
-    pub fn create_ray_tracing_pipeline(&self, info: RayTracingPipelineInfo) -> RayTracingPipeline {
+    pub fn create_ray_tracing_pipeline(
+        &self,
+        mut info: RayTracingPipelineInfo,
+    ) -> RayTracingPipeline {
+        if cfg!(debug_assertions) {
+            validate_ray_tracing_groups(&info.shaders, &info.groups);
+        }
+
+        let max_ray_recursion_depth = self
+            .inner
+            .physical_device
+            .info()
+            .raytracing_properties
+            .max_ray_recursion_depth;
+        if info.max_recursion_depth > max_ray_recursion_depth {
+            tracing::warn!(
+                "requested ray recursion depth {} exceeds the device limit, clamping to {}",
+                info.max_recursion_depth,
+                max_ray_recursion_depth
+            );
+            info.max_recursion_depth = max_ray_recursion_depth;
+        }
+
         let shader_entry_name = CString::new("main").unwrap();
         let stages = info
             .shaders
@@ -1121,6 +1757,7 @@ impl Device {
                 .unwrap();
         }
 
+        self.set_object_name(pipeline, "ray tracing pipeline");
         self.inner.pipelines.lock().insert(pipeline);
 
         RayTracingPipeline::new(info, pipeline, bytes.into())
@@ -1141,7 +1778,16 @@ impl Device {
             + info.hit.len()
             + info.callable.len()) as u32;
 
-        let group_stride = align_up(group_align, group_size).unwrap() as u64;
+        let group_stride = shader_group_stride(
+            rt_properties.shader_group_handle_size,
+            rt_properties.shader_group_base_alignment,
+        );
+        assert!(
+            group_stride <= rt_properties.max_shader_group_stride as u64,
+            "shader group stride {} exceeds the device limit of {}",
+            group_stride,
+            rt_properties.max_shader_group_stride
+        );
 
         let total_size = group_stride.checked_mul(group_count as _).unwrap() as usize;
 
@@ -1195,35 +1841,22 @@ impl Device {
                     | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
                 allocation_flags: gpu_alloc::UsageFlags::DEVICE_ADDRESS
                     | gpu_alloc::UsageFlags::HOST_ACCESS,
+                name: Some("shader binding table"),
             },
             &bytes,
         );
 
+        let sbt = BufferRegion {
+            stride: Some(group_stride),
+            ..BufferRegion::whole(sbt_buffer)
+        };
+        let region = |range: Range<u64>| sbt.sub(range.start, range.end - range.start);
+
         ShaderBindingTable {
-            raygen: raygen_handlers.map(|range| BufferRegion {
-                buffer: sbt_buffer.clone(),
-                offset: range.start,
-                size: range.end - range.start,
-                stride: Some(group_stride),
-            }),
-            miss: miss_handlers.map(|range| BufferRegion {
-                buffer: sbt_buffer.clone(),
-                offset: range.start,
-                size: range.end - range.start,
-                stride: Some(group_stride),
-            }),
-            hit: hit_handlers.map(|range| BufferRegion {
-                buffer: sbt_buffer.clone(),
-                offset: range.start,
-                size: range.end - range.start,
-                stride: Some(group_stride),
-            }),
-            callable: callable_handlers.map(|range| BufferRegion {
-                buffer: sbt_buffer.clone(),
-                offset: range.start,
-                size: range.end - range.start,
-                stride: Some(group_stride),
-            }),
+            raygen: raygen_handlers.map(region),
+            miss: miss_handlers.map(region),
+            hit: hit_handlers.map(region),
+            callable: callable_handlers.map(region),
         }
     }
 
@@ -1312,6 +1945,14 @@ impl Device {
                 .unwrap()
         };
 
+        self.set_object_name(
+            acceleration_structure,
+            match info.level {
+                AccelerationStructureLevel::Bottom => "BLAS",
+                AccelerationStructureLevel::Top => "TLAS",
+            },
+        );
+
         self.inner
             .acceleration_structures
             .lock()
@@ -1326,6 +1967,60 @@ impl Device {
 
         AccelerationStructure::new(info, acceleration_structure, device_address)
     }
+
+    /// Panics if an attachment uses a sample count the device can't render to, or if a subpass
+    /// resolves a different number of attachments than it renders.
+    fn validate_sample_counts(&self, info: &RenderPassInfo) {
+        let limits = &self.inner.physical_device.info().device_properties.limits;
+
+        for subpass in &info.subpasses {
+            assert!(
+                subpass.resolves.is_empty() || subpass.resolves.len() == subpass.colors.len(),
+                "a subpass has {} color attachments but resolves {}",
+                subpass.colors.len(),
+                subpass.resolves.len()
+            );
+
+            let colors = subpass
+                .colors
+                .iter()
+                .map(|&color| (color, limits.framebuffer_color_sample_counts));
+            let depth = subpass
+                .depth
+                .map(|depth| (depth, limits.framebuffer_depth_sample_counts));
+
+            for (index, supported) in colors.chain(depth) {
+                let samples = info.attachments[index].samples;
+                assert!(
+                    supported.contains(samples.bitmask()),
+                    "attachment {} uses {:?} samples, the device supports {:?}",
+                    index,
+                    samples,
+                    supported
+                );
+            }
+        }
+    }
+
+    /// Names `handle` in validation messages and graphics debuggers. Only debug builds enable
+    /// `VK_EXT_debug_utils`, so this does nothing in release builds.
+    pub fn set_object_name<T: DebugObject>(&self, handle: T, name: &str) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+
+        let name = CString::new(name).unwrap();
+        unsafe {
+            self.handle()
+                .set_debug_utils_object_name_ext(
+                    &vk::DebugUtilsObjectNameInfoEXTBuilder::new()
+                        .object_type(T::OBJECT_TYPE)
+                        .object_handle(handle.object_handle())
+                        .object_name(&name),
+                )
+                .unwrap();
+        }
+    }
 }
 
 fn get_allocator_memory_usage(usage: &vk::ImageUsageFlags) -> UsageFlags {
@@ -1336,6 +2031,12 @@ fn get_allocator_memory_usage(usage: &vk::ImageUsageFlags) -> UsageFlags {
     }
 }
 
+/// Bytes between the records of a shader binding table. Every record is base aligned, which also
+/// lets instances index into the hit region with their SBT offset.
+fn shader_group_stride(handle_size: u32, base_alignment: u32) -> u64 {
+    align_up(u64::from(base_alignment) - 1, u64::from(handle_size)).unwrap()
+}
+
 fn copy_group_handlers(
     group_handlers: &[u8],
     write: &mut [u8],
@@ -1366,3 +2067,242 @@ fn copy_group_handlers(
     let result_end = u64::try_from(*write_offset).ok()?;
     Some(result_start..result_end)
 }
+
+fn is_alive<T: PartialEq>(slab: &Mutex<Slab<T>>, handle: T) -> bool {
+    slab.lock().iter().any(|(_, alive)| *alive == handle)
+}
+
+/// Panics if a shader group references a shader that doesn't exist or has the wrong stage, which
+/// the driver would otherwise accept and turn into a broken pipeline.
+fn validate_ray_tracing_groups(shaders: &[Shader], groups: &[RayTracingShaderGroupInfo]) {
+    let check = |group: usize, index: u32, expected: vk::ShaderStageFlagBits| {
+        let shader = shaders.get(index as usize).unwrap_or_else(|| {
+            panic!(
+                "ray tracing group {} references shader {}, but the pipeline only has {} shaders",
+                group,
+                index,
+                shaders.len()
+            )
+        });
+        assert!(
+            shader.stage == expected,
+            "ray tracing group {} expects shader {} to be a {:?} shader, found {:?}",
+            group,
+            index,
+            expected,
+            shader.stage
+        );
+    };
+
+    for (group, group_info) in groups.iter().enumerate() {
+        match *group_info {
+            RayTracingShaderGroupInfo::Raygen { raygen } => {
+                check(group, raygen, vk::ShaderStageFlagBits::RAYGEN_KHR)
+            }
+            RayTracingShaderGroupInfo::Miss { miss } => {
+                check(group, miss, vk::ShaderStageFlagBits::MISS_KHR)
+            }
+            RayTracingShaderGroupInfo::Triangle {
+                any_hit,
+                closest_hit,
+            } => {
+                if let Some(any_hit) = any_hit {
+                    check(group, any_hit, vk::ShaderStageFlagBits::ANY_HIT_KHR);
+                }
+                if let Some(closest_hit) = closest_hit {
+                    check(group, closest_hit, vk::ShaderStageFlagBits::CLOSEST_HIT_KHR);
+                }
+            }
+        }
+    }
+}
+
+fn copy_descriptor_set<'a>(copy: &CopyDescriptorSet) -> vk::CopyDescriptorSetBuilder<'a> {
+    vk::CopyDescriptorSetBuilder::new()
+        .src_set(copy.src.handle())
+        .src_binding(copy.src_binding)
+        .src_array_element(copy.src_element)
+        .dst_set(copy.dst.handle())
+        .dst_binding(copy.dst_binding)
+        .dst_array_element(copy.dst_element)
+        .descriptor_count(copy.count)
+}
+
+/// The range of the memory object to flush after writing `written` bytes of a block spanning
+/// `block` in it, `None` when nothing has to be flushed. Flushed ranges are rounded out to the
+/// atom size, gpu-alloc aligns non-coherent blocks to it so this stays within the block.
+fn flush_range(
+    props: MemoryPropertyFlags,
+    atom_size: u64,
+    block: Range<u64>,
+    written: Range<u64>,
+) -> Option<Range<u64>> {
+    if props.contains(MemoryPropertyFlags::HOST_COHERENT) || written.start >= written.end {
+        return None;
+    }
+
+    let atom_mask = atom_size - 1;
+    let start = align_down(atom_mask, block.start + written.start);
+    let end = align_up(atom_mask, block.start + written.end)
+        .unwrap()
+        .min(block.end);
+    Some(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coherent_memory_is_not_flushed() {
+        let props = MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT;
+
+        assert_eq!(flush_range(props, 64, 256..1280, 10..20), None);
+    }
+
+    #[test]
+    fn non_coherent_memory_is_flushed_in_whole_atoms() {
+        let props = MemoryPropertyFlags::HOST_VISIBLE;
+
+        assert_eq!(flush_range(props, 64, 256..1280, 10..20), Some(256..320));
+        assert_eq!(flush_range(props, 64, 256..1280, 60..70), Some(256..384));
+        // Clamped to the end of the block
+        assert_eq!(flush_range(props, 64, 256..1000, 700..744), Some(896..1000));
+        assert_eq!(flush_range(props, 64, 256..1280, 10..10), None);
+    }
+
+    fn descriptor_set(handle: u64) -> DescriptorSet {
+        let layout = DescriptorSetLayout::new(
+            DescriptorSetLayoutInfo {
+                bindings: vec![],
+                flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            },
+            vk::DescriptorSetLayout::null(),
+            DescriptorSizes::from_bindings(&[]),
+        );
+        DescriptorSet::new(
+            DescriptorSetInfo { layout },
+            vk::DescriptorSet(handle),
+            vk::DescriptorPool::null(),
+        )
+    }
+
+    #[test]
+    fn descriptor_set_copies_keep_source_and_destination_apart() {
+        let src = descriptor_set(1);
+        let dst = descriptor_set(2);
+        let copy = copy_descriptor_set(&CopyDescriptorSet {
+            src: &src,
+            src_binding: 3,
+            src_element: 4,
+            dst: &dst,
+            dst_binding: 5,
+            dst_element: 6,
+            count: 7,
+        });
+
+        assert_eq!(copy.s_type, vk::StructureType::COPY_DESCRIPTOR_SET);
+        assert_eq!(copy.src_set, vk::DescriptorSet(1));
+        assert_eq!(copy.src_binding, 3);
+        assert_eq!(copy.src_array_element, 4);
+        assert_eq!(copy.dst_set, vk::DescriptorSet(2));
+        assert_eq!(copy.dst_binding, 5);
+        assert_eq!(copy.dst_array_element, 6);
+        assert_eq!(copy.descriptor_count, 7);
+    }
+
+    fn shader(stage: vk::ShaderStageFlagBits) -> Shader {
+        let module = ShaderModule::new(
+            ShaderModuleInfo { code: Box::new([]) },
+            vk::ShaderModule::null(),
+        );
+        Shader::new(module, stage)
+    }
+
+    #[test]
+    fn matching_ray_tracing_groups_are_accepted() {
+        let shaders = [
+            shader(vk::ShaderStageFlagBits::RAYGEN_KHR),
+            shader(vk::ShaderStageFlagBits::MISS_KHR),
+            shader(vk::ShaderStageFlagBits::CLOSEST_HIT_KHR),
+        ];
+        let groups = [
+            RayTracingShaderGroupInfo::Raygen { raygen: 0 },
+            RayTracingShaderGroupInfo::Miss { miss: 1 },
+            RayTracingShaderGroupInfo::Triangle {
+                any_hit: None,
+                closest_hit: Some(2),
+            },
+        ];
+
+        validate_ray_tracing_groups(&shaders, &groups);
+    }
+
+    #[test]
+    #[should_panic(expected = "ray tracing group 1 expects shader 1 to be a RAYGEN_KHR shader")]
+    fn raygen_group_pointing_at_a_miss_shader_is_rejected() {
+        let shaders = [
+            shader(vk::ShaderStageFlagBits::MISS_KHR),
+            shader(vk::ShaderStageFlagBits::MISS_KHR),
+        ];
+        let groups = [
+            RayTracingShaderGroupInfo::Miss { miss: 0 },
+            RayTracingShaderGroupInfo::Raygen { raygen: 1 },
+        ];
+
+        validate_ray_tracing_groups(&shaders, &groups);
+    }
+
+    #[test]
+    #[should_panic(expected = "references shader 3, but the pipeline only has 1 shaders")]
+    fn missing_shaders_are_rejected() {
+        let shaders = [shader(vk::ShaderStageFlagBits::RAYGEN_KHR)];
+        let groups = [RayTracingShaderGroupInfo::Raygen { raygen: 3 }];
+
+        validate_ray_tracing_groups(&shaders, &groups);
+    }
+
+    #[test]
+    fn shader_group_records_are_base_aligned() {
+        assert_eq!(shader_group_stride(32, 64), 64);
+        assert_eq!(shader_group_stride(32, 32), 32);
+        assert_eq!(shader_group_stride(96, 64), 128);
+    }
+
+    #[test]
+    fn hit_region_holds_one_record_per_hit_group() {
+        let (handle_size, stride) = (32, shader_group_stride(32, 64));
+        // Raygen, miss and three hit groups, each handle filled with its group index
+        let handlers = (0..5u8)
+            .flat_map(|group| std::iter::repeat(group).take(handle_size))
+            .collect::<Vec<_>>();
+        let mut bytes = vec![0xff; 5 * stride as usize];
+        let mut write_offset = 0;
+
+        let mut copy = |groups: &[u32]| {
+            copy_group_handlers(
+                &handlers,
+                &mut bytes,
+                groups.iter().copied(),
+                &mut write_offset,
+                handle_size as u64,
+                stride as usize,
+            )
+            .unwrap()
+        };
+        let raygen = copy(&[0]);
+        let miss = copy(&[1]);
+        let hit = copy(&[2, 3, 4]);
+
+        assert_eq!(raygen, 0..stride);
+        assert_eq!(miss, stride..2 * stride);
+        assert_eq!(hit, 2 * stride..5 * stride);
+        assert_eq!(hit.end - hit.start, 3 * stride);
+        for (record, group) in (2..5).zip(2u8..) {
+            let start = record * stride as usize;
+            assert!(bytes[start..start + handle_size]
+                .iter()
+                .all(|&byte| byte == group));
+        }
+    }
+}
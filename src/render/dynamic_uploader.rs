@@ -0,0 +1,85 @@
+use crate::render::{
+    buffer::{BufferInfo, BufferRegion},
+    device::Device,
+    mapped_buffer::MappedBuffer,
+    util::align_up,
+};
+use bytemuck::Pod;
+use erupt::vk;
+
+/// Persistently mapped ring buffer for data rewritten every frame.
+///
+/// The buffer is split in one segment per frame in flight. [`DynamicUploader::begin_frame`]
+/// rewinds to the start of that frame's segment, which is only safe once the fence of the frame
+/// that last used the segment has been waited on.
+pub struct DynamicUploader {
+    buffer: MappedBuffer,
+    frame_size: u64,
+    frames_in_flight: u64,
+    segment_start: u64,
+    offset: u64,
+}
+
+impl DynamicUploader {
+    /// Every region returned by `push` starts at a multiple of this many bytes.
+    pub const ALIGNMENT: u64 = 256;
+
+    pub fn new(
+        device: &Device,
+        frame_size: u64,
+        frames_in_flight: u64,
+        usage_flags: vk::BufferUsageFlags,
+    ) -> Self {
+        let frame_size = align_up(Self::ALIGNMENT - 1, frame_size).unwrap();
+
+        let buffer = MappedBuffer::new(
+            device,
+            BufferInfo {
+                align: Self::ALIGNMENT - 1,
+                size: frame_size * frames_in_flight,
+                usage_flags,
+                allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS
+                    | gpu_alloc::UsageFlags::UPLOAD,
+                name: None,
+            },
+        );
+
+        DynamicUploader {
+            buffer,
+            frame_size,
+            frames_in_flight,
+            segment_start: 0,
+            offset: 0,
+        }
+    }
+
+    pub fn begin_frame(&mut self, frame: u64) {
+        self.segment_start = (frame % self.frames_in_flight) * self.frame_size;
+        self.offset = 0;
+    }
+
+    /// Copies `data` into the current frame's segment, `None` if the segment is full.
+    pub fn push<T>(&mut self, data: &[T]) -> Option<BufferRegion>
+    where
+        T: Pod,
+    {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let offset = align_up(Self::ALIGNMENT - 1, self.offset)?;
+        let size = bytes.len() as u64;
+
+        if offset + size > self.frame_size {
+            return None;
+        }
+        self.offset = offset + size;
+
+        let offset = self.segment_start + offset;
+        self.buffer.write(offset, bytes);
+
+        Some(BufferRegion {
+            buffer: self.buffer.buffer().clone(),
+            offset,
+            size,
+            stride: None,
+        })
+    }
+}
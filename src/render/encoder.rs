@@ -2,12 +2,12 @@ use crate::render::{
     acceleration_structures::AccelerationStructureBuildGeometryInfo,
     command_buffer::CommandBuffer,
     device::Device,
-    image::ImageMemoryBarrier,
+    image::{Image, ImageMemoryBarrier, ImageSubresourceLayers, ImageSubresourceRange},
     pipeline::ShaderBindingTable,
     render_pass::ClearValue,
     resources::{
-        Buffer, DescriptorSet, Framebuffer, GraphicsPipeline, PipelineLayout, RayTracingPipeline,
-        RenderPass,
+        Buffer, ComputePipeline, DescriptorSet, Framebuffer, GraphicsPipeline, PipelineLayout,
+        QueryPool, RayTracingPipeline, RenderPass,
     },
 };
 use crevice::internal::bytemuck::Pod;
@@ -82,6 +82,11 @@ impl<'a> EncoderInner<'a> {
             .push(Command::BindRayTracingPipeline { pipeline })
     }
 
+    pub fn bind_compute_pipeline(&mut self, pipeline: &'a ComputePipeline) {
+        self.commands
+            .push(Command::BindComputePipeline { pipeline })
+    }
+
     pub fn bind_descriptor_sets(
         &mut self,
         bind_point: vk::PipelineBindPoint,
@@ -122,6 +127,30 @@ impl<'a> EncoderInner<'a> {
         });
     }
 
+    /// Draws with `draw_count` `vk::DrawIndexedIndirectCommand`s read from `buffer` at `offset`,
+    /// `stride` bytes apart, e.g. written by a compute pass. More than one draw needs
+    /// [`DeviceCapabilities::multi_draw_indirect`], which is enabled whenever it's supported.
+    /// Panics if `offset` or `stride` break the alignment rules of `vkCmdDrawIndexedIndirect`.
+    ///
+    /// [`DeviceCapabilities::multi_draw_indirect`]: crate::render::physical_device::DeviceCapabilities::multi_draw_indirect
+    pub fn draw_indexed_indirect(
+        &mut self,
+        buffer: &'a Buffer,
+        offset: u64,
+        draw_count: u32,
+        stride: u32,
+    ) {
+        if let Err(err) = validate_indirect_draw(offset, draw_count, stride) {
+            panic!("invalid indirect draw: {}", err);
+        }
+        self.commands.push(Command::DrawIndexedIndirect {
+            buffer,
+            offset,
+            draw_count,
+            stride,
+        });
+    }
+
     pub fn update_buffer<T>(&mut self, buffer: &'a Buffer, offset: u64, data: &'a [T])
     where
         T: Pod,
@@ -137,6 +166,101 @@ impl<'a> EncoderInner<'a> {
         })
     }
 
+    pub fn copy_buffer(
+        &mut self,
+        src: &'a Buffer,
+        src_offset: u64,
+        dst: &'a Buffer,
+        dst_offset: u64,
+        size: u64,
+    ) {
+        self.commands.push(Command::CopyBuffer {
+            src,
+            src_offset,
+            dst,
+            dst_offset,
+            size,
+        })
+    }
+
+    /// Copies mip 0, layer 0 of `image` into `buffer` as tightly packed rows. `image` has to be in
+    /// `layout`, which is either `TRANSFER_SRC_OPTIMAL` or `GENERAL`.
+    pub fn copy_image_to_buffer(
+        &mut self,
+        image: &'a Image,
+        layout: vk::ImageLayout,
+        buffer: &'a Buffer,
+        buffer_offset: u64,
+    ) {
+        self.commands.push(Command::CopyImageToBuffer {
+            image,
+            layout,
+            buffer,
+            buffer_offset,
+        })
+    }
+
+    /// Copies tightly packed texels from `buffer` into `extent` texels at `offset` of `subresource`.
+    /// `image` has to be in `layout`, which is either `TRANSFER_DST_OPTIMAL` or `GENERAL`.
+    pub fn copy_buffer_to_image(
+        &mut self,
+        buffer: &'a Buffer,
+        image: &'a Image,
+        layout: vk::ImageLayout,
+        subresource: &'a ImageSubresourceLayers,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+    ) {
+        self.commands.push(Command::CopyBufferToImage {
+            buffer,
+            image,
+            layout,
+            subresource,
+            offset,
+            extent,
+        })
+    }
+
+    /// Fills `ranges` of `image` with `color` outside of a render pass. `image` has to be in
+    /// `layout`, which is either `TRANSFER_DST_OPTIMAL` or `GENERAL`.
+    pub fn clear_color_image(
+        &mut self,
+        image: &'a Image,
+        layout: vk::ImageLayout,
+        color: [f32; 4],
+        ranges: &'a [ImageSubresourceRange],
+    ) {
+        self.commands.push(Command::ClearColorImage {
+            image,
+            layout,
+            color,
+            ranges,
+        })
+    }
+
+    /// Resets `queries` of `query_pool` so they can be written again.
+    pub fn reset_query_pool(&mut self, query_pool: &'a QueryPool, queries: Range<u32>) {
+        self.commands.push(Command::ResetQueryPool {
+            query_pool,
+            queries,
+        })
+    }
+
+    /// Writes the GPU time to `query` once every command submitted before it has completed
+    /// `stage`.
+    pub fn write_timestamp(
+        &mut self,
+        stage: vk::PipelineStageFlagBits,
+        query_pool: &'a QueryPool,
+        query: u32,
+    ) {
+        self.commands.push(Command::WriteTimestamp {
+            stage,
+            query_pool,
+            query,
+        })
+    }
+
     pub fn bind_vertex_buffers(&mut self, first: u32, buffers: &'a [(Buffer, u64)]) {
         self.commands
             .push(Command::BindVertexBuffers { first, buffers })
@@ -167,17 +291,42 @@ impl<'a> EncoderInner<'a> {
             .push(Command::BuildAccelerationStructure { infos })
     }
 
+    /// Launches a ray per pixel of `extent`.
     pub fn trace_rays(
         &mut self,
         shader_binding_table: &'a ShaderBindingTable,
         extent: vk::Extent2D,
+    ) {
+        self.trace_rays_layers(shader_binding_table, extent, 1)
+    }
+
+    /// Launches `extent.width * extent.height * depth` rays, for ray generation shaders that write
+    /// several layers, e.g. the faces of a light probe.
+    pub fn trace_rays_layers(
+        &mut self,
+        shader_binding_table: &'a ShaderBindingTable,
+        extent: vk::Extent2D,
+        depth: u32,
     ) {
         self.commands.push(Command::TraceRays {
             shader_binding_table,
             extent,
+            depth,
         })
     }
 
+    /// Dispatches `group_count` workgroups of the bound compute pipeline.
+    pub fn dispatch(&mut self, group_count: [u32; 3]) {
+        self.commands.push(Command::Dispatch { group_count })
+    }
+
+    /// Like [`EncoderInner::dispatch`] with the group count read from a
+    /// `vk::DispatchIndirectCommand` in `buffer` at `offset`.
+    pub fn dispatch_indirect(&mut self, buffer: &'a Buffer, offset: u64) {
+        self.commands
+            .push(Command::DispatchIndirect { buffer, offset })
+    }
+
     pub fn pipeline_barrier(
         &mut self,
         src: vk::PipelineStageFlags,
@@ -211,6 +360,16 @@ impl<'a> EncoderInner<'a> {
             data: bytemuck::cast_slice(data),
         });
     }
+
+    /// Opens a labeled region shown by graphics debuggers, closed by
+    /// [`EncoderInner::end_debug_label`]. Only recorded when debug utils is enabled.
+    pub fn begin_debug_label(&mut self, name: &'a str, color: [f32; 4]) {
+        self.commands.push(Command::BeginDebugLabel { name, color })
+    }
+
+    pub fn end_debug_label(&mut self) {
+        self.commands.push(Command::EndDebugLabel)
+    }
 }
 
 pub enum Command<'a> {
@@ -229,6 +388,10 @@ pub enum Command<'a> {
         pipeline: &'a RayTracingPipeline,
     },
 
+    BindComputePipeline {
+        pipeline: &'a ComputePipeline,
+    },
+
     BindDescriptorSets {
         bind_point: vk::PipelineBindPoint,
         layout: &'a PipelineLayout,
@@ -256,12 +419,61 @@ pub enum Command<'a> {
         instances: Range<u32>,
     },
 
+    DrawIndexedIndirect {
+        buffer: &'a Buffer,
+        offset: u64,
+        draw_count: u32,
+        stride: u32,
+    },
+
     UpdateBuffer {
         buffer: &'a Buffer,
         offset: u64,
         data: &'a [u8],
     },
 
+    CopyBuffer {
+        src: &'a Buffer,
+        src_offset: u64,
+        dst: &'a Buffer,
+        dst_offset: u64,
+        size: u64,
+    },
+
+    CopyImageToBuffer {
+        image: &'a Image,
+        layout: vk::ImageLayout,
+        buffer: &'a Buffer,
+        buffer_offset: u64,
+    },
+
+    CopyBufferToImage {
+        buffer: &'a Buffer,
+        image: &'a Image,
+        layout: vk::ImageLayout,
+        subresource: &'a ImageSubresourceLayers,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+    },
+
+    ClearColorImage {
+        image: &'a Image,
+        layout: vk::ImageLayout,
+        color: [f32; 4],
+        ranges: &'a [ImageSubresourceRange],
+    },
+
+    ResetQueryPool {
+        query_pool: &'a QueryPool,
+        queries: Range<u32>,
+    },
+
+    WriteTimestamp {
+        stage: vk::PipelineStageFlagBits,
+        query_pool: &'a QueryPool,
+        query: u32,
+    },
+
     BindVertexBuffers {
         first: u32,
         buffers: &'a [(Buffer, u64)],
@@ -280,6 +492,16 @@ pub enum Command<'a> {
     TraceRays {
         shader_binding_table: &'a ShaderBindingTable,
         extent: vk::Extent2D,
+        depth: u32,
+    },
+
+    Dispatch {
+        group_count: [u32; 3],
+    },
+
+    DispatchIndirect {
+        buffer: &'a Buffer,
+        offset: u64,
     },
 
     PipelineBarrier {
@@ -296,4 +518,273 @@ pub enum Command<'a> {
         offset: u32,
         data: &'a [u8],
     },
+
+    BeginDebugLabel {
+        name: &'a str,
+        color: [f32; 4],
+    },
+    EndDebugLabel,
+}
+
+/// Checks the offset and stride of an indexed indirect draw against the rules of
+/// `vkCmdDrawIndexedIndirect`.
+fn validate_indirect_draw(offset: u64, draw_count: u32, stride: u32) -> Result<(), String> {
+    let command_size = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+    if offset % 4 != 0 {
+        return Err(format!("offset {} isn't a multiple of 4", offset));
+    }
+    if draw_count > 1 && (stride % 4 != 0 || stride < command_size) {
+        return Err(format!(
+            "stride {} has to be a multiple of 4 of at least {} bytes",
+            stride, command_size
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{
+        framebuffer::FramebufferInfo, image::ImageInfo, pipeline::PipelineLayoutInfo,
+        render_pass::RenderPassInfo,
+    };
+
+    #[test]
+    fn indirect_draw_alignment() {
+        assert!(validate_indirect_draw(0, 1, 0).is_ok());
+        assert!(validate_indirect_draw(64, 4, 20).is_ok());
+        assert!(validate_indirect_draw(64, 4, 32).is_ok());
+        assert!(validate_indirect_draw(2, 1, 20).is_err());
+        assert!(validate_indirect_draw(0, 4, 16).is_err());
+        assert!(validate_indirect_draw(0, 4, 22).is_err());
+    }
+
+    fn encoder<'a>() -> EncoderInner<'a> {
+        EncoderInner {
+            commands: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn clear_color_image_is_recorded() {
+        let image = Image::new(
+            ImageInfo {
+                extent: vk::Extent2D {
+                    width: 4,
+                    height: 4,
+                },
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                mip_levels: 1,
+                array_layers: 2,
+                samples: vk::SampleCountFlagBits::_1,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST,
+                flags: vk::ImageCreateFlags::empty(),
+                name: None,
+            },
+            vk::Image::null(),
+            None,
+        );
+        let ranges = [ImageSubresourceRange::new(
+            vk::ImageAspectFlags::COLOR,
+            0..1,
+            1..2,
+        )];
+
+        let mut encoder = encoder();
+        encoder.clear_color_image(
+            &image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            [0.25, 0.5, 0.75, 1.0],
+            &ranges,
+        );
+
+        match encoder.commands.as_slice() {
+            [Command::ClearColorImage {
+                image: recorded,
+                layout,
+                color,
+                ranges,
+            }] => {
+                assert!(std::ptr::eq(*recorded, &image));
+                assert_eq!(*layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+                assert_eq!(*color, [0.25, 0.5, 0.75, 1.0]);
+                assert_eq!(ranges.len(), 1);
+                assert_eq!(ranges[0].aspect, vk::ImageAspectFlags::COLOR);
+                assert_eq!((ranges[0].first_level, ranges[0].level_count), (0, 1));
+                assert_eq!((ranges[0].first_layer, ranges[0].layer_count), (1, 1));
+            }
+            _ => panic!("expected a single ClearColorImage"),
+        }
+    }
+
+    #[test]
+    fn trace_rays_depth_defaults_to_one() {
+        let sbt = ShaderBindingTable {
+            raygen: None,
+            miss: None,
+            hit: None,
+            callable: None,
+        };
+        let extent = vk::Extent2D {
+            width: 16,
+            height: 16,
+        };
+
+        let mut encoder = encoder();
+        encoder.trace_rays(&sbt, extent);
+        encoder.trace_rays_layers(&sbt, extent, 6);
+
+        let depths = encoder
+            .commands
+            .iter()
+            .map(|command| match command {
+                Command::TraceRays { extent, depth, .. } => (extent.width, extent.height, *depth),
+                _ => panic!("expected only TraceRays"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(depths, vec![(16, 16, 1), (16, 16, 6)]);
+    }
+
+    #[test]
+    fn draw_fullscreen_records_a_single_triangle() {
+        let mut encoder = encoder();
+        crate::render::pass::fullscreen_triangle::draw_fullscreen(&mut encoder);
+
+        match encoder.commands.as_slice() {
+            [Command::Draw {
+                vertices,
+                instances,
+            }] => {
+                assert_eq!(*vertices, 0..3);
+                assert_eq!(*instances, 0..1);
+            }
+            _ => panic!("expected a single Draw"),
+        }
+    }
+
+    #[test]
+    fn clear_color_reaches_begin_render_pass() {
+        let render_pass = RenderPass::new(
+            RenderPassInfo {
+                attachments: Default::default(),
+                subpasses: Default::default(),
+                dependencies: vec![],
+            },
+            vk::RenderPass::null(),
+        );
+        let framebuffer = Framebuffer::new(
+            FramebufferInfo {
+                render_pass: render_pass.clone(),
+                views: Default::default(),
+                extent: vk::Extent2D {
+                    width: 4,
+                    height: 4,
+                },
+            },
+            vk::Framebuffer::null(),
+        );
+        let clear_color = [0.1, 0.2, 0.3, 1.0];
+        let clears = [ClearValue::from(clear_color)];
+
+        let mut encoder = encoder();
+        encoder.begin_render_pass(&render_pass, &framebuffer, &clears);
+
+        match encoder.commands.as_slice() {
+            [Command::BeginRenderPass { clears, .. }] => match clears {
+                [ClearValue::Color(r, g, b, a)] => assert_eq!([*r, *g, *b, *a], clear_color),
+                _ => panic!("expected a single color clear"),
+            },
+            _ => panic!("expected a single BeginRenderPass"),
+        }
+    }
+
+    #[test]
+    fn debug_labels_are_recorded_in_pairs() {
+        let mut encoder = encoder();
+        encoder.begin_debug_label("Frame", [1.0; 4]);
+        encoder.begin_debug_label("Tonemap", [0.2, 0.6, 0.9, 1.0]);
+        encoder.draw(0..3, 0..1);
+        encoder.end_debug_label();
+        encoder.end_debug_label();
+
+        let mut open = vec![];
+        for command in &encoder.commands {
+            match command {
+                Command::BeginDebugLabel { name, .. } => open.push(*name),
+                Command::EndDebugLabel => {
+                    open.pop().expect("EndDebugLabel without a BeginDebugLabel");
+                }
+                _ => assert_eq!(open, ["Frame", "Tonemap"]),
+            }
+        }
+        assert!(open.is_empty());
+    }
+
+    #[test]
+    fn tlas_build_comes_after_the_build_barrier() {
+        let mut encoder = encoder();
+        // Stands in for the build, an AccelerationStructure can't be created without a device
+        crate::render::pass::raytracing_pass::record_tlas_build(&mut encoder, |encoder| {
+            encoder.dispatch([1, 1, 1])
+        });
+
+        match encoder.commands.as_slice() {
+            [Command::PipelineBarrier {
+                src,
+                dst,
+                src_access_mask,
+                dst_access_mask,
+                image_barriers,
+            }, Command::Dispatch { .. }] => {
+                assert_eq!(
+                    *src,
+                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR
+                );
+                assert_eq!(
+                    *dst,
+                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR
+                );
+                assert_eq!(
+                    *src_access_mask,
+                    vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR
+                );
+                assert_eq!(
+                    *dst_access_mask,
+                    vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR
+                );
+                assert!(image_barriers.is_empty());
+            }
+            _ => panic!("expected the barrier followed by the build"),
+        }
+    }
+
+    #[test]
+    fn dynamic_offsets_are_recorded_with_the_bind() {
+        let layout = PipelineLayout::new(
+            PipelineLayoutInfo {
+                sets: vec![],
+                push_constants: vec![],
+            },
+            vk::PipelineLayout::null(),
+        );
+        let offsets = [0, 256, 512];
+
+        let mut encoder = encoder();
+        encoder.bind_descriptor_sets(vk::PipelineBindPoint::GRAPHICS, &layout, 1, &[], &offsets);
+
+        match encoder.commands.as_slice() {
+            [Command::BindDescriptorSets {
+                bind_point,
+                first_set,
+                dynamic_offsets,
+                ..
+            }] => {
+                assert_eq!(*bind_point, vk::PipelineBindPoint::GRAPHICS);
+                assert_eq!(*first_set, 1);
+                assert_eq!(*dynamic_offsets, [0, 256, 512]);
+            }
+            _ => panic!("expected a single BindDescriptorSets"),
+        }
+    }
 }
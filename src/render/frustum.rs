@@ -0,0 +1,166 @@
+use glam::{Mat4, Vec3, Vec4};
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Smallest box containing every point, `None` if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+
+        Some(points.fold(
+            Aabb {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Aabb {
+                min: aabb.min.min(point),
+                max: aabb.max.max(point),
+            },
+        ))
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Box containing this one after it is transformed by `matrix`.
+    pub fn transformed(&self, matrix: &Mat4) -> Self {
+        let center = matrix.transform_point3(self.center());
+        let half_extents = self.half_extents();
+        let half_extents = matrix.x_axis.truncate().abs() * half_extents.x
+            + matrix.y_axis.truncate().abs() * half_extents.y
+            + matrix.z_axis.truncate().abs() * half_extents.z;
+
+        Aabb {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+}
+
+/// The six planes bounding what a camera sees, normals point inwards.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a view-projection matrix with Vulkan's `[0, 1]` depth range.
+    pub fn from_view_proj(view_proj: &Mat4) -> Self {
+        let rows = view_proj.transpose();
+        let (x, y, z, w) = (rows.x_axis, rows.y_axis, rows.z_axis, rows.w_axis);
+
+        let mut planes = [w + x, w - x, w + y, w - y, z, w - z];
+        for plane in &mut planes {
+            *plane /= plane.truncate().length();
+        }
+
+        Frustum { planes }
+    }
+
+    /// Whether any part of `aabb` may be inside, boxes crossing a corner can give false positives.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            // The corner furthest along the plane normal
+            let corner = Vec3::new(
+                if normal.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if normal.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if normal.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Frustum {
+        // At the origin looking down -Z
+        let proj = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        let view = Mat4::look_at_rh(Vec3::ZERO, -Vec3::Z, Vec3::Y);
+        Frustum::from_view_proj(&(proj * view))
+    }
+
+    fn cube(center: Vec3) -> Aabb {
+        Aabb {
+            min: center - Vec3::ONE,
+            max: center + Vec3::ONE,
+        }
+    }
+
+    #[test]
+    fn planes_of_the_identity_are_the_clip_volume() {
+        let frustum = Frustum::from_view_proj(&Mat4::IDENTITY);
+
+        assert_eq!(
+            frustum.planes,
+            [
+                Vec4::new(1.0, 0.0, 0.0, 1.0),
+                Vec4::new(-1.0, 0.0, 0.0, 1.0),
+                Vec4::new(0.0, 1.0, 0.0, 1.0),
+                Vec4::new(0.0, -1.0, 0.0, 1.0),
+                Vec4::new(0.0, 0.0, 1.0, 0.0),
+                Vec4::new(0.0, 0.0, -1.0, 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn boxes_in_front_of_the_camera_are_visible() {
+        let frustum = camera();
+
+        assert!(frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, -10.0))));
+        // Straddling the near plane
+        assert!(frustum.intersects_aabb(&cube(Vec3::ZERO)));
+        // Only a corner pokes into the right side of the view
+        assert!(frustum.intersects_aabb(&cube(Vec3::new(10.5, 0.0, -10.0))));
+    }
+
+    #[test]
+    fn boxes_outside_the_frustum_are_culled() {
+        let frustum = camera();
+
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, 10.0))));
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(20.0, 0.0, -10.0))));
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(0.0, -20.0, -10.0))));
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, -200.0))));
+    }
+
+    #[test]
+    fn transformed_boxes_contain_the_transformed_corners() {
+        let aabb = cube(Vec3::ZERO);
+        let matrix = Mat4::from_translation(Vec3::new(5.0, 0.0, 0.0))
+            * Mat4::from_rotation_z(std::f32::consts::FRAC_PI_4);
+
+        let transformed = aabb.transformed(&matrix);
+
+        let extent = std::f32::consts::SQRT_2;
+        assert!((transformed.min - Vec3::new(5.0 - extent, -extent, -1.0)).length() < 1e-5);
+        assert!((transformed.max - Vec3::new(5.0 + extent, extent, 1.0)).length() < 1e-5);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::render::{render_context::RenderContext, resources::QueryPool};
+use erupt::vk;
+use std::time::Duration;
+
+/// Timestamps available to each frame in flight, the start of the frame and the end of every pass.
+const MAX_TIMESTAMPS: u32 = 16;
+
+/// Measures how long each pass of a frame takes on the GPU.
+///
+/// Every frame in flight owns a range of [`MAX_TIMESTAMPS`] queries. A timestamp is written at the
+/// start of the frame and after each pass, the results of a slot are read back right before it's
+/// reused, once its frame has completed.
+pub struct GpuTimer {
+    query_pool: QueryPool,
+    /// The passes timed in each frame slot, in submission order.
+    labels: Vec<Vec<&'static str>>,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+}
+
+impl GpuTimer {
+    /// `None` if the device can't write timestamps.
+    pub fn new(render_context: &RenderContext, frames_in_flight: usize) -> Option<Self> {
+        let query_pool =
+            render_context.create_timestamp_query_pool(MAX_TIMESTAMPS * frames_in_flight as u32)?;
+
+        Some(GpuTimer {
+            query_pool,
+            labels: vec![Vec::new(); frames_in_flight],
+            period: render_context.timestamp_period(),
+        })
+    }
+
+    /// Returns the pass times of the frame that last used the slot of `frame`, which must have
+    /// completed, then starts timing `frame`. Empty if the slot wasn't used yet.
+    pub fn begin_frame(
+        &mut self,
+        render_context: &mut RenderContext,
+        frame: u64,
+    ) -> Vec<(&'static str, Duration)> {
+        let slot = self.slot(frame);
+        let first = slot as u32 * MAX_TIMESTAMPS;
+
+        let labels = std::mem::take(&mut self.labels[slot]);
+        let pass_times = if labels.is_empty() {
+            Vec::new()
+        } else {
+            render_context
+                .timestamps(&self.query_pool, first..first + labels.len() as u32 + 1)
+                .map(|timestamps| pass_times(&timestamps, &labels, self.period))
+                .unwrap_or_default()
+        };
+
+        let mut encoder = render_context.queue.create_enconder();
+        encoder.reset_query_pool(&self.query_pool, first..first + MAX_TIMESTAMPS);
+        encoder.write_timestamp(
+            vk::PipelineStageFlagBits::TOP_OF_PIPE,
+            &self.query_pool,
+            first,
+        );
+        let command_buffer = encoder.finish(&render_context.device);
+        render_context.queue.submit(command_buffer, &[], &[], None);
+
+        pass_times
+    }
+
+    /// Writes a timestamp once the GPU is done with everything submitted so far in `frame`,
+    /// closing the pass named `label`. Passes past [`MAX_TIMESTAMPS`] aren't timed.
+    pub fn end_pass(
+        &mut self,
+        render_context: &mut RenderContext,
+        frame: u64,
+        label: &'static str,
+    ) {
+        let slot = self.slot(frame);
+        let labels = &mut self.labels[slot];
+        if labels.len() as u32 + 1 >= MAX_TIMESTAMPS {
+            return;
+        }
+        labels.push(label);
+        let query = slot as u32 * MAX_TIMESTAMPS + labels.len() as u32;
+
+        let mut encoder = render_context.queue.create_enconder();
+        encoder.write_timestamp(
+            vk::PipelineStageFlagBits::BOTTOM_OF_PIPE,
+            &self.query_pool,
+            query,
+        );
+        let command_buffer = encoder.finish(&render_context.device);
+        render_context.queue.submit(command_buffer, &[], &[], None);
+    }
+
+    fn slot(&self, frame: u64) -> usize {
+        (frame % self.labels.len() as u64) as usize
+    }
+}
+
+/// Pairs each label with the time between its timestamp and the previous one. The counter can
+/// wrap around between two timestamps.
+fn pass_times(
+    timestamps: &[u64],
+    labels: &[&'static str],
+    period: f32,
+) -> Vec<(&'static str, Duration)> {
+    timestamps
+        .windows(2)
+        .zip(labels)
+        .map(|(pair, &label)| {
+            let ticks = pair[1].wrapping_sub(pair[0]);
+            let nanos = (ticks as f64 * period as f64) as u64;
+            (label, Duration::from_nanos(nanos))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pass_times_are_the_deltas_between_timestamps() {
+        let times = pass_times(&[100, 150, 250, 250], &["trace", "denoise", "tonemap"], 2.0);
+
+        assert_eq!(
+            times,
+            vec![
+                ("trace", Duration::from_nanos(100)),
+                ("denoise", Duration::from_nanos(200)),
+                ("tonemap", Duration::from_nanos(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn pass_times_handle_a_wrapping_counter() {
+        let times = pass_times(&[u64::MAX - 4, 5], &["trace"], 1.0);
+
+        assert_eq!(times, vec![("trace", Duration::from_nanos(10))]);
+    }
+}
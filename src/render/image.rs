@@ -13,6 +13,54 @@ pub struct ImageInfo {
     pub array_layers: u32,
     pub samples: vk::SampleCountFlagBits,
     pub usage: vk::ImageUsageFlags,
+    pub flags: vk::ImageCreateFlags,
+    /// Attached to the image with `VK_EXT_debug_utils` in debug builds.
+    pub name: Option<&'static str>,
+}
+
+/// How the texel values of uploaded data are encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureColorSpace {
+    Linear,
+    /// sRGB encoded color, like glTF base color and emissive textures. Alpha is always linear.
+    Srgb,
+}
+
+impl TextureColorSpace {
+    /// The format to create an image holding `format` data in this color space with, so that
+    /// sampling decodes it to linear in hardware. `None` if the format has no sRGB variant.
+    pub fn format(self, format: vk::Format) -> Option<vk::Format> {
+        if self == TextureColorSpace::Linear {
+            return Some(format);
+        }
+
+        let srgb_format = match format {
+            vk::Format::R8_UNORM => vk::Format::R8_SRGB,
+            vk::Format::R8G8_UNORM => vk::Format::R8G8_SRGB,
+            vk::Format::R8G8B8_UNORM => vk::Format::R8G8B8_SRGB,
+            vk::Format::B8G8R8_UNORM => vk::Format::B8G8R8_SRGB,
+            vk::Format::R8G8B8A8_UNORM => vk::Format::R8G8B8A8_SRGB,
+            vk::Format::B8G8R8A8_UNORM => vk::Format::B8G8R8A8_SRGB,
+            vk::Format::BC1_RGB_UNORM_BLOCK => vk::Format::BC1_RGB_SRGB_BLOCK,
+            vk::Format::BC1_RGBA_UNORM_BLOCK => vk::Format::BC1_RGBA_SRGB_BLOCK,
+            vk::Format::BC2_UNORM_BLOCK => vk::Format::BC2_SRGB_BLOCK,
+            vk::Format::BC3_UNORM_BLOCK => vk::Format::BC3_SRGB_BLOCK,
+            vk::Format::BC7_UNORM_BLOCK => vk::Format::BC7_SRGB_BLOCK,
+            vk::Format::ETC2_R8G8B8_UNORM_BLOCK => vk::Format::ETC2_R8G8B8_SRGB_BLOCK,
+            vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK => vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+            _ => return None,
+        };
+        Some(srgb_format)
+    }
+}
+
+/// Decodes an sRGB encoded channel in `[0, 1]` to linear.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
 }
 
 #[derive(Clone)]
@@ -82,6 +130,8 @@ pub struct ImageMemoryBarrier<'a> {
     pub new_layout: vk::ImageLayout,
     pub family_transfer: Option<Range<u32>>,
     pub subresource: ImageSubresourceRange,
+    /// Overrides the access masks of the surrounding `pipeline_barrier` for this image only.
+    pub access: Option<Range<vk::AccessFlags>>,
 }
 
 impl<'a> ImageMemoryBarrier<'a> {
@@ -92,6 +142,7 @@ impl<'a> ImageMemoryBarrier<'a> {
             old_layout: Some(layouts.start),
             new_layout: layouts.end,
             family_transfer: None,
+            access: None,
         }
     }
 
@@ -102,8 +153,32 @@ impl<'a> ImageMemoryBarrier<'a> {
             old_layout: None,
             new_layout: layout,
             family_transfer: None,
+            access: None,
+        }
+    }
+
+    /// Releases `image` from `src_family` and acquires it on `dst_family`.
+    /// The same barrier has to be recorded on both queues for the transfer to complete.
+    pub fn queue_transfer(
+        image: &'a Image,
+        layouts: Range<vk::ImageLayout>,
+        src_family: u32,
+        dst_family: u32,
+    ) -> Self {
+        ImageMemoryBarrier {
+            subresource: ImageSubresourceRange::whole(image.info(), vk::ImageAspectFlags::COLOR),
+            image,
+            old_layout: Some(layouts.start),
+            new_layout: layouts.end,
+            family_transfer: Some(src_family..dst_family),
+            access: None,
         }
     }
+
+    pub fn with_access(mut self, access: Range<vk::AccessFlags>) -> Self {
+        self.access = Some(access);
+        self
+    }
 }
 
 #[derive(Clone)]
@@ -121,12 +196,65 @@ impl ImageViewInfo {
             view_type: vk::ImageViewType::_2D,
             subresource: ImageSubresourceRange::new(
                 image_aspect_flags,
-                0..info.array_layers,
+                0..info.mip_levels,
                 0..info.array_layers,
             ),
             image,
         }
     }
+
+    /// Views the first six layers of `image` as a cubemap.
+    /// The image must have been created with `vk::ImageCreateFlags::CUBE_COMPATIBLE`.
+    pub fn cube(image: Image) -> Self {
+        let info = image.info();
+        debug_assert!(info.flags.contains(vk::ImageCreateFlags::CUBE_COMPATIBLE));
+        debug_assert!(info.array_layers >= 6);
+
+        ImageViewInfo {
+            view_type: vk::ImageViewType::CUBE,
+            subresource: ImageSubresourceRange::new(
+                vk::ImageAspectFlags::COLOR,
+                0..info.mip_levels,
+                0..6,
+            ),
+            image,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerInfo {
+    /// Used for both minification and magnification.
+    pub filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// Used for all three coordinates.
+    pub address_mode: vk::SamplerAddressMode,
+    /// Only read with `CLAMP_TO_BORDER`.
+    pub border_color: vk::BorderColor,
+}
+
+impl Default for SamplerInfo {
+    fn default() -> Self {
+        SamplerInfo {
+            filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+            border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+        }
+    }
+}
+
+impl SamplerInfo {
+    /// Bilinear filtering that repeats the edge texels, for resampling a full image without
+    /// darkening its borders.
+    pub fn linear_clamp_to_edge() -> Self {
+        SamplerInfo {
+            filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        }
+    }
 }
 
 struct ImageInner {
@@ -199,3 +327,79 @@ impl ImageView {
         self.handle
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(mip_levels: u32, array_layers: u32, flags: vk::ImageCreateFlags) -> Image {
+        Image::new(
+            ImageInfo {
+                extent: vk::Extent2D {
+                    width: 64,
+                    height: 64,
+                },
+                format: vk::Format::R16G16B16A16_SFLOAT,
+                mip_levels,
+                array_layers,
+                samples: vk::SampleCountFlagBits::_1,
+                usage: vk::ImageUsageFlags::SAMPLED,
+                flags,
+                name: None,
+            },
+            vk::Image::null(),
+            None,
+        )
+    }
+
+    #[test]
+    fn views_cover_every_level_and_layer() {
+        let info = ImageViewInfo::new(
+            image(4, 6, vk::ImageCreateFlags::empty()),
+            vk::ImageAspectFlags::COLOR,
+        );
+
+        assert_eq!(info.subresource.first_level, 0);
+        assert_eq!(info.subresource.level_count, 4);
+        assert_eq!(info.subresource.first_layer, 0);
+        assert_eq!(info.subresource.layer_count, 6);
+    }
+
+    #[test]
+    fn cube_views_take_six_layers() {
+        let info = ImageViewInfo::cube(image(3, 6, vk::ImageCreateFlags::CUBE_COMPATIBLE));
+
+        assert_eq!(info.view_type, vk::ImageViewType::CUBE);
+        assert_eq!(info.subresource.aspect, vk::ImageAspectFlags::COLOR);
+        assert_eq!(info.subresource.level_count, 3);
+        assert_eq!(
+            (info.subresource.first_layer, info.subresource.layer_count),
+            (0, 6)
+        );
+    }
+
+    #[test]
+    fn srgb_sources_select_srgb_formats() {
+        assert_eq!(
+            TextureColorSpace::Srgb.format(vk::Format::R8G8B8A8_UNORM),
+            Some(vk::Format::R8G8B8A8_SRGB)
+        );
+        assert_eq!(
+            TextureColorSpace::Linear.format(vk::Format::R8G8B8A8_UNORM),
+            Some(vk::Format::R8G8B8A8_UNORM)
+        );
+        // Float data has no sRGB variant and has to be converted on upload instead
+        assert_eq!(
+            TextureColorSpace::Srgb.format(vk::Format::R16G16B16A16_SFLOAT),
+            None
+        );
+    }
+
+    #[test]
+    fn srgb_decoding_matches_the_transfer_function() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_to_linear(0.04045) - 0.04045 / 12.92).abs() < 1e-6);
+        assert!((srgb_to_linear(0.5) - 0.214_041_14).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::render::{
+    device::Device,
+    image::{Image, ImageInfo},
+};
+use erupt::vk;
+use std::collections::HashMap;
+
+/// What an image in the pool is looked up by. The name is part of the key so two images of a pass
+/// with the same format and usage, e.g. albedo and normals, don't end up aliasing each other.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct ImageKey {
+    name: Option<&'static str>,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    width: u32,
+    height: u32,
+}
+
+impl From<&ImageInfo> for ImageKey {
+    fn from(info: &ImageInfo) -> Self {
+        ImageKey {
+            name: info.name,
+            format: info.format,
+            usage: info.usage,
+            width: info.extent.width,
+            height: info.extent.height,
+        }
+    }
+}
+
+/// Output, depth and accumulation images of the passes, which have to be recreated whenever the
+/// window is resized.
+///
+/// Passes request their images with [`ImagePool::get`] on creation and again after a resize, an
+/// image is only created the first time its key is requested. [`ImagePool::resize`] drops every
+/// image that doesn't match the new extent, like other resources their memory is only released
+/// with the device.
+#[derive(Default)]
+pub struct ImagePool {
+    extent: Option<(u32, u32)>,
+    images: HashMap<ImageKey, Image>,
+}
+
+impl ImagePool {
+    pub fn get(&mut self, device: &Device, info: ImageInfo) -> Image {
+        self.get_or_create(info, |info| device.create_image(info))
+    }
+
+    fn get_or_create(&mut self, info: ImageInfo, create: impl FnOnce(ImageInfo) -> Image) -> Image {
+        self.images
+            .entry(ImageKey::from(&info))
+            .or_insert_with(|| create(info))
+            .clone()
+    }
+
+    /// Invalidates the images created for a different extent. Returns `false` if `extent` is the
+    /// current one and the passes can keep their images.
+    pub fn resize(&mut self, extent: vk::Extent2D) -> bool {
+        if self.extent == Some((extent.width, extent.height)) {
+            return false;
+        }
+
+        self.extent = Some((extent.width, extent.height));
+        self.images
+            .retain(|key, _| key.width == extent.width && key.height == extent.height);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &'static str, width: u32, height: u32) -> ImageInfo {
+        ImageInfo {
+            extent: vk::Extent2D { width, height },
+            format: vk::Format::R32G32B32A32_SFLOAT,
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlagBits::_1,
+            usage: vk::ImageUsageFlags::STORAGE,
+            flags: vk::ImageCreateFlags::empty(),
+            name: Some(name),
+        }
+    }
+
+    #[test]
+    fn reuses_images_until_the_extent_changes() {
+        let mut pool = ImagePool::default();
+        let mut created = 0;
+        let mut create = |info| {
+            created += 1;
+            Image::new(info, vk::Image::null(), None)
+        };
+
+        assert!(pool.resize(vk::Extent2D {
+            width: 800,
+            height: 600
+        }));
+        pool.get_or_create(info("color", 800, 600), &mut create);
+        pool.get_or_create(info("color", 800, 600), &mut create);
+        pool.get_or_create(info("albedo", 800, 600), &mut create);
+        assert!(!pool.resize(vk::Extent2D {
+            width: 800,
+            height: 600
+        }));
+        pool.get_or_create(info("color", 800, 600), &mut create);
+
+        assert!(pool.resize(vk::Extent2D {
+            width: 1024,
+            height: 768
+        }));
+        assert!(pool.images.is_empty());
+        pool.get_or_create(info("color", 1024, 768), &mut create);
+
+        assert_eq!(created, 3);
+    }
+}
@@ -22,6 +22,18 @@ pub fn create_instance(window: &Window, entry: &EntryLoader) -> InstanceLoader {
     #[cfg(target_os = "windows")]
     {
         instance_extensions.push(vk::KHR_WIN32_SURFACE_EXTENSION_NAME);
+
+        // Required by VK_EXT_full_screen_exclusive
+        let supported_extensions =
+            unsafe { entry.enumerate_instance_extension_properties(None, None) }.unwrap();
+        let get_surface_capabilities2 =
+            unsafe { std::ffi::CStr::from_ptr(vk::KHR_GET_SURFACE_CAPABILITIES_2_EXTENSION_NAME) };
+        if supported_extensions.iter().any(|properties| unsafe {
+            std::ffi::CStr::from_ptr(properties.extension_name.as_ptr())
+                == get_surface_capabilities2
+        }) {
+            instance_extensions.push(vk::KHR_GET_SURFACE_CAPABILITIES_2_EXTENSION_NAME);
+        }
     }
 
     let mut instance_layers = Vec::new();
@@ -0,0 +1,276 @@
+use crate::render::{
+    device::Device,
+    image::{Image, ImageInfo},
+};
+use erupt::vk;
+use std::convert::TryInto;
+use std::ops::Range;
+use thiserror::Error;
+
+const IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+
+/// Size of the fixed header and the index that follows it, the level index starts right after.
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+#[derive(Error, Debug)]
+pub enum Ktx2Error {
+    #[error("failed to read KTX2 file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("not a KTX2 file")]
+    InvalidIdentifier,
+    #[error("KTX2 file is truncated")]
+    Truncated,
+    #[error("supercompressed KTX2 files are not supported (scheme {0})")]
+    Supercompressed(u32),
+    #[error("only 2D textures with a single layer and face are supported")]
+    UnsupportedShape,
+    #[error("{0:?} is not a supported block-compressed format")]
+    UnsupportedFormat(vk::Format),
+    #[error("the device can't sample {0:?} images")]
+    UnsupportedByDevice(vk::Format),
+    #[error("{level_count} mip levels, a {width}x{height} image has at most {max}")]
+    TooManyLevels {
+        level_count: u32,
+        width: u32,
+        height: u32,
+        max: u32,
+    },
+    #[error("mip level {level} is {actual} bytes, expected {expected}")]
+    InvalidLevelSize {
+        level: u32,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+/// The parts of a KTX2 header needed to upload its mip levels as is.
+#[derive(Clone, Debug)]
+pub struct Ktx2Header {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub level_count: u32,
+    /// Byte range of every mip level in the file, starting with the largest.
+    pub levels: Vec<Range<usize>>,
+}
+
+impl Ktx2Header {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Ktx2Error> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(Ktx2Error::Truncated);
+        }
+        if bytes[..IDENTIFIER.len()] != IDENTIFIER {
+            return Err(Ktx2Error::InvalidIdentifier);
+        }
+
+        let format = vk::Format(read_u32(bytes, 12) as i32);
+        let width = read_u32(bytes, 20);
+        let height = read_u32(bytes, 24);
+        let depth = read_u32(bytes, 28);
+        let layer_count = read_u32(bytes, 32);
+        let face_count = read_u32(bytes, 36);
+        // 0 asks the loader to generate the mips, which block-compressed data can't have
+        let level_count = read_u32(bytes, 40).max(1);
+        let supercompression_scheme = read_u32(bytes, 44);
+
+        if supercompression_scheme != 0 {
+            return Err(Ktx2Error::Supercompressed(supercompression_scheme));
+        }
+        if depth > 1 || layer_count > 1 || face_count != 1 || height == 0 {
+            return Err(Ktx2Error::UnsupportedShape);
+        }
+        if block_size(format).is_none() {
+            return Err(Ktx2Error::UnsupportedFormat(format));
+        }
+
+        // Also keeps the shifts computing the level extents in range
+        let max_levels = 32 - width.max(height).leading_zeros();
+        if level_count > max_levels {
+            return Err(Ktx2Error::TooManyLevels {
+                level_count,
+                width,
+                height,
+                max: max_levels,
+            });
+        }
+
+        let level_index_end = HEADER_SIZE + level_count as usize * LEVEL_INDEX_ENTRY_SIZE;
+        if bytes.len() < level_index_end {
+            return Err(Ktx2Error::Truncated);
+        }
+
+        let levels = (0..level_count)
+            .map(|level| {
+                let entry = HEADER_SIZE + level as usize * LEVEL_INDEX_ENTRY_SIZE;
+                let offset = read_u64(bytes, entry);
+                let length = read_u64(bytes, entry + 8);
+
+                let expected = level_size(format, width, height, level);
+                if length != expected {
+                    return Err(Ktx2Error::InvalidLevelSize {
+                        level,
+                        expected,
+                        actual: length,
+                    });
+                }
+
+                match offset.checked_add(length) {
+                    Some(end) if end <= bytes.len() as u64 => Ok(offset as usize..end as usize),
+                    _ => Err(Ktx2Error::Truncated),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Ktx2Header {
+            format,
+            width,
+            height,
+            level_count,
+            levels,
+        })
+    }
+}
+
+/// Uploads the mip levels of a KTX2 file as they are stored, without transcoding.
+pub fn load_ktx2(device: &Device, bytes: &[u8]) -> Result<Image, Ktx2Error> {
+    let header = Ktx2Header::parse(bytes)?;
+
+    if !device
+        .format_features(header.format)
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+    {
+        return Err(Ktx2Error::UnsupportedByDevice(header.format));
+    }
+
+    let levels = header
+        .levels
+        .iter()
+        .map(|range| &bytes[range.clone()])
+        .collect::<Vec<_>>();
+
+    Ok(device.create_image_with_levels(
+        ImageInfo {
+            extent: vk::Extent2D {
+                width: header.width,
+                height: header.height,
+            },
+            format: header.format,
+            mip_levels: header.level_count,
+            array_layers: 1,
+            samples: vk::SampleCountFlagBits::_1,
+            usage: vk::ImageUsageFlags::SAMPLED,
+            flags: vk::ImageCreateFlags::empty(),
+            name: Some("KTX2 texture"),
+        },
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        &levels,
+    ))
+}
+
+/// Bytes taken by mip `level` of a `width` x `height` image, rounded up to whole 4x4 blocks.
+pub fn level_size(format: vk::Format, width: u32, height: u32, level: u32) -> u64 {
+    let block_size = block_size(format).expect("not a block-compressed format");
+    let blocks_wide = ((width >> level).max(1) + 3) / 4;
+    let blocks_high = ((height >> level).max(1) + 3) / 4;
+    blocks_wide as u64 * blocks_high as u64 * block_size
+}
+
+/// Bytes per 4x4 block of the supported BC and ETC2 formats.
+fn block_size(format: vk::Format) -> Option<u64> {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK => Some(8),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK => Some(16),
+        _ => None,
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2D `BC1_RGBA_UNORM_BLOCK` file with the given level index entries and no level data.
+    fn header(width: u32, height: u32, levels: &[(u64, u64)]) -> Vec<u8> {
+        let mut bytes = IDENTIFIER.to_vec();
+        let fields = [
+            vk::Format::BC1_RGBA_UNORM_BLOCK.0 as u32,
+            1,
+            width,
+            height,
+            0,
+            0,
+            1,
+            levels.len() as u32,
+            0,
+        ];
+        for field in &fields {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.resize(HEADER_SIZE, 0);
+        for &(offset, length) in levels {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            bytes.extend_from_slice(&length.to_le_bytes());
+            bytes.extend_from_slice(&length.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_levels() {
+        let mut bytes = header(8, 8, &[(128, 32), (160, 8)]);
+        bytes.resize(168, 0);
+
+        let header = Ktx2Header::parse(&bytes).unwrap();
+        assert_eq!(header.level_count, 2);
+        assert_eq!(header.levels, vec![128..160, 160..168]);
+    }
+
+    #[test]
+    fn level_past_the_end_is_truncated() {
+        let bytes = header(4, 4, &[(u64::MAX - 4, 8)]);
+        assert!(matches!(
+            Ktx2Header::parse(&bytes),
+            Err(Ktx2Error::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_more_levels_than_the_extent_has() {
+        // A 4x4 image has 3 levels, 40 would shift the extent out of range
+        let bytes = header(4, 4, &[(0, 8); 40]);
+        assert!(matches!(
+            Ktx2Header::parse(&bytes),
+            Err(Ktx2Error::TooManyLevels { max: 3, .. })
+        ));
+    }
+}
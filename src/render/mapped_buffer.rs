@@ -0,0 +1,175 @@
+use crate::render::{buffer::BufferInfo, device::Device, resources::Buffer};
+use bytemuck::Pod;
+use gpu_alloc::UsageFlags;
+use gpu_alloc_erupt::EruptMemoryDevice;
+use std::ptr::NonNull;
+
+/// The memory behind a [`MappedBuffer`].
+pub trait MappableMemory {
+    fn size(&self) -> u64;
+
+    /// Maps the whole memory, only called once when the [`MappedBuffer`] is created.
+    fn map(&mut self) -> NonNull<u8>;
+
+    /// Makes host writes to `size` bytes at `offset` visible to the device.
+    fn flush(&mut self, offset: u64, size: u64);
+
+    fn unmap(&mut self);
+}
+
+/// A host visible buffer of the device.
+pub struct BufferMemory {
+    buffer: Buffer,
+    device: Device,
+}
+
+impl MappableMemory for BufferMemory {
+    fn size(&self) -> u64 {
+        self.buffer.info().size
+    }
+
+    fn map(&mut self) -> NonNull<u8> {
+        self.device.map_buffer(&mut self.buffer)
+    }
+
+    fn flush(&mut self, offset: u64, size: u64) {
+        self.device.flush_buffer(&mut self.buffer, offset, size);
+    }
+
+    fn unmap(&mut self) {
+        // Destroying the device released the memory, mapping included
+        if self.device.is_destroyed() {
+            return;
+        }
+
+        unsafe {
+            self.buffer
+                .memory_block()
+                .unmap(EruptMemoryDevice::wrap(self.device.handle()));
+        }
+    }
+}
+
+/// Host visible buffer mapped once when created and unmapped when dropped, for data written
+/// often. Writes through [`MappedBuffer::write`] are flushed when the memory isn't coherent.
+pub struct MappedBuffer<M: MappableMemory = BufferMemory> {
+    memory: M,
+    mapped: NonNull<u8>,
+}
+
+impl MappedBuffer {
+    /// Panics if `info` doesn't ask for `HOST_ACCESS`.
+    pub fn new(device: &Device, info: BufferInfo) -> Self {
+        assert!(
+            info.allocation_flags.contains(UsageFlags::HOST_ACCESS),
+            "mapped buffers need HOST_ACCESS"
+        );
+
+        Self::from_memory(BufferMemory {
+            buffer: device.create_buffer(info),
+            device: device.clone(),
+        })
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.memory.buffer
+    }
+}
+
+impl<M: MappableMemory> MappedBuffer<M> {
+    fn from_memory(mut memory: M) -> Self {
+        let mapped = memory.map();
+        MappedBuffer { memory, mapped }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.memory.size()
+    }
+
+    /// Copies `data` to `offset` bytes into the buffer. Panics if it doesn't fit.
+    pub fn write<T>(&mut self, offset: u64, data: &[T])
+    where
+        T: Pod,
+    {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let size = bytes.len() as u64;
+        assert!(
+            offset
+                .checked_add(size)
+                .map_or(false, |end| end <= self.size()),
+            "writing {} bytes at {} overflows a {} byte buffer",
+            size,
+            offset,
+            self.size()
+        );
+
+        self.bytes_mut()[offset as usize..(offset + size) as usize].copy_from_slice(bytes);
+        self.flush(offset, size);
+    }
+
+    /// The mapped memory. Writes made through it have to be followed by [`MappedBuffer::flush`].
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.mapped.as_ptr(), self.size() as usize) }
+    }
+
+    /// Makes writes to `size` bytes at `offset` visible to the device.
+    pub fn flush(&mut self, offset: u64, size: u64) {
+        self.memory.flush(offset, size);
+    }
+}
+
+impl<M: MappableMemory> Drop for MappedBuffer<M> {
+    fn drop(&mut self) {
+        self.memory.unmap();
+    }
+}
+
+unsafe impl<M: MappableMemory + Send> Send for MappedBuffer<M> {}
+unsafe impl<M: MappableMemory + Sync> Sync for MappedBuffer<M> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct StubMemory {
+        bytes: Vec<u8>,
+        maps: usize,
+        flushes: Vec<(u64, u64)>,
+    }
+
+    impl MappableMemory for StubMemory {
+        fn size(&self) -> u64 {
+            self.bytes.len() as u64
+        }
+
+        fn map(&mut self) -> NonNull<u8> {
+            self.maps += 1;
+            NonNull::new(self.bytes.as_mut_ptr()).unwrap()
+        }
+
+        fn flush(&mut self, offset: u64, size: u64) {
+            self.flushes.push((offset, size));
+        }
+
+        fn unmap(&mut self) {}
+    }
+
+    #[test]
+    fn writes_go_through_the_mapping_made_on_creation() {
+        let mut buffer = MappedBuffer::from_memory(StubMemory {
+            bytes: vec![0; 16],
+            ..Default::default()
+        });
+
+        buffer.write(0, &[1u32, 2]);
+        buffer.write(8, &[3u32]);
+
+        assert_eq!(buffer.memory.maps, 1);
+        assert_eq!(buffer.memory.flushes, vec![(0, 8), (8, 4)]);
+        assert_eq!(
+            buffer.memory.bytes,
+            bytemuck::cast_slice::<u32, u8>(&[1, 2, 3, 0])
+        );
+    }
+}
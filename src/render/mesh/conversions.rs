@@ -72,8 +72,94 @@ impl From<Vec<[u32; 4]>> for VertexAttributeValues {
     }
 }
 
+// 8 and 16-bit integers map to the normalized variants like `[u8; 4]` colors do, the
+// `Sint`/`Uint` ones share their types and have to be constructed explicitly.
+
+impl From<Vec<[i16; 2]>> for VertexAttributeValues {
+    fn from(vec: Vec<[i16; 2]>) -> Self {
+        VertexAttributeValues::Snorm16x2(vec)
+    }
+}
+
+impl From<Vec<[u16; 2]>> for VertexAttributeValues {
+    fn from(vec: Vec<[u16; 2]>) -> Self {
+        VertexAttributeValues::Unorm16x2(vec)
+    }
+}
+
+impl From<Vec<[i16; 4]>> for VertexAttributeValues {
+    fn from(vec: Vec<[i16; 4]>) -> Self {
+        VertexAttributeValues::Snorm16x4(vec)
+    }
+}
+
+impl From<Vec<[u16; 4]>> for VertexAttributeValues {
+    fn from(vec: Vec<[u16; 4]>) -> Self {
+        VertexAttributeValues::Unorm16x4(vec)
+    }
+}
+
+impl From<Vec<[i8; 2]>> for VertexAttributeValues {
+    fn from(vec: Vec<[i8; 2]>) -> Self {
+        VertexAttributeValues::Snorm8x2(vec)
+    }
+}
+
+impl From<Vec<[u8; 2]>> for VertexAttributeValues {
+    fn from(vec: Vec<[u8; 2]>) -> Self {
+        VertexAttributeValues::Unorm8x2(vec)
+    }
+}
+
+impl From<Vec<[i8; 4]>> for VertexAttributeValues {
+    fn from(vec: Vec<[i8; 4]>) -> Self {
+        VertexAttributeValues::Snorm8x4(vec)
+    }
+}
+
 impl From<Vec<[u8; 4]>> for VertexAttributeValues {
     fn from(vec: Vec<[u8; 4]>) -> Self {
         VertexAttributeValues::Unorm8x4(vec)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_integers_convert_to_their_variants() {
+        assert!(matches!(
+            VertexAttributeValues::from(vec![[-1i16, 1]]),
+            VertexAttributeValues::Snorm16x2(values) if values == [[-1, 1]]
+        ));
+        assert!(matches!(
+            VertexAttributeValues::from(vec![[1u16, 2]]),
+            VertexAttributeValues::Unorm16x2(values) if values == [[1, 2]]
+        ));
+        assert!(matches!(
+            VertexAttributeValues::from(vec![[-1i16, 1, 2, 3]]),
+            VertexAttributeValues::Snorm16x4(values) if values == [[-1, 1, 2, 3]]
+        ));
+        assert!(matches!(
+            VertexAttributeValues::from(vec![[1u16, 2, 3, 4]]),
+            VertexAttributeValues::Unorm16x4(values) if values == [[1, 2, 3, 4]]
+        ));
+        assert!(matches!(
+            VertexAttributeValues::from(vec![[-1i8, 1]]),
+            VertexAttributeValues::Snorm8x2(values) if values == [[-1, 1]]
+        ));
+        assert!(matches!(
+            VertexAttributeValues::from(vec![[1u8, 2]]),
+            VertexAttributeValues::Unorm8x2(values) if values == [[1, 2]]
+        ));
+        assert!(matches!(
+            VertexAttributeValues::from(vec![[-1i8, 1, 2, 3]]),
+            VertexAttributeValues::Snorm8x4(values) if values == [[-1, 1, 2, 3]]
+        ));
+        assert!(matches!(
+            VertexAttributeValues::from(vec![[1u8, 2, 3, 4]]),
+            VertexAttributeValues::Unorm8x4(values) if values == [[1, 2, 3, 4]]
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 mod conversions;
+mod vertex_cache;
 
 use crate::material::Material;
 use crate::render::acceleration_structures::IndexData;
@@ -9,25 +10,104 @@ use crate::render::{
         AccelerationStructureGeometryInfo, AccelerationStructureInfo, AccelerationStructureLevel,
     },
     buffer::{BufferInfo, BufferRegion},
+    buffer_arena::BufferArena,
     device::Device,
     encoder::Encoder,
-    resources::{AccelerationStructure, Buffer},
+    frustum::Aabb,
+    resources::AccelerationStructure,
+    scratch_pool::ScratchPool,
     vertex::{Indices, PrimitiveTopology},
 };
 use bevy::asset::Handle;
 use bevy::ecs::bundle::Bundle;
 use bevy::reflect::TypeUuid;
+use bevy::transform::components::{GlobalTransform, Transform};
 use bumpalo::Bump;
 use bytemuck::cast_slice;
 use erupt::vk;
 use glam::Vec3;
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MeshError {
+    #[error("mesh has no `{}` attribute", Mesh::ATTRIBUTE_POSITION)]
+    MissingPositions,
+    #[error("{0:?} positions can't be used to build acceleration structures")]
+    UnsupportedVertexFormat(VertexFormat),
+    #[error("the device can't build acceleration structures from {0:?} positions")]
+    UnsupportedByDevice(vk::Format),
+    #[error("mesh has no `{0}` attribute")]
+    MissingAttribute(String),
+    #[error("attribute `{name}` has {len} values, expected {expected}")]
+    AttributeLengthMismatch {
+        name: String,
+        len: usize,
+        expected: usize,
+    },
+}
+
+/// Compact id the renderer assigns to a mesh the first time it sees it. It keys the per-mesh GPU
+/// resources and is stored as the custom index of the mesh's instances.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GeometryId(pub u32);
+
+/// The [`GeometryId`] of every mesh the renderer has seen.
+#[derive(Default)]
+pub struct GeometryIds {
+    ids: HashMap<Handle<Mesh>, GeometryId>,
+}
+
+impl GeometryIds {
+    /// `None` if `handle` was never assigned an id.
+    pub fn get(&self, handle: &Handle<Mesh>) -> Option<GeometryId> {
+        self.ids.get(handle).copied()
+    }
+
+    /// Returns the id of `handle`, assigning the next free one if it has none. Ids are handed out
+    /// sequentially starting at 0 and never reused.
+    pub fn get_or_assign(&mut self, handle: &Handle<Mesh>) -> GeometryId {
+        let next_id = GeometryId(self.ids.len() as u32);
+        *self.ids.entry(handle.clone_weak()).or_insert(next_id)
+    }
+}
+
+/// Position and index buffers uploaded for a mesh, shared by its BLAS and the raster passes.
+pub struct MeshBuffers {
+    pub vertex_buffer: BufferRegion,
+    pub vertex_count: u32,
+    /// Format of the positions, the BLAS build reads them as its
+    /// [`VertexFormat::acceleration_structure_format`].
+    pub vertex_format: VertexFormat,
+    /// `None` for non-indexed meshes, whose vertices are drawn as a plain triangle list.
+    pub index_buffer: Option<BufferRegion>,
+    pub index_type: vk::IndexType,
+    pub index_count: u32,
+    /// Linear RGBA `[f32; 4]` per vertex, `None` when the mesh has no [`Mesh::ATTRIBUTE_COLOR`].
+    pub color_buffer: Option<BufferRegion>,
+    /// The [`Mesh::shading_vertices`], `None` when the mesh has none.
+    pub shading_buffer: Option<BufferRegion>,
+    /// Object space bounds of the positions, `None` for meshes without vertices.
+    pub aabb: Option<Aabb>,
+}
+
+impl MeshBuffers {
+    pub fn triangle_count(&self) -> u32 {
+        if self.index_buffer.is_some() {
+            self.index_count / 3
+        } else {
+            self.vertex_count / 3
+        }
+    }
+}
 
 #[derive(Bundle)]
 pub struct MeshBundle {
     pub mesh: Handle<Mesh>,
     pub material: Handle<Material>,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
 }
 
 #[derive(Debug, TypeUuid, Clone)]
@@ -117,10 +197,6 @@ impl Mesh {
     /// This can dramatically increase the vertex count, so make sure this is what you want.
     /// Does nothing if no [Indices] are set.
     pub fn duplicate_vertices(&mut self) {
-        fn duplicate<T: Copy>(values: &[T], indices: impl Iterator<Item = usize>) -> Vec<T> {
-            indices.map(|i| values[i]).collect()
-        }
-
         assert!(
             matches!(self.primitive_topology, PrimitiveTopology::TriangleList),
             "can only duplicate vertices for `TriangleList`s"
@@ -131,37 +207,102 @@ impl Mesh {
             None => return,
         };
         for (_, attributes) in self.attributes.iter_mut() {
-            let indices = indices.iter();
-            match attributes {
-                VertexAttributeValues::Float32(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Sint32(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Uint32(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Float32x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Sint32x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Uint32x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Float32x3(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Sint32x3(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Uint32x3(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Sint32x4(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Uint32x4(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Float32x4(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Sint16x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Snorm16x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Uint16x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Unorm16x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Sint16x4(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Snorm16x4(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Uint16x4(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Unorm16x4(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Sint8x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Snorm8x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Uint8x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Unorm8x2(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Sint8x4(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Snorm8x4(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Uint8x4(vec) => *vec = duplicate(&vec, indices),
-                VertexAttributeValues::Unorm8x4(vec) => *vec = duplicate(&vec, indices),
+            attributes.gather(indices.iter());
+        }
+    }
+
+    /// Merges vertices whose attributes all match into one, the inverse of
+    /// [`Mesh::duplicate_vertices`]. Float attributes match when they round to the same multiple
+    /// of `epsilon`, the others when they are equal. Non-indexed meshes become indexed, and
+    /// 16-bit indices are kept.
+    pub fn weld_vertices(&mut self, epsilon: f32) {
+        let vertex_count = self.count_vertices();
+        let attributes = self
+            .attributes
+            .values()
+            .map(|values| {
+                let size = VertexFormat::from(values).get_size() as usize;
+                let quantize = matches!(
+                    values,
+                    VertexAttributeValues::Float32(_)
+                        | VertexAttributeValues::Float32x2(_)
+                        | VertexAttributeValues::Float32x3(_)
+                        | VertexAttributeValues::Float32x4(_)
+                ) && epsilon > 0.0;
+                (values.get_bytes(), size, quantize)
+            })
+            .collect::<Vec<_>>();
+
+        let mut unique = HashMap::new();
+        // For each vertex, the index of the vertex it is merged into
+        let mut remap = Vec::with_capacity(vertex_count);
+        // The first vertex of each merged group, in order
+        let mut kept = vec![];
+        for vertex in 0..vertex_count {
+            let mut key = Vec::new();
+            for &(bytes, size, quantize) in &attributes {
+                let bytes = &bytes[vertex * size..(vertex + 1) * size];
+                if quantize {
+                    for &value in cast_slice::<_, f32>(bytes) {
+                        key.extend_from_slice(&((value / epsilon).round() as i64).to_ne_bytes());
+                    }
+                } else {
+                    key.extend_from_slice(bytes);
+                }
+            }
+
+            let index = *unique.entry(key).or_insert_with(|| {
+                kept.push(vertex);
+                kept.len() as u32 - 1
+            });
+            remap.push(index);
+        }
+
+        let indices = match &self.indices {
+            Some(Indices::U16(indices)) => Indices::U16(
+                indices
+                    .iter()
+                    .map(|&index| remap[index as usize] as u16)
+                    .collect(),
+            ),
+            Some(Indices::U32(indices)) => {
+                Indices::U32(indices.iter().map(|&index| remap[index as usize]).collect())
+            }
+            None => Indices::U32(remap),
+        };
+
+        for values in self.attributes.values_mut() {
+            values.gather(kept.iter().copied());
+        }
+        self.indices = Some(indices);
+    }
+
+    /// Reorders the triangles of the index buffer so consecutive triangles share vertices, which
+    /// makes better use of the post-transform vertex cache. The vertices are left untouched.
+    ///
+    /// Does nothing if no [Indices] are set.
+    pub fn optimize_vertex_cache(&mut self) {
+        assert!(
+            matches!(self.primitive_topology, PrimitiveTopology::TriangleList),
+            "can only optimize the vertex cache of `TriangleList`s"
+        );
+
+        let vertex_count = self.count_vertices();
+        match &mut self.indices {
+            Some(Indices::U16(indices)) => {
+                let widened = indices
+                    .iter()
+                    .map(|&index| index as u32)
+                    .collect::<Vec<_>>();
+                *indices = vertex_cache::optimize(&widened, vertex_count)
+                    .into_iter()
+                    .map(|index| index as u16)
+                    .collect();
             }
+            Some(Indices::U32(indices)) => {
+                *indices = vertex_cache::optimize(indices, vertex_count);
+            }
+            None => {}
         }
     }
 
@@ -183,58 +324,213 @@ impl Mesh {
         let normals: Vec<_> = positions
             .chunks_exact(3)
             .map(|p| face_normal(p[0], p[1], p[2]))
-            .flat_map(|normal| std::array::IntoIter::new([normal, normal, normal]))
+            .flat_map(|normal| IntoIterator::into_iter([normal, normal, normal]))
             .collect();
 
         self.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
     }
 
+    /// Bounds of [`Mesh::ATTRIBUTE_POSITION`] as the BLAS build reads them: two component
+    /// positions have a zero z, a fourth component is ignored and normalized integers are mapped to
+    /// floats. `None` if the mesh has no positions or they are in a format a BLAS can't be built
+    /// from.
+    pub fn compute_aabb(&self) -> Option<Aabb> {
+        let snorm16 = |value: i16| (value as f32 / i16::MAX as f32).max(-1.0);
+        let unorm16 = |value: u16| value as f32 / u16::MAX as f32;
+        let snorm8 = |value: i8| (value as f32 / i8::MAX as f32).max(-1.0);
+        let unorm8 = |value: u8| value as f32 / u8::MAX as f32;
+
+        let positions = match self.attribute(Mesh::ATTRIBUTE_POSITION)? {
+            VertexAttributeValues::Float32x2(values) => values
+                .iter()
+                .map(|&[x, y]| Vec3::new(x, y, 0.0))
+                .collect::<Vec<_>>(),
+            VertexAttributeValues::Float32x3(values) => values
+                .iter()
+                .map(|&position| Vec3::from(position))
+                .collect(),
+            VertexAttributeValues::Float32x4(values) => values
+                .iter()
+                .map(|&[x, y, z, _]| Vec3::new(x, y, z))
+                .collect(),
+            VertexAttributeValues::Snorm16x2(values) => values
+                .iter()
+                .map(|&[x, y]| Vec3::new(snorm16(x), snorm16(y), 0.0))
+                .collect(),
+            VertexAttributeValues::Snorm16x4(values) => values
+                .iter()
+                .map(|&[x, y, z, _]| Vec3::new(snorm16(x), snorm16(y), snorm16(z)))
+                .collect(),
+            VertexAttributeValues::Unorm16x2(values) => values
+                .iter()
+                .map(|&[x, y]| Vec3::new(unorm16(x), unorm16(y), 0.0))
+                .collect(),
+            VertexAttributeValues::Unorm16x4(values) => values
+                .iter()
+                .map(|&[x, y, z, _]| Vec3::new(unorm16(x), unorm16(y), unorm16(z)))
+                .collect(),
+            VertexAttributeValues::Snorm8x2(values) => values
+                .iter()
+                .map(|&[x, y]| Vec3::new(snorm8(x), snorm8(y), 0.0))
+                .collect(),
+            VertexAttributeValues::Snorm8x4(values) => values
+                .iter()
+                .map(|&[x, y, z, _]| Vec3::new(snorm8(x), snorm8(y), snorm8(z)))
+                .collect(),
+            VertexAttributeValues::Unorm8x2(values) => values
+                .iter()
+                .map(|&[x, y]| Vec3::new(unorm8(x), unorm8(y), 0.0))
+                .collect(),
+            VertexAttributeValues::Unorm8x4(values) => values
+                .iter()
+                .map(|&[x, y, z, _]| Vec3::new(unorm8(x), unorm8(y), unorm8(z)))
+                .collect(),
+            _ => return None,
+        };
+
+        Aabb::from_points(positions)
+    }
+
+    /// [`Mesh::ATTRIBUTE_NORMAL`] and [`Mesh::ATTRIBUTE_UV_0`] interleaved as the `vec3` normal and
+    /// `vec2` uv the hit shaders read per vertex. `None` unless the mesh has both, as floats.
+    pub fn shading_vertices(&self) -> Option<Vec<u8>> {
+        match (
+            self.attribute(Mesh::ATTRIBUTE_NORMAL),
+            self.attribute(Mesh::ATTRIBUTE_UV_0),
+        ) {
+            (
+                Some(VertexAttributeValues::Float32x3(_)),
+                Some(VertexAttributeValues::Float32x2(_)),
+            ) => self
+                .build_interleaved_vertices(&[Mesh::ATTRIBUTE_NORMAL, Mesh::ATTRIBUTE_UV_0])
+                .ok()
+                .map(|(bytes, _)| bytes),
+            _ => None,
+        }
+    }
+
+    /// [`Mesh::ATTRIBUTE_COLOR`] as linear RGBA floats, `Unorm8x4` colors are normalized.
+    pub fn vertex_colors(&self) -> Option<Vec<[f32; 4]>> {
+        match self.attribute(Mesh::ATTRIBUTE_COLOR)? {
+            VertexAttributeValues::Float32x4(colors) => Some(colors.clone()),
+            VertexAttributeValues::Unorm8x4(colors) => Some(
+                colors
+                    .iter()
+                    .map(|color| {
+                        let [r, g, b, a] = *color;
+                        [
+                            r as f32 / 255.0,
+                            g as f32 / 255.0,
+                            b as f32 / 255.0,
+                            a as f32 / 255.0,
+                        ]
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Interleaves the attributes named in `layout`, in that order and tightly packed, into one
+    /// vertex buffer. Returns the bytes and the stride between vertices.
+    pub fn build_interleaved_vertices(&self, layout: &[&str]) -> Result<(Vec<u8>, u32), MeshError> {
+        let attributes = layout
+            .iter()
+            .map(|&name| {
+                self.attributes
+                    .get(name)
+                    .ok_or_else(|| MeshError::MissingAttribute(name.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let vertex_count = attributes.first().map_or(0, |values| values.len());
+        for (&name, values) in layout.iter().zip(&attributes) {
+            if values.len() != vertex_count {
+                return Err(MeshError::AttributeLengthMismatch {
+                    name: name.to_string(),
+                    len: values.len(),
+                    expected: vertex_count,
+                });
+            }
+        }
+
+        let sizes = attributes
+            .iter()
+            .map(|&values| VertexFormat::from(values).get_size() as usize)
+            .collect::<Vec<_>>();
+        let stride = sizes.iter().sum::<usize>();
+
+        let mut bytes = Vec::with_capacity(stride * vertex_count);
+        for vertex in 0..vertex_count {
+            for (values, &size) in attributes.iter().zip(&sizes) {
+                bytes.extend_from_slice(&values.get_bytes()[vertex * size..(vertex + 1) * size]);
+            }
+        }
+
+        Ok((bytes, stride as u32))
+    }
+
     pub fn build_triangle_blas<'a>(
         &self,
         device: &Device,
         encoder: &mut Encoder<'a>,
         bump: &'a Bump,
-    ) -> (AccelerationStructure, Buffer, Buffer, Buffer) {
-        let vertices = self.attributes.get(Mesh::ATTRIBUTE_POSITION).unwrap();
+        arena: &mut BufferArena,
+        scratch_pool: &mut ScratchPool,
+        flags: vk::BuildAccelerationStructureFlagsKHR,
+    ) -> Result<(AccelerationStructure, MeshBuffers), MeshError> {
+        let vertices = self
+            .attributes
+            .get(Mesh::ATTRIBUTE_POSITION)
+            .ok_or(MeshError::MissingPositions)?;
         let vertex_count = vertices.len() as u64;
-        let vertex_stride = VertexFormat::from(vertices).get_size();
-        let vertex_buffer = device.create_buffer_with_data(
-            BufferInfo {
-                align: 255,
-                size: vertex_stride * vertex_count,
-                usage_flags: vk::BufferUsageFlags::VERTEX_BUFFER
-                    | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
-                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-                allocation_flags: gpu_alloc::UsageFlags::DEVICE_ADDRESS
-                    | gpu_alloc::UsageFlags::HOST_ACCESS,
-            },
-            vertices.get_bytes(),
-        );
+        let vertex_format = VertexFormat::from(vertices);
+        let vertex_stride = vertex_format.get_size();
+        let blas_vertex_format = vertex_format
+            .acceleration_structure_format()
+            .ok_or(MeshError::UnsupportedVertexFormat(vertex_format))?;
+        if !device
+            .format_buffer_features(blas_vertex_format)
+            .contains(vk::FormatFeatureFlags::ACCELERATION_STRUCTURE_VERTEX_BUFFER_KHR)
+        {
+            return Err(MeshError::UnsupportedByDevice(blas_vertex_format));
+        }
 
-        let indices = self.indices().expect("Mesh without indices");
-        let triangle_count = indices.len() / 3;
-
-        let index_buffer = device.create_buffer_with_data(
-            BufferInfo {
-                align: 255,
-                size: indices.get_total_size() as u64,
-                usage_flags: vk::BufferUsageFlags::INDEX_BUFFER
-                    | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
-                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-                allocation_flags: gpu_alloc::UsageFlags::DEVICE_ADDRESS
-                    | gpu_alloc::UsageFlags::HOST_ACCESS,
-            },
-            self.get_index_buffer_bytes().unwrap(),
-        );
+        let vertex_buffer = arena.allocate_with_data(device, vertices.get_bytes());
+
+        let indices = self.indices();
+        let (index_type, index_count) = match indices {
+            Some(Indices::U16(values)) => (vk::IndexType::UINT16, values.len()),
+            Some(Indices::U32(values)) => (vk::IndexType::UINT32, values.len()),
+            // Triangle soup, every three vertices make a triangle
+            None => (vk::IndexType::NONE_KHR, 0),
+        };
+        let triangle_count = if indices.is_some() {
+            index_count / 3
+        } else {
+            vertex_count as usize / 3
+        };
+
+        let index_buffer = self
+            .get_index_buffer_bytes()
+            .map(|bytes| arena.allocate_with_data(device, bytes));
+
+        let color_buffer = self
+            .vertex_colors()
+            .map(|colors| arena.allocate_with_data(device, &colors));
+
+        let shading_buffer = self
+            .shading_vertices()
+            .map(|vertices| arena.allocate_with_data(device, &vertices));
 
         let sizes = device.get_acceleration_structure_build_sizes(
             AccelerationStructureLevel::Bottom,
-            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE_KHR,
+            flags,
             &[AccelerationStructureGeometryInfo::Triangles {
                 max_primitive_count: triangle_count as u32,
                 max_vertex_count: vertex_count as u32,
-                vertex_format: vk::Format::R32G32B32_SFLOAT,
-                index_type: vk::IndexType::UINT16,
+                vertex_format: blas_vertex_format,
+                index_type,
             }],
         );
 
@@ -243,6 +539,7 @@ impl Mesh {
             size: sizes.acceleration_structure_size,
             usage_flags: vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
             allocation_flags: gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+            name: Some("BLAS"),
         });
 
         let blas = device.create_acceleration_structure(AccelerationStructureInfo {
@@ -255,40 +552,58 @@ impl Mesh {
             },
         });
 
-        let scratch = device.create_buffer(BufferInfo {
-            align: 255,
-            size: sizes.build_scratch_size,
-            usage_flags: vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::STORAGE_BUFFER,
-            allocation_flags: gpu_alloc::UsageFlags::DEVICE_ADDRESS,
-        });
+        let scratch = scratch_pool.scratch(device, sizes.build_scratch_size);
 
         let geometries = bump.alloc([AccelerationStructureGeometry::Triangles {
             flags: vk::GeometryFlagsKHR::empty(),
-            vertex_format: vk::Format::R32G32B32_SFLOAT,
+            vertex_format: blas_vertex_format,
             vertex_data: vertex_buffer.device_address().unwrap(),
             vertex_stride: vertex_stride as _,
             vertex_count: vertex_count as _,
             first_vertex: 0,
             primitive_count: triangle_count as _,
-            index_data: match indices {
-                Indices::U16(_) => Some(IndexData::U16(index_buffer.device_address().unwrap())),
-                Indices::U32(_) => Some(IndexData::U32(index_buffer.device_address().unwrap())),
-            },
+            index_data: index_buffer.as_ref().map(|index_buffer| {
+                let device_address = index_buffer.device_address().unwrap();
+                match index_type {
+                    vk::IndexType::UINT16 => IndexData::U16(device_address),
+                    _ => IndexData::U32(device_address),
+                }
+            }),
             transform_data: None,
         }]);
 
         let build_info = bump.alloc([AccelerationStructureBuildGeometryInfo {
             src: None,
             dst: blas.clone(),
-            flags: vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE_KHR,
+            flags,
             geometries,
-            scratch: scratch.device_address().unwrap(),
+            scratch,
         }]);
 
+        // The scratch may still be in use by the previous build from the pool
+        encoder.pipeline_barrier(
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+            vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR
+                | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+            &[],
+        );
         encoder.build_acceleration_structure(build_info);
 
-        (blas, vertex_buffer, index_buffer, scratch)
+        let buffers = MeshBuffers {
+            vertex_buffer,
+            vertex_count: vertex_count as _,
+            vertex_format,
+            index_buffer,
+            index_type,
+            index_count: index_count as _,
+            color_buffer,
+            shading_buffer,
+            aabb: self.compute_aabb(),
+        };
+
+        Ok((blas, buffers))
     }
 }
 
@@ -362,6 +677,44 @@ impl VertexAttributeValues {
         self.len() == 0
     }
 
+    /// Replaces the values with the ones at `indices`, in that order.
+    fn gather(&mut self, indices: impl Iterator<Item = usize>) {
+        fn gather<T: Copy>(values: &[T], indices: impl Iterator<Item = usize>) -> Vec<T> {
+            indices.map(|i| values[i]).collect()
+        }
+
+        match self {
+            VertexAttributeValues::Float32(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Sint32(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Uint32(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Float32x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Sint32x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Uint32x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Float32x3(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Sint32x3(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Uint32x3(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Sint32x4(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Uint32x4(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Float32x4(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Sint16x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Snorm16x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Uint16x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Unorm16x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Sint16x4(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Snorm16x4(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Uint16x4(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Unorm16x4(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Sint8x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Snorm8x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Uint8x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Unorm8x2(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Sint8x4(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Snorm8x4(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Uint8x4(vec) => *vec = gather(vec, indices),
+            VertexAttributeValues::Unorm8x4(vec) => *vec = gather(vec, indices),
+        }
+    }
+
     fn as_float3(&self) -> Option<&[[f32; 3]]> {
         match self {
             VertexAttributeValues::Float32x3(values) => Some(values),
@@ -442,3 +795,162 @@ fn face_normal(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> [f32; 3] {
     let (a, b, c) = (Vec3::from(a), Vec3::from(b), Vec3::from(c));
     (b - a).cross(c - a).normalize().into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::asset::HandleId;
+
+    #[test]
+    fn geometry_ids_follow_load_order() {
+        let handles = (0..3)
+            .map(|_| Handle::<Mesh>::weak(HandleId::random::<Mesh>()))
+            .collect::<Vec<_>>();
+        let mut ids = GeometryIds::default();
+
+        assert_eq!(ids.get(&handles[0]), None);
+        for (index, handle) in handles.iter().enumerate() {
+            assert_eq!(ids.get_or_assign(handle), GeometryId(index as u32));
+        }
+        assert_eq!(ids.get_or_assign(&handles[1]), GeometryId(1));
+        assert_eq!(ids.get(&handles[2]), Some(GeometryId(2)));
+    }
+
+    #[test]
+    fn aabb_of_normalized_and_two_component_positions() {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::Snorm16x4(vec![[i16::MIN, 0, i16::MAX, 7], [0, i16::MAX, 0, 7]]),
+        );
+        let aabb = mesh.compute_aabb().unwrap();
+        assert_eq!(aabb.min, Vec3::new(-1.0, 0.0, 0.0));
+        assert_eq!(aabb.max, Vec3::new(0.0, 1.0, 1.0));
+
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, vec![[1.0f32, 2.0], [-1.0, 4.0]]);
+        let aabb = mesh.compute_aabb().unwrap();
+        assert_eq!(aabb.min, Vec3::new(-1.0, 2.0, 0.0));
+        assert_eq!(aabb.max, Vec3::new(1.0, 4.0, 0.0));
+
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, vec![[1u32, 2, 3]]);
+        assert!(mesh.compute_aabb().is_none());
+    }
+
+    /// Two triangles of a quad sharing the `(1, 0, 0)`-`(0, 1, 0)` edge, with every vertex
+    /// duplicated.
+    fn duplicated_quad() -> Mesh {
+        let positions = vec![
+            [0.0f32, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+        let uvs = positions
+            .iter()
+            .map(|&[x, y, _]| [x, y])
+            .collect::<Vec<_>>();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0f32, 0.0, 1.0]; 6]);
+        mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh
+    }
+
+    #[test]
+    fn welding_merges_the_shared_edge() {
+        let mut mesh = duplicated_quad();
+        mesh.weld_vertices(1e-5);
+
+        assert_eq!(mesh.count_vertices(), 4);
+        let indices = mesh.indices().unwrap().iter().collect::<Vec<_>>();
+        assert_eq!(indices, vec![0, 1, 2, 1, 3, 2]);
+        assert_eq!(
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+                .unwrap()
+                .as_float3()
+                .unwrap(),
+            &[
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0]
+            ]
+        );
+    }
+
+    #[test]
+    fn welding_only_merges_vertices_whose_attributes_all_match() {
+        let mut mesh = duplicated_quad();
+        // The second triangle faces the other way, so none of its vertices can be shared
+        let mut normals = vec![[0.0f32, 0.0, 1.0]; 3];
+        normals.extend_from_slice(&[[0.0, 0.0, -1.0]; 3]);
+        mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.weld_vertices(1e-5);
+
+        assert_eq!(mesh.count_vertices(), 6);
+    }
+
+    #[test]
+    fn welding_tolerates_differences_below_epsilon_and_keeps_16_bit_indices() {
+        let mut mesh = duplicated_quad();
+        let mut positions = mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .unwrap()
+            .as_float3()
+            .unwrap()
+            .to_vec();
+        positions[3][0] += 1e-4;
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.set_indices(Some(Indices::U16(vec![0, 1, 2, 3, 4, 5])));
+        mesh.weld_vertices(1e-3);
+
+        assert_eq!(mesh.count_vertices(), 4);
+        assert!(
+            matches!(mesh.indices(), Some(Indices::U16(indices)) if indices == &[0, 1, 2, 1, 3, 2])
+        );
+    }
+
+    #[test]
+    fn interleaved_position_and_uv() {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            vec![[1.0f32, 2.0, 3.0], [4.0, 5.0, 6.0]],
+        );
+        mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.25f32, 0.5], [0.75, 1.0]]);
+
+        let (bytes, stride) = mesh
+            .build_interleaved_vertices(&[Mesh::ATTRIBUTE_POSITION, Mesh::ATTRIBUTE_UV_0])
+            .unwrap();
+
+        assert_eq!(stride, 20);
+        assert_eq!(
+            bytes,
+            cast_slice::<f32, u8>(&[1.0, 2.0, 3.0, 0.25, 0.5, 4.0, 5.0, 6.0, 0.75, 1.0])
+        );
+    }
+
+    #[test]
+    fn interleaving_rejects_missing_and_mismatched_attributes() {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, vec![[0.0f32; 3]; 3]);
+        assert!(matches!(
+            mesh.build_interleaved_vertices(&[Mesh::ATTRIBUTE_POSITION, Mesh::ATTRIBUTE_UV_0]),
+            Err(MeshError::MissingAttribute(name)) if name == Mesh::ATTRIBUTE_UV_0
+        ));
+        assert!(mesh.shading_vertices().is_none());
+
+        mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0f32; 2]; 2]);
+        assert!(matches!(
+            mesh.build_interleaved_vertices(&[Mesh::ATTRIBUTE_POSITION, Mesh::ATTRIBUTE_UV_0]),
+            Err(MeshError::AttributeLengthMismatch {
+                len: 2,
+                expected: 3,
+                ..
+            })
+        ));
+    }
+}
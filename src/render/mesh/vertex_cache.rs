@@ -0,0 +1,180 @@
+//! Triangle reordering for post-transform vertex cache locality, following Tom Forsyth's
+//! "Linear-Speed Vertex Cache Optimisation".
+
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+struct Vertex {
+    /// Triangles using this vertex that haven't been emitted yet.
+    triangles: Vec<usize>,
+    cache_position: Option<usize>,
+    score: f32,
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The vertices of the last triangle are scored lower than the ones behind them, so the
+        // same edge isn't reused over and over in strip-like order
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+
+    // Vertices with few triangles left are boosted to finish them off and free their cache slot
+    cache_score + VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Returns the triangles of the triangle list `indices` in an order that reuses recently
+/// transformed vertices. Only the order of the triangles changes, each keeps its winding.
+pub fn optimize(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let corners = |triangle: usize| &indices[3 * triangle..3 * triangle + 3];
+
+    let mut vertices = (0..vertex_count)
+        .map(|_| Vertex {
+            triangles: vec![],
+            cache_position: None,
+            score: 0.0,
+        })
+        .collect::<Vec<_>>();
+    for triangle in 0..triangle_count {
+        for &vertex in corners(triangle) {
+            vertices[vertex as usize].triangles.push(triangle);
+        }
+    }
+    for vertex in &mut vertices {
+        vertex.score = vertex_score(None, vertex.triangles.len());
+    }
+
+    let triangle_score = |vertices: &[Vertex], triangle: usize| {
+        corners(triangle)
+            .iter()
+            .map(|&vertex| vertices[vertex as usize].score)
+            .sum::<f32>()
+    };
+    let mut added = vec![false; triangle_count];
+    let mut cache = Vec::<u32>::with_capacity(CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(triangle_count * 3);
+    let mut best = None;
+    // Triangles before this one have all been emitted
+    let mut next_triangle = 0;
+
+    for _ in 0..triangle_count {
+        let triangle = match best.take() {
+            Some(triangle) => triangle,
+            // No cached vertex has triangles left, restart from the first remaining one
+            None => {
+                while added[next_triangle] {
+                    next_triangle += 1;
+                }
+                next_triangle
+            }
+        };
+
+        added[triangle] = true;
+        output.extend_from_slice(corners(triangle));
+        for &vertex in corners(triangle) {
+            let triangles = &mut vertices[vertex as usize].triangles;
+            let position = triangles.iter().position(|&t| t == triangle).unwrap();
+            triangles.swap_remove(position);
+        }
+
+        // The triangle's vertices move to the front, the ones pushed past the end are evicted
+        let mut touched = Vec::with_capacity(CACHE_SIZE + 3);
+        for &vertex in corners(triangle) {
+            if !touched.contains(&vertex) {
+                touched.push(vertex);
+            }
+        }
+        let front = touched.len();
+        for &vertex in &cache {
+            if !touched[..front].contains(&vertex) {
+                touched.push(vertex);
+            }
+        }
+
+        for (position, &vertex) in touched.iter().enumerate() {
+            let vertex = &mut vertices[vertex as usize];
+            vertex.cache_position = Some(position).filter(|&position| position < CACHE_SIZE);
+            vertex.score = vertex_score(vertex.cache_position, vertex.triangles.len());
+        }
+
+        let mut best_score = f32::MIN;
+        for &vertex in &touched {
+            for &triangle in &vertices[vertex as usize].triangles {
+                let score = triangle_score(&vertices, triangle);
+                if score > best_score {
+                    best_score = score;
+                    best = Some(triangle);
+                }
+            }
+        }
+
+        touched.truncate(CACHE_SIZE);
+        cache = touched;
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Triangles of a `size` by `size` grid of quads, in a scattered order.
+    fn grid(size: u32) -> Vec<u32> {
+        let vertex = |x: u32, y: u32| y * (size + 1) + x;
+        let mut triangles = vec![];
+        for y in 0..size {
+            for x in 0..size {
+                triangles.push([vertex(x, y), vertex(x + 1, y), vertex(x, y + 1)]);
+                triangles.push([vertex(x + 1, y), vertex(x + 1, y + 1), vertex(x, y + 1)]);
+            }
+        }
+        // Interleave both ends so neighbouring triangles aren't next to each other
+        let (front, back) = triangles.split_at(triangles.len() / 2);
+        front
+            .iter()
+            .zip(back.iter().rev())
+            .flat_map(|(a, b)| a.iter().chain(b.iter()))
+            .copied()
+            .collect()
+    }
+
+    /// Each triangle rotated to start at its smallest index, which keeps the winding, sorted.
+    fn triangle_set(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles = indices
+            .chunks(3)
+            .map(|triangle| {
+                let first = (0..3).min_by_key(|&corner| triangle[corner]).unwrap();
+                [
+                    triangle[first],
+                    triangle[(first + 1) % 3],
+                    triangle[(first + 2) % 3],
+                ]
+            })
+            .collect::<Vec<_>>();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn optimized_indices_reference_the_same_triangles() {
+        let indices = grid(8);
+        let optimized = optimize(&indices, 81);
+
+        assert_eq!(optimized.len() % 3, 0);
+        assert_eq!(optimized.len(), indices.len());
+        assert_eq!(triangle_set(&optimized), triangle_set(&indices));
+    }
+}
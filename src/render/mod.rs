@@ -1,6 +1,6 @@
 use crate::material::Material;
 use crate::render::mesh::Mesh;
-use crate::render::renderer::Renderer;
+use crate::render::renderer::{RenderSettings, Renderer};
 use crate::Camera;
 use bevy::app::AppExit;
 use bevy::prelude::*;
@@ -10,14 +10,21 @@ use bevy::winit::WinitWindows;
 
 mod acceleration_structures;
 mod buffer;
+mod buffer_arena;
 mod command_buffer;
 mod debug;
 mod descriptor;
 mod device;
+mod dynamic_uploader;
 mod encoder;
 mod framebuffer;
+mod frustum;
+mod gpu_timer;
 mod image;
+mod image_pool;
 mod instance;
+mod ktx2;
+mod mapped_buffer;
 pub mod mesh;
 mod pass;
 mod physical_device;
@@ -27,7 +34,10 @@ mod render_context;
 mod render_pass;
 pub mod renderer;
 mod resources;
+mod scratch_pool;
+mod screenshot;
 mod shader;
+pub mod stats;
 mod surface;
 mod swapchain;
 mod util;
@@ -36,14 +46,47 @@ pub mod vertex;
 #[derive(Default)]
 pub struct RenderPlugin;
 
+/// Labels used to order the render systems within a frame.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub enum RenderSystem {
+    /// Uploads newly created meshes and records their BLAS builds.
+    LoadMeshes,
+    /// Copies the world transforms and materials of mesh entities into their TLAS instances.
+    UpdateTransforms,
+    /// Builds the TLAS and draws the frame. Always runs after [`RenderSystem::LoadMeshes`] so
+    /// that every BLAS referenced by the TLAS has been submitted before it.
+    Draw,
+}
+
 impl Plugin for RenderPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.add_asset::<Mesh>()
             .add_asset::<Material>()
             .add_startup_system_to_stage(StartupStage::PreStartup, setup.system())
-            .add_system(load_gltf_models.system())
+            .add_system(load_gltf_models.system().label(RenderSystem::LoadMeshes))
+            .add_system(
+                update_mesh_transforms
+                    .system()
+                    .label(RenderSystem::UpdateTransforms),
+            )
+            .add_system(
+                remove_mesh_instances
+                    .system()
+                    .label(RenderSystem::UpdateTransforms),
+            )
+            .add_system(
+                update_mesh_materials
+                    .system()
+                    .label(RenderSystem::UpdateTransforms),
+            )
             .add_system_to_stage(CoreStage::PreUpdate, window_resize.system())
-            .add_system_to_stage(CoreStage::Update, draw.system())
+            .add_system_to_stage(
+                CoreStage::Update,
+                draw.system()
+                    .label(RenderSystem::Draw)
+                    .after(RenderSystem::LoadMeshes)
+                    .after(RenderSystem::UpdateTransforms),
+            )
             .add_system_to_stage(CoreStage::Last, world_cleanup.system());
     }
 }
@@ -52,6 +95,7 @@ fn setup(
     mut commands: Commands,
     mut window_created_events: EventReader<WindowCreated>,
     winit_windows: Res<WinitWindows>,
+    settings: Option<Res<RenderSettings>>,
 ) {
     let window_id = window_created_events
         .iter()
@@ -60,7 +104,10 @@ fn setup(
         .unwrap();
 
     let winit_window = winit_windows.get_window(window_id).unwrap();
-    let renderer = Renderer::new(winit_window);
+    let settings = settings
+        .map(|settings| (*settings).clone())
+        .unwrap_or_default();
+    let renderer = Renderer::new(winit_window, &settings);
 
     commands.insert_resource(renderer);
 }
@@ -71,6 +118,7 @@ fn load_gltf_models(
     mut mesh_events: EventReader<AssetEvent<Mesh>>,
 ) {
     let mut changed_meshes = HashSet::default();
+    let mut removed_meshes = Vec::new();
     for event in mesh_events.iter() {
         match event {
             AssetEvent::Created { ref handle } => {
@@ -84,27 +132,67 @@ fn load_gltf_models(
             AssetEvent::Removed { ref handle } => {
                 tracing::info!("removed mesh");
                 changed_meshes.remove(handle);
+                removed_meshes.push(handle.clone_weak());
             }
         }
     }
 
-    for changed_mesh_handle in changed_meshes.iter() {
-        if let Some(mesh) = meshes.get(changed_mesh_handle) {
-            renderer.load_models(changed_mesh_handle, mesh);
+    // Waits for the GPU once for every mesh removed this frame
+    renderer.unload_meshes(&removed_meshes);
+
+    for changed_mesh_handle in changed_meshes {
+        renderer.queue_mesh(changed_mesh_handle);
+    }
+
+    renderer.build_queued_meshes(&meshes);
+}
+
+fn update_mesh_materials(
+    mut renderer: ResMut<Renderer>,
+    materials: Res<Assets<Material>>,
+    query: Query<(&Handle<Mesh>, &Handle<Material>), Changed<Handle<Material>>>,
+) {
+    for (mesh, material) in query.iter() {
+        if let Some(material) = materials.get(material) {
+            renderer.set_mesh_material(mesh, material);
         }
     }
 }
 
+/// Every mesh entity gets its own TLAS instance, so a mesh referenced by several glTF nodes is
+/// drawn at each of them.
+fn update_mesh_transforms(
+    mut renderer: ResMut<Renderer>,
+    query: Query<(Entity, &Handle<Mesh>, &GlobalTransform), Changed<GlobalTransform>>,
+) {
+    for (entity, mesh, transform) in query.iter() {
+        renderer.set_mesh_transform(entity, mesh, transform);
+    }
+}
+
+fn remove_mesh_instances(mut renderer: ResMut<Renderer>, removed: RemovedComponents<Handle<Mesh>>) {
+    for entity in removed.iter() {
+        renderer.remove_mesh_instance(entity);
+    }
+}
+
 fn draw(mut renderer: ResMut<Renderer>, mut query: Query<(&Camera, &GlobalTransform)>) {
     let (_camera, transform) = query.single_mut().unwrap();
     renderer.draw(transform);
 }
 
-fn window_resize(mut window_resized_event: EventReader<WindowResized>) {
-    for event in window_resized_event.iter() {
-        if event.width != 0.0 && event.height != 0.0 {
-            tracing::debug!("window resized")
+fn window_resize(
+    mut renderer: ResMut<Renderer>,
+    mut window_resized_event: EventReader<WindowResized>,
+) {
+    // Only the last size matters, intermediate ones would recreate the swapchain for nothing
+    if let Some(event) = window_resized_event.iter().last() {
+        if event.width == 0.0 || event.height == 0.0 {
+            tracing::debug!("window minimized");
+        } else {
+            tracing::debug!("window resized");
         }
+        renderer.resize();
     }
 }
 
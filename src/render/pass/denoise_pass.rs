@@ -0,0 +1,331 @@
+use crate::render::pass::Pass;
+use crate::render::{
+    descriptor::{
+        DescriptorSetInfo, DescriptorSetLayoutBinding, DescriptorSetLayoutInfo, DescriptorType,
+        Descriptors, WriteDescriptorSet,
+    },
+    image::{Image, ImageInfo, ImageMemoryBarrier, ImageViewInfo, SamplerInfo},
+    image_pool::ImagePool,
+    pipeline::{ComputePipelineInfo, PipelineLayoutInfo, PushConstant},
+    render_context::RenderContext,
+    resources::{ComputePipeline, DescriptorSet, Fence, PipelineLayout, Sampler, Semaphore},
+    shader::{Shader, ShaderModuleInfo},
+};
+use bevy::prelude::GlobalTransform;
+use bumpalo::Bump;
+use erupt::vk;
+
+/// Matches `local_size_x` and `local_size_y` in `denoise.comp`.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// The noisy color and the G-buffer channels guiding the filter, all read with
+/// `SHADER_READ_ONLY_OPTIMAL` layout.
+pub struct Input {
+    pub color: Image,
+    pub albedo: Image,
+    pub normal: Image,
+}
+
+pub struct Output {
+    pub output_image: Image,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DenoiseSettings {
+    /// Distance in pixels between the kernel taps, doubling it between iterations of the
+    /// a-trous filter widens the kernel without adding taps.
+    pub step_width: i32,
+    /// How quickly samples with a different color or albedo are rejected, lower is sharper.
+    pub color_phi: f32,
+    /// Exponent applied to the cosine between normals, higher is sharper.
+    pub normal_phi: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        DenoiseSettings {
+            step_width: 1,
+            color_phi: 0.5,
+            normal_phi: 64.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+    step_width: i32,
+    color_phi: f32,
+    normal_phi: f32,
+}
+
+unsafe impl bytemuck::Zeroable for PushConstants {}
+unsafe impl bytemuck::Pod for PushConstants {}
+
+/// Edge-avoiding a-trous filter run as a compute pass over the path traced image.
+pub struct DenoisePass {
+    pipeline_layout: PipelineLayout,
+    pipeline: ComputePipeline,
+
+    /// One descriptor set per frame in flight, indexed by `frame % len`, along with the inputs
+    /// last written into it.
+    descriptor_sets: Vec<DescriptorSet>,
+    inputs: Vec<Option<[Image; 3]>>,
+    sampler: Sampler,
+
+    output_image: Image,
+    settings: DenoiseSettings,
+}
+
+impl Pass<'_> for DenoisePass {
+    type Input = Input;
+    type Output = Output;
+
+    fn draw(
+        &mut self,
+        input: Input,
+        frame: u64,
+        wait: &[(vk::PipelineStageFlags, Semaphore)],
+        signal: &[Semaphore],
+        fence: Option<&Fence>,
+        render_context: &mut RenderContext,
+        bump: &Bump,
+        _camera: &GlobalTransform,
+    ) -> Output {
+        let frame_id = (frame % self.descriptor_sets.len() as u64) as usize;
+        let descriptor_set = &self.descriptor_sets[frame_id];
+
+        let inputs = [input.color, input.albedo, input.normal];
+        if self.inputs[frame_id].as_ref() != Some(&inputs) {
+            let write_descriptor_sets = inputs
+                .iter()
+                .enumerate()
+                .map(|(binding, image)| {
+                    let view = render_context.create_image_view(ImageViewInfo::new(
+                        image.clone(),
+                        vk::ImageAspectFlags::COLOR,
+                    ));
+                    WriteDescriptorSet {
+                        descriptor_set,
+                        binding: binding as u32,
+                        element: 0,
+                        descriptors: Descriptors::CombinedImageSampler(bump.alloc([(
+                            view,
+                            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                            self.sampler.clone(),
+                        )])),
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            render_context.update_descriptor_sets(&write_descriptor_sets, &[]);
+            self.inputs[frame_id] = Some(inputs);
+        }
+
+        let mut encoder = render_context.queue.create_enconder();
+        encoder.begin_debug_label("Denoise", [0.4, 0.8, 0.4, 1.0]);
+
+        // Every pixel is overwritten, the previous contents can be discarded
+        let image_barriers = bump.alloc([ImageMemoryBarrier::initialize_whole(
+            &self.output_image,
+            vk::ImageLayout::GENERAL,
+        )]);
+        encoder.pipeline_barrier(
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+            vk::AccessFlags::SHADER_WRITE,
+            image_barriers,
+        );
+
+        encoder.bind_compute_pipeline(&self.pipeline);
+        encoder.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.pipeline_layout,
+            0,
+            std::slice::from_ref(descriptor_set),
+            &[],
+        );
+
+        let push_constants = bump.alloc([PushConstants {
+            step_width: self.settings.step_width,
+            color_phi: self.settings.color_phi,
+            normal_phi: self.settings.normal_phi,
+        }]);
+        encoder.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            push_constants,
+        );
+
+        let extent = self.output_image.info().extent;
+        encoder.dispatch([
+            (extent.width + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+            (extent.height + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+            1,
+        ]);
+
+        let image_barriers = bump.alloc([ImageMemoryBarrier::transition_whole(
+            &self.output_image,
+            vk::ImageLayout::GENERAL..vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+        .with_access(vk::AccessFlags::SHADER_WRITE..vk::AccessFlags::SHADER_READ)]);
+        encoder.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+            image_barriers,
+        );
+
+        encoder.end_debug_label();
+
+        let command_buffer = encoder.finish(&render_context.device);
+
+        render_context
+            .queue
+            .submit(command_buffer, wait, signal, fence);
+
+        Output {
+            output_image: self.output_image.clone(),
+        }
+    }
+}
+
+impl DenoisePass {
+    pub fn new(
+        render_context: &RenderContext,
+        image_pool: &mut ImagePool,
+        extent: vk::Extent2D,
+        frames_in_flight: usize,
+    ) -> Self {
+        let input_binding = |binding| DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: DescriptorType::CombinedImageSampler,
+            count: 1,
+            stages: vk::ShaderStageFlags::COMPUTE,
+            flags: vk::DescriptorBindingFlags::empty(),
+        };
+
+        let descriptor_set_layout =
+            render_context.create_descriptor_set_layout(DescriptorSetLayoutInfo {
+                bindings: vec![
+                    // Noisy color
+                    input_binding(0),
+                    // Albedo
+                    input_binding(1),
+                    // Normal
+                    input_binding(2),
+                    // Denoised output
+                    DescriptorSetLayoutBinding {
+                        binding: 3,
+                        descriptor_type: DescriptorType::StorageImage,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::COMPUTE,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                ],
+                flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            });
+
+        let pipeline_layout = render_context.create_pipeline_layout(PipelineLayoutInfo {
+            sets: vec![descriptor_set_layout.clone()],
+            push_constants: vec![PushConstant {
+                stages: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>() as u32,
+            }],
+        });
+
+        let shader = Shader::new(
+            render_context.create_shader_module(ShaderModuleInfo::new("denoise.comp.spv")),
+            vk::ShaderStageFlagBits::COMPUTE,
+        );
+
+        let pipeline = render_context
+            .create_compute_pipeline(ComputePipelineInfo {
+                shader,
+                layout: pipeline_layout.clone(),
+            })
+            .expect("Failed to create denoise pipeline");
+
+        let descriptor_sets = (0..frames_in_flight)
+            .map(|_| {
+                render_context.create_descriptor_set(DescriptorSetInfo {
+                    layout: descriptor_set_layout.clone(),
+                })
+            })
+            .collect();
+
+        let mut pass = DenoisePass {
+            pipeline_layout,
+            pipeline,
+            descriptor_sets,
+            inputs: vec![None; frames_in_flight],
+            sampler: render_context.create_sampler(SamplerInfo::default()),
+            output_image: Self::create_output_image(render_context, image_pool, extent),
+            settings: DenoiseSettings::default(),
+        };
+        pass.bind_output_image(render_context);
+        pass
+    }
+
+    /// Takes an output image for the new `extent` from `image_pool`. The GPU must be done with the
+    /// previous frames, their descriptor sets are rewritten.
+    pub fn resize(
+        &mut self,
+        render_context: &RenderContext,
+        image_pool: &mut ImagePool,
+        extent: vk::Extent2D,
+    ) {
+        self.output_image = Self::create_output_image(render_context, image_pool, extent);
+        self.bind_output_image(render_context);
+    }
+
+    fn create_output_image(
+        render_context: &RenderContext,
+        image_pool: &mut ImagePool,
+        extent: vk::Extent2D,
+    ) -> Image {
+        image_pool.get(
+            render_context,
+            ImageInfo {
+                extent,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                mip_levels: 1,
+                array_layers: 1,
+                samples: vk::SampleCountFlagBits::_1,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                flags: vk::ImageCreateFlags::empty(),
+                name: Some("denoised output"),
+            },
+        )
+    }
+
+    fn bind_output_image(&self, render_context: &RenderContext) {
+        let output_image_view = render_context.create_image_view(ImageViewInfo::new(
+            self.output_image.clone(),
+            vk::ImageAspectFlags::COLOR,
+        ));
+
+        for descriptor_set in &self.descriptor_sets {
+            render_context.update_descriptor_sets(
+                &[WriteDescriptorSet {
+                    descriptor_set,
+                    binding: 3,
+                    element: 0,
+                    descriptors: Descriptors::StorageImage(&[(
+                        output_image_view.clone(),
+                        vk::ImageLayout::GENERAL,
+                    )]),
+                }],
+                &[],
+            );
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: DenoiseSettings) {
+        self.settings = settings;
+    }
+}
@@ -0,0 +1,20 @@
+use crate::render::{
+    encoder::EncoderInner,
+    render_context::RenderContext,
+    shader::{Shader, ShaderModuleInfo},
+};
+use erupt::vk;
+
+/// Creates the vertex shader that generates a single triangle covering the whole viewport from
+/// `gl_VertexIndex`, so no vertex buffer needs to be bound.
+pub fn vertex_shader(render_context: &RenderContext) -> Shader {
+    Shader::new(
+        render_context.create_shader_module(ShaderModuleInfo::new("fullscreen.vert.spv")),
+        vk::ShaderStageFlagBits::VERTEX,
+    )
+}
+
+/// Records the draw of the fullscreen triangle created by [`vertex_shader`].
+pub fn draw_fullscreen(encoder: &mut EncoderInner<'_>) {
+    encoder.draw(0..3, 0..1);
+}
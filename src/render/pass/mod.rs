@@ -1,7 +1,11 @@
+pub mod denoise_pass;
+pub mod fullscreen_triangle;
 pub mod raster_pass;
+pub mod ray_query_shadow_pass;
 pub mod raytracing_pass;
 pub mod tonemap_pass;
 pub mod ui_pass;
+pub mod wireframe_pass;
 
 use crate::render::{
     render_context::RenderContext,
@@ -10,6 +14,7 @@ use crate::render::{
 use bevy::prelude::GlobalTransform;
 use bumpalo::Bump;
 use erupt::vk;
+use glam::Mat4;
 pub use raster_pass::*;
 
 pub trait Pass<'a> {
@@ -28,3 +33,34 @@ pub trait Pass<'a> {
         camera: &GlobalTransform,
     ) -> Self::Output;
 }
+
+/// View and projection matrices shared by the ray traced and rasterized passes so both agree on
+/// where the camera is. The aspect ratio is the one of `extent`, the size of the render target.
+pub fn camera_matrices(camera: &GlobalTransform, extent: vk::Extent2D) -> (Mat4, Mat4) {
+    let view = Mat4::from_scale_rotation_translation(
+        camera.scale,
+        camera.rotation.inverse(),
+        -camera.translation,
+    );
+    let aspect_ratio = extent.width as f32 / extent.height.max(1) as f32;
+    let proj = Mat4::perspective_rh(90.0f32.to_radians(), aspect_ratio, 0.001, 10000.0);
+
+    (view, proj)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projection_uses_the_target_aspect_ratio() {
+        let extent = vk::Extent2D {
+            width: 1920,
+            height: 1080,
+        };
+        let (_, proj) = camera_matrices(&GlobalTransform::identity(), extent);
+
+        let aspect_ratio = proj.y_axis.y / proj.x_axis.x;
+        assert!((aspect_ratio - 1920.0 / 1080.0).abs() < 1e-5);
+    }
+}
@@ -4,7 +4,10 @@ use crate::render::{
     pass::Pass,
     pipeline::{GraphicsPipelineInfo, PipelineLayoutInfo, Rasterizer},
     render_context::RenderContext,
-    render_pass::{AttachmentInfo, ClearValue, RenderPassInfo, Subpass},
+    render_pass::{
+        AttachmentInfo, ClearValue, RenderPassInfo, Subpass, SubpassDependency,
+        DEFAULT_ATTACHMENT_COUNT,
+    },
     resources::{Fence, Framebuffer, GraphicsPipeline, PipelineLayout, RenderPass, Semaphore},
     shader::{Shader, ShaderModuleInfo},
 };
@@ -12,7 +15,7 @@ use bevy::prelude::GlobalTransform;
 use bumpalo::Bump;
 use erupt::vk;
 use lru::LruCache;
-use smallvec::smallvec;
+use smallvec::{smallvec, SmallVec};
 
 pub struct RasterPass {
     render_pass: RenderPass,
@@ -22,6 +25,8 @@ pub struct RasterPass {
     framebuffers: LruCache<Image, Framebuffer>,
 
     depth_image: Image,
+    /// Multisampled color target resolved into the input target, `None` without MSAA.
+    color_image: Option<Image>,
 
     vertex_shader: Shader,
     fragment_shader: Shader,
@@ -29,6 +34,7 @@ pub struct RasterPass {
 
 pub struct Input {
     pub target: Image,
+    pub clear_color: [f32; 4],
 }
 
 pub struct Output;
@@ -51,7 +57,7 @@ impl Pass<'_> for RasterPass {
         let fb;
         let framebuffer = match self.framebuffers.get(&input.target) {
             None => {
-                let color_view = render_context.create_image_view(ImageViewInfo::new(
+                let target_view = render_context.create_image_view(ImageViewInfo::new(
                     input.target.clone(),
                     vk::ImageAspectFlags::COLOR,
                 ));
@@ -61,9 +67,20 @@ impl Pass<'_> for RasterPass {
                     vk::ImageAspectFlags::DEPTH,
                 ));
 
+                let views = match &self.color_image {
+                    Some(color_image) => {
+                        let color_view = render_context.create_image_view(ImageViewInfo::new(
+                            color_image.clone(),
+                            vk::ImageAspectFlags::COLOR,
+                        ));
+                        smallvec![color_view, depth_view, target_view]
+                    }
+                    None => smallvec![target_view, depth_view],
+                };
+
                 fb = render_context.create_framebuffer(FramebufferInfo {
                     render_pass: self.render_pass.clone(),
-                    views: smallvec![color_view, depth_view],
+                    views,
                     extent: input.target.info().extent,
                 });
 
@@ -73,16 +90,15 @@ impl Pass<'_> for RasterPass {
             Some(framebuffer) => framebuffer,
         };
 
+        // Only the first attachments' clears are used, the resolve attachment isn't cleared
+        let clears = [
+            ClearValue::from(input.clear_color),
+            ClearValue::DepthStencil(1.0, 0),
+            ClearValue::from(input.clear_color),
+        ];
         let mut encoder = render_context.queue.create_enconder();
 
-        encoder.begin_render_pass(
-            &self.render_pass,
-            framebuffer,
-            &[
-                ClearValue::Color(0.5, 0.2, 0.2, 0.0),
-                ClearValue::DepthStencil(1.0, 0),
-            ],
-        );
+        encoder.begin_render_pass(&self.render_pass, framebuffer, &clears);
 
         encoder.bind_graphics_pipeline(&self.graphics_pipeline);
 
@@ -119,6 +135,7 @@ impl RasterPass {
         render_context: &RenderContext,
         surface_format: vk::Format,
         extent: vk::Extent2D,
+        samples: vk::SampleCountFlagBits,
     ) -> Self {
         let vertex_shader = Shader::new(
             render_context.create_shader_module(ShaderModuleInfo::new("shader.vert.spv")),
@@ -135,33 +152,79 @@ impl RasterPass {
             format: vk::Format::D32_SFLOAT,
             mip_levels: 1,
             array_layers: 1,
-            samples: vk::SampleCountFlagBits::_1,
+            samples,
             usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            flags: vk::ImageCreateFlags::empty(),
+            name: Some("raster depth"),
         });
 
-        let render_pass = render_context.create_render_pass(RenderPassInfo {
-            attachments: smallvec![
-                AttachmentInfo {
-                    format: surface_format,
-                    samples: vk::SampleCountFlags::_1,
-                    load_op: vk::AttachmentLoadOp::CLEAR,
-                    store_op: vk::AttachmentStoreOp::STORE,
-                    initial_layout: None,
-                    final_layout: vk::ImageLayout::PRESENT_SRC_KHR
+        let multisampled = samples != vk::SampleCountFlagBits::_1;
+
+        let color_image = if multisampled {
+            Some(render_context.create_image(ImageInfo {
+                extent,
+                format: surface_format,
+                mip_levels: 1,
+                array_layers: 1,
+                samples,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                flags: vk::ImageCreateFlags::empty(),
+                name: Some("raster multisampled color"),
+            }))
+        } else {
+            None
+        };
+
+        let mut attachments: SmallVec<[AttachmentInfo; DEFAULT_ATTACHMENT_COUNT]> = smallvec![
+            AttachmentInfo {
+                format: surface_format,
+                samples,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                // The multisampled color only lives until it is resolved
+                store_op: if multisampled {
+                    vk::AttachmentStoreOp::DONT_CARE
+                } else {
+                    vk::AttachmentStoreOp::STORE
                 },
-                AttachmentInfo {
-                    format: vk::Format::D32_SFLOAT,
-                    samples: vk::SampleCountFlags::_1,
-                    load_op: vk::AttachmentLoadOp::CLEAR,
-                    store_op: vk::AttachmentStoreOp::DONT_CARE,
-                    initial_layout: None,
-                    final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+                initial_layout: None,
+                final_layout: if multisampled {
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+                } else {
+                    vk::ImageLayout::PRESENT_SRC_KHR
                 },
-            ],
+            },
+            AttachmentInfo {
+                format: vk::Format::D32_SFLOAT,
+                samples,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: None,
+                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            },
+        ];
+
+        let mut resolves = smallvec![];
+        if multisampled {
+            resolves.push(attachments.len());
+            attachments.push(AttachmentInfo {
+                format: surface_format,
+                samples: vk::SampleCountFlagBits::_1,
+                load_op: vk::AttachmentLoadOp::DONT_CARE,
+                store_op: vk::AttachmentStoreOp::STORE,
+                initial_layout: None,
+                final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            });
+        }
+
+        let render_pass = render_context.create_render_pass(RenderPassInfo {
+            attachments,
             subpasses: smallvec![Subpass {
                 colors: smallvec![0],
                 depth: Some(1),
+                resolves,
             }],
+            dependencies: vec![SubpassDependency::color_attachment_to_present()],
         });
 
         let pipeline_layout = render_context.create_pipeline_layout(PipelineLayoutInfo {
@@ -169,30 +232,34 @@ impl RasterPass {
             push_constants: vec![],
         });
 
-        let graphics_pipeline = render_context.create_graphics_pipeline(GraphicsPipelineInfo {
-            vertex_bindings: vec![],
-            vertex_attributes: vec![],
-            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-            vertex_shader: vertex_shader.clone(),
-            rasterizer: Some(Rasterizer {
-                viewport: vk::Viewport {
-                    x: 0.0,
-                    y: 0.0,
-                    width: extent.width as _,
-                    height: extent.height as _,
-                    min_depth: 0.0,
-                    max_depth: 1.0,
-                },
-                depth_clamp: false,
-                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-                cull_mode: vk::CullModeFlags::NONE,
-                polygon_mode: vk::PolygonMode::FILL,
-                fragment_shader: Some(fragment_shader.clone()),
-            }),
-            layout: pipeline_layout.clone(),
-            render_pass: render_pass.clone(),
-            subpass: 0,
-        });
+        let graphics_pipeline = render_context
+            .create_graphics_pipeline(GraphicsPipelineInfo {
+                vertex_bindings: vec![],
+                vertex_attributes: vec![],
+                primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                vertex_shader: vertex_shader.clone(),
+                rasterizer: Some(Rasterizer {
+                    viewport: vk::Viewport {
+                        x: 0.0,
+                        y: 0.0,
+                        width: extent.width as _,
+                        height: extent.height as _,
+                        min_depth: 0.0,
+                        max_depth: 1.0,
+                    },
+                    depth_clamp: false,
+                    front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                    cull_mode: vk::CullModeFlags::NONE,
+                    polygon_mode: vk::PolygonMode::FILL,
+                    depth_bias: None,
+                    samples,
+                    fragment_shader: Some(fragment_shader.clone()),
+                }),
+                layout: pipeline_layout.clone(),
+                render_pass: render_pass.clone(),
+                subpass: 0,
+            })
+            .expect("Failed to create raster pipeline");
 
         RasterPass {
             render_pass,
@@ -200,6 +267,7 @@ impl RasterPass {
             graphics_pipeline,
             framebuffers: LruCache::new(4),
             depth_image,
+            color_image,
             vertex_shader,
             fragment_shader,
         }
@@ -0,0 +1,333 @@
+use crate::render::pass::{camera_matrices, Pass};
+use crate::render::{
+    descriptor::{
+        DescriptorSetInfo, DescriptorSetLayoutBinding, DescriptorSetLayoutInfo, DescriptorType,
+        Descriptors, WriteDescriptorSet,
+    },
+    image::{Image, ImageInfo, ImageMemoryBarrier, ImageViewInfo, SamplerInfo},
+    image_pool::ImagePool,
+    pipeline::{ComputePipelineInfo, PipelineLayoutInfo, PushConstant},
+    render_context::RenderContext,
+    resources::{
+        AccelerationStructure, ComputePipeline, DescriptorSet, Fence, PipelineLayout, Sampler,
+        Semaphore,
+    },
+    shader::{Shader, ShaderModuleInfo},
+};
+use bevy::prelude::GlobalTransform;
+use bumpalo::Bump;
+use erupt::vk;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+/// Matches `local_size_x` and `local_size_y` in `ray_query_shadow.comp`.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// The scene and the G-buffer of the ray tracing pass, the images read with
+/// `SHADER_READ_ONLY_OPTIMAL` layout.
+pub struct Input {
+    pub tlas: AccelerationStructure,
+    pub normal: Image,
+    pub depth: Image,
+}
+
+pub struct Output {
+    /// 1 where the primary hit sees the light, 0 where it's occluded or faces away from it.
+    pub shadow_image: Image,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+    view_inverse: Mat4,
+    light_direction: Vec4,
+    proj_inverse_scale: Vec2,
+    // The Vec4 aligns the struct to 16 bytes, Pod can't have implicit padding
+    _padding: Vec2,
+}
+
+unsafe impl bytemuck::Zeroable for PushConstants {}
+unsafe impl bytemuck::Pod for PushConstants {}
+
+/// Traces one shadow ray per pixel towards a directional light with `VK_KHR_ray_query` from a
+/// compute shader, only available when the device supports ray queries.
+pub struct RayQueryShadowPass {
+    pipeline_layout: PipelineLayout,
+    pipeline: ComputePipeline,
+
+    /// One descriptor set per frame in flight, indexed by `frame % len`, along with the inputs
+    /// last written into it.
+    descriptor_sets: Vec<DescriptorSet>,
+    inputs: Vec<Option<(vk::AccelerationStructureKHR, [Image; 2])>>,
+    sampler: Sampler,
+
+    shadow_image: Image,
+    light_direction: Vec3,
+}
+
+impl Pass<'_> for RayQueryShadowPass {
+    type Input = Input;
+    type Output = Output;
+
+    fn draw(
+        &mut self,
+        input: Input,
+        frame: u64,
+        wait: &[(vk::PipelineStageFlags, Semaphore)],
+        signal: &[Semaphore],
+        fence: Option<&Fence>,
+        render_context: &mut RenderContext,
+        bump: &Bump,
+        camera: &GlobalTransform,
+    ) -> Output {
+        let frame_id = (frame % self.descriptor_sets.len() as u64) as usize;
+        let descriptor_set = &self.descriptor_sets[frame_id];
+
+        let inputs = (input.tlas.handle(), [input.normal, input.depth]);
+        if self.inputs[frame_id].as_ref() != Some(&inputs) {
+            let tlas = bump.alloc([input.tlas]);
+            let mut write_descriptor_sets = vec![WriteDescriptorSet {
+                descriptor_set,
+                binding: 0,
+                element: 0,
+                descriptors: Descriptors::AccelerationStructure(tlas),
+            }];
+            write_descriptor_sets.extend(inputs.1.iter().enumerate().map(|(index, image)| {
+                let view = render_context.create_image_view(ImageViewInfo::new(
+                    image.clone(),
+                    vk::ImageAspectFlags::COLOR,
+                ));
+                WriteDescriptorSet {
+                    descriptor_set,
+                    binding: index as u32 + 1,
+                    element: 0,
+                    descriptors: Descriptors::CombinedImageSampler(bump.alloc([(
+                        view,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        self.sampler.clone(),
+                    )])),
+                }
+            }));
+
+            render_context.update_descriptor_sets(&write_descriptor_sets, &[]);
+            self.inputs[frame_id] = Some(inputs);
+        }
+
+        let mut encoder = render_context.queue.create_enconder();
+        encoder.begin_debug_label("Ray Query Shadows", [0.3, 0.3, 0.5, 1.0]);
+
+        // The TLAS was built in an earlier submission, and every pixel of the shadow image is
+        // overwritten so its previous contents can be discarded
+        let image_barriers = bump.alloc([ImageMemoryBarrier::initialize_whole(
+            &self.shadow_image,
+            vk::ImageLayout::GENERAL,
+        )]);
+        encoder.pipeline_barrier(
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR | vk::AccessFlags::SHADER_READ,
+            vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR | vk::AccessFlags::SHADER_WRITE,
+            image_barriers,
+        );
+
+        encoder.bind_compute_pipeline(&self.pipeline);
+        encoder.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.pipeline_layout,
+            0,
+            std::slice::from_ref(descriptor_set),
+            &[],
+        );
+
+        let (view, proj) = camera_matrices(camera, self.shadow_image.info().extent);
+        let proj_inverse = proj.inverse();
+        let push_constants = bump.alloc([PushConstants {
+            view_inverse: view.inverse(),
+            light_direction: self.light_direction.extend(0.0),
+            proj_inverse_scale: Vec2::new(proj_inverse.x_axis.x, proj_inverse.y_axis.y),
+            _padding: Vec2::ZERO,
+        }]);
+        encoder.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            push_constants,
+        );
+
+        let extent = self.shadow_image.info().extent;
+        encoder.dispatch([
+            (extent.width + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+            (extent.height + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+            1,
+        ]);
+
+        let image_barriers = bump.alloc([ImageMemoryBarrier::transition_whole(
+            &self.shadow_image,
+            vk::ImageLayout::GENERAL..vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+        .with_access(vk::AccessFlags::SHADER_WRITE..vk::AccessFlags::SHADER_READ)]);
+        encoder.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+            image_barriers,
+        );
+
+        encoder.end_debug_label();
+
+        let command_buffer = encoder.finish(&render_context.device);
+
+        render_context
+            .queue
+            .submit(command_buffer, wait, signal, fence);
+
+        Output {
+            shadow_image: self.shadow_image.clone(),
+        }
+    }
+}
+
+impl RayQueryShadowPass {
+    pub fn new(
+        render_context: &RenderContext,
+        image_pool: &mut ImagePool,
+        extent: vk::Extent2D,
+        frames_in_flight: usize,
+    ) -> Self {
+        let input_binding = |binding| DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: DescriptorType::CombinedImageSampler,
+            count: 1,
+            stages: vk::ShaderStageFlags::COMPUTE,
+            flags: vk::DescriptorBindingFlags::empty(),
+        };
+
+        let descriptor_set_layout =
+            render_context.create_descriptor_set_layout(DescriptorSetLayoutInfo {
+                bindings: vec![
+                    // TLAS
+                    DescriptorSetLayoutBinding {
+                        binding: 0,
+                        descriptor_type: DescriptorType::AccelerationStructure,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::COMPUTE,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                    // Normal
+                    input_binding(1),
+                    // Depth
+                    input_binding(2),
+                    // Shadow factor
+                    DescriptorSetLayoutBinding {
+                        binding: 3,
+                        descriptor_type: DescriptorType::StorageImage,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::COMPUTE,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                ],
+                flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            });
+
+        let pipeline_layout = render_context.create_pipeline_layout(PipelineLayoutInfo {
+            sets: vec![descriptor_set_layout.clone()],
+            push_constants: vec![PushConstant {
+                stages: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>() as u32,
+            }],
+        });
+
+        let shader = Shader::new(
+            render_context.create_shader_module(ShaderModuleInfo::new("ray_query_shadow.comp.spv")),
+            vk::ShaderStageFlagBits::COMPUTE,
+        );
+
+        let pipeline = render_context
+            .create_compute_pipeline(ComputePipelineInfo {
+                shader,
+                layout: pipeline_layout.clone(),
+            })
+            .expect("Failed to create ray query shadow pipeline");
+
+        let descriptor_sets = (0..frames_in_flight)
+            .map(|_| {
+                render_context.create_descriptor_set(DescriptorSetInfo {
+                    layout: descriptor_set_layout.clone(),
+                })
+            })
+            .collect();
+
+        let mut pass = RayQueryShadowPass {
+            pipeline_layout,
+            pipeline,
+            descriptor_sets,
+            inputs: vec![None; frames_in_flight],
+            sampler: render_context.create_sampler(SamplerInfo::default()),
+            shadow_image: Self::create_shadow_image(render_context, image_pool, extent),
+            light_direction: Vec3::Y,
+        };
+        pass.bind_shadow_image(render_context);
+        pass
+    }
+
+    /// Takes a shadow image for the new `extent` from `image_pool`. The GPU must be done with the
+    /// previous frames, their descriptor sets are rewritten.
+    pub fn resize(
+        &mut self,
+        render_context: &RenderContext,
+        image_pool: &mut ImagePool,
+        extent: vk::Extent2D,
+    ) {
+        self.shadow_image = Self::create_shadow_image(render_context, image_pool, extent);
+        self.bind_shadow_image(render_context);
+    }
+
+    fn create_shadow_image(
+        render_context: &RenderContext,
+        image_pool: &mut ImagePool,
+        extent: vk::Extent2D,
+    ) -> Image {
+        image_pool.get(
+            render_context,
+            ImageInfo {
+                extent,
+                format: vk::Format::R32_SFLOAT,
+                mip_levels: 1,
+                array_layers: 1,
+                samples: vk::SampleCountFlagBits::_1,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                flags: vk::ImageCreateFlags::empty(),
+                name: Some("ray query shadows"),
+            },
+        )
+    }
+
+    fn bind_shadow_image(&self, render_context: &RenderContext) {
+        let shadow_image_view = render_context.create_image_view(ImageViewInfo::new(
+            self.shadow_image.clone(),
+            vk::ImageAspectFlags::COLOR,
+        ));
+
+        for descriptor_set in &self.descriptor_sets {
+            render_context.update_descriptor_sets(
+                &[WriteDescriptorSet {
+                    descriptor_set,
+                    binding: 3,
+                    element: 0,
+                    descriptors: Descriptors::StorageImage(&[(
+                        shadow_image_view.clone(),
+                        vk::ImageLayout::GENERAL,
+                    )]),
+                }],
+                &[],
+            );
+        }
+    }
+
+    /// Direction towards the light, normalized before use.
+    pub fn set_light_direction(&mut self, light_direction: Vec3) {
+        self.light_direction = light_direction.normalize();
+    }
+}
@@ -1,20 +1,26 @@
-use crate::render::mesh::Mesh;
-use crate::render::pass::Pass;
+use crate::render::mesh::{GeometryId, MeshBuffers};
+use crate::render::pass::{camera_matrices, Pass};
 use crate::render::{
     acceleration_structures::{
         AccelerationStructureBuildGeometryInfo, AccelerationStructureGeometry,
         AccelerationStructureGeometryInfo, AccelerationStructureInfo,
         AccelerationStructureInstance, AccelerationStructureLevel, TransformMatrix,
     },
-    buffer::{BufferInfo, BufferRegion},
+    buffer::{BufferInfo, BufferRegion, DeviceAddress, UniformBuffer},
     descriptor::{
         DescriptorSetInfo, DescriptorSetLayoutBinding, DescriptorSetLayoutInfo, DescriptorType,
         Descriptors, WriteDescriptorSet,
     },
-    image::{Image, ImageInfo, ImageMemoryBarrier, ImageViewInfo},
+    encoder::EncoderInner,
+    frustum::Frustum,
+    image::{
+        Image, ImageInfo, ImageMemoryBarrier, ImageSubresourceRange, ImageView, ImageViewInfo,
+    },
+    image_pool::ImagePool,
+    pipeline::vertex_format::VertexFormat,
     pipeline::{
-        PipelineLayoutInfo, RayTracingPipelineInfo, RayTracingShaderGroupInfo, ShaderBindingTable,
-        ShaderBindingTableInfo,
+        PipelineLayoutInfo, PushConstant, RayTracingPipelineInfo, RayTracingShaderGroupInfo,
+        ShaderBindingTable, ShaderBindingTableInfo,
     },
     render_context::RenderContext,
     resources::{
@@ -22,23 +28,55 @@ use crate::render::{
         Semaphore,
     },
     shader::{Shader, ShaderModuleInfo},
+    util::ToMint,
 };
-use bevy::asset::Handle;
-use bevy::prelude::GlobalTransform;
-use bumpalo::{collections::Vec as BumpVec, Bump};
-use crevice::std430::{AsStd430, Std430};
+use bevy::prelude::{Entity, GlobalTransform};
+use bumpalo::Bump;
+use crevice::std430::AsStd430;
 use erupt::vk;
-use glam::{vec3, vec4, Mat4, Vec3};
-use std::collections::HashMap;
+use glam::{vec3, vec4, Mat3, Mat4, Vec3};
+use memoffset::offset_of;
+use std::collections::{HashMap, HashSet};
 
-const MAX_INSTANCE_COUNT: u32 = 2048;
+/// Instances the TLAS buffers are first sized for, they grow to the next power of two when a
+/// frame has more.
+const INITIAL_INSTANCE_CAPACITY: u32 = 64;
 
+/// Mirrors `struct Globals` in `common/descriptors.glsl`, read as a std430 block:
+///
+/// ```glsl
+/// struct Globals {
+///     Camera camera;           // offset 0, 256 bytes
+///     vec4 color;              // offset 256
+///     mat4 previous_view_proj; // offset 272
+///     uint history_valid;      // offset 336
+///     uint samples_per_pixel;  // offset 340
+///     uint max_bounces;        // offset 344
+///     uint seed;               // offset 348
+/// };                           // 352 bytes
+/// ```
+///
+/// Fields have to be reordered on both sides together, the `globals_std430_layout` test catches
+/// the Rust side drifting from these offsets.
 #[derive(AsStd430)]
 pub struct Globals {
     camera: CameraUniform,
     color: mint::Vector4<f32>,
+    /// View-projection of the previous frame, primary hits are reprojected with it to find their
+    /// history.
+    previous_view_proj: mint::ColumnMatrix4<f32>,
+    /// 0 when there is no previous frame to reproject into.
+    history_valid: u32,
+    /// Primary rays traced per pixel every frame, averaged before the temporal blend.
+    samples_per_pixel: u32,
+    /// Bounces after the primary hit, each one a diffuse ray traced recursively from the
+    /// closest-hit shader, so at most the pipeline recursion depth minus the primary ray.
+    max_bounces: u32,
+    /// Mixed with the pixel and the frame to seed the shader random numbers.
+    seed: u32,
 }
 
+/// Mirrors `struct Camera`, four `mat4`s 64 bytes apart starting with `view`.
 #[derive(AsStd430)]
 pub struct CameraUniform {
     view: mint::ColumnMatrix4<f32>,
@@ -47,26 +85,463 @@ pub struct CameraUniform {
     proj_inverse: mint::ColumnMatrix4<f32>,
 }
 
+// A size mismatch with the shader block fails to compile
+const _: [(); 256] = [(); std::mem::size_of::<Std430CameraUniform>()];
+const _: [(); 352] = [(); std::mem::size_of::<Std430Globals>()];
+
+/// Keeps the view-projection of the last drawn frame for temporal reprojection.
+#[derive(Default)]
+struct CameraHistory {
+    view_proj: Option<Mat4>,
+}
+
+impl CameraHistory {
+    /// Records the matrix of the frame about to be drawn and returns the previous frame's one.
+    fn advance(&mut self, view_proj: Mat4) -> Option<Mat4> {
+        self.view_proj.replace(view_proj)
+    }
+}
+
+/// Color and view depth of one frame, read back by the next frame's reprojection.
+struct HistoryImages {
+    color: Image,
+    color_view: ImageView,
+    depth: Image,
+    depth_view: ImageView,
+}
+
+/// The images sized by the render extent, taken from the [`ImagePool`] on creation and resize.
+struct TargetImages {
+    output: Image,
+    output_view: ImageView,
+    /// G-buffer of the primary hits, written next to the color output.
+    albedo: Image,
+    albedo_view: ImageView,
+    normal: Image,
+    normal_view: ImageView,
+    depth: Image,
+    depth_view: ImageView,
+    /// Ping-ponged history, frame `n` writes `history[n % 2]` and reads the other one.
+    history: [HistoryImages; 2],
+}
+
+impl TargetImages {
+    fn new(
+        render_context: &RenderContext,
+        image_pool: &mut ImagePool,
+        extent: vk::Extent2D,
+    ) -> Self {
+        let mut create_image =
+            |format: vk::Format, usage: vk::ImageUsageFlags, name: &'static str| {
+                let image = image_pool.get(
+                    render_context,
+                    ImageInfo {
+                        extent,
+                        format,
+                        mip_levels: 1,
+                        array_layers: 1,
+                        samples: vk::SampleCountFlagBits::_1,
+                        usage,
+                        flags: vk::ImageCreateFlags::empty(),
+                        name: Some(name),
+                    },
+                );
+                let view = render_context.create_image_view(ImageViewInfo::new(
+                    image.clone(),
+                    vk::ImageAspectFlags::COLOR,
+                ));
+                (image, view)
+            };
+
+        let (output, output_view) = create_image(
+            vk::Format::R32G32B32A32_SFLOAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            "path tracing output",
+        );
+
+        // Transfer destination for `reset_accumulation` to clear the history
+        let usage = vk::ImageUsageFlags::STORAGE
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_DST;
+        let (albedo, albedo_view) =
+            create_image(vk::Format::R16G16B16A16_SFLOAT, usage, "G-buffer albedo");
+        let (normal, normal_view) =
+            create_image(vk::Format::R16G16B16A16_SFLOAT, usage, "G-buffer normal");
+        let (depth, depth_view) = create_image(vk::Format::R32_SFLOAT, usage, "G-buffer depth");
+
+        // The pool tells images apart by name, so both histories need their own
+        let mut create_history = |color_name: &'static str, depth_name: &'static str| {
+            let (color, color_view) =
+                create_image(vk::Format::R32G32B32A32_SFLOAT, usage, color_name);
+            let (depth, depth_view) = create_image(vk::Format::R32_SFLOAT, usage, depth_name);
+            HistoryImages {
+                color,
+                color_view,
+                depth,
+                depth_view,
+            }
+        };
+        let history = [
+            create_history("history color 0", "history depth 0"),
+            create_history("history color 1", "history depth 1"),
+        ];
+
+        TargetImages {
+            output,
+            output_view,
+            albedo,
+            albedo_view,
+            normal,
+            normal_view,
+            depth,
+            depth_view,
+            history,
+        }
+    }
+}
+
+/// What the instances in a set of buffers were built from, they are rebuilt when it changes.
+#[derive(Clone, Copy, PartialEq)]
+struct InstanceKey {
+    /// [`RayTracingPass::instances_generation`] at the time of the build.
+    generation: u64,
+    /// The view projection the instances were culled with.
+    view_proj: [f32; 16],
+}
+
+/// The TLAS and the buffers sized by the instance count, recreated together when it grows.
+struct InstanceBuffers {
+    capacity: u32,
+    /// `None` until the first build.
+    built_for: Option<InstanceKey>,
+    /// The instances written by the last rebuild, and the mesh of each one.
+    instances: Vec<AccelerationStructureInstance>,
+    instance_meshes: Vec<GeometryId>,
+    /// The hidden meshes the masks in `instances_buffer` were written for.
+    hidden_meshes: HashSet<GeometryId>,
+    /// Instances written by the last rebuild.
+    instance_count: u32,
+    /// Emissive instances written to the lights buffer by the last rebuild.
+    light_count: u32,
+    tlas: AccelerationStructure,
+    scratch_buffer: Buffer,
+    instances_buffer: Buffer,
+    /// The geometry-reference buffer: an [`InstanceData`] per instance, in the same order, bound
+    /// at binding 3.
+    instance_data_buffer: Buffer,
+    /// The emissive instances of the current TLAS.
+    lights_buffer: Buffer,
+}
+
+impl InstanceBuffers {
+    fn new(
+        render_context: &RenderContext,
+        capacity: u32,
+        tlas_build_flags: vk::BuildAccelerationStructureFlagsKHR,
+    ) -> Self {
+        let tlas_build_sizes = render_context.get_acceleration_structure_build_sizes(
+            AccelerationStructureLevel::Top,
+            tlas_build_flags,
+            &[AccelerationStructureGeometryInfo::Instances {
+                max_primitive_count: capacity,
+            }],
+        );
+
+        let tlas_buffer = render_context.create_buffer(BufferInfo {
+            align: 255,
+            size: tlas_build_sizes.acceleration_structure_size,
+            usage_flags: vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
+            allocation_flags: gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+            name: Some("TLAS"),
+        });
+
+        let tlas = render_context.create_acceleration_structure(AccelerationStructureInfo {
+            level: AccelerationStructureLevel::Top,
+            region: BufferRegion::whole(tlas_buffer),
+        });
+
+        let scratch_buffer = render_context.create_buffer(BufferInfo {
+            align: render_context.scratch_align_mask(),
+            size: tlas_build_sizes.build_scratch_size,
+            usage_flags: vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::STORAGE_BUFFER,
+            allocation_flags: gpu_alloc::UsageFlags::DEVICE_ADDRESS,
+            name: Some("TLAS scratch"),
+        });
+
+        let instances_buffer = render_context.create_buffer(BufferInfo {
+            align: 255,
+            size: (std::mem::size_of::<vk::AccelerationStructureInstanceKHR>() * capacity as usize)
+                as _,
+            usage_flags: vk::BufferUsageFlags::UNIFORM_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            allocation_flags: gpu_alloc::UsageFlags::DEVICE_ADDRESS
+                | gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS
+                | gpu_alloc::UsageFlags::HOST_ACCESS,
+            name: Some("TLAS instances"),
+        });
+
+        let instance_data_buffer = render_context.create_buffer(BufferInfo {
+            align: 255,
+            size: Self::instance_data_size(capacity),
+            usage_flags: vk::BufferUsageFlags::STORAGE_BUFFER,
+            allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS,
+            name: Some("TLAS instance data"),
+        });
+
+        let lights_buffer = render_context.create_buffer(BufferInfo {
+            align: 255,
+            size: Self::lights_size(capacity),
+            usage_flags: vk::BufferUsageFlags::STORAGE_BUFFER,
+            allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS,
+            name: Some("lights"),
+        });
+
+        InstanceBuffers {
+            capacity,
+            built_for: None,
+            instances: vec![],
+            instance_meshes: vec![],
+            hidden_meshes: HashSet::new(),
+            instance_count: 0,
+            light_count: 0,
+            tlas,
+            scratch_buffer,
+            instances_buffer,
+            instance_data_buffer,
+            lights_buffer,
+        }
+    }
+
+    fn instance_data_size(capacity: u32) -> u64 {
+        (std::mem::size_of::<Std430InstanceData>() * capacity as usize) as u64
+    }
+
+    fn lights_size(capacity: u32) -> u64 {
+        (std::mem::size_of::<Std430Light>() * capacity as usize) as u64
+    }
+}
+
+/// Records the TLAS build with `record_build` behind the barrier making the BLAS writes of earlier
+/// submissions visible to it. Acceleration structures are ordered within a frame as:
+///
+/// ```text
+/// BLAS builds (`Renderer::load_models`, earlier submission on the same queue)
+///   -> this barrier -> TLAS build -> barrier after the build -> trace_rays / ray queries
+/// ```
+///
+/// The TLAS and scratch of a frame slot were last used by the frame waited on before drawing.
+pub fn record_tlas_build<'a>(
+    encoder: &mut EncoderInner<'a>,
+    record_build: impl FnOnce(&mut EncoderInner<'a>),
+) {
+    encoder.pipeline_barrier(
+        vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+        vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+        vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+        vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+        &[],
+    );
+    record_build(encoder);
+}
+
+/// The capacity instance buffers holding `capacity` instances have to be recreated with to fit
+/// `instance_count`, rounded up to a power of two so a growing scene rarely reallocates. `None`
+/// if they already fit.
+fn grown_instance_capacity(capacity: u32, instance_count: u32) -> Option<u32> {
+    if instance_count > capacity {
+        Some(instance_count.next_power_of_two())
+    } else {
+        None
+    }
+}
+
+/// The bounces a pipeline with `max_recursion_depth` can trace recursively, one level is taken by
+/// the primary ray.
+fn clamp_bounces(max_bounces: u32, max_recursion_depth: u32) -> u32 {
+    max_bounces.min(max_recursion_depth.saturating_sub(1))
+}
+
+/// Per-mesh options of its TLAS instance.
+#[derive(Clone, Copy, Debug)]
+pub struct InstanceConfig {
+    /// Read back as `gl_InstanceCustomIndexEXT`, e.g. to look up the material. Must fit in 24 bits.
+    pub custom_index: u32,
+    /// Rays only hit the instance if their cull mask shares a bit with it.
+    pub mask: u8,
+    /// Index of the hit group record used for this instance, which is also the index of its
+    /// closest-hit shader in the list the pass was created with.
+    pub sbt_offset: u32,
+}
+
+impl InstanceConfig {
+    /// The config of meshes that weren't given one: their geometry id and a full mask.
+    pub fn new(mesh: GeometryId) -> Self {
+        InstanceConfig {
+            custom_index: mesh.0,
+            mask: !0,
+            sbt_offset: 0,
+        }
+    }
+}
+
+/// Per-instance data read by the hit shaders, indexed by `gl_InstanceID`. The array of them is the
+/// geometry-reference buffer at binding 3, mirroring `struct InstanceData` in
+/// `common/descriptors.glsl`.
+#[derive(AsStd430)]
+pub struct InstanceData {
+    normal_matrix: mint::ColumnMatrix3<f32>,
+    /// Device address of the tightly packed `vec3` positions, 0 for other vertex formats.
+    positions: mint::Vector2<u32>,
+    /// Device address of the 32-bit indices, 0 for non-indexed meshes.
+    indices: mint::Vector2<u32>,
+    /// Device address of the per-vertex colors, 0 if the mesh has none.
+    colors: mint::Vector2<u32>,
+    /// Device address of the interleaved normals and uvs, 0 if the mesh has none.
+    shading: mint::Vector2<u32>,
+}
+
+impl InstanceData {
+    fn new(transform: &Mat4, buffers: Option<&MeshBuffers>) -> Self {
+        let colors = buffers.and_then(|buffers| buffers.color_buffer.as_ref());
+        let shading = buffers.and_then(|buffers| buffers.shading_buffer.as_ref());
+        let indices = buffers.and_then(|buffers| buffers.index_buffer.as_ref());
+        let positions = buffers
+            .filter(|buffers| buffers.vertex_format == VertexFormat::Float32x3)
+            .map(|buffers| &buffers.vertex_buffer);
+
+        // The hit shaders only fetch 32-bit indices, 16-bit meshes are shaded without colors and
+        // get no G-buffer normals
+        let (colors, positions, shading) = match buffers.map(|buffers| buffers.index_type) {
+            Some(vk::IndexType::UINT16) => (None, None, None),
+            _ => (colors, positions, shading),
+        };
+
+        InstanceData {
+            normal_matrix: normal_matrix(transform).to_mint(),
+            positions: address_to_uvec2(positions.and_then(|positions| positions.device_address())),
+            indices: address_to_uvec2(indices.and_then(|indices| indices.device_address())),
+            colors: address_to_uvec2(colors.and_then(|colors| colors.device_address())),
+            shading: address_to_uvec2(shading.and_then(|shading| shading.device_address())),
+        }
+    }
+}
+
+/// An emissive instance the ray generation shader can sample for next-event estimation.
+#[derive(AsStd430)]
+pub struct Light {
+    emission: mint::Vector3<f32>,
+    /// Index of the instance in the TLAS, which is also its index in the instance data.
+    instance: u32,
+    triangle_count: u32,
+}
+
+/// The instances of a set of buffers written while `written_hidden` were hidden whose mask has to
+/// change now that `hidden` are, with their new mask. `mask` is the mask of visible instances.
+fn mask_patches(
+    instance_meshes: &[GeometryId],
+    written_hidden: &HashSet<GeometryId>,
+    hidden: &HashSet<GeometryId>,
+    mask: impl Fn(GeometryId) -> u8,
+) -> Vec<(usize, u8)> {
+    instance_meshes
+        .iter()
+        .enumerate()
+        .filter(|(_, mesh)| hidden.contains(mesh) != written_hidden.contains(mesh))
+        .map(|(index, &mesh)| {
+            if hidden.contains(&mesh) {
+                (index, 0)
+            } else {
+                (index, mask(mesh))
+            }
+        })
+        .collect()
+}
+
+/// Splits a device address into the `uvec2` the shaders turn back into a buffer reference.
+fn address_to_uvec2(address: Option<DeviceAddress>) -> mint::Vector2<u32> {
+    let address = address.map_or(0, |address| address.0.get());
+    mint::Vector2 {
+        x: address as u32,
+        y: (address >> 32) as u32,
+    }
+}
+
+/// Inverse-transpose of the upper 3x3 of `model`, which keeps normals perpendicular to the
+/// surface under non-uniform scale. A model scaled to zero along an axis has no inverse, its
+/// instance has no area to shade so the identity is returned instead of NaNs.
+pub fn normal_matrix(model: &Mat4) -> Mat3 {
+    let upper = Mat3::from_cols(
+        model.x_axis.truncate(),
+        model.y_axis.truncate(),
+        model.z_axis.truncate(),
+    );
+    let determinant = upper.determinant();
+    if determinant == 0.0 || !determinant.is_finite() {
+        return Mat3::IDENTITY;
+    }
+    upper.inverse().transpose()
+}
+
+/// Per-frame parameters that change too often to be worth a descriptor update.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+    frame: u32,
+    light_count: u32,
+}
+
+unsafe impl bytemuck::Zeroable for PushConstants {}
+unsafe impl bytemuck::Pod for PushConstants {}
+
 pub struct RayTracingPass {
     pipeline_layout: PipelineLayout,
     pipeline: RayTracingPipeline,
     shader_binding_table: ShaderBindingTable,
-    tlas: AccelerationStructure,
-    scratch_buffer: Buffer,
-    descriptor_set: DescriptorSet,
+    tlas_build_flags: vk::BuildAccelerationStructureFlagsKHR,
+    /// One descriptor set and globals buffer per frame in flight, indexed by `frame % len`.
+    descriptor_sets: Vec<DescriptorSet>,
     globals: Globals,
-    globals_buffer: Buffer,
-    instances_buffer: Buffer,
-    output_image: Image,
+    globals_buffers: Vec<UniformBuffer<Globals>>,
+    /// One set per frame in flight, indexed like `globals_buffers`, so the instances and TLAS a
+    /// frame still traces against are never rewritten by the next one.
+    instance_buffers: Vec<InstanceBuffers>,
+    /// The instance capacity of the buffers bound to each descriptor set, `None` until it is first
+    /// used.
+    descriptor_set_capacity: Vec<Option<u32>>,
+    /// Bumped by every change to the instances other than their visibility, see
+    /// [`RayTracingPass::invalidate_instances`].
+    instances_generation: u64,
+    emissions: HashMap<GeometryId, Vec3>,
+    /// The world transform of every entity drawing each mesh, each one gets its own instance.
+    instance_transforms: HashMap<GeometryId, HashMap<Entity, Mat4>>,
+    instance_configs: HashMap<GeometryId, InstanceConfig>,
+    hidden_meshes: HashSet<GeometryId>,
+    images: TargetImages,
+    /// The history written by each descriptor set, `None` until it is first used.
+    descriptor_set_history: Vec<Option<usize>>,
+    camera_history: CameraHistory,
+    /// Set by [`RayTracingPass::reset_accumulation`], the next frame clears the history it reads.
+    clear_history: bool,
 }
 
 pub struct Input<'a> {
-    pub blases: &'a HashMap<Handle<Mesh>, AccelerationStructure>,
+    pub blases: &'a HashMap<GeometryId, AccelerationStructure>,
+    /// Provides the bounds instances are frustum culled with.
+    pub meshes: &'a HashMap<GeometryId, MeshBuffers>,
 }
 
 pub struct Output {
     pub tlas: AccelerationStructure,
     pub output_image: Image,
+    /// Surface color at the primary hit.
+    pub albedo_image: Image,
+    /// World space normal at the primary hit, zero where the ray missed.
+    pub normal_image: Image,
+    /// View space depth of the primary hit, zero where the ray missed.
+    pub depth_image: Image,
 }
 
 impl<'a> Pass<'a> for RayTracingPass {
@@ -76,7 +551,7 @@ impl<'a> Pass<'a> for RayTracingPass {
     fn draw(
         &mut self,
         input: Self::Input,
-        _frame: u64,
+        frame: u64,
         wait: &[(vk::PipelineStageFlags, Semaphore)],
         signal: &[Semaphore],
         fence: Option<&Fence>,
@@ -85,61 +560,76 @@ impl<'a> Pass<'a> for RayTracingPass {
         camera: &GlobalTransform,
     ) -> Self::Output {
         let mut encoder = render_context.queue.create_enconder();
+        encoder.begin_debug_label("Ray tracing", [0.8, 0.3, 0.1, 1.0]);
+        let frame_index = (frame % self.descriptor_sets.len() as u64) as usize;
 
-        let mut as_instances = BumpVec::new_in(bump);
+        let (view, proj) = camera_matrices(camera, self.images.output.info().extent);
 
-        for blas in input.blases.values() {
-            as_instances.push(
-                AccelerationStructureInstance::new(blas.device_address())
-                    .with_transform(TransformMatrix::identity()),
+        // Culling depends on the camera, so the instances of this frame slot are rebuilt when it
+        // moves as well as when the scene changes. Visibility changes alone only patch the masks.
+        let key = InstanceKey {
+            generation: self.instances_generation,
+            view_proj: (proj * view).to_cols_array(),
+        };
+        let build_tlas = if self.instance_buffers[frame_index].built_for != Some(key) {
+            let frustum = Frustum::from_view_proj(&(proj * view));
+            self.rebuild_instances(
+                render_context,
+                frame_index,
+                input.blases,
+                input.meshes,
+                &frustum,
             );
-        }
-
-        encoder.pipeline_barrier(
-            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
-            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
-            vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
-            vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
-            &[],
-        );
+            self.instance_buffers[frame_index].built_for = Some(key);
+            true
+        } else {
+            self.patch_visibility(render_context, frame_index)
+        };
+        self.bind_instance_buffers(render_context, frame_index);
 
-        let view = Mat4::from_scale_rotation_translation(
-            camera.scale,
-            camera.rotation.inverse(),
-            -camera.translation,
-        );
-        let proj = Mat4::perspective_rh(90.0f32.to_radians(), 800.0 / 600.0, 0.001, 10000.0);
+        // Bound before recording, the encoder borrows the pipeline from here on
+        let history_index = (frame % 2) as usize;
+        self.bind_history(render_context, frame_index, history_index);
 
         self.globals.camera = CameraUniform {
-            view: view.into(),
-            proj: proj.into(),
-            view_inverse: view.inverse().into(),
-            proj_inverse: proj.inverse().into(),
+            view: view.to_mint(),
+            proj: proj.to_mint(),
+            view_inverse: view.inverse().to_mint(),
+            proj_inverse: proj.inverse().to_mint(),
         };
-        render_context.write_buffer(
-            &mut self.globals_buffer,
-            0,
-            self.globals.as_std430().as_bytes(),
-        );
+        let clear_history = std::mem::take(&mut self.clear_history);
+        if clear_history {
+            self.camera_history = CameraHistory::default();
+        }
+        let previous_view_proj = self.camera_history.advance(proj * view);
+        self.globals.previous_view_proj = previous_view_proj.unwrap_or(Mat4::IDENTITY).to_mint();
+        self.globals.history_valid = previous_view_proj.is_some() as u32;
+        self.globals_buffers[frame_index].update(render_context, &self.globals);
 
-        let build_info = bump.alloc([AccelerationStructureBuildGeometryInfo {
-            src: None,
-            dst: self.tlas.clone(),
-            flags: vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD_KHR,
-            geometries: bump.alloc([AccelerationStructureGeometry::Instances {
-                flags: vk::GeometryFlagsKHR::OPAQUE_KHR,
-                data: self.instances_buffer.device_address().unwrap(),
-                primitive_count: as_instances.len() as _,
-            }]),
-            scratch: self.scratch_buffer.device_address().unwrap(),
-        }]);
-        render_context.write_buffer(&mut self.instances_buffer, 0, &as_instances);
+        if build_tlas {
+            // Before any mesh is loaded this builds an empty TLAS, which is valid: every ray
+            // misses and the frame only shows the background.
+            let instance_buffers = &self.instance_buffers[frame_index];
+            let build_info = bump.alloc([AccelerationStructureBuildGeometryInfo {
+                src: None,
+                dst: instance_buffers.tlas.clone(),
+                flags: self.tlas_build_flags,
+                geometries: bump.alloc([AccelerationStructureGeometry::Instances {
+                    flags: vk::GeometryFlagsKHR::OPAQUE_KHR,
+                    data: instance_buffers.instances_buffer.device_address().unwrap(),
+                    primitive_count: instance_buffers.instance_count,
+                }]),
+                scratch: instance_buffers.scratch_buffer.device_address().unwrap(),
+            }]);
 
-        encoder.build_acceleration_structure(build_info);
+            record_tlas_build(&mut encoder, move |encoder| {
+                encoder.build_acceleration_structure(build_info)
+            });
+        }
 
         encoder.bind_ray_tracing_pipeline(&self.pipeline);
 
-        let descriptor_sets = [self.descriptor_set.clone()];
+        let descriptor_sets = [self.descriptor_sets[frame_index].clone()];
         encoder.bind_descriptor_sets(
             vk::PipelineBindPoint::RAY_TRACING_KHR,
             &self.pipeline_layout,
@@ -148,41 +638,131 @@ impl<'a> Pass<'a> for RayTracingPass {
             &[],
         );
 
-        let image_barriers = [ImageMemoryBarrier::initialize_whole(
-            &self.output_image,
-            vk::ImageLayout::GENERAL,
-        )];
+        let current = &self.images.history[history_index];
+        let previous = &self.images.history[1 - history_index];
+        if clear_history {
+            let ranges = bump.alloc([ImageSubresourceRange::new(
+                vk::ImageAspectFlags::COLOR,
+                0..1,
+                0..1,
+            )]);
+            let image_barriers = bump.alloc([
+                ImageMemoryBarrier::initialize_whole(
+                    &previous.color,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                ),
+                ImageMemoryBarrier::initialize_whole(
+                    &previous.depth,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                ),
+            ]);
+            encoder.pipeline_barrier(
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                vk::AccessFlags::TRANSFER_WRITE,
+                image_barriers,
+            );
+            encoder.clear_color_image(
+                &previous.color,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                [0.0; 4],
+                ranges,
+            );
+            encoder.clear_color_image(
+                &previous.depth,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                [0.0; 4],
+                ranges,
+            );
+        }
+
+        let read_history = |image| {
+            // A cleared history is read as zeros. The first frame has nothing to read, the history
+            // only needs a valid layout
+            if clear_history {
+                ImageMemoryBarrier::transition_whole(
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL..vk::ImageLayout::GENERAL,
+                )
+                .with_access(vk::AccessFlags::TRANSFER_WRITE..vk::AccessFlags::SHADER_READ)
+            } else if previous_view_proj.is_some() {
+                ImageMemoryBarrier::transition_whole(
+                    image,
+                    vk::ImageLayout::GENERAL..vk::ImageLayout::GENERAL,
+                )
+                .with_access(vk::AccessFlags::SHADER_WRITE..vk::AccessFlags::SHADER_READ)
+            } else {
+                ImageMemoryBarrier::initialize_whole(image, vk::ImageLayout::GENERAL)
+            }
+        };
+        let image_barriers = [
+            ImageMemoryBarrier::initialize_whole(&self.images.output, vk::ImageLayout::GENERAL),
+            ImageMemoryBarrier::initialize_whole(&self.images.albedo, vk::ImageLayout::GENERAL),
+            ImageMemoryBarrier::initialize_whole(&self.images.normal, vk::ImageLayout::GENERAL),
+            ImageMemoryBarrier::initialize_whole(&self.images.depth, vk::ImageLayout::GENERAL),
+            ImageMemoryBarrier::initialize_whole(&current.color, vk::ImageLayout::GENERAL),
+            ImageMemoryBarrier::initialize_whole(&current.depth, vk::ImageLayout::GENERAL),
+            read_history(&previous.color),
+            read_history(&previous.depth),
+        ];
 
         encoder.pipeline_barrier(
-            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR
+                | vk::PipelineStageFlags::TRANSFER,
             vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
             vk::AccessFlags::MEMORY_WRITE,
             vk::AccessFlags::MEMORY_WRITE,
             &image_barriers,
         );
 
+        // The TLAS must be fully built before any ray is traced against it
         encoder.pipeline_barrier(
             vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
             vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
-            vk::AccessFlags::MEMORY_WRITE,
-            vk::AccessFlags::MEMORY_WRITE,
+            vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+            vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
             &[],
         );
 
-        encoder.trace_rays(&self.shader_binding_table, self.output_image.info().extent);
+        let push_constants = bump.alloc([PushConstants {
+            frame: frame as u32,
+            light_count: self.instance_buffers[frame_index].light_count,
+        }]);
+        encoder.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::RAYGEN_KHR | vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+            0,
+            push_constants,
+        );
 
-        let image_barriers = [ImageMemoryBarrier::transition_whole(
-            &self.output_image,
-            vk::ImageLayout::GENERAL..vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-        )];
+        encoder.trace_rays(&self.shader_binding_table, self.images.output.info().extent);
+
+        let read_only = |image| {
+            ImageMemoryBarrier::transition_whole(
+                image,
+                vk::ImageLayout::GENERAL..vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )
+            .with_access(vk::AccessFlags::SHADER_WRITE..vk::AccessFlags::SHADER_READ)
+        };
+        let image_barriers = [
+            read_only(&self.images.output),
+            read_only(&self.images.albedo),
+            read_only(&self.images.normal),
+            read_only(&self.images.depth),
+        ];
+        // The outputs are read by the fragment shaders of the tonemap pass or by the denoiser
         encoder.pipeline_barrier(
             vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
-            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
             vk::AccessFlags::MEMORY_WRITE,
             vk::AccessFlags::MEMORY_WRITE,
             &image_barriers,
         );
 
+        encoder.end_debug_label();
+
         let command_buffer = encoder.finish(&render_context.device);
 
         render_context
@@ -190,14 +770,38 @@ impl<'a> Pass<'a> for RayTracingPass {
             .submit(command_buffer, wait, signal, fence);
 
         Output {
-            tlas: self.tlas.clone(),
-            output_image: self.output_image.clone(),
+            tlas: self.instance_buffers[frame_index].tlas.clone(),
+            output_image: self.images.output.clone(),
+            albedo_image: self.images.albedo.clone(),
+            normal_image: self.images.normal.clone(),
+            depth_image: self.images.depth.clone(),
         }
     }
 }
 
 impl RayTracingPass {
-    pub fn new(render_context: &RenderContext, extent: vk::Extent2D) -> Self {
+    /// `hit_shaders` are the SPIR-V files of the closest-hit shaders, each gets its own hit group
+    /// and instances pick one with [`InstanceConfig::sbt_offset`]. `max_recursion_depth` counts the
+    /// primary ray, every level above it allows one more bounce.
+    pub fn new(
+        render_context: &RenderContext,
+        extent: vk::Extent2D,
+        tlas_build_flags: vk::BuildAccelerationStructureFlagsKHR,
+        frames_in_flight: usize,
+        hit_shaders: &[&str],
+        max_recursion_depth: u32,
+        image_pool: &mut ImagePool,
+    ) -> Self {
+        assert!(frames_in_flight > 0);
+        assert!(
+            max_recursion_depth > 0,
+            "the recursion depth has to fit the primary ray"
+        );
+        assert!(
+            !hit_shaders.is_empty(),
+            "at least one hit shader is required"
+        );
+
         let descriptor_set_layout =
             render_context.create_descriptor_set_layout(DescriptorSetLayoutInfo {
                 bindings: vec![
@@ -228,13 +832,90 @@ impl RayTracingPass {
                             | vk::ShaderStageFlags::MISS_KHR,
                         flags: vk::DescriptorBindingFlags::empty(),
                     },
+                    // Geometry references: the normal matrix and buffer addresses of each instance
+                    DescriptorSetLayoutBinding {
+                        binding: 3,
+                        descriptor_type: DescriptorType::StorageBuffer,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                    // Lights
+                    DescriptorSetLayoutBinding {
+                        binding: 4,
+                        descriptor_type: DescriptorType::StorageBuffer,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::RAYGEN_KHR
+                            | vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                    // G-buffer albedo
+                    DescriptorSetLayoutBinding {
+                        binding: 5,
+                        descriptor_type: DescriptorType::StorageImage,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::RAYGEN_KHR,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                    // G-buffer normal
+                    DescriptorSetLayoutBinding {
+                        binding: 6,
+                        descriptor_type: DescriptorType::StorageImage,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::RAYGEN_KHR,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                    // G-buffer depth
+                    DescriptorSetLayoutBinding {
+                        binding: 7,
+                        descriptor_type: DescriptorType::StorageImage,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::RAYGEN_KHR,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                    // History color written this frame
+                    DescriptorSetLayoutBinding {
+                        binding: 8,
+                        descriptor_type: DescriptorType::StorageImage,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::RAYGEN_KHR,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                    // History depth written this frame
+                    DescriptorSetLayoutBinding {
+                        binding: 9,
+                        descriptor_type: DescriptorType::StorageImage,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::RAYGEN_KHR,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                    // Previous frame color
+                    DescriptorSetLayoutBinding {
+                        binding: 10,
+                        descriptor_type: DescriptorType::StorageImage,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::RAYGEN_KHR,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
+                    // Previous frame depth
+                    DescriptorSetLayoutBinding {
+                        binding: 11,
+                        descriptor_type: DescriptorType::StorageImage,
+                        count: 1,
+                        stages: vk::ShaderStageFlags::RAYGEN_KHR,
+                        flags: vk::DescriptorBindingFlags::empty(),
+                    },
                 ],
                 flags: vk::DescriptorSetLayoutCreateFlags::empty(),
             });
 
         let pipeline_layout = render_context.create_pipeline_layout(PipelineLayoutInfo {
             sets: vec![descriptor_set_layout.clone()],
-            push_constants: vec![],
+            push_constants: vec![PushConstant {
+                stages: vk::ShaderStageFlags::RAYGEN_KHR | vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>() as u32,
+            }],
         });
 
         let ray_gen_shader = Shader::new(
@@ -247,162 +928,620 @@ impl RayTracingPass {
             vk::ShaderStageFlagBits::MISS_KHR,
         );
 
-        let closest_hit_shader = Shader::new(
-            render_context.create_shader_module(ShaderModuleInfo::new("raytrace.rchit.spv")),
-            vk::ShaderStageFlagBits::CLOSEST_HIT_KHR,
-        );
+        let mut shaders = vec![ray_gen_shader, miss_shader];
+        let mut groups = vec![
+            RayTracingShaderGroupInfo::Raygen { raygen: 0 },
+            RayTracingShaderGroupInfo::Miss { miss: 1 },
+        ];
+
+        // One triangle group per closest-hit shader, shader and group indices line up
+        for &file in hit_shaders {
+            groups.push(RayTracingShaderGroupInfo::Triangle {
+                any_hit: None,
+                closest_hit: Some(shaders.len() as u32),
+            });
+            shaders.push(Shader::new(
+                render_context.create_shader_module(ShaderModuleInfo::new(file)),
+                vk::ShaderStageFlagBits::CLOSEST_HIT_KHR,
+            ));
+        }
 
         let pipeline = render_context.create_ray_tracing_pipeline(RayTracingPipelineInfo {
-            shaders: vec![ray_gen_shader, miss_shader, closest_hit_shader],
-            groups: vec![
-                RayTracingShaderGroupInfo::Raygen { raygen: 0 },
-                RayTracingShaderGroupInfo::Miss { miss: 1 },
-                RayTracingShaderGroupInfo::Triangle {
-                    any_hit: None,
-                    closest_hit: Some(2),
-                },
-            ],
-            max_recursion_depth: 2,
+            shaders,
+            groups,
+            max_recursion_depth,
             layout: pipeline_layout.clone(),
         });
 
-        let shader_binding_table = render_context.create_shader_binding_table(
-            &pipeline,
-            ShaderBindingTableInfo {
-                raygen: Some(0),
-                miss: &[1],
-                hit: &[2],
-                callable: &[],
+        let shader_binding_table = Self::create_shader_binding_table(render_context, &pipeline);
+
+        let instance_buffers = (0..frames_in_flight)
+            .map(|_| {
+                InstanceBuffers::new(render_context, INITIAL_INSTANCE_CAPACITY, tlas_build_flags)
+            })
+            .collect();
+
+        let cam_pos = vec3(0.0, 1.0, -1.0);
+        let view = Mat4::look_at_rh(cam_pos, Vec3::ZERO, Vec3::Y);
+        let aspect_ratio = extent.width as f32 / extent.height.max(1) as f32;
+        let proj = Mat4::perspective_rh(70.0f32.to_radians(), aspect_ratio, 0.001, 10000.0);
+
+        let globals = Globals {
+            color: vec4(0.8, 0.0, 0.0, 1.0).to_mint(),
+            camera: CameraUniform {
+                view: view.to_mint(),
+                proj: proj.to_mint(),
+                view_inverse: view.inverse().to_mint(),
+                proj_inverse: proj.inverse().to_mint(),
             },
-        );
+            previous_view_proj: Mat4::IDENTITY.to_mint(),
+            history_valid: 0,
+            samples_per_pixel: 1,
+            max_bounces: clamp_bounces(u32::MAX, pipeline.max_recursion_depth()),
+            seed: 0,
+        };
 
-        let tlas_build_sizes = render_context.get_acceleration_structure_build_sizes(
-            AccelerationStructureLevel::Top,
-            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD_KHR,
-            &[AccelerationStructureGeometryInfo::Instances {
-                max_primitive_count: MAX_INSTANCE_COUNT,
-            }],
-        );
+        let mut descriptor_sets = Vec::with_capacity(frames_in_flight);
+        let mut globals_buffers = Vec::with_capacity(frames_in_flight);
 
-        let tlas_buffer = render_context.create_buffer(BufferInfo {
-            align: 255,
-            size: tlas_build_sizes.acceleration_structure_size,
-            usage_flags: vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
-            allocation_flags: gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
-        });
+        for _ in 0..frames_in_flight {
+            let globals_buffer =
+                UniformBuffer::new(render_context, &globals, "ray tracing globals");
 
-        let tlas = render_context.create_acceleration_structure(AccelerationStructureInfo {
-            level: AccelerationStructureLevel::Top,
-            region: BufferRegion::whole(tlas_buffer),
-        });
+            let descriptor_set = render_context.create_descriptor_set(DescriptorSetInfo {
+                layout: descriptor_set_layout.clone(),
+            });
 
-        let scratch_buffer = render_context.create_buffer(BufferInfo {
-            align: 255,
-            size: tlas_build_sizes.build_scratch_size,
-            usage_flags: vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                | vk::BufferUsageFlags::STORAGE_BUFFER,
-            allocation_flags: gpu_alloc::UsageFlags::DEVICE_ADDRESS,
-        });
+            render_context.update_descriptor_sets(
+                &[WriteDescriptorSet {
+                    descriptor_set: &descriptor_set,
+                    binding: 2,
+                    element: 0,
+                    descriptors: Descriptors::UniformBuffer(&[(
+                        globals_buffer.buffer().clone(),
+                        0,
+                        UniformBuffer::<Globals>::binding_size(),
+                    )]),
+                }],
+                &[],
+            );
 
-        let instances_buffer = render_context.create_buffer(BufferInfo {
-            align: 255,
-            size: std::mem::size_of::<
-                [vk::AccelerationStructureInstanceKHR; MAX_INSTANCE_COUNT as usize],
-            >() as _,
-            usage_flags: vk::BufferUsageFlags::UNIFORM_BUFFER
-                | vk::BufferUsageFlags::STORAGE_BUFFER
-                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
-                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-            allocation_flags: gpu_alloc::UsageFlags::DEVICE_ADDRESS
-                | gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS
-                | gpu_alloc::UsageFlags::HOST_ACCESS,
-        });
+            descriptor_sets.push(descriptor_set);
+            globals_buffers.push(globals_buffer);
+        }
 
-        let mut globals_buffer = render_context.create_buffer(BufferInfo {
-            align: 255,
-            size: std::mem::size_of::<Std430Globals>() as _,
-            usage_flags: vk::BufferUsageFlags::UNIFORM_BUFFER
-                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-            allocation_flags: gpu_alloc::UsageFlags::DEVICE_ADDRESS
-                | gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS
-                | gpu_alloc::UsageFlags::HOST_ACCESS,
-        });
+        let mut pass = RayTracingPass {
+            pipeline_layout,
+            pipeline,
+            shader_binding_table,
+            tlas_build_flags,
+            descriptor_sets,
+            globals,
+            globals_buffers,
+            instance_buffers,
+            descriptor_set_capacity: vec![None; frames_in_flight],
+            instances_generation: 0,
+            emissions: HashMap::new(),
+            instance_transforms: HashMap::new(),
+            instance_configs: HashMap::new(),
+            hidden_meshes: HashSet::new(),
+            images: TargetImages::new(render_context, image_pool, extent),
+            descriptor_set_history: vec![None; frames_in_flight],
+            camera_history: CameraHistory::default(),
+            clear_history: false,
+        };
+        pass.bind_images(render_context);
+        pass
+    }
 
-        let cam_pos = vec3(0.0, 1.0, -1.0);
-        let view = Mat4::look_at_rh(cam_pos, Vec3::ZERO, Vec3::Y);
-        let proj = Mat4::perspective_rh(70.0f32.to_radians(), 800.0 / 600.0, 0.001, 10000.0);
+    /// Recreates the pipeline and its shader binding table with a new recursion depth, clamping
+    /// the bounces to it. The GPU must be done with every submitted frame.
+    pub fn set_max_recursion_depth(
+        &mut self,
+        render_context: &RenderContext,
+        max_recursion_depth: u32,
+    ) {
+        assert!(
+            max_recursion_depth > 0,
+            "the recursion depth has to fit the primary ray"
+        );
 
-        let globals = Globals {
-            color: vec4(0.8, 0.0, 0.0, 1.0).into(),
-            camera: CameraUniform {
-                view: view.into(),
-                proj: proj.into(),
-                view_inverse: view.inverse().into(),
-                proj_inverse: proj.inverse().into(),
+        let mut info = self.pipeline.info().clone();
+        info.max_recursion_depth = max_recursion_depth;
+        self.pipeline = render_context.create_ray_tracing_pipeline(info);
+        self.shader_binding_table =
+            Self::create_shader_binding_table(render_context, &self.pipeline);
+
+        self.globals.max_bounces = clamp_bounces(
+            self.globals.max_bounces,
+            self.pipeline.max_recursion_depth(),
+        );
+    }
+
+    /// The raygen group comes first, then the miss group, then a hit record per triangle group.
+    fn create_shader_binding_table(
+        render_context: &RenderContext,
+        pipeline: &RayTracingPipeline,
+    ) -> ShaderBindingTable {
+        let hit_groups = pipeline
+            .info()
+            .groups
+            .iter()
+            .enumerate()
+            .filter(|(_, group)| matches!(group, RayTracingShaderGroupInfo::Triangle { .. }))
+            .map(|(index, _)| index as u32)
+            .collect::<Vec<_>>();
+
+        render_context.create_shader_binding_table(
+            pipeline,
+            ShaderBindingTableInfo {
+                raygen: Some(0),
+                miss: &[1],
+                hit: &hit_groups,
+                callable: &[],
             },
-        };
-        render_context.write_buffer(&mut globals_buffer, 0, globals.as_std430().as_bytes());
-
-        let output_image = render_context.create_image(ImageInfo {
-            extent,
-            format: vk::Format::R32G32B32A32_SFLOAT,
-            mip_levels: 1,
-            array_layers: 1,
-            samples: vk::SampleCountFlagBits::_1,
-            usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
-        });
+        )
+    }
 
-        let output_image_view = render_context.create_image_view(ImageViewInfo::new(
-            output_image.clone(),
-            vk::ImageAspectFlags::COLOR,
-        ));
+    /// Takes the images for the new `extent` from `image_pool` and starts the accumulation over.
+    /// The GPU must be done with the previous frames, their descriptor sets are rewritten.
+    pub fn resize(
+        &mut self,
+        render_context: &RenderContext,
+        image_pool: &mut ImagePool,
+        extent: vk::Extent2D,
+    ) {
+        self.images = TargetImages::new(render_context, image_pool, extent);
+        self.bind_images(render_context);
+        self.descriptor_set_history = vec![None; self.descriptor_sets.len()];
+        self.reset_accumulation();
+    }
 
-        let descriptor_set = render_context.create_descriptor_set(DescriptorSetInfo {
-            layout: descriptor_set_layout.clone(),
-        });
+    /// Points the output and G-buffer bindings of every descriptor set at the current images.
+    fn bind_images(&self, render_context: &RenderContext) {
+        let views = [
+            [(self.images.output_view.clone(), vk::ImageLayout::GENERAL)],
+            [(self.images.albedo_view.clone(), vk::ImageLayout::GENERAL)],
+            [(self.images.normal_view.clone(), vk::ImageLayout::GENERAL)],
+            [(self.images.depth_view.clone(), vk::ImageLayout::GENERAL)],
+        ];
+        let writes = self
+            .descriptor_sets
+            .iter()
+            .flat_map(|descriptor_set| {
+                views
+                    .iter()
+                    .zip([1, 5, 6, 7].iter())
+                    .map(move |(view, &binding)| WriteDescriptorSet {
+                        descriptor_set,
+                        binding,
+                        element: 0,
+                        descriptors: Descriptors::StorageImage(view),
+                    })
+            })
+            .collect::<Vec<_>>();
+        render_context.update_descriptor_sets(&writes, &[]);
+    }
+
+    /// Points the history bindings of a descriptor set at the images of `history_index`. The set
+    /// is not in use by the GPU, its frame slot was waited on before drawing.
+    fn bind_history(
+        &mut self,
+        render_context: &RenderContext,
+        frame_index: usize,
+        history_index: usize,
+    ) {
+        if self.descriptor_set_history[frame_index] == Some(history_index) {
+            return;
+        }
+
+        let current = &self.images.history[history_index];
+        let previous = &self.images.history[1 - history_index];
+        let views = [
+            [(current.color_view.clone(), vk::ImageLayout::GENERAL)],
+            [(current.depth_view.clone(), vk::ImageLayout::GENERAL)],
+            [(previous.color_view.clone(), vk::ImageLayout::GENERAL)],
+            [(previous.depth_view.clone(), vk::ImageLayout::GENERAL)],
+        ];
+        let writes = views
+            .iter()
+            .zip(8..)
+            .map(|(view, binding)| WriteDescriptorSet {
+                descriptor_set: &self.descriptor_sets[frame_index],
+                binding,
+                element: 0,
+                descriptors: Descriptors::StorageImage(view),
+            })
+            .collect::<Vec<_>>();
+        render_context.update_descriptor_sets(&writes, &[]);
 
+        self.descriptor_set_history[frame_index] = Some(history_index);
+    }
+
+    /// Points the TLAS, instance data and lights bindings of a descriptor set at the instance
+    /// buffers of its frame. Like [`RayTracingPass::bind_history`], the set is not in use by the
+    /// GPU.
+    fn bind_instance_buffers(&mut self, render_context: &RenderContext, frame_index: usize) {
+        let buffers = &self.instance_buffers[frame_index];
+        if self.descriptor_set_capacity[frame_index] == Some(buffers.capacity) {
+            return;
+        }
+
+        let descriptor_set = &self.descriptor_sets[frame_index];
+        let instance_data = [(
+            buffers.instance_data_buffer.clone(),
+            0,
+            InstanceBuffers::instance_data_size(buffers.capacity),
+        )];
+        let lights = [(
+            buffers.lights_buffer.clone(),
+            0,
+            InstanceBuffers::lights_size(buffers.capacity),
+        )];
         render_context.update_descriptor_sets(
             &[
                 WriteDescriptorSet {
-                    descriptor_set: &descriptor_set,
+                    descriptor_set,
                     binding: 0,
                     element: 0,
-                    descriptors: Descriptors::AccelerationStructure(std::slice::from_ref(&tlas)),
+                    descriptors: Descriptors::AccelerationStructure(std::slice::from_ref(
+                        &buffers.tlas,
+                    )),
                 },
                 WriteDescriptorSet {
-                    descriptor_set: &descriptor_set,
-                    binding: 1,
+                    descriptor_set,
+                    binding: 3,
                     element: 0,
-                    descriptors: Descriptors::StorageImage(&[(
-                        output_image_view.clone(),
-                        vk::ImageLayout::GENERAL,
-                    )]),
+                    descriptors: Descriptors::StorageBuffer(&instance_data),
                 },
                 WriteDescriptorSet {
-                    descriptor_set: &descriptor_set,
-                    binding: 2,
+                    descriptor_set,
+                    binding: 4,
                     element: 0,
-                    descriptors: Descriptors::UniformBuffer(&[(
-                        globals_buffer.clone(),
-                        0,
-                        std::mem::size_of::<Std430Globals>() as _,
-                    )]),
+                    descriptors: Descriptors::StorageBuffer(&lights),
                 },
             ],
             &[],
         );
 
-        RayTracingPass {
-            pipeline_layout,
-            pipeline,
-            shader_binding_table,
-            tlas,
-            scratch_buffer,
-            descriptor_set,
-            globals,
-            globals_buffer,
-            instances_buffer,
-            output_image,
+        self.descriptor_set_capacity[frame_index] = Some(buffers.capacity);
+    }
+
+    /// Shows or hides the instance of `mesh` by clearing its mask, takes effect on the next frame.
+    /// Only the mask of the instance is rewritten, the rest of the instances are left untouched.
+    pub fn set_visibility(&mut self, mesh: GeometryId, visible: bool) {
+        if visible {
+            self.hidden_meshes.remove(&mesh);
+        } else {
+            self.hidden_meshes.insert(mesh);
         }
     }
+
+    /// Places the instance of `mesh` drawn by `entity` with `transform`. A mesh gets one instance
+    /// per entity, meshes without any get a single one at the origin.
+    pub fn set_instance_transform(&mut self, mesh: GeometryId, entity: Entity, transform: Mat4) {
+        self.instance_transforms
+            .entry(mesh)
+            .or_default()
+            .insert(entity, transform);
+        self.invalidate_instances();
+    }
+
+    /// Where the instances of `mesh` are placed, the identity for meshes no entity places.
+    pub fn instance_transforms(&self, mesh: GeometryId) -> Vec<Mat4> {
+        match self.instance_transforms.get(&mesh) {
+            Some(transforms) if !transforms.is_empty() => transforms.values().copied().collect(),
+            _ => vec![Mat4::IDENTITY],
+        }
+    }
+
+    /// Drops the instance drawn by `entity`, once it is despawned or stops drawing its mesh.
+    pub fn remove_instance(&mut self, entity: Entity) {
+        for transforms in self.instance_transforms.values_mut() {
+            if transforms.remove(&entity).is_some() {
+                self.instances_generation += 1;
+            }
+        }
+    }
+
+    /// Changes the custom index, mask and hit group of the instances of `mesh`, takes effect on the
+    /// next frame.
+    pub fn set_instance_config(&mut self, mesh: GeometryId, config: InstanceConfig) {
+        assert!(
+            config.custom_index < 1 << 24,
+            "instance custom index {} doesn't fit in 24 bits",
+            config.custom_index
+        );
+        let hit_records = self
+            .shader_binding_table
+            .hit
+            .as_ref()
+            .map_or(0, |hit| hit.size / hit.stride.unwrap());
+        assert!(
+            (config.sbt_offset as u64) < hit_records,
+            "instance SBT offset {} is out of the {} hit records",
+            config.sbt_offset,
+            hit_records
+        );
+        self.instance_configs.insert(mesh, config);
+        self.invalidate_instances();
+    }
+
+    /// Rebuilds the instances of every frame slot on their next frame. Called for every BLAS added
+    /// or removed, as well as by the setters that change the instances.
+    pub fn invalidate_instances(&mut self) {
+        self.instances_generation += 1;
+    }
+
+    fn instance_config(&self, mesh: GeometryId) -> InstanceConfig {
+        self.instance_configs
+            .get(&mesh)
+            .copied()
+            .unwrap_or_else(|| InstanceConfig::new(mesh))
+    }
+
+    /// Primary rays traced per pixel every frame, spread over the pixel and averaged before the
+    /// temporal blend. Takes effect with the next frame, the globals are written every frame.
+    pub fn set_samples_per_pixel(&mut self, samples_per_pixel: u32) {
+        assert!(
+            samples_per_pixel > 0,
+            "at least one sample per pixel is required"
+        );
+        self.globals.samples_per_pixel = samples_per_pixel;
+    }
+
+    /// Limits the path length. Bounces are traced recursively, so the count is clamped to what the
+    /// pipeline recursion depth leaves after the primary ray.
+    pub fn set_max_bounces(&mut self, max_bounces: u32) {
+        let clamped = clamp_bounces(max_bounces, self.pipeline.max_recursion_depth());
+        if clamped < max_bounces {
+            tracing::warn!(
+                "{} bounces exceed the ray recursion depth, clamping to {}",
+                max_bounces,
+                clamped
+            );
+        }
+        self.globals.max_bounces = clamped;
+    }
+
+    pub fn set_seed(&mut self, seed: u32) {
+        self.globals.seed = seed;
+    }
+
+    /// Discards the accumulated history, the next frame clears it to zero and is drawn without
+    /// reprojection.
+    pub fn reset_accumulation(&mut self) {
+        self.clear_history = true;
+    }
+
+    /// Makes the instance of `mesh` a light emitting `emission`, zero turns it back into a regular
+    /// surface.
+    pub fn set_emission(&mut self, mesh: GeometryId, emission: Vec3) {
+        if emission == Vec3::ZERO {
+            self.emissions.remove(&mesh);
+        } else {
+            self.emissions.insert(mesh, emission);
+        }
+        self.invalidate_instances();
+    }
+
+    /// Writes an instance for every entity drawing a BLAS whose world space bounds intersect
+    /// `frustum` to the buffers of `frame_index`. Culled meshes can't be hit at all, including by
+    /// secondary rays, so lights are never culled. The emissive instances are also collected into
+    /// the light list.
+    fn rebuild_instances(
+        &mut self,
+        render_context: &RenderContext,
+        frame_index: usize,
+        blases: &HashMap<GeometryId, AccelerationStructure>,
+        meshes: &HashMap<GeometryId, MeshBuffers>,
+        frustum: &Frustum,
+    ) {
+        let mut instances = Vec::with_capacity(blases.len());
+        let mut instance_meshes = Vec::with_capacity(blases.len());
+        let mut instance_data = Vec::with_capacity(blases.len());
+        let mut lights = vec![];
+
+        let placements = blases.iter().flat_map(|(&mesh, blas)| {
+            self.instance_transforms(mesh)
+                .into_iter()
+                .map(move |transform| (mesh, blas, transform))
+        });
+
+        for (mesh, blas, transform) in placements {
+            let buffers = meshes.get(&mesh);
+            let world_aabb = buffers
+                .and_then(|buffers| buffers.aabb)
+                .map(|aabb| aabb.transformed(&transform));
+            let emission = self.emissions.get(&mesh).copied();
+            if let (Some(world_aabb), None) = (world_aabb, emission) {
+                if !frustum.intersects_aabb(&world_aabb) {
+                    continue;
+                }
+            }
+
+            if let (Some(emission), Some(buffers)) = (emission, buffers) {
+                lights.push(
+                    Light {
+                        emission: emission.to_mint(),
+                        instance: instances.len() as u32,
+                        triangle_count: buffers.triangle_count(),
+                    }
+                    .as_std430(),
+                );
+            }
+
+            let config = self.instance_config(mesh);
+            let mask = if self.hidden_meshes.contains(&mesh) {
+                0
+            } else {
+                config.mask
+            };
+            let instance = AccelerationStructureInstance::new(blas.device_address())
+                .with_transform(TransformMatrix::from(transform))
+                .with_custom_index(config.custom_index)
+                .with_shader_binding_offset(
+                    config.sbt_offset,
+                    vk::GeometryInstanceFlagsKHR::empty(),
+                )
+                .with_mask(mask);
+
+            instances.push(instance);
+            instance_meshes.push(mesh);
+            instance_data.push(InstanceData::new(&transform, buffers).as_std430());
+        }
+
+        // Grows only: buffers are freed with the device, so shrinking would just allocate more
+        let buffers = &mut self.instance_buffers[frame_index];
+        if let Some(capacity) = grown_instance_capacity(buffers.capacity, instances.len() as u32) {
+            *buffers = InstanceBuffers::new(render_context, capacity, self.tlas_build_flags);
+        }
+
+        render_context.write_buffer(&mut buffers.instances_buffer, 0, &instances);
+        render_context.write_buffer(&mut buffers.instance_data_buffer, 0, &instance_data);
+        render_context.write_buffer(&mut buffers.lights_buffer, 0, &lights);
+        buffers.instance_count = instances.len() as u32;
+        buffers.light_count = lights.len() as u32;
+        buffers.instances = instances;
+        buffers.instance_meshes = instance_meshes;
+        buffers.hidden_meshes = self.hidden_meshes.clone();
+    }
+
+    /// Rewrites the mask of the instances in the buffers of `frame_index` whose visibility changed
+    /// since they were written. Returns whether any did, the TLAS has to be rebuilt then.
+    ///
+    /// Only the buffers of `frame_index` are written, the ones of the other frame slots may still
+    /// be read by their TLAS build and are patched when their slot comes around.
+    fn patch_visibility(&mut self, render_context: &RenderContext, frame_index: usize) -> bool {
+        let buffers = &self.instance_buffers[frame_index];
+        if buffers.hidden_meshes == self.hidden_meshes {
+            return false;
+        }
+
+        let patches = mask_patches(
+            &buffers.instance_meshes,
+            &buffers.hidden_meshes,
+            &self.hidden_meshes,
+            |mesh| self.instance_config(mesh).mask,
+        );
+
+        let buffers = &mut self.instance_buffers[frame_index];
+        for (index, mask) in patches {
+            let instance = &mut buffers.instances[index];
+            instance.set_mask(mask);
+
+            let offset = index * std::mem::size_of::<AccelerationStructureInstance>()
+                + offset_of!(AccelerationStructureInstance, custom_index_mask);
+            render_context.write_buffer(
+                &mut buffers.instances_buffer,
+                offset as _,
+                &[instance.custom_index_mask.0],
+            );
+        }
+
+        buffers.hidden_meshes = self.hidden_meshes.clone();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globals_std430_layout() {
+        assert_eq!(offset_of!(Std430CameraUniform, view), 0);
+        assert_eq!(offset_of!(Std430CameraUniform, proj), 64);
+        assert_eq!(offset_of!(Std430CameraUniform, view_inverse), 128);
+        assert_eq!(offset_of!(Std430CameraUniform, proj_inverse), 192);
+
+        assert_eq!(offset_of!(Std430Globals, camera), 0);
+        assert_eq!(offset_of!(Std430Globals, color), 256);
+        assert_eq!(offset_of!(Std430Globals, previous_view_proj), 272);
+        assert_eq!(offset_of!(Std430Globals, history_valid), 336);
+        assert_eq!(offset_of!(Std430Globals, samples_per_pixel), 340);
+        assert_eq!(offset_of!(Std430Globals, max_bounces), 344);
+        assert_eq!(offset_of!(Std430Globals, seed), 348);
+    }
+
+    #[test]
+    fn each_frame_slot_patches_the_masks_it_wrote() {
+        let meshes = [GeometryId(0), GeometryId(1), GeometryId(0), GeometryId(2)];
+        let mask = |mesh: GeometryId| 0x10 | mesh.0 as u8;
+        let none = HashSet::new();
+        let hidden = [GeometryId(0)].iter().copied().collect::<HashSet<_>>();
+
+        // The slot drawn after hiding mesh 0 clears both of its instances
+        assert_eq!(
+            mask_patches(&meshes, &none, &hidden, mask),
+            vec![(0, 0), (2, 0)]
+        );
+        // Once patched it is up to date, while a slot that was in flight still gets its own patch
+        assert!(mask_patches(&meshes, &hidden, &hidden, mask).is_empty());
+        assert_eq!(
+            mask_patches(&meshes, &none, &hidden, mask),
+            vec![(0, 0), (2, 0)]
+        );
+
+        // Showing it again restores the configured mask
+        let shown_again = [GeometryId(2)].iter().copied().collect::<HashSet<_>>();
+        assert_eq!(
+            mask_patches(&meshes, &hidden, &shown_again, mask),
+            vec![(0, 0x10), (2, 0x10), (3, 0)]
+        );
+    }
+
+    #[test]
+    fn camera_history_carries_the_previous_matrix_over() {
+        let first = Mat4::from_translation(Vec3::X);
+        let second = Mat4::from_translation(Vec3::Y);
+        let mut history = CameraHistory::default();
+
+        assert_eq!(history.advance(first), None);
+        assert_eq!(history.advance(second), Some(first));
+        assert_eq!(history.advance(second), Some(second));
+    }
+
+    #[test]
+    fn normal_matrix_of_a_non_uniform_scale() {
+        let model = Mat4::from_scale(Vec3::new(2.0, 1.0, 0.5));
+        let upper = Mat3::from_cols(
+            model.x_axis.truncate(),
+            model.y_axis.truncate(),
+            model.z_axis.truncate(),
+        );
+
+        let normal = normal_matrix(&model);
+        assert!(!normal.abs_diff_eq(upper, 1e-6));
+        assert!(normal.abs_diff_eq(Mat3::from_diagonal(Vec3::new(0.5, 1.0, 2.0)), 1e-6));
+    }
+
+    #[test]
+    fn normal_matrix_of_a_zero_scale_is_finite() {
+        let model = Mat4::from_scale(Vec3::new(1.0, 0.0, 1.0));
+
+        assert_eq!(normal_matrix(&model), Mat3::IDENTITY);
+    }
+
+    #[test]
+    fn instance_buffers_grow_to_the_next_power_of_two() {
+        // 2048 was the old fixed MAX_INSTANCE_COUNT
+        assert_eq!(grown_instance_capacity(2048, 3000), Some(4096));
+        assert_eq!(
+            grown_instance_capacity(INITIAL_INSTANCE_CAPACITY, 3000),
+            Some(4096)
+        );
+        assert_eq!(grown_instance_capacity(4096, 3000), None);
+        assert_eq!(grown_instance_capacity(64, 64), None);
+        assert_eq!(grown_instance_capacity(64, 65), Some(128));
+    }
+
+    #[test]
+    fn bounces_are_clamped_to_the_recursion_depth() {
+        assert_eq!(clamp_bounces(8, 2), 1);
+        assert_eq!(clamp_bounces(8, 31), 8);
+        assert_eq!(clamp_bounces(30, 31), 30);
+        assert_eq!(clamp_bounces(31, 31), 30);
+        assert_eq!(clamp_bounces(u32::MAX, 1), 0);
+    }
 }
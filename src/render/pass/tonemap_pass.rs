@@ -1,14 +1,14 @@
-use crate::render::pass::Pass;
+use crate::render::pass::{fullscreen_triangle, Pass};
 use crate::render::{
     descriptor::{
         DescriptorSetInfo, DescriptorSetLayoutBinding, DescriptorSetLayoutInfo, DescriptorType,
         Descriptors, WriteDescriptorSet,
     },
     framebuffer::FramebufferInfo,
-    image::{Image, ImageView, ImageViewInfo},
-    pipeline::{GraphicsPipelineInfo, PipelineLayoutInfo, Rasterizer},
+    image::{Image, ImageView, ImageViewInfo, SamplerInfo},
+    pipeline::{GraphicsPipelineInfo, PipelineLayoutInfo, PushConstant, Rasterizer},
     render_context::RenderContext,
-    render_pass::{AttachmentInfo, ClearValue, RenderPassInfo, Subpass},
+    render_pass::{AttachmentInfo, ClearValue, RenderPassInfo, Subpass, SubpassDependency},
     resources::{
         DescriptorSet, Fence, Framebuffer, GraphicsPipeline, PipelineLayout, RenderPass, Sampler,
         Semaphore,
@@ -24,20 +24,64 @@ use smallvec::smallvec;
 pub struct Input {
     pub initial_image: Image,
     pub final_image: Image,
+    pub clear_color: [f32; 4],
 }
 
 pub struct Output;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TonemapOperator {
+    /// Only applies the exposure, values above 1.0 are clipped.
+    Linear = 0,
+    /// Extended Reinhard, `white_point` is the radiance mapped to pure white.
+    Reinhard = 1,
+    /// Filmic ACES fit, rescaled so `white_point` maps to pure white.
+    Aces = 2,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TonemapSettings {
+    pub operator: TonemapOperator,
+    /// Exposure adjustment in stops, the image is scaled by `2^exposure_compensation`.
+    pub exposure_compensation: f32,
+    pub white_point: f32,
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        TonemapSettings {
+            operator: TonemapOperator::Linear,
+            exposure_compensation: 0.0,
+            white_point: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+    exposure: f32,
+    white_point: f32,
+    operator: u32,
+}
+
+unsafe impl bytemuck::Zeroable for PushConstants {}
+unsafe impl bytemuck::Pod for PushConstants {}
+
 pub struct TonemapPass {
     render_pass: RenderPass,
     pipeline_layout: PipelineLayout,
     graphics_pipeline: GraphicsPipeline,
 
-    descriptor_sets: [DescriptorSet; 2],
-    initial_images: [Option<ImageView>; 2],
+    /// One descriptor set per frame in flight, indexed by `frame % len`.
+    descriptor_sets: Vec<DescriptorSet>,
+    initial_images: Vec<Option<ImageView>>,
     sampler: Sampler,
 
     framebuffers: LruCache<Image, Framebuffer>,
+
+    settings: TonemapSettings,
 }
 
 impl Pass<'_> for TonemapPass {
@@ -77,7 +121,7 @@ impl Pass<'_> for TonemapPass {
 
         let mut write_descriptor_sets = vec![];
 
-        let frame_id = (frame % 2) as usize;
+        let frame_id = (frame % self.descriptor_sets.len() as u64) as usize;
         let descriptor_set = &self.descriptor_sets[frame_id];
 
         match &self.initial_images[frame_id] {
@@ -104,13 +148,11 @@ impl Pass<'_> for TonemapPass {
 
         render_context.update_descriptor_sets(&write_descriptor_sets, &[]);
 
+        let clears = [ClearValue::from(input.clear_color)];
         let mut encoder = render_context.queue.create_enconder();
+        encoder.begin_debug_label("Tonemap", [0.2, 0.6, 0.9, 1.0]);
 
-        encoder.begin_render_pass(
-            &self.render_pass,
-            &framebuffer,
-            &[ClearValue::Color(0.5, 0.2, 0.2, 0.0)],
-        );
+        encoder.begin_render_pass(&self.render_pass, &framebuffer, &clears);
 
         encoder.bind_graphics_pipeline(&self.graphics_pipeline);
         encoder.bind_descriptor_sets(
@@ -121,6 +163,18 @@ impl Pass<'_> for TonemapPass {
             &[],
         );
 
+        let push_constants = bump.alloc([PushConstants {
+            exposure: self.settings.exposure_compensation.exp2(),
+            white_point: self.settings.white_point,
+            operator: self.settings.operator as u32,
+        }]);
+        encoder.push_constants(
+            &self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            push_constants,
+        );
+
         encoder.set_viewport(vk::Viewport {
             x: 0.0,
             y: framebuffer.info().extent.height as f32,
@@ -135,10 +189,12 @@ impl Pass<'_> for TonemapPass {
             extent: framebuffer.info().extent,
         });
 
-        encoder.draw(0..3, 0..1);
+        fullscreen_triangle::draw_fullscreen(&mut encoder);
 
         encoder.end_render_pass();
 
+        encoder.end_debug_label();
+
         let command_buffer = encoder.finish(&render_context.device);
 
         render_context
@@ -154,6 +210,7 @@ impl TonemapPass {
         render_context: &RenderContext,
         surface_format: vk::Format,
         extent: vk::Extent2D,
+        frames_in_flight: usize,
     ) -> Self {
         let descriptor_set_layout =
             render_context.create_descriptor_set_layout(DescriptorSetLayoutInfo {
@@ -170,10 +227,7 @@ impl TonemapPass {
                 flags: vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
             });
 
-        let vertex_shader = Shader::new(
-            render_context.create_shader_module(ShaderModuleInfo::new("tonemap.vert.spv")),
-            vk::ShaderStageFlagBits::VERTEX,
-        );
+        let vertex_shader = fullscreen_triangle::vertex_shader(render_context);
 
         let fragment_shader = Shader::new(
             render_context.create_shader_module(ShaderModuleInfo::new("tonemap.frag.spv")),
@@ -183,7 +237,7 @@ impl TonemapPass {
         let render_pass = render_context.create_render_pass(RenderPassInfo {
             attachments: smallvec![AttachmentInfo {
                 format: surface_format,
-                samples: vk::SampleCountFlags::_1,
+                samples: vk::SampleCountFlagBits::_1,
                 load_op: vk::AttachmentLoadOp::CLEAR,
                 store_op: vk::AttachmentStoreOp::STORE,
                 initial_layout: None,
@@ -192,58 +246,83 @@ impl TonemapPass {
             subpasses: smallvec![Subpass {
                 colors: smallvec![0],
                 depth: None,
+                resolves: smallvec![],
             }],
+            dependencies: vec![SubpassDependency::color_attachment_to_present()],
         });
 
         let pipeline_layout = render_context.create_pipeline_layout(PipelineLayoutInfo {
             sets: vec![descriptor_set_layout.clone()],
-            push_constants: vec![],
-        });
-
-        let graphics_pipeline = render_context.create_graphics_pipeline(GraphicsPipelineInfo {
-            vertex_bindings: vec![],
-            vertex_attributes: vec![],
-            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-            vertex_shader: vertex_shader.clone(),
-            rasterizer: Some(Rasterizer {
-                viewport: vk::Viewport {
-                    x: 0.0,
-                    y: 0.0,
-                    width: extent.width as _,
-                    height: extent.height as _,
-                    min_depth: 0.0,
-                    max_depth: 1.0,
-                },
-                depth_clamp: false,
-                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-                cull_mode: vk::CullModeFlags::NONE,
-                polygon_mode: vk::PolygonMode::FILL,
-                fragment_shader: Some(fragment_shader.clone()),
-            }),
-            layout: pipeline_layout.clone(),
-            render_pass: render_pass.clone(),
-            subpass: 0,
+            push_constants: vec![PushConstant {
+                stages: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<PushConstants>() as u32,
+            }],
         });
 
-        let descriptor_sets = [
-            render_context.create_descriptor_set(DescriptorSetInfo {
-                layout: descriptor_set_layout.clone(),
-            }),
-            render_context.create_descriptor_set(DescriptorSetInfo {
-                layout: descriptor_set_layout.clone(),
-            }),
-        ];
-
-        let sampler = render_context.create_sampler();
+        let graphics_pipeline = render_context
+            .create_graphics_pipeline(GraphicsPipelineInfo {
+                vertex_bindings: vec![],
+                vertex_attributes: vec![],
+                primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                vertex_shader: vertex_shader.clone(),
+                rasterizer: Some(Rasterizer {
+                    viewport: vk::Viewport {
+                        x: 0.0,
+                        y: 0.0,
+                        width: extent.width as _,
+                        height: extent.height as _,
+                        min_depth: 0.0,
+                        max_depth: 1.0,
+                    },
+                    depth_clamp: false,
+                    front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                    cull_mode: vk::CullModeFlags::NONE,
+                    polygon_mode: vk::PolygonMode::FILL,
+                    depth_bias: None,
+                    samples: vk::SampleCountFlagBits::_1,
+                    fragment_shader: Some(fragment_shader.clone()),
+                }),
+                layout: pipeline_layout.clone(),
+                render_pass: render_pass.clone(),
+                subpass: 0,
+            })
+            .expect("Failed to create tonemap pipeline");
+
+        let descriptor_sets = (0..frames_in_flight)
+            .map(|_| {
+                render_context.create_descriptor_set(DescriptorSetInfo {
+                    layout: descriptor_set_layout.clone(),
+                })
+            })
+            .collect();
+
+        // The input may not match the target size, clamping to the edge keeps the resampled
+        // borders from fading into the border color. Linear filtering of the 32-bit float path
+        // traced images is optional, devices without it fall back to nearest.
+        let mut sampler_info = SamplerInfo::linear_clamp_to_edge();
+        if !render_context
+            .format_features(vk::Format::R32G32B32A32_SFLOAT)
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+        {
+            sampler_info.filter = vk::Filter::NEAREST;
+            sampler_info.mipmap_mode = vk::SamplerMipmapMode::NEAREST;
+        }
+        let sampler = render_context.create_sampler(sampler_info);
 
         TonemapPass {
             render_pass,
             pipeline_layout,
             graphics_pipeline,
             descriptor_sets,
-            initial_images: [None, None],
+            initial_images: vec![None; frames_in_flight],
             sampler,
             framebuffers: LruCache::new(4),
+            settings: TonemapSettings::default(),
         }
     }
+
+    pub fn set_settings(&mut self, settings: TonemapSettings) {
+        self.settings = settings;
+    }
 }
@@ -1,10 +1,10 @@
-use crate::render::buffer::BufferInfo;
 use crate::render::descriptor::{Descriptors, WriteDescriptorSet};
+use crate::render::dynamic_uploader::DynamicUploader;
 use crate::render::framebuffer::FramebufferInfo;
-use crate::render::image::{ImageInfo, ImageView, ImageViewInfo};
-use crate::render::pipeline::{PushConstant, VertexInputAttribute, VertexInputBinding};
+use crate::render::image::{ImageInfo, ImageView, ImageViewInfo, SamplerInfo};
+use crate::render::pipeline::{vertex_format::VertexFormat, PushConstant, VertexLayoutBuilder};
 use crate::render::render_pass::ClearValue;
-use crate::render::resources::{Buffer, Framebuffer, Sampler};
+use crate::render::resources::{Framebuffer, Sampler};
 use crate::render::vertex::Vertex;
 use crate::render::{
     descriptor::{
@@ -14,13 +14,13 @@ use crate::render::{
     pass::Pass,
     pipeline::{GraphicsPipelineInfo, PipelineLayoutInfo, Rasterizer},
     render_context::RenderContext,
-    render_pass::{AttachmentInfo, RenderPassInfo, Subpass},
+    render_pass::{AttachmentInfo, RenderPassInfo, Subpass, SubpassDependency},
     resources::{DescriptorSet, Fence, GraphicsPipeline, PipelineLayout, RenderPass, Semaphore},
     shader::{Shader, ShaderModuleInfo},
 };
 use bevy::core::AsBytes;
 use bevy::prelude::GlobalTransform;
-use bumpalo::{collections::Vec as BumpVec, Bump};
+use bumpalo::Bump;
 use egui::paint::ClippedShape;
 use egui::{epaint, ClippedMesh, CtxRef, Pos2, RawInput, Rect, TextureId};
 use erupt::vk;
@@ -30,6 +30,7 @@ use std::sync::Arc;
 
 pub struct Input {
     pub target: Image,
+    pub clear_color: [f32; 4],
 }
 
 pub struct Output;
@@ -44,9 +45,8 @@ pub struct UIPass {
 
     framebuffers: LruCache<Image, Framebuffer>,
 
-    descriptor_sets: [DescriptorSet; 2],
-    vertex_buffers: [Buffer; 2],
-    index_buffers: [Buffer; 2],
+    descriptor_sets: Vec<DescriptorSet>,
+    uploader: DynamicUploader,
 
     font_sampler: Sampler,
     font_image: Option<Image>,
@@ -60,6 +60,7 @@ impl UIPass {
         render_context: &RenderContext,
         surface_format: vk::Format,
         extent: vk::Extent2D,
+        frames_in_flight: usize,
     ) -> Self {
         let egui_context = CtxRef::default();
 
@@ -97,7 +98,7 @@ impl UIPass {
         let render_pass = render_context.create_render_pass(RenderPassInfo {
             attachments: smallvec![AttachmentInfo {
                 format: surface_format,
-                samples: vk::SampleCountFlags::_1,
+                samples: vk::SampleCountFlagBits::_1,
                 load_op: vk::AttachmentLoadOp::DONT_CARE,
                 store_op: vk::AttachmentStoreOp::STORE,
                 initial_layout: None,
@@ -106,7 +107,9 @@ impl UIPass {
             subpasses: smallvec![Subpass {
                 colors: smallvec![0],
                 depth: None,
+                resolves: smallvec![],
             }],
+            dependencies: vec![SubpassDependency::color_attachment_to_present()],
         });
 
         let pipeline_layout = render_context.create_pipeline_layout(PipelineLayoutInfo {
@@ -118,101 +121,61 @@ impl UIPass {
             }],
         });
 
-        let graphics_pipeline = render_context.create_graphics_pipeline(GraphicsPipelineInfo {
-            vertex_bindings: vec![VertexInputBinding {
-                input_rate: vk::VertexInputRate::VERTEX,
-                // epaint::Vertex stride
-                stride: 5 * std::mem::size_of::<f32>() as u32,
-            }],
-            vertex_attributes: vec![
-                // position
-                VertexInputAttribute {
-                    location: 0,
-                    format: vk::Format::R32G32_SFLOAT,
-                    binding: 0,
-                    offset: 0,
-                },
-                // uv
-                VertexInputAttribute {
-                    location: 1,
-                    format: vk::Format::R32G32_SFLOAT,
-                    binding: 0,
-                    offset: 8,
-                },
-                // color
-                VertexInputAttribute {
-                    location: 2,
-                    format: vk::Format::R8G8B8A8_UNORM,
-                    binding: 0,
-                    offset: 16,
-                },
-            ],
-            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-            vertex_shader: vertex_shader.clone(),
-            rasterizer: Some(Rasterizer {
-                viewport: vk::Viewport {
-                    x: 0.0,
-                    y: 0.0,
-                    width: extent.width as _,
-                    height: extent.height as _,
-                    min_depth: 0.0,
-                    max_depth: 1.0,
-                },
-                depth_clamp: false,
-                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-                cull_mode: vk::CullModeFlags::NONE,
-                polygon_mode: vk::PolygonMode::FILL,
-                fragment_shader: Some(fragment_shader.clone()),
-            }),
-            layout: pipeline_layout.clone(),
-            render_pass: render_pass.clone(),
-            subpass: 0,
-        });
+        // epaint::Vertex: position, uv and color
+        let (vertex_bindings, vertex_attributes) = VertexLayoutBuilder::default()
+            .binding(vk::VertexInputRate::VERTEX)
+            .attributes(&[
+                VertexFormat::Float32x2,
+                VertexFormat::Float32x2,
+                VertexFormat::Unorm8x4,
+            ])
+            .build();
+
+        let graphics_pipeline = render_context
+            .create_graphics_pipeline(GraphicsPipelineInfo {
+                vertex_bindings,
+                vertex_attributes,
+                primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                vertex_shader: vertex_shader.clone(),
+                rasterizer: Some(Rasterizer {
+                    viewport: vk::Viewport {
+                        x: 0.0,
+                        y: 0.0,
+                        width: extent.width as _,
+                        height: extent.height as _,
+                        min_depth: 0.0,
+                        max_depth: 1.0,
+                    },
+                    depth_clamp: false,
+                    front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                    cull_mode: vk::CullModeFlags::NONE,
+                    polygon_mode: vk::PolygonMode::FILL,
+                    depth_bias: None,
+                    samples: vk::SampleCountFlagBits::_1,
+                    fragment_shader: Some(fragment_shader.clone()),
+                }),
+                layout: pipeline_layout.clone(),
+                render_pass: render_pass.clone(),
+                subpass: 0,
+            })
+            .expect("Failed to create UI pipeline");
+
+        let descriptor_sets = (0..frames_in_flight)
+            .map(|_| {
+                render_context.create_descriptor_set(DescriptorSetInfo {
+                    layout: descriptor_set_layout.clone(),
+                })
+            })
+            .collect();
+
+        let uploader = DynamicUploader::new(
+            render_context,
+            Self::upload_buffer_size(),
+            frames_in_flight as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER,
+        );
 
-        let descriptor_sets = [
-            render_context.create_descriptor_set(DescriptorSetInfo {
-                layout: descriptor_set_layout.clone(),
-            }),
-            render_context.create_descriptor_set(DescriptorSetInfo {
-                layout: descriptor_set_layout.clone(),
-            }),
-        ];
-
-        let vertex_buffers = [
-            render_context.create_buffer(BufferInfo {
-                align: 255,
-                size: Self::vertex_buffer_size(),
-                usage_flags: vk::BufferUsageFlags::VERTEX_BUFFER
-                    | vk::BufferUsageFlags::TRANSFER_DST,
-                allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS,
-            }),
-            render_context.create_buffer(BufferInfo {
-                align: 255,
-                size: Self::vertex_buffer_size(),
-                usage_flags: vk::BufferUsageFlags::VERTEX_BUFFER
-                    | vk::BufferUsageFlags::TRANSFER_DST,
-                allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS,
-            }),
-        ];
-
-        let index_buffers = [
-            render_context.create_buffer(BufferInfo {
-                align: 255,
-                size: Self::index_buffer_size(),
-                usage_flags: vk::BufferUsageFlags::INDEX_BUFFER
-                    | vk::BufferUsageFlags::TRANSFER_DST,
-                allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS,
-            }),
-            render_context.create_buffer(BufferInfo {
-                align: 255,
-                size: Self::index_buffer_size(),
-                usage_flags: vk::BufferUsageFlags::INDEX_BUFFER
-                    | vk::BufferUsageFlags::TRANSFER_DST,
-                allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS,
-            }),
-        ];
-
-        let sampler = render_context.create_sampler();
+        let sampler = render_context.create_sampler(SamplerInfo::default());
 
         UIPass {
             egui_context,
@@ -222,8 +185,7 @@ impl UIPass {
             graphics_pipeline,
             framebuffers: LruCache::new(4),
             descriptor_sets,
-            vertex_buffers,
-            index_buffers,
+            uploader,
             font_sampler: sampler,
             font_image: None,
             clipped_meshes: vec![],
@@ -231,12 +193,9 @@ impl UIPass {
         }
     }
 
-    fn vertex_buffer_size() -> u64 {
-        1024 * 1024 * 4
-    }
-
-    fn index_buffer_size() -> u64 {
-        1024 * 1024 * 2
+    /// Bytes of vertex and index data the UI can upload each frame.
+    fn upload_buffer_size() -> u64 {
+        1024 * 1024 * 6
     }
 
     pub fn context(&self) -> CtxRef {
@@ -299,6 +258,8 @@ impl UIPass {
                 array_layers: 1,
                 samples: vk::SampleCountFlagBits::_1,
                 usage: vk::ImageUsageFlags::SAMPLED,
+                flags: vk::ImageCreateFlags::empty(),
+                name: Some("egui font texture"),
             },
             vk::ImageLayout::GENERAL,
             image_data,
@@ -342,33 +303,19 @@ impl Pass<'_> for UIPass {
             Some(framebuffer) => framebuffer.clone(),
         };
 
-        let frame_id = (frame % 2) as usize;
+        let frame_id = (frame % self.descriptor_sets.len() as u64) as usize;
 
         self.update_set(render_context, frame_id);
 
+        let clears = [ClearValue::from(input.clear_color)];
         let mut encoder = render_context.queue.create_enconder();
+        encoder.begin_debug_label("UI", [0.6, 0.6, 0.6, 1.0]);
 
-        encoder.begin_render_pass(
-            &self.render_pass,
-            &framebuffer,
-            &[ClearValue::Color(0.5, 0.2, 0.2, 0.0)],
-        );
+        encoder.begin_render_pass(&self.render_pass, &framebuffer, &clears);
 
         encoder.bind_graphics_pipeline(&self.graphics_pipeline);
 
-        let mut to_bind = BumpVec::with_capacity_in(self.vertex_buffers.len(), bump);
-        let mut vertex_buffer = self.vertex_buffers[frame_id].clone();
-
-        to_bind.push((self.vertex_buffers[frame_id].clone(), 0));
-
-        encoder.bind_vertex_buffers(0, to_bind.into_bump_slice());
-
-        let mut index_buffer = self.index_buffers[frame_id].clone();
-        encoder.bind_index_buffer(
-            bump.alloc(self.index_buffers[frame_id].clone()),
-            0,
-            vk::IndexType::UINT32,
-        );
+        self.uploader.begin_frame(frame);
 
         encoder.set_viewport(vk::Viewport {
             x: 0.0,
@@ -390,10 +337,6 @@ impl Pass<'_> for UIPass {
         );
 
         // render meshes
-        let mut vertex_base = 0;
-        let mut index_base = 0;
-        let mut vertex_offset = 0;
-        let mut index_offset = 0;
         for ClippedMesh(rect, mesh) in &self.clipped_meshes {
             if let TextureId::User(id) = mesh.texture_id {
                 unimplemented!()
@@ -421,19 +364,26 @@ impl Pass<'_> for UIPass {
                 })
                 .collect::<Vec<Vertex>>();
 
-            render_context.write_buffer(&mut vertex_buffer, vertex_offset as _, &vertices);
-            render_context.write_buffer(&mut index_buffer, index_offset as _, &mesh.indices);
-
-            let v_slice = &mesh.vertices;
-            let v_size = std::mem::size_of_val(&[v_slice[0]]);
-            let v_copy_size = v_slice.len() * v_size;
-
-            let i_slice = &mesh.indices;
-            let i_size = std::mem::size_of_val(&i_slice[0]);
-            let i_copy_size = i_slice.len() * i_size;
-
-            vertex_offset += v_copy_size;
-            index_offset += i_copy_size;
+            let (vertex_region, index_region) = match (
+                self.uploader.push(&vertices),
+                self.uploader.push(&mesh.indices),
+            ) {
+                (Some(vertex_region), Some(index_region)) => (vertex_region, index_region),
+                _ => {
+                    tracing::warn!("UI upload buffer is full, skipping the remaining meshes");
+                    break;
+                }
+            };
+
+            encoder.bind_vertex_buffers(
+                0,
+                bump.alloc([(vertex_region.buffer.clone(), vertex_region.offset)]),
+            );
+            encoder.bind_index_buffer(
+                bump.alloc(index_region.buffer),
+                index_region.offset,
+                vk::IndexType::UINT32,
+            );
 
             encoder.set_scissor(vk::Rect2D {
                 offset: vk::Offset2D {
@@ -446,18 +396,13 @@ impl Pass<'_> for UIPass {
                 },
             });
 
-            encoder.draw_indexed(
-                index_base..index_base + mesh.indices.len() as u32,
-                vertex_base as i32,
-                0..1,
-            );
-
-            vertex_base += mesh.vertices.len() as u32;
-            index_base += mesh.indices.len() as u32;
+            encoder.draw_indexed(0..mesh.indices.len() as u32, 0, 0..1);
         }
 
         encoder.end_render_pass();
 
+        encoder.end_debug_label();
+
         let command_buffer = encoder.finish(&render_context.device);
 
         render_context
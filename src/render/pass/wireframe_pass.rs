@@ -0,0 +1,330 @@
+use crate::render::mesh::{GeometryId, MeshBuffers};
+use crate::render::{
+    framebuffer::FramebufferInfo,
+    image::{Image, ImageInfo, ImageViewInfo},
+    pass::{camera_matrices, Pass},
+    pipeline::{
+        vertex_format::VertexFormat, DepthBias, GraphicsPipelineInfo, PipelineLayoutInfo,
+        PushConstant, Rasterizer, VertexLayoutBuilder,
+    },
+    render_context::RenderContext,
+    render_pass::{AttachmentInfo, ClearValue, RenderPassInfo, Subpass, SubpassDependency},
+    resources::{Fence, Framebuffer, GraphicsPipeline, PipelineLayout, RenderPass, Semaphore},
+    shader::{Shader, ShaderModuleInfo},
+};
+use bevy::prelude::GlobalTransform;
+use bumpalo::Bump;
+use erupt::vk;
+use glam::{Mat4, Vec4};
+use lru::LruCache;
+use smallvec::smallvec;
+use std::collections::HashMap;
+
+pub struct Input<'a> {
+    pub target: Image,
+    pub meshes: &'a HashMap<GeometryId, MeshBuffers>,
+    /// The world transform of each instance of a mesh, as placed in the TLAS.
+    pub instances: &'a HashMap<GeometryId, Vec<Mat4>>,
+    pub edge_color: Vec4,
+}
+
+pub struct Output;
+
+/// Draws the triangle edges of every mesh on top of the shaded image.
+///
+/// The path traced image has no depth, so the meshes are first rasterized into a depth buffer
+/// with a fully transparent color, then the edges are drawn with a depth bias so only the visible
+/// ones pass the depth test.
+pub struct WireframePass {
+    render_pass: RenderPass,
+    pipeline_layout: PipelineLayout,
+    vertex_shader: Shader,
+    fragment_shader: Shader,
+    extent: vk::Extent2D,
+    /// The depth and wireframe pipelines of each position format, created the first time a mesh
+    /// with that format is drawn.
+    pipelines: HashMap<VertexFormat, [GraphicsPipeline; 2]>,
+
+    framebuffers: LruCache<Image, Framebuffer>,
+
+    depth_image: Image,
+}
+
+impl<'a> Pass<'a> for WireframePass {
+    type Input = Input<'a>;
+    type Output = Output;
+
+    fn draw(
+        &mut self,
+        input: Self::Input,
+        _frame: u64,
+        wait: &[(vk::PipelineStageFlags, Semaphore)],
+        signal: &[Semaphore],
+        fence: Option<&Fence>,
+        render_context: &mut RenderContext,
+        bump: &Bump,
+        camera: &GlobalTransform,
+    ) -> Self::Output {
+        let framebuffer = match self.framebuffers.get(&input.target) {
+            None => {
+                let color_view = render_context.create_image_view(ImageViewInfo::new(
+                    input.target.clone(),
+                    vk::ImageAspectFlags::COLOR,
+                ));
+
+                let depth_view = render_context.create_image_view(ImageViewInfo::new(
+                    self.depth_image.clone(),
+                    vk::ImageAspectFlags::DEPTH,
+                ));
+
+                let framebuffer = render_context.create_framebuffer(FramebufferInfo {
+                    render_pass: self.render_pass.clone(),
+                    views: smallvec![color_view, depth_view],
+                    extent: input.target.info().extent,
+                });
+
+                self.framebuffers
+                    .put(input.target.clone(), framebuffer.clone());
+                framebuffer
+            }
+            Some(framebuffer) => framebuffer.clone(),
+        };
+
+        // Sorted by format so each pipeline is bound once per pass
+        let mut meshes = input.meshes.iter().collect::<Vec<_>>();
+        meshes.sort_by_key(|(_, buffers)| buffers.vertex_format as u32);
+        for (_, buffers) in &meshes {
+            if !self.pipelines.contains_key(&buffers.vertex_format) {
+                let pipelines = self.create_pipelines(render_context, buffers.vertex_format);
+                self.pipelines.insert(buffers.vertex_format, pipelines);
+            }
+        }
+
+        let (view, proj) = camera_matrices(camera, input.target.info().extent);
+        let view_proj = proj * view;
+
+        let mut encoder = render_context.queue.create_enconder();
+        encoder.begin_debug_label("Wireframe", [0.2, 0.8, 0.3, 1.0]);
+
+        encoder.begin_render_pass(
+            &self.render_pass,
+            &framebuffer,
+            &[
+                ClearValue::Color(0.0, 0.0, 0.0, 0.0),
+                ClearValue::DepthStencil(1.0, 0),
+            ],
+        );
+
+        encoder.set_viewport(vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: framebuffer.info().extent.width as f32,
+            height: framebuffer.info().extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        });
+
+        encoder.set_scissor(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: framebuffer.info().extent,
+        });
+
+        // The depth pre-pass outputs transparent black, which the premultiplied blend leaves
+        // untouched
+        for (pass, &color) in [Vec4::ZERO, input.edge_color].iter().enumerate() {
+            let mut bound_format = None;
+            for (mesh, buffers) in &meshes {
+                if bound_format != Some(buffers.vertex_format) {
+                    encoder.bind_graphics_pipeline(&self.pipelines[&buffers.vertex_format][pass]);
+                    bound_format = Some(buffers.vertex_format);
+                }
+
+                let vertex_buffer = &buffers.vertex_buffer;
+                encoder.bind_vertex_buffers(
+                    0,
+                    bump.alloc([(vertex_buffer.buffer.clone(), vertex_buffer.offset)]),
+                );
+                if let Some(index_buffer) = &buffers.index_buffer {
+                    encoder.bind_index_buffer(
+                        &index_buffer.buffer,
+                        index_buffer.offset,
+                        buffers.index_type,
+                    );
+                }
+
+                // One draw per instance, at the same place as its TLAS instance
+                let transforms = input.instances.get(mesh).map_or(&[][..], Vec::as_slice);
+                for transform in transforms {
+                    encoder.push_constants(
+                        &self.pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        &*bump.alloc(Self::push_constants(view_proj * *transform, color)),
+                    );
+                    if buffers.index_buffer.is_some() {
+                        encoder.draw_indexed(0..buffers.index_count, 0, 0..1);
+                    } else {
+                        encoder.draw(0..buffers.vertex_count, 0..1);
+                    }
+                }
+            }
+        }
+
+        encoder.end_render_pass();
+
+        encoder.end_debug_label();
+
+        let command_buffer = encoder.finish(&render_context.device);
+
+        render_context
+            .queue
+            .submit(command_buffer, wait, signal, fence);
+
+        Output
+    }
+}
+
+impl WireframePass {
+    pub fn new(
+        render_context: &RenderContext,
+        surface_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Self {
+        let vertex_shader = Shader::new(
+            render_context.create_shader_module(ShaderModuleInfo::new("wireframe.vert.spv")),
+            vk::ShaderStageFlagBits::VERTEX,
+        );
+
+        let fragment_shader = Shader::new(
+            render_context.create_shader_module(ShaderModuleInfo::new("wireframe.frag.spv")),
+            vk::ShaderStageFlagBits::FRAGMENT,
+        );
+
+        let depth_image = render_context.create_image(ImageInfo {
+            extent,
+            format: vk::Format::D32_SFLOAT,
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlagBits::_1,
+            usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            flags: vk::ImageCreateFlags::empty(),
+            name: Some("wireframe depth"),
+        });
+
+        let render_pass = render_context.create_render_pass(RenderPassInfo {
+            attachments: smallvec![
+                AttachmentInfo {
+                    format: surface_format,
+                    samples: vk::SampleCountFlagBits::_1,
+                    load_op: vk::AttachmentLoadOp::LOAD,
+                    store_op: vk::AttachmentStoreOp::STORE,
+                    initial_layout: Some(vk::ImageLayout::PRESENT_SRC_KHR),
+                    final_layout: vk::ImageLayout::PRESENT_SRC_KHR
+                },
+                AttachmentInfo {
+                    format: vk::Format::D32_SFLOAT,
+                    samples: vk::SampleCountFlagBits::_1,
+                    load_op: vk::AttachmentLoadOp::CLEAR,
+                    store_op: vk::AttachmentStoreOp::DONT_CARE,
+                    initial_layout: None,
+                    final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+                },
+            ],
+            subpasses: smallvec![Subpass {
+                colors: smallvec![0],
+                depth: Some(1),
+                resolves: smallvec![],
+            }],
+            // The target is loaded, so the tonemap pass has to be done writing it first
+            dependencies: vec![
+                SubpassDependency::external_to_color_attachment(),
+                SubpassDependency::color_attachment_to_present(),
+            ],
+        });
+
+        let pipeline_layout = render_context.create_pipeline_layout(PipelineLayoutInfo {
+            sets: vec![],
+            push_constants: vec![PushConstant {
+                stages: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<[f32; 20]>() as u32,
+            }],
+        });
+
+        WireframePass {
+            render_pass,
+            pipeline_layout,
+            vertex_shader,
+            fragment_shader,
+            extent,
+            pipelines: HashMap::new(),
+            framebuffers: LruCache::new(4),
+            depth_image,
+        }
+    }
+
+    /// The depth and wireframe pipelines reading positions in `vertex_format`. The vertex shader
+    /// takes a `vec3`, missing components read as zero and normalized ones are converted to floats.
+    fn create_pipelines(
+        &self,
+        render_context: &RenderContext,
+        vertex_format: VertexFormat,
+    ) -> [GraphicsPipeline; 2] {
+        // Only the positions are read
+        let (vertex_bindings, vertex_attributes) = VertexLayoutBuilder::default()
+            .binding(vk::VertexInputRate::VERTEX)
+            .attribute(vertex_format)
+            .build();
+
+        let create_pipeline = |polygon_mode, depth_bias| {
+            render_context
+                .create_graphics_pipeline(GraphicsPipelineInfo {
+                    vertex_bindings: vertex_bindings.clone(),
+                    vertex_attributes: vertex_attributes.clone(),
+                    primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                    vertex_shader: self.vertex_shader.clone(),
+                    rasterizer: Some(Rasterizer {
+                        viewport: vk::Viewport {
+                            x: 0.0,
+                            y: 0.0,
+                            width: self.extent.width as _,
+                            height: self.extent.height as _,
+                            min_depth: 0.0,
+                            max_depth: 1.0,
+                        },
+                        depth_clamp: false,
+                        front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                        cull_mode: vk::CullModeFlags::NONE,
+                        polygon_mode,
+                        depth_bias,
+                        samples: vk::SampleCountFlagBits::_1,
+                        fragment_shader: Some(self.fragment_shader.clone()),
+                    }),
+                    layout: self.pipeline_layout.clone(),
+                    render_pass: self.render_pass.clone(),
+                    subpass: 0,
+                })
+                .expect("Failed to create wireframe pipeline")
+        };
+
+        let depth_pipeline = create_pipeline(vk::PolygonMode::FILL, None);
+        // Pulls the edges towards the camera so they win the depth test against their own faces
+        let wireframe_pipeline = create_pipeline(
+            vk::PolygonMode::LINE,
+            Some(DepthBias {
+                constant_factor: -1.0,
+                clamp: 0.0,
+                slope_factor: -1.0,
+            }),
+        );
+
+        [depth_pipeline, wireframe_pipeline]
+    }
+
+    fn push_constants(model_view_proj: Mat4, color: Vec4) -> [f32; 20] {
+        let mut push_constants = [0.0; 20];
+        push_constants[..16].copy_from_slice(&model_view_proj.to_cols_array());
+        push_constants[16..].copy_from_slice(&<[f32; 4]>::from(color));
+        push_constants
+    }
+}
@@ -9,8 +9,81 @@ pub struct PhysicalDevice {
     handle: vk::PhysicalDevice,
 }
 
+/// Which of the supported devices [`PhysicalDevice::select_one`] picks.
+#[derive(Clone, Debug)]
+pub enum DeviceSelection {
+    /// A discrete GPU if there is one, then integrated, virtual and CPU devices.
+    Preferred,
+    /// The device at this position in [`PhysicalDevice::enumerate`].
+    Index(usize),
+    /// The first device whose name contains this string.
+    Name(String),
+}
+
+impl DeviceSelection {
+    fn pick(&self, devices: Vec<PhysicalDevice>) -> Option<PhysicalDevice> {
+        match self {
+            DeviceSelection::Preferred => devices
+                .into_iter()
+                .min_by_key(|device| type_preference(device.info.device_type)),
+            DeviceSelection::Index(index) => devices.into_iter().nth(*index),
+            DeviceSelection::Name(name) => devices
+                .into_iter()
+                .find(|device| device.info.name.contains(name.as_str())),
+        }
+    }
+}
+
+impl Default for DeviceSelection {
+    fn default() -> Self {
+        DeviceSelection::Preferred
+    }
+}
+
+/// Optional features the device supports, only these are enabled by
+/// [`PhysicalDevice::create_device`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceCapabilities {
+    pub ray_tracing_pipeline: bool,
+    /// `rayQueryEXT` from any shader stage, needs `VK_KHR_ray_query`.
+    pub ray_query: bool,
+    pub acceleration_structure: bool,
+    pub buffer_device_address: bool,
+    /// Runtime sized descriptor arrays.
+    pub descriptor_indexing: bool,
+    /// 16-bit types in storage buffers.
+    pub storage_16bit: bool,
+    /// `clockARB` subgroup timers, needs `VK_KHR_shader_clock`.
+    pub shader_clock: bool,
+    /// Resetting queries from the host with `vkResetQueryPool`.
+    pub host_query_reset: bool,
+    /// Semaphores with a counter, used for frame sync when
+    /// [`RenderSettings::timeline_frame_sync`] is set.
+    ///
+    /// [`RenderSettings::timeline_frame_sync`]: crate::render::renderer::RenderSettings::timeline_frame_sync
+    pub timeline_semaphore: bool,
+    /// More than one draw per indirect draw command.
+    pub multi_draw_indirect: bool,
+}
+
+impl DeviceCapabilities {
+    /// The extensions of the optional features that are supported, on top of the required ones.
+    fn optional_extensions(&self) -> Vec<*const i8> {
+        let mut extensions = Vec::new();
+        if self.ray_query {
+            extensions.push(vk::KHR_RAY_QUERY_EXTENSION_NAME);
+        }
+        if self.shader_clock {
+            extensions.push(vk::KHR_SHADER_CLOCK_EXTENSION_NAME);
+        }
+        extensions
+    }
+}
+
 #[derive(Clone)]
 pub struct PhysicalDeviceInfo {
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
     pub queue_index: u32,
     pub surface_format: vk::SurfaceFormatKHR,
     pub present_mode: vk::PresentModeKHR,
@@ -18,17 +91,20 @@ pub struct PhysicalDeviceInfo {
     pub surface_capabilities: vk::SurfaceCapabilitiesKHR,
     pub raytracing_properties: vk::PhysicalDeviceRayTracingPipelinePropertiesKHR,
     pub accel_properties: vk::PhysicalDeviceAccelerationStructurePropertiesKHR,
+    pub capabilities: DeviceCapabilities,
 }
 
 unsafe impl Send for PhysicalDeviceInfo {}
 unsafe impl Sync for PhysicalDeviceInfo {}
 
 impl PhysicalDevice {
-    pub fn select_one(
+    /// Every device that can present to `surface` and supports `device_extensions`, in the order
+    /// the driver reports them.
+    pub fn enumerate(
         instance: &InstanceLoader,
         surface: &Surface,
         device_extensions: &[*const i8],
-    ) -> Self {
+    ) -> Vec<Self> {
         let devices = unsafe { instance.enumerate_physical_devices(None).unwrap() };
 
         devices
@@ -47,8 +123,36 @@ impl PhysicalDevice {
                     }),
                 }
             })
-            .next()
-            .unwrap_or_else(|| panic!("No supported devices found"))
+            .collect()
+    }
+
+    pub fn select_one(
+        instance: &InstanceLoader,
+        surface: &Surface,
+        device_extensions: &[*const i8],
+        selection: &DeviceSelection,
+    ) -> Self {
+        let devices = PhysicalDevice::enumerate(instance, surface, device_extensions);
+        for (index, device) in devices.iter().enumerate() {
+            tracing::debug!(
+                "supported device {}: {} ({:?})",
+                index,
+                device.info.name,
+                device.info.device_type
+            );
+        }
+
+        let device = selection
+            .pick(devices)
+            .unwrap_or_else(|| panic!("No supported devices found for {:?}", selection));
+
+        tracing::info!(
+            "selected {} ({:?})",
+            device.info.name,
+            device.info.device_type
+        );
+
+        device
     }
 
     fn supports_requirements(
@@ -141,6 +245,9 @@ impl PhysicalDevice {
             instance.get_physical_device_properties2(physical_device, Some(*properties2))
         };
         let device_properties = device_properties2.properties;
+        let name = unsafe { CStr::from_ptr(device_properties.device_name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
 
         let surface_capabilities = unsafe {
             instance
@@ -148,7 +255,12 @@ impl PhysicalDevice {
                 .unwrap()
         };
 
+        let capabilities = query_capabilities(instance, physical_device);
+
         Some(PhysicalDeviceInfo {
+            capabilities,
+            name,
+            device_type: device_properties.device_type,
             queue_index: queue_family,
             surface_format,
             present_mode,
@@ -163,6 +275,32 @@ impl PhysicalDevice {
         &self.info
     }
 
+    /// Queries the surface capabilities again, the current extent changes with the window size.
+    pub fn update_surface_capabilities(&mut self, instance: &InstanceLoader, surface: &Surface) {
+        self.info.surface_capabilities = unsafe {
+            instance
+                .get_physical_device_surface_capabilities_khr(self.handle, surface.handle())
+                .unwrap()
+        };
+    }
+
+    /// Whether the surface has no area to present to, as happens while the window is minimized.
+    pub fn is_surface_empty(&self) -> bool {
+        let extent = self.info.surface_capabilities.current_extent;
+        extent.width == 0 || extent.height == 0
+    }
+
+    /// Whether the device supports `extension`, for extensions that are only enabled when present.
+    pub fn supports_extension(&self, instance: &InstanceLoader, extension: *const i8) -> bool {
+        supports_extension(instance, self.handle, extension)
+    }
+
+    /// Queries the optional features of the device. Also available without a query as
+    /// `info().capabilities`, filled in when the device was enumerated.
+    pub fn query_capabilities(&self, instance: &InstanceLoader) -> DeviceCapabilities {
+        query_capabilities(instance, self.handle)
+    }
+
     pub fn handle(&self) -> vk::PhysicalDevice {
         self.handle
     }
@@ -175,7 +313,13 @@ impl PhysicalDevice {
         let queue_info = [vk::DeviceQueueCreateInfoBuilder::new()
             .queue_family_index(self.info.queue_index)
             .queue_priorities(&[1.0])];
-        let features = vk::PhysicalDeviceFeaturesBuilder::new();
+        // Enabling an unsupported feature fails device creation on some drivers
+        let capabilities = self.info.capabilities;
+
+        // Line polygon mode is used by the wireframe overlay
+        let features = vk::PhysicalDeviceFeaturesBuilder::new()
+            .fill_mode_non_solid(true)
+            .multi_draw_indirect(capabilities.multi_draw_indirect);
 
         let mut device_layers = Vec::new();
 
@@ -183,20 +327,49 @@ impl PhysicalDevice {
             device_layers.push(VALIDATION_LAYER)
         }
 
+        for (name, supported) in [
+            ("ray tracing pipeline", capabilities.ray_tracing_pipeline),
+            (
+                "acceleration structure",
+                capabilities.acceleration_structure,
+            ),
+            ("buffer device address", capabilities.buffer_device_address),
+            ("descriptor indexing", capabilities.descriptor_indexing),
+        ]
+        .iter()
+        {
+            if !supported {
+                tracing::warn!("{} isn't supported by {}", name, self.info.name);
+            }
+        }
+
+        let mut device_extensions = device_extensions.to_vec();
+        device_extensions.extend(capabilities.optional_extensions());
+
         let mut buffer_device_address_features =
-            vk::PhysicalDeviceBufferDeviceAddressFeaturesBuilder::new().buffer_device_address(true);
+            vk::PhysicalDeviceBufferDeviceAddressFeaturesBuilder::new()
+                .buffer_device_address(capabilities.buffer_device_address);
         let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeaturesBuilder::new()
-            .runtime_descriptor_array(true);
-        let mut reset_query_features =
-            vk::PhysicalDeviceHostQueryResetFeaturesBuilder::new().host_query_reset(true);
+            .runtime_descriptor_array(capabilities.descriptor_indexing);
+        let mut reset_query_features = vk::PhysicalDeviceHostQueryResetFeaturesBuilder::new()
+            .host_query_reset(capabilities.host_query_reset);
         let mut acceleration_structure_features =
             vk::PhysicalDeviceAccelerationStructureFeaturesKHRBuilder::new()
-                .acceleration_structure(true);
+                .acceleration_structure(capabilities.acceleration_structure);
         let mut ray_tracing_features =
             vk::PhysicalDeviceRayTracingPipelineFeaturesKHRBuilder::new()
-                .ray_tracing_pipeline(true);
+                .ray_tracing_pipeline(capabilities.ray_tracing_pipeline);
+        let mut timeline_semaphore_features =
+            vk::PhysicalDeviceTimelineSemaphoreFeaturesBuilder::new()
+                .timeline_semaphore(capabilities.timeline_semaphore);
+        let mut storage_16bit_features = vk::PhysicalDevice16BitStorageFeaturesBuilder::new()
+            .storage_buffer16_bit_access(capabilities.storage_16bit);
+        let mut ray_query_features =
+            vk::PhysicalDeviceRayQueryFeaturesKHRBuilder::new().ray_query(capabilities.ray_query);
+        let mut shader_clock_features = vk::PhysicalDeviceShaderClockFeaturesKHRBuilder::new()
+            .shader_subgroup_clock(capabilities.shader_clock);
 
-        let device_info = vk::DeviceCreateInfoBuilder::new()
+        let mut device_info = vk::DeviceCreateInfoBuilder::new()
             .queue_create_infos(&queue_info)
             .enabled_features(&features)
             .enabled_extension_names(&device_extensions)
@@ -205,7 +378,16 @@ impl PhysicalDevice {
             .extend_from(&mut indexing_features)
             .extend_from(&mut reset_query_features)
             .extend_from(&mut acceleration_structure_features)
-            .extend_from(&mut ray_tracing_features);
+            .extend_from(&mut ray_tracing_features)
+            .extend_from(&mut timeline_semaphore_features)
+            .extend_from(&mut storage_16bit_features);
+        // Extension feature structs are only valid when their extension is enabled
+        if capabilities.ray_query {
+            device_info = device_info.extend_from(&mut ray_query_features);
+        }
+        if capabilities.shader_clock {
+            device_info = device_info.extend_from(&mut shader_clock_features);
+        }
 
         let device =
             unsafe { DeviceLoader::new(&instance, self.handle, &device_info, None).unwrap() };
@@ -217,3 +399,171 @@ impl PhysicalDevice {
         (device, queue)
     }
 }
+
+/// Lower is preferred, ties keep the driver's order.
+fn type_preference(device_type: vk::PhysicalDeviceType) -> u32 {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 3,
+        _ => 4,
+    }
+}
+
+fn supports_extension(
+    instance: &InstanceLoader,
+    physical_device: vk::PhysicalDevice,
+    extension: *const i8,
+) -> bool {
+    let extension = unsafe { CStr::from_ptr(extension) };
+    let supported_extensions = unsafe {
+        instance
+            .enumerate_device_extension_properties(physical_device, None, None)
+            .unwrap()
+    };
+
+    supported_extensions.iter().any(|properties| unsafe {
+        CStr::from_ptr(properties.extension_name.as_ptr()) == extension
+    })
+}
+
+fn query_capabilities(
+    instance: &InstanceLoader,
+    physical_device: vk::PhysicalDevice,
+) -> DeviceCapabilities {
+    let ray_query_extension =
+        supports_extension(instance, physical_device, vk::KHR_RAY_QUERY_EXTENSION_NAME);
+    let shader_clock_extension = supports_extension(
+        instance,
+        physical_device,
+        vk::KHR_SHADER_CLOCK_EXTENSION_NAME,
+    );
+
+    let mut ray_tracing_features =
+        vk::PhysicalDeviceRayTracingPipelineFeaturesKHRBuilder::new().build();
+    let mut acceleration_structure_features =
+        vk::PhysicalDeviceAccelerationStructureFeaturesKHRBuilder::new().build();
+    let mut buffer_device_address_features =
+        vk::PhysicalDeviceBufferDeviceAddressFeaturesBuilder::new().build();
+    let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeaturesBuilder::new().build();
+    let mut storage_16bit_features = vk::PhysicalDevice16BitStorageFeaturesBuilder::new().build();
+    let mut ray_query_features = vk::PhysicalDeviceRayQueryFeaturesKHRBuilder::new().build();
+    let mut shader_clock_features = vk::PhysicalDeviceShaderClockFeaturesKHRBuilder::new().build();
+    let mut reset_query_features = vk::PhysicalDeviceHostQueryResetFeaturesBuilder::new().build();
+    let mut timeline_semaphore_features =
+        vk::PhysicalDeviceTimelineSemaphoreFeaturesBuilder::new().build();
+
+    let mut features2 = vk::PhysicalDeviceFeatures2Builder::new()
+        .extend_from(&mut ray_tracing_features)
+        .extend_from(&mut acceleration_structure_features)
+        .extend_from(&mut buffer_device_address_features)
+        .extend_from(&mut indexing_features)
+        .extend_from(&mut storage_16bit_features)
+        .extend_from(&mut reset_query_features)
+        .extend_from(&mut timeline_semaphore_features);
+    if ray_query_extension {
+        features2 = features2.extend_from(&mut ray_query_features);
+    }
+    if shader_clock_extension {
+        features2 = features2.extend_from(&mut shader_clock_features);
+    }
+
+    let features2 =
+        unsafe { instance.get_physical_device_features2(physical_device, Some(*features2)) };
+
+    DeviceCapabilities {
+        ray_tracing_pipeline: ray_tracing_features.ray_tracing_pipeline == vk::TRUE,
+        ray_query: ray_query_extension && ray_query_features.ray_query == vk::TRUE,
+        acceleration_structure: acceleration_structure_features.acceleration_structure == vk::TRUE,
+        buffer_device_address: buffer_device_address_features.buffer_device_address == vk::TRUE,
+        descriptor_indexing: indexing_features.runtime_descriptor_array == vk::TRUE,
+        storage_16bit: storage_16bit_features.storage_buffer16_bit_access == vk::TRUE,
+        shader_clock: shader_clock_extension
+            && shader_clock_features.shader_subgroup_clock == vk::TRUE,
+        host_query_reset: reset_query_features.host_query_reset == vk::TRUE,
+        timeline_semaphore: timeline_semaphore_features.timeline_semaphore == vk::TRUE,
+        multi_draw_indirect: features2.features.multi_draw_indirect == vk::TRUE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_features_add_no_extensions() {
+        assert!(DeviceCapabilities::default()
+            .optional_extensions()
+            .is_empty());
+
+        let capabilities = DeviceCapabilities {
+            shader_clock: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            capabilities.optional_extensions(),
+            vec![vk::KHR_SHADER_CLOCK_EXTENSION_NAME]
+        );
+    }
+
+    fn device(name: &str, device_type: vk::PhysicalDeviceType) -> PhysicalDevice {
+        PhysicalDevice {
+            info: PhysicalDeviceInfo {
+                name: name.to_owned(),
+                device_type,
+                queue_index: 0,
+                surface_format: Default::default(),
+                present_mode: vk::PresentModeKHR::FIFO_KHR,
+                device_properties: Default::default(),
+                surface_capabilities: Default::default(),
+                raytracing_properties: Default::default(),
+                accel_properties: Default::default(),
+                capabilities: Default::default(),
+            },
+            handle: vk::PhysicalDevice::null(),
+        }
+    }
+
+    fn laptop_devices() -> Vec<PhysicalDevice> {
+        vec![
+            device("Intel UHD", vk::PhysicalDeviceType::INTEGRATED_GPU),
+            device("llvmpipe", vk::PhysicalDeviceType::CPU),
+            device("NVIDIA RTX", vk::PhysicalDeviceType::DISCRETE_GPU),
+        ]
+    }
+
+    #[test]
+    fn discrete_gpu_is_preferred_by_default() {
+        let selected = DeviceSelection::default().pick(laptop_devices()).unwrap();
+
+        assert_eq!(selected.info.name, "NVIDIA RTX");
+    }
+
+    #[test]
+    fn integrated_gpu_is_preferred_over_cpu() {
+        let devices = laptop_devices()
+            .into_iter()
+            .filter(|device| device.info.device_type != vk::PhysicalDeviceType::DISCRETE_GPU)
+            .collect();
+        let selected = DeviceSelection::Preferred.pick(devices).unwrap();
+
+        assert_eq!(selected.info.name, "Intel UHD");
+    }
+
+    #[test]
+    fn manual_selection_overrides_the_preference() {
+        let by_index = DeviceSelection::Index(0).pick(laptop_devices()).unwrap();
+        assert_eq!(by_index.info.name, "Intel UHD");
+
+        let by_name = DeviceSelection::Name("llvm".to_owned())
+            .pick(laptop_devices())
+            .unwrap();
+        assert_eq!(by_name.info.name, "llvmpipe");
+
+        assert!(DeviceSelection::Index(3).pick(laptop_devices()).is_none());
+        assert!(DeviceSelection::Name("AMD".to_owned())
+            .pick(laptop_devices())
+            .is_none());
+    }
+}
@@ -0,0 +1,8 @@
+use crate::render::resources::PipelineLayout;
+use crate::render::shader::Shader;
+
+#[derive(Clone)]
+pub struct ComputePipelineInfo {
+    pub shader: Shader,
+    pub layout: PipelineLayout,
+}
@@ -1,5 +1,7 @@
+use crate::render::pipeline::vertex_format::VertexFormat;
 use crate::render::resources::{PipelineLayout, RenderPass};
 use crate::render::shader::Shader;
+use crate::render::util::ToErupt;
 use erupt::vk;
 
 #[derive(Clone)]
@@ -20,6 +22,22 @@ pub struct VertexInputBinding {
     pub stride: u32,
 }
 
+impl VertexInputBinding {
+    pub fn per_vertex(stride: u32) -> Self {
+        VertexInputBinding {
+            input_rate: vk::VertexInputRate::VERTEX,
+            stride,
+        }
+    }
+
+    pub fn per_instance(stride: u32) -> Self {
+        VertexInputBinding {
+            input_rate: vk::VertexInputRate::INSTANCE,
+            stride,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct VertexInputAttribute {
     pub location: u32,
@@ -28,6 +46,57 @@ pub struct VertexInputAttribute {
     pub offset: u32,
 }
 
+/// Builds the bindings and attributes of a pipeline from the formats of each binding, packing
+/// the attributes back to back. Locations are assigned in order across all bindings.
+#[derive(Clone, Default)]
+pub struct VertexLayoutBuilder {
+    bindings: Vec<VertexInputBinding>,
+    attributes: Vec<VertexInputAttribute>,
+}
+
+impl VertexLayoutBuilder {
+    /// Starts a new binding, the following attributes are read from it.
+    pub fn binding(mut self, input_rate: vk::VertexInputRate) -> Self {
+        self.bindings.push(VertexInputBinding {
+            input_rate,
+            stride: 0,
+        });
+        self
+    }
+
+    /// Appends an attribute to the last binding, at the next location and right after the
+    /// previous attribute.
+    pub fn attribute(mut self, format: VertexFormat) -> Self {
+        let binding = self
+            .bindings
+            .len()
+            .checked_sub(1)
+            .expect("VertexLayoutBuilder::binding must be called before adding attributes");
+        let stride = &mut self.bindings[binding].stride;
+
+        self.attributes.push(VertexInputAttribute {
+            location: self.attributes.len() as u32,
+            format: format.to_erupt(),
+            binding: binding as u32,
+            offset: *stride,
+        });
+        *stride += format.get_size() as u32;
+        self
+    }
+
+    /// Appends every format of `formats`, e.g. the four columns of a `Mat4` from
+    /// [`AsVertexFormats`](super::vertex_format::AsVertexFormats).
+    pub fn attributes(self, formats: &[VertexFormat]) -> Self {
+        formats
+            .iter()
+            .fold(self, |builder, &format| builder.attribute(format))
+    }
+
+    pub fn build(self) -> (Vec<VertexInputBinding>, Vec<VertexInputAttribute>) {
+        (self.bindings, self.attributes)
+    }
+}
+
 #[derive(Clone)]
 pub struct Rasterizer {
     pub viewport: vk::Viewport,
@@ -35,5 +104,90 @@ pub struct Rasterizer {
     pub front_face: vk::FrontFace,
     pub cull_mode: vk::CullModeFlags,
     pub polygon_mode: vk::PolygonMode,
+    pub depth_bias: Option<DepthBias>,
+    /// Has to match the sample count of the render pass attachments.
+    pub samples: vk::SampleCountFlagBits,
     pub fragment_shader: Option<Shader>,
 }
+
+#[derive(Clone, Copy, Default)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    pub clamp: f32,
+    pub slope_factor: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_are_packed_back_to_back() {
+        let (bindings, attributes) = VertexLayoutBuilder::default()
+            .binding(vk::VertexInputRate::VERTEX)
+            .attributes(&[
+                VertexFormat::Float32x3,
+                VertexFormat::Float32x3,
+                VertexFormat::Float32x2,
+            ])
+            .build();
+
+        assert_eq!(bindings.len(), 1);
+        assert_eq!(bindings[0].input_rate, vk::VertexInputRate::VERTEX);
+        assert_eq!(bindings[0].stride, 32);
+        let layout = attributes
+            .iter()
+            .map(|attribute| {
+                (
+                    attribute.location,
+                    attribute.binding,
+                    attribute.offset,
+                    attribute.format,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            layout,
+            vec![
+                (0, 0, 0, vk::Format::R32G32B32_SFLOAT),
+                (1, 0, 12, vk::Format::R32G32B32_SFLOAT),
+                (2, 0, 24, vk::Format::R32G32_SFLOAT),
+            ]
+        );
+    }
+
+    #[test]
+    fn each_binding_starts_at_offset_zero() {
+        let (bindings, attributes) = VertexLayoutBuilder::default()
+            .binding(vk::VertexInputRate::VERTEX)
+            .attribute(VertexFormat::Float32x3)
+            .binding(vk::VertexInputRate::INSTANCE)
+            .attribute(VertexFormat::Float32x4)
+            .attribute(VertexFormat::Float32x4)
+            .build();
+
+        assert_eq!(bindings[0].stride, 12);
+        assert_eq!(bindings[1].input_rate, vk::VertexInputRate::INSTANCE);
+        assert_eq!(bindings[1].stride, 32);
+        let layout = attributes
+            .iter()
+            .map(|attribute| (attribute.location, attribute.binding, attribute.offset))
+            .collect::<Vec<_>>();
+        assert_eq!(layout, vec![(0, 0, 0), (1, 1, 0), (2, 1, 16)]);
+    }
+
+    #[test]
+    fn per_vertex_and_per_instance_bindings() {
+        let binding = VertexInputBinding::per_vertex(20);
+        assert_eq!(
+            (binding.input_rate, binding.stride),
+            (vk::VertexInputRate::VERTEX, 20)
+        );
+
+        let binding = VertexInputBinding::per_instance(64);
+        assert_eq!(
+            (binding.input_rate, binding.stride),
+            (vk::VertexInputRate::INSTANCE, 64)
+        );
+    }
+}
@@ -1,18 +1,20 @@
+pub use self::compute_pipeline::*;
 pub use self::graphics_pipeline::*;
 pub use self::ray_tracing_pipeline::*;
 
-use crate::render::mesh::Mesh;
+use crate::render::mesh::{GeometryId, MeshBuffers};
 use crate::render::{
     image::Image,
     render_context::RenderContext,
     resources::{AccelerationStructure, DescriptorSetLayout, Semaphore},
 };
-use bevy::asset::Handle;
 use bevy::prelude::GlobalTransform;
 use bumpalo::Bump;
 use erupt::vk;
 use std::collections::HashMap;
+use thiserror::Error;
 
+mod compute_pipeline;
 mod graphics_pipeline;
 mod ray_tracing_pipeline;
 pub mod vertex_format;
@@ -24,12 +26,22 @@ pub trait Pipeline {
         target: Image,
         target_wait: &Semaphore,
         target_signal: &Semaphore,
-        blases: &HashMap<Handle<Mesh>, AccelerationStructure>,
+        blases: &HashMap<GeometryId, AccelerationStructure>,
+        meshes: &HashMap<GeometryId, MeshBuffers>,
         bump: &Bump,
         camera: &GlobalTransform,
     );
 }
 
+/// An error that occurs when the driver rejects a pipeline
+#[derive(Error, Debug)]
+pub enum PipelineError {
+    #[error("pipeline creation failed: {0}")]
+    Creation(vk::Result),
+    #[error("pipeline creation returned a null handle ({0})")]
+    NullHandle(vk::Result),
+}
+
 #[derive(Clone)]
 pub struct PipelineLayoutInfo {
     pub sets: Vec<DescriptorSetLayout>,
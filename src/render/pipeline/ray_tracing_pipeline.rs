@@ -1,22 +1,28 @@
-use crate::render::mesh::Mesh;
+use crate::render::mesh::{GeometryId, MeshBuffers};
 use crate::render::pass::ui_pass::UIPass;
-use crate::render::pass::{ui_pass, Pass};
+use crate::render::pass::wireframe_pass::WireframePass;
+use crate::render::pass::{ui_pass, wireframe_pass, Pass};
 use crate::render::{
     buffer::BufferRegion,
+    gpu_timer::GpuTimer,
     image::Image,
-    pass::raytracing_pass::RayTracingPass,
-    pass::tonemap_pass::TonemapPass,
-    pass::{raytracing_pass, tonemap_pass},
+    image_pool::ImagePool,
+    pass::denoise_pass::{DenoisePass, DenoiseSettings},
+    pass::ray_query_shadow_pass::RayQueryShadowPass,
+    pass::raytracing_pass::{InstanceConfig, RayTracingPass},
+    pass::tonemap_pass::{TonemapPass, TonemapSettings},
+    pass::{denoise_pass, ray_query_shadow_pass, raytracing_pass, tonemap_pass},
     pipeline::Pipeline,
     render_context::RenderContext,
     resources::{AccelerationStructure, Fence, PipelineLayout, Semaphore},
     shader::Shader,
 };
-use bevy::asset::Handle;
-use bevy::prelude::GlobalTransform;
+use bevy::prelude::{Entity, GlobalTransform};
 use bumpalo::Bump;
 use erupt::vk;
+use glam::{Mat4, Vec3, Vec4};
 use std::collections::HashMap;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct RayTracingPipelineInfo {
@@ -55,12 +61,34 @@ pub struct ShaderBindingTable {
     pub callable: Option<BufferRegion>,
 }
 
+/// How the CPU waits for a frame slot to be free before recording into it again.
+enum FrameSync {
+    /// One fence per frame in flight, signaled by the last submit of the frame.
+    Fences(Vec<Fence>),
+    /// A single timeline semaphore that reaches `frame + 1` once `frame` has completed.
+    Timeline(Semaphore),
+}
+
 pub struct PathTracingPipeline {
     raytracing_pass: RayTracingPass,
+    denoise_pass: DenoisePass,
+    /// Only created when the device supports ray queries.
+    shadow_pass: Option<RayQueryShadowPass>,
     tonemap_pass: TonemapPass,
+    wireframe_pass: WireframePass,
     ui_pass: UIPass,
+    clear_color: [f32; 4],
+    wireframe_color: Option<Vec4>,
+    denoise: bool,
+    ray_query_shadows: bool,
     frame: u64,
-    fences: [Fence; 2],
+    frames_in_flight: usize,
+    frame_sync: FrameSync,
+    /// The images of the passes sized by the render extent.
+    image_pool: ImagePool,
+    /// `None` when the device can't write timestamps.
+    gpu_timer: Option<GpuTimer>,
+    gpu_pass_times: Vec<(&'static str, Duration)>,
 }
 
 impl PathTracingPipeline {
@@ -68,13 +96,199 @@ impl PathTracingPipeline {
         render_context: &RenderContext,
         surface_format: vk::Format,
         extent: vk::Extent2D,
+        tlas_build_flags: vk::BuildAccelerationStructureFlagsKHR,
+        frames_in_flight: usize,
+        timeline_frame_sync: bool,
+        max_recursion_depth: u32,
     ) -> Self {
+        assert!(
+            (1..=3).contains(&frames_in_flight),
+            "frames in flight must be between 1 and 3, got {}",
+            frames_in_flight
+        );
+
+        let frame_sync = if timeline_frame_sync {
+            FrameSync::Timeline(render_context.create_timeline_semaphore(0))
+        } else {
+            FrameSync::Fences(
+                (0..frames_in_flight)
+                    .map(|_| render_context.create_fence())
+                    .collect(),
+            )
+        };
+
+        let mut image_pool = ImagePool::default();
+        image_pool.resize(extent);
+
         PathTracingPipeline {
-            raytracing_pass: RayTracingPass::new(render_context, extent),
-            tonemap_pass: TonemapPass::new(render_context, surface_format, extent),
-            ui_pass: UIPass::new(render_context, surface_format, extent),
+            raytracing_pass: RayTracingPass::new(
+                render_context,
+                extent,
+                tlas_build_flags,
+                frames_in_flight,
+                &["raytrace.rchit.spv"],
+                max_recursion_depth,
+                &mut image_pool,
+            ),
+            denoise_pass: DenoisePass::new(
+                render_context,
+                &mut image_pool,
+                extent,
+                frames_in_flight,
+            ),
+            shadow_pass: render_context
+                .device
+                .physical_device()
+                .info()
+                .capabilities
+                .ray_query
+                .then(|| {
+                    RayQueryShadowPass::new(
+                        render_context,
+                        &mut image_pool,
+                        extent,
+                        frames_in_flight,
+                    )
+                }),
+            tonemap_pass: TonemapPass::new(
+                render_context,
+                surface_format,
+                extent,
+                frames_in_flight,
+            ),
+            wireframe_pass: WireframePass::new(render_context, surface_format, extent),
+            ui_pass: UIPass::new(render_context, surface_format, extent, frames_in_flight),
+            wireframe_color: None,
+            denoise: false,
+            ray_query_shadows: false,
+            clear_color: [0.5, 0.2, 0.2, 0.0],
             frame: 0,
-            fences: [render_context.create_fence(), render_context.create_fence()],
+            frames_in_flight,
+            frame_sync,
+            image_pool,
+            gpu_timer: GpuTimer::new(render_context, frames_in_flight),
+            gpu_pass_times: Vec::new(),
+        }
+    }
+
+    /// Recreates the images sized by the render extent, nothing happens if it didn't change. The
+    /// GPU must be done with every submitted frame.
+    pub fn resize(&mut self, render_context: &RenderContext, extent: vk::Extent2D) {
+        if !self.image_pool.resize(extent) {
+            return;
+        }
+
+        self.raytracing_pass
+            .resize(render_context, &mut self.image_pool, extent);
+        self.denoise_pass
+            .resize(render_context, &mut self.image_pool, extent);
+        if let Some(shadow_pass) = &mut self.shadow_pass {
+            shadow_pass.resize(render_context, &mut self.image_pool, extent);
+        }
+    }
+
+    /// Recreates the ray tracing pipeline with a new recursion depth. The GPU must be done with
+    /// every submitted frame.
+    pub fn set_max_recursion_depth(
+        &mut self,
+        render_context: &RenderContext,
+        max_recursion_depth: u32,
+    ) {
+        self.raytracing_pass
+            .set_max_recursion_depth(render_context, max_recursion_depth);
+    }
+
+    pub fn set_visibility(&mut self, mesh: GeometryId, visible: bool) {
+        self.raytracing_pass.set_visibility(mesh, visible);
+    }
+
+    /// Rebuilds the TLAS instances on the next frame, called whenever a BLAS is added or removed.
+    pub fn invalidate_instances(&mut self) {
+        self.raytracing_pass.invalidate_instances();
+    }
+
+    pub fn set_instance_transform(&mut self, mesh: GeometryId, entity: Entity, transform: Mat4) {
+        self.raytracing_pass
+            .set_instance_transform(mesh, entity, transform);
+    }
+
+    pub fn remove_instance(&mut self, entity: Entity) {
+        self.raytracing_pass.remove_instance(entity);
+    }
+
+    pub fn set_instance_config(&mut self, mesh: GeometryId, config: InstanceConfig) {
+        self.raytracing_pass.set_instance_config(mesh, config);
+    }
+
+    pub fn set_emission(&mut self, mesh: GeometryId, emission: Vec3) {
+        self.raytracing_pass.set_emission(mesh, emission);
+    }
+
+    pub fn set_samples_per_pixel(&mut self, samples_per_pixel: u32) {
+        self.raytracing_pass
+            .set_samples_per_pixel(samples_per_pixel);
+    }
+
+    pub fn set_max_bounces(&mut self, max_bounces: u32) {
+        self.raytracing_pass.set_max_bounces(max_bounces);
+    }
+
+    pub fn set_seed(&mut self, seed: u32) {
+        self.raytracing_pass.set_seed(seed);
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.raytracing_pass.reset_accumulation();
+    }
+
+    pub fn set_tonemap_settings(&mut self, settings: TonemapSettings) {
+        self.tonemap_pass.set_settings(settings);
+    }
+
+    /// Filters the path traced image with the G-buffer before tonemapping, `None` disables the
+    /// denoiser.
+    pub fn set_denoise(&mut self, settings: Option<DenoiseSettings>) {
+        if let Some(settings) = settings {
+            self.denoise_pass.set_settings(settings);
+        }
+        self.denoise = settings.is_some();
+    }
+
+    /// Traces a shadow ray per pixel towards a directional light with ray queries, `None` stops.
+    /// Returns false when the device doesn't support ray queries. Nothing samples the shadow
+    /// factor yet.
+    pub fn set_ray_query_shadows(&mut self, light_direction: Option<Vec3>) -> bool {
+        let shadow_pass = match &mut self.shadow_pass {
+            Some(shadow_pass) => shadow_pass,
+            None => return light_direction.is_none(),
+        };
+
+        if let Some(light_direction) = light_direction {
+            shadow_pass.set_light_direction(light_direction);
+        }
+        self.ray_query_shadows = light_direction.is_some();
+        true
+    }
+
+    /// Draws the mesh edges in `edge_color` on top of the shaded image, `None` disables the
+    /// overlay.
+    pub fn set_wireframe_overlay(&mut self, edge_color: Option<Vec4>) {
+        self.wireframe_color = edge_color;
+    }
+
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+    }
+
+    /// GPU time of each pass of the frame that last used the current frame slot, frames in flight
+    /// behind the last one drawn. Empty when the device can't write timestamps.
+    pub fn gpu_pass_times(&self) -> &[(&'static str, Duration)] {
+        &self.gpu_pass_times
+    }
+
+    fn end_pass(&mut self, render_context: &mut RenderContext, label: &'static str) {
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.end_pass(render_context, self.frame, label);
         }
     }
 }
@@ -86,18 +300,36 @@ impl Pipeline for PathTracingPipeline {
         target: Image,
         target_wait: &Semaphore,
         target_signal: &Semaphore,
-        blases: &HashMap<Handle<Mesh>, AccelerationStructure>,
+        blases: &HashMap<GeometryId, AccelerationStructure>,
+        meshes: &HashMap<GeometryId, MeshBuffers>,
         bump: &Bump,
         camera: &GlobalTransform,
     ) {
-        let fence = &self.fences[(self.frame % 2) as usize];
-        if self.frame > 1 {
-            render_context.wait_fences(&[fence], true);
-            render_context.reset_fences(&[fence]);
+        let (slot, previous_frame) = frame_slot(self.frame, self.frames_in_flight);
+        let fence = match &self.frame_sync {
+            FrameSync::Fences(fences) => {
+                let fence = &fences[slot];
+                if previous_frame.is_some() {
+                    render_context.wait_fences(&[fence], true);
+                    render_context.reset_fences(&[fence]);
+                }
+                // Cloned out so the passes below can borrow `self` mutably
+                Some(fence.clone())
+            }
+            FrameSync::Timeline(timeline) => {
+                if let Some(previous_frame) = previous_frame {
+                    render_context.wait_semaphores(&[(timeline, previous_frame + 1)], !0);
+                }
+                None
+            }
+        };
+
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            self.gpu_pass_times = gpu_timer.begin_frame(render_context, self.frame);
         }
 
         let raytracing_output = self.raytracing_pass.draw(
-            raytracing_pass::Input { blases },
+            raytracing_pass::Input { blases, meshes },
             self.frame,
             &[],
             &[],
@@ -106,24 +338,95 @@ impl Pipeline for PathTracingPipeline {
             bump,
             camera,
         );
+        self.end_pass(render_context, "raytracing");
+
+        let ray_query_shadows = self.ray_query_shadows;
+        if let Some(shadow_pass) = self.shadow_pass.as_mut().filter(|_| ray_query_shadows) {
+            shadow_pass.draw(
+                ray_query_shadow_pass::Input {
+                    tlas: raytracing_output.tlas.clone(),
+                    normal: raytracing_output.normal_image.clone(),
+                    depth: raytracing_output.depth_image.clone(),
+                },
+                self.frame,
+                &[],
+                &[],
+                None,
+                render_context,
+                bump,
+                camera,
+            );
+            self.end_pass(render_context, "shadow");
+        }
+
+        let color_image = if self.denoise {
+            let output_image = self
+                .denoise_pass
+                .draw(
+                    denoise_pass::Input {
+                        color: raytracing_output.output_image.clone(),
+                        albedo: raytracing_output.albedo_image.clone(),
+                        normal: raytracing_output.normal_image.clone(),
+                    },
+                    self.frame,
+                    &[],
+                    &[],
+                    None,
+                    render_context,
+                    bump,
+                    camera,
+                )
+                .output_image;
+            self.end_pass(render_context, "denoise");
+            output_image
+        } else {
+            raytracing_output.output_image.clone()
+        };
 
         self.tonemap_pass.draw(
             tonemap_pass::Input {
-                initial_image: raytracing_output.output_image.clone(),
+                initial_image: color_image,
                 final_image: target.clone(),
+                clear_color: self.clear_color,
             },
             self.frame,
             &[(
                 vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
                 target_wait.clone(),
             )],
-            std::slice::from_ref(target_signal),
-            Some(fence),
+            &[],
+            None,
             render_context,
             bump,
             camera,
         );
+        self.end_pass(render_context, "tonemap");
+
+        if let Some(edge_color) = self.wireframe_color {
+            let instances = meshes
+                .keys()
+                .map(|&mesh| (mesh, self.raytracing_pass.instance_transforms(mesh)))
+                .collect();
+            self.wireframe_pass.draw(
+                wireframe_pass::Input {
+                    target: target.clone(),
+                    meshes,
+                    instances: &instances,
+                    edge_color,
+                },
+                self.frame,
+                &[],
+                &[],
+                None,
+                render_context,
+                bump,
+                camera,
+            );
+            self.end_pass(render_context, "wireframe");
+        }
 
+        // The UI pass isn't timed, its submit signals the frame slot so a timestamp after it may
+        // not be written yet when the slot is reused
         self.ui_pass.begin_frame();
 
         egui::Window::new("Options")
@@ -140,16 +443,70 @@ impl Pipeline for PathTracingPipeline {
         self.ui_pass.end_frame();
 
         self.ui_pass.draw(
-            ui_pass::Input { target },
+            ui_pass::Input {
+                target,
+                clear_color: self.clear_color,
+            },
             self.frame,
             &[],
-            &[],
-            None,
+            // The UI pass is the last one to touch the target, and its upload ring is reused once
+            // this frame slot is waited on
+            std::slice::from_ref(target_signal),
+            fence.as_ref(),
             render_context,
             bump,
             camera,
         );
 
+        if let FrameSync::Timeline(timeline) = &self.frame_sync {
+            render_context
+                .queue
+                .signal_timeline(timeline, self.frame + 1);
+        }
+
         self.frame += 1;
     }
 }
+
+/// The frame slot `frame` records into, the same `frame % frames_in_flight` the passes pick their
+/// per-frame descriptor sets with, and the earlier frame that last used the slot and has to have
+/// completed first. `None` while the first `frames_in_flight` frames are recorded.
+fn frame_slot(frame: u64, frames_in_flight: usize) -> (usize, Option<u64>) {
+    let frames_in_flight = frames_in_flight as u64;
+    let slot = (frame % frames_in_flight) as usize;
+    (slot, frame.checked_sub(frames_in_flight))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn three_frames_in_flight_use_three_slots() {
+        let slots = (0..9).map(|frame| frame_slot(frame, 3)).collect::<Vec<_>>();
+
+        // One fence and one descriptor set per pass for each distinct slot
+        let distinct = slots.iter().map(|(slot, _)| *slot).collect::<HashSet<_>>();
+        assert_eq!(distinct.len(), 3);
+
+        assert_eq!(
+            slots[..6],
+            [
+                (0, None),
+                (1, None),
+                (2, None),
+                (0, Some(0)),
+                (1, Some(1)),
+                (2, Some(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_single_frame_in_flight_waits_for_the_previous_frame() {
+        assert_eq!(frame_slot(0, 1), (0, None));
+        assert_eq!(frame_slot(1, 1), (0, Some(0)));
+        assert_eq!(frame_slot(5, 1), (0, Some(4)));
+    }
+}
@@ -1,6 +1,8 @@
+use crate::render::util::ToErupt;
 use bevy::math::{Mat4, Vec2, Vec3, Vec4};
+use erupt::vk;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum VertexFormat {
     Uint8x2 = 1,
     Uint8x4 = 3,
@@ -35,38 +37,133 @@ pub enum VertexFormat {
 }
 
 impl VertexFormat {
+    /// Size in bytes of one attribute, also the stride of a tightly packed buffer of them.
     pub fn get_size(&self) -> u64 {
+        self.component_size() * self.component_count()
+    }
+
+    /// Size in bytes of each component.
+    pub fn component_size(&self) -> u64 {
+        match *self {
+            VertexFormat::Uint8x2
+            | VertexFormat::Uint8x4
+            | VertexFormat::Sint8x2
+            | VertexFormat::Sint8x4
+            | VertexFormat::Unorm8x2
+            | VertexFormat::Unorm8x4
+            | VertexFormat::Snorm8x2
+            | VertexFormat::Snorm8x4 => 1,
+            VertexFormat::Uint16x2
+            | VertexFormat::Uint16x4
+            | VertexFormat::Sint16x2
+            | VertexFormat::Sint16x4
+            | VertexFormat::Unorm16x2
+            | VertexFormat::Unorm16x4
+            | VertexFormat::Snorm16x2
+            | VertexFormat::Snorm16x4
+            | VertexFormat::Float16x2
+            | VertexFormat::Float16x4 => 2,
+            VertexFormat::Float32
+            | VertexFormat::Float32x2
+            | VertexFormat::Float32x3
+            | VertexFormat::Float32x4
+            | VertexFormat::Uint32
+            | VertexFormat::Uint32x2
+            | VertexFormat::Uint32x3
+            | VertexFormat::Uint32x4
+            | VertexFormat::Sint32
+            | VertexFormat::Sint32x2
+            | VertexFormat::Sint32x3
+            | VertexFormat::Sint32x4 => 4,
+        }
+    }
+
+    pub fn component_count(&self) -> u64 {
         match *self {
-            VertexFormat::Uint8x2 => 2,
-            VertexFormat::Uint8x4 => 4,
-            VertexFormat::Sint8x2 => 2,
-            VertexFormat::Sint8x4 => 4,
-            VertexFormat::Unorm8x2 => 2,
-            VertexFormat::Unorm8x4 => 4,
-            VertexFormat::Snorm8x2 => 2,
-            VertexFormat::Snorm8x4 => 4,
-            VertexFormat::Uint16x2 => 2 * 2,
-            VertexFormat::Uint16x4 => 2 * 4,
-            VertexFormat::Sint16x2 => 2 * 2,
-            VertexFormat::Sint16x4 => 2 * 4,
-            VertexFormat::Unorm16x2 => 2 * 2,
-            VertexFormat::Unorm16x4 => 2 * 4,
-            VertexFormat::Snorm16x2 => 2 * 2,
-            VertexFormat::Snorm16x4 => 2 * 4,
-            VertexFormat::Float16x2 => 2 * 2,
-            VertexFormat::Float16x4 => 2 * 4,
-            VertexFormat::Float32 => 4,
-            VertexFormat::Float32x2 => 4 * 2,
-            VertexFormat::Float32x3 => 4 * 3,
-            VertexFormat::Float32x4 => 4 * 4,
-            VertexFormat::Uint32 => 4,
-            VertexFormat::Uint32x2 => 4 * 2,
-            VertexFormat::Uint32x3 => 4 * 3,
-            VertexFormat::Uint32x4 => 4 * 4,
-            VertexFormat::Sint32 => 4,
-            VertexFormat::Sint32x2 => 4 * 2,
-            VertexFormat::Sint32x3 => 4 * 3,
-            VertexFormat::Sint32x4 => 4 * 4,
+            VertexFormat::Float32 | VertexFormat::Uint32 | VertexFormat::Sint32 => 1,
+            VertexFormat::Uint8x2
+            | VertexFormat::Sint8x2
+            | VertexFormat::Unorm8x2
+            | VertexFormat::Snorm8x2
+            | VertexFormat::Uint16x2
+            | VertexFormat::Sint16x2
+            | VertexFormat::Unorm16x2
+            | VertexFormat::Snorm16x2
+            | VertexFormat::Float16x2
+            | VertexFormat::Float32x2
+            | VertexFormat::Uint32x2
+            | VertexFormat::Sint32x2 => 2,
+            VertexFormat::Float32x3 | VertexFormat::Uint32x3 | VertexFormat::Sint32x3 => 3,
+            VertexFormat::Uint8x4
+            | VertexFormat::Sint8x4
+            | VertexFormat::Unorm8x4
+            | VertexFormat::Snorm8x4
+            | VertexFormat::Uint16x4
+            | VertexFormat::Sint16x4
+            | VertexFormat::Unorm16x4
+            | VertexFormat::Snorm16x4
+            | VertexFormat::Float16x4
+            | VertexFormat::Float32x4
+            | VertexFormat::Uint32x4
+            | VertexFormat::Sint32x4 => 4,
+        }
+    }
+
+    /// Format of this attribute when used as acceleration structure vertex positions, `None` for
+    /// formats that can't be. Only the float and snorm ones are guaranteed by the spec, the rest
+    /// must be checked against the device's format features.
+    pub fn acceleration_structure_format(&self) -> Option<vk::Format> {
+        match *self {
+            VertexFormat::Float32x2 => Some(vk::Format::R32G32_SFLOAT),
+            VertexFormat::Float32x3 => Some(vk::Format::R32G32B32_SFLOAT),
+            VertexFormat::Float16x2 => Some(vk::Format::R16G16_SFLOAT),
+            VertexFormat::Float16x4 => Some(vk::Format::R16G16B16A16_SFLOAT),
+            VertexFormat::Snorm16x2 => Some(vk::Format::R16G16_SNORM),
+            VertexFormat::Snorm16x4 => Some(vk::Format::R16G16B16A16_SNORM),
+            VertexFormat::Unorm16x2 => Some(vk::Format::R16G16_UNORM),
+            VertexFormat::Unorm16x4 => Some(vk::Format::R16G16B16A16_UNORM),
+            VertexFormat::Snorm8x2 => Some(vk::Format::R8G8_SNORM),
+            VertexFormat::Snorm8x4 => Some(vk::Format::R8G8B8A8_SNORM),
+            VertexFormat::Unorm8x2 => Some(vk::Format::R8G8_UNORM),
+            VertexFormat::Unorm8x4 => Some(vk::Format::R8G8B8A8_UNORM),
+            _ => None,
+        }
+    }
+}
+
+impl ToErupt<vk::Format> for VertexFormat {
+    fn to_erupt(&self) -> vk::Format {
+        match *self {
+            VertexFormat::Uint8x2 => vk::Format::R8G8_UINT,
+            VertexFormat::Uint8x4 => vk::Format::R8G8B8A8_UINT,
+            VertexFormat::Sint8x2 => vk::Format::R8G8_SINT,
+            VertexFormat::Sint8x4 => vk::Format::R8G8B8A8_SINT,
+            VertexFormat::Unorm8x2 => vk::Format::R8G8_UNORM,
+            VertexFormat::Unorm8x4 => vk::Format::R8G8B8A8_UNORM,
+            VertexFormat::Snorm8x2 => vk::Format::R8G8_SNORM,
+            VertexFormat::Snorm8x4 => vk::Format::R8G8B8A8_SNORM,
+            VertexFormat::Uint16x2 => vk::Format::R16G16_UINT,
+            VertexFormat::Uint16x4 => vk::Format::R16G16B16A16_UINT,
+            VertexFormat::Sint16x2 => vk::Format::R16G16_SINT,
+            VertexFormat::Sint16x4 => vk::Format::R16G16B16A16_SINT,
+            VertexFormat::Unorm16x2 => vk::Format::R16G16_UNORM,
+            VertexFormat::Unorm16x4 => vk::Format::R16G16B16A16_UNORM,
+            VertexFormat::Snorm16x2 => vk::Format::R16G16_SNORM,
+            VertexFormat::Snorm16x4 => vk::Format::R16G16B16A16_SNORM,
+            VertexFormat::Float16x2 => vk::Format::R16G16_SFLOAT,
+            VertexFormat::Float16x4 => vk::Format::R16G16B16A16_SFLOAT,
+            VertexFormat::Float32 => vk::Format::R32_SFLOAT,
+            VertexFormat::Float32x2 => vk::Format::R32G32_SFLOAT,
+            VertexFormat::Float32x3 => vk::Format::R32G32B32_SFLOAT,
+            VertexFormat::Float32x4 => vk::Format::R32G32B32A32_SFLOAT,
+            VertexFormat::Uint32 => vk::Format::R32_UINT,
+            VertexFormat::Uint32x2 => vk::Format::R32G32_UINT,
+            VertexFormat::Uint32x3 => vk::Format::R32G32B32_UINT,
+            VertexFormat::Uint32x4 => vk::Format::R32G32B32A32_UINT,
+            VertexFormat::Sint32 => vk::Format::R32_SINT,
+            VertexFormat::Sint32x2 => vk::Format::R32G32_SINT,
+            VertexFormat::Sint32x3 => vk::Format::R32G32B32_SINT,
+            VertexFormat::Sint32x4 => vk::Format::R32G32B32A32_SINT,
         }
     }
 }
@@ -127,3 +224,48 @@ impl AsVertexFormats for [f32; 4] {
         &[VertexFormat::Float32x4]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_of_every_format() {
+        let sizes = [
+            (VertexFormat::Uint8x2, 2),
+            (VertexFormat::Uint8x4, 4),
+            (VertexFormat::Sint8x2, 2),
+            (VertexFormat::Sint8x4, 4),
+            (VertexFormat::Unorm8x2, 2),
+            (VertexFormat::Unorm8x4, 4),
+            (VertexFormat::Snorm8x2, 2),
+            (VertexFormat::Snorm8x4, 4),
+            (VertexFormat::Uint16x2, 4),
+            (VertexFormat::Uint16x4, 8),
+            (VertexFormat::Sint16x2, 4),
+            (VertexFormat::Sint16x4, 8),
+            (VertexFormat::Unorm16x2, 4),
+            (VertexFormat::Unorm16x4, 8),
+            (VertexFormat::Snorm16x2, 4),
+            (VertexFormat::Snorm16x4, 8),
+            (VertexFormat::Float16x2, 4),
+            (VertexFormat::Float16x4, 8),
+            (VertexFormat::Float32, 4),
+            (VertexFormat::Float32x2, 8),
+            (VertexFormat::Float32x3, 12),
+            (VertexFormat::Float32x4, 16),
+            (VertexFormat::Uint32, 4),
+            (VertexFormat::Uint32x2, 8),
+            (VertexFormat::Uint32x3, 12),
+            (VertexFormat::Uint32x4, 16),
+            (VertexFormat::Sint32, 4),
+            (VertexFormat::Sint32x2, 8),
+            (VertexFormat::Sint32x3, 12),
+            (VertexFormat::Sint32x4, 16),
+        ];
+
+        for &(format, size) in &sizes {
+            assert_eq!(format.get_size(), size, "{:?}", format);
+        }
+    }
+}
@@ -3,10 +3,10 @@ use crate::render::{
     device::Device,
     encoder::Encoder,
     resources::{Fence, Semaphore},
-    swapchain::SwapchainImage,
+    swapchain::{PresentStatus, SwapchainImage},
 };
-use erupt::vk;
 use erupt::vk::{PipelineStageFlags, PresentInfoKHRBuilder};
+use erupt::{vk, ExtendableFromConst};
 use smallvec::SmallVec;
 
 pub struct Queue {
@@ -92,21 +92,40 @@ impl Queue {
         }
     }
 
-    pub fn present(&mut self, swapchain_image: SwapchainImage) {
+    /// Sets `timeline` to `value` once every command submitted before has completed.
+    pub fn signal_timeline(&self, timeline: &Semaphore, value: u64) {
+        let values = [value];
+        let mut timeline_info =
+            vk::TimelineSemaphoreSubmitInfoBuilder::new().signal_semaphore_values(&values);
+
         unsafe {
             self.device
                 .handle()
-                .queue_present_khr(
+                .queue_submit(
                     self.handle,
-                    &PresentInfoKHRBuilder::new()
-                        .swapchains(&[swapchain_image.handle()])
-                        .wait_semaphores(&[swapchain_image.info().signal.handle()])
-                        .image_indices(&[swapchain_image.index()]),
+                    &[vk::SubmitInfoBuilder::new()
+                        .signal_semaphores(&[timeline.handle()])
+                        .extend_from(&mut timeline_info)],
+                    None,
                 )
-                .unwrap();
+                .unwrap()
         }
     }
 
+    pub fn present(&mut self, swapchain_image: SwapchainImage) -> PresentStatus {
+        let result = unsafe {
+            self.device.handle().queue_present_khr(
+                self.handle,
+                &PresentInfoKHRBuilder::new()
+                    .swapchains(&[swapchain_image.handle()])
+                    .wait_semaphores(&[swapchain_image.info().signal.handle()])
+                    .image_indices(&[swapchain_image.index()]),
+            )
+        };
+
+        PresentStatus::from_result(result.raw)
+    }
+
     pub fn cleanup(&mut self, device: &Device) {
         unsafe { device.handle().destroy_command_pool(Some(self.pool), None) }
     }
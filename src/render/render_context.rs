@@ -1,9 +1,19 @@
-use crate::render::{device::Device, queue::Queue};
+use crate::render::{
+    buffer::BufferInfo,
+    device::Device,
+    queue::Queue,
+    resources::{Buffer, Fence},
+};
+use bytemuck::Pod;
+use erupt::vk;
 use std::ops::Deref;
 
 pub struct RenderContext {
     pub device: Device,
     pub queue: Queue,
+    /// The staging buffer and fence of [`RenderContext::read_device_buffer`], created on the first
+    /// read and replaced by a larger buffer when a read doesn't fit.
+    read_back: Option<(Buffer, Fence)>,
 }
 
 impl Deref for RenderContext {
@@ -16,7 +26,75 @@ impl Deref for RenderContext {
 
 impl RenderContext {
     pub fn new(device: Device, queue: Queue) -> Self {
-        RenderContext { device, queue }
+        RenderContext {
+            device,
+            queue,
+            read_back: None,
+        }
+    }
+
+    /// The device, for uploads and resource creation outside of a pass.
+    ///
+    /// Creating resources and [`Device::write_buffer`] can happen at any point of a frame, as long
+    /// as the written range isn't read by a frame still in flight, passes keep one buffer per frame
+    /// in flight for that reason. Recreating or destroying resources used by submitted work needs
+    /// [`Device::wait_idle`] first.
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Like [`Device::read_buffer`], but device local buffers are first copied into a staging
+    /// buffer, which needs them to have `TRANSFER_SRC` usage. Waits for the queue, so this is only
+    /// meant for debugging.
+    pub fn read_device_buffer<T: Pod>(
+        &mut self,
+        buffer: &Buffer,
+        offset: u64,
+        count: usize,
+    ) -> Vec<T> {
+        if self.device.is_host_visible(buffer) {
+            return self.device.read_buffer(buffer, offset, count);
+        }
+
+        let size = (std::mem::size_of::<T>() * count) as u64;
+        let capacity = self
+            .read_back
+            .as_ref()
+            .map(|(staging, _)| staging.info().size);
+        if let Some(capacity) = read_back_capacity(capacity, size) {
+            // Like every buffer the previous one is only freed with the device
+            let staging = self.device.create_buffer(BufferInfo {
+                align: 0,
+                size: capacity,
+                usage_flags: vk::BufferUsageFlags::TRANSFER_DST,
+                allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS
+                    | gpu_alloc::UsageFlags::DOWNLOAD,
+                name: Some("read back staging"),
+            });
+            let fence = match self.read_back.take() {
+                Some((_, fence)) => fence,
+                None => self.device.create_fence(),
+            };
+            self.read_back = Some((staging, fence));
+        }
+        let (staging, fence) = self.read_back.as_ref().unwrap();
+
+        let mut encoder = self.queue.create_enconder();
+        // Whatever was submitted before may still be writing the buffer
+        encoder.pipeline_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::MEMORY_WRITE,
+            vk::AccessFlags::TRANSFER_READ,
+            &[],
+        );
+        encoder.copy_buffer(buffer, offset, staging, 0, size);
+        let command_buffer = encoder.finish(&self.device);
+        self.queue.submit(command_buffer, &[], &[], Some(fence));
+        self.device.wait_fences(&[fence], true);
+        self.device.reset_fences(&[fence]);
+
+        self.device.read_buffer(staging, 0, count)
     }
 
     pub fn destroy_context(&mut self) {
@@ -25,3 +103,25 @@ impl RenderContext {
         self.device.cleanup();
     }
 }
+
+/// The size of the staging buffer to create for a read of `size` bytes, `None` if the current one
+/// of `capacity` bytes fits it. Grows to powers of two so repeated reads settle on one buffer.
+fn read_back_capacity(capacity: Option<u64>, size: u64) -> Option<u64> {
+    match capacity {
+        Some(capacity) if capacity >= size => None,
+        _ => Some(size.next_power_of_two()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_back_reuses_the_staging_buffer_while_it_fits() {
+        assert_eq!(read_back_capacity(None, 100), Some(128));
+        assert_eq!(read_back_capacity(Some(128), 100), None);
+        assert_eq!(read_back_capacity(Some(128), 128), None);
+        assert_eq!(read_back_capacity(Some(128), 129), Some(256));
+    }
+}
@@ -8,12 +8,13 @@ pub const DEFAULT_SUBPASS_COUNT: usize = 1;
 pub struct RenderPassInfo {
     pub attachments: SmallVec<[AttachmentInfo; DEFAULT_ATTACHMENT_COUNT]>,
     pub subpasses: SmallVec<[Subpass; DEFAULT_SUBPASS_COUNT]>,
+    pub dependencies: Vec<SubpassDependency>,
 }
 
 #[derive(Clone)]
 pub struct AttachmentInfo {
     pub format: vk::Format,
-    pub samples: vk::SampleCountFlags,
+    pub samples: vk::SampleCountFlagBits,
     pub load_op: vk::AttachmentLoadOp,
     pub store_op: vk::AttachmentStoreOp,
     pub initial_layout: Option<vk::ImageLayout>,
@@ -24,17 +25,58 @@ pub struct AttachmentInfo {
 pub struct Subpass {
     pub colors: SmallVec<[usize; DEFAULT_ATTACHMENT_COUNT]>,
     pub depth: Option<usize>,
+    /// Single sampled attachments the multisampled `colors` are resolved into, one per color
+    /// attachment. Empty when the subpass doesn't resolve.
+    pub resolves: SmallVec<[usize; DEFAULT_ATTACHMENT_COUNT]>,
 }
 
+/// `None` for `src` or `dst` stands for `VK_SUBPASS_EXTERNAL`.
 #[derive(Clone)]
 pub struct SubpassDependency {
     pub src: Option<usize>,
     pub dst: Option<usize>,
     pub src_stages: vk::PipelineStageFlags,
     pub dst_stages: vk::PipelineStageFlags,
+    pub src_access: vk::AccessFlags,
+    pub dst_access: vk::AccessFlags,
+}
+
+impl SubpassDependency {
+    /// Waits for the color writes of earlier submissions, such as a previous pass drawing to the
+    /// same image, before the first subpass loads and writes the attachment.
+    pub fn external_to_color_attachment() -> Self {
+        SubpassDependency {
+            src: None,
+            dst: Some(0),
+            src_stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            dst_stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        }
+    }
+
+    /// Makes the color writes of the first subpass available before the attachment is
+    /// transitioned for presentation.
+    pub fn color_attachment_to_present() -> Self {
+        SubpassDependency {
+            src: Some(0),
+            dst: None,
+            src_stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            dst_stages: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            src_access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access: vk::AccessFlags::empty(),
+        }
+    }
 }
 
 pub enum ClearValue {
     Color(f32, f32, f32, f32),
     DepthStencil(f32, u32),
 }
+
+impl From<[f32; 4]> for ClearValue {
+    fn from([r, g, b, a]: [f32; 4]) -> Self {
+        ClearValue::Color(r, g, b, a)
+    }
+}
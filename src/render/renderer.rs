@@ -1,55 +1,140 @@
+use crate::material::Material;
+pub use crate::render::pass::denoise_pass::DenoiseSettings;
+pub use crate::render::pass::raytracing_pass::InstanceConfig;
+pub use crate::render::pass::tonemap_pass::{TonemapOperator, TonemapSettings};
+pub use crate::render::physical_device::DeviceSelection;
+pub use crate::render::screenshot::ScreenshotError;
 use crate::render::{
+    buffer_arena::BufferArena,
     debug::DebugMessenger,
+    image::Image,
     instance,
-    mesh::Mesh,
+    ktx2::{self, Ktx2Error},
+    mesh::{GeometryId, GeometryIds, Mesh, MeshBuffers},
     physical_device::PhysicalDevice,
     pipeline::PathTracingPipeline,
     pipeline::Pipeline,
     render_context::RenderContext,
-    resources::{AccelerationStructure, Buffer},
+    resources::{AccelerationStructure, Semaphore},
+    scratch_pool::ScratchPool,
+    screenshot::{PixelOrder, Readback, Recording},
+    stats::{FrameStats, StatsFormat, StatsLog},
     surface::Surface,
-    swapchain::Swapchain,
+    swapchain::{FullScreenExclusive, Swapchain, SwapchainImage},
 };
 use bevy::prelude::*;
 use bumpalo::Bump;
 use erupt::{vk, EntryLoader, InstanceLoader};
 use parking_lot::Mutex;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::mem::ManuallyDrop;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 use winit::window::Window;
 
+/// Size of the buffers mesh vertices and indices are sub-allocated from.
+const GEOMETRY_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Options read once when the [`Renderer`] is created. Insert it as a resource before the
+/// `RenderPlugin` to override the defaults.
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    /// Builds every acceleration structure with `LOW_MEMORY_KHR`, trading traversal speed for a
+    /// smaller memory footprint.
+    pub low_memory_acceleration_structures: bool,
+    /// Caps how many BLASes are built each frame so loading a large scene is spread over several
+    /// frames instead of stalling one. `None` builds every queued mesh right away.
+    pub max_blas_builds_per_frame: Option<usize>,
+    /// How many frames the CPU may record ahead of the GPU, between 1 and 3. 1 gives the lowest
+    /// input latency, 3 the smoothest throughput.
+    pub frames_in_flight: usize,
+    /// Paces frames with a single timeline semaphore instead of one fence per frame in flight.
+    /// Falls back to fences when the device doesn't support timeline semaphores.
+    pub timeline_frame_sync: bool,
+    /// Recursion depth of the path tracing pipeline. The primary ray takes one level and every
+    /// other level allows one more bounce, see [`Renderer::set_max_bounces`]. Clamped to what the
+    /// device supports.
+    pub max_ray_recursion_depth: u32,
+    /// Which GPU to render with when there are several, a discrete one by default.
+    pub device: DeviceSelection,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            low_memory_acceleration_structures: false,
+            max_blas_builds_per_frame: None,
+            frames_in_flight: 2,
+            timeline_frame_sync: false,
+            max_ray_recursion_depth: 2,
+            device: DeviceSelection::default(),
+        }
+    }
+}
+
 pub struct Renderer {
-    surface: Surface,
-    swapchain: Swapchain,
+    /// Holds the only long-lived clone of the surface, dropped by hand so the surface is
+    /// destroyed after the swapchains and before the instance.
+    swapchain: ManuallyDrop<Swapchain>,
     debug_messenger: DebugMessenger,
     physical_device: PhysicalDevice,
     render_context: RenderContext,
     path_tracing_pipeline: PathTracingPipeline,
-    blases: HashMap<Handle<Mesh>, AccelerationStructure>,
-    vertex_buffer: HashMap<Handle<Mesh>, Buffer>,
-    index_buffer: HashMap<Handle<Mesh>, Buffer>,
-    blas_scratch: HashMap<Handle<Mesh>, Buffer>,
+    geometry_ids: GeometryIds,
+    blases: HashMap<GeometryId, AccelerationStructure>,
+    mesh_buffers: HashMap<GeometryId, MeshBuffers>,
+    geometry_arena: BufferArena,
+    scratch_pool: ScratchPool,
+    blas_build_flags: vk::BuildAccelerationStructureFlagsKHR,
+    pending_meshes: VecDeque<Handle<Mesh>>,
+    max_blas_builds_per_frame: Option<usize>,
     bump: Mutex<Bump>,
+    frame: u64,
+    last_frame_start: Option<Instant>,
+    stats_log: Option<StatsLog>,
+    /// Where to save the next presented frame, see [`Renderer::save_screenshot`].
+    pending_screenshot: Option<PathBuf>,
+    recording: Option<Recording>,
+    readback: Option<Readback>,
+    /// Signaled by the pipeline instead of the swapchain semaphore when the frame is copied out
+    /// before being presented.
+    screenshot_semaphore: Semaphore,
+    /// Set when the device supports exclusive fullscreen, see
+    /// [`Renderer::set_fullscreen_exclusive`].
+    full_screen_exclusive: Option<FullScreenExclusive>,
     instance: Arc<InstanceLoader>,
     _entry: EntryLoader,
+    /// Set once [`Renderer::teardown`] ran, so dropping a destroyed renderer does nothing.
+    destroyed: bool,
 }
 
 impl Renderer {
-    pub fn new(window: &Window) -> Self {
+    pub fn new(window: &Window, settings: &RenderSettings) -> Self {
         let entry = EntryLoader::new().unwrap();
         let instance = Arc::new(instance::create_instance(window, &entry));
         let debug_messenger = DebugMessenger::new(&instance);
         let surface = Surface::new(&instance, window);
 
-        let device_extensions = vec![
+        let mut device_extensions = vec![
             vk::KHR_SWAPCHAIN_EXTENSION_NAME,
             vk::KHR_ACCELERATION_STRUCTURE_EXTENSION_NAME,
             vk::KHR_RAY_TRACING_PIPELINE_EXTENSION_NAME,
             vk::KHR_BUFFER_DEVICE_ADDRESS_EXTENSION_NAME,
             vk::KHR_DEFERRED_HOST_OPERATIONS_EXTENSION_NAME,
         ];
-        let physical_device = PhysicalDevice::select_one(&instance, &surface, &device_extensions);
+        let physical_device =
+            PhysicalDevice::select_one(&instance, &surface, &device_extensions, &settings.device);
+
+        let full_screen_exclusive = FullScreenExclusive::for_window(window).filter(|_| {
+            physical_device
+                .supports_extension(&instance, vk::EXT_FULL_SCREEN_EXCLUSIVE_EXTENSION_NAME)
+        });
+        if full_screen_exclusive.is_some() {
+            device_extensions.push(vk::EXT_FULL_SCREEN_EXCLUSIVE_EXTENSION_NAME);
+        }
         let (device, queue) = physical_device.create_device(instance.clone(), &device_extensions);
         let render_context = RenderContext::new(device, queue);
 
@@ -58,47 +143,361 @@ impl Renderer {
 
         let bump = Mutex::new(Bump::with_capacity(10000));
 
+        let low_memory_flags = if settings.low_memory_acceleration_structures {
+            vk::BuildAccelerationStructureFlagsKHR::LOW_MEMORY_KHR
+        } else {
+            vk::BuildAccelerationStructureFlagsKHR::empty()
+        };
+
+        let timeline_frame_sync =
+            settings.timeline_frame_sync && physical_device.info().capabilities.timeline_semaphore;
+        if settings.timeline_frame_sync && !timeline_frame_sync {
+            tracing::warn!(
+                "timeline semaphores aren't supported by {}, syncing frames with fences",
+                physical_device.info().name
+            );
+        }
+
         let path_tracing_pipeline = PathTracingPipeline::new(
             &render_context,
             physical_device.info().surface_format.format,
             physical_device.info().surface_capabilities.current_extent,
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD_KHR | low_memory_flags,
+            settings.frames_in_flight,
+            timeline_frame_sync,
+            settings.max_ray_recursion_depth,
         );
 
+        let screenshot_semaphore = render_context.create_semaphore();
+
         Renderer {
-            surface,
-            swapchain,
+            swapchain: ManuallyDrop::new(swapchain),
             debug_messenger,
             physical_device,
             render_context,
             path_tracing_pipeline,
+            geometry_ids: Default::default(),
             blases: Default::default(),
-            vertex_buffer: Default::default(),
-            index_buffer: Default::default(),
-            blas_scratch: Default::default(),
+            mesh_buffers: Default::default(),
+            geometry_arena: BufferArena::new(
+                GEOMETRY_BLOCK_SIZE,
+                vk::BufferUsageFlags::VERTEX_BUFFER
+                    | vk::BufferUsageFlags::INDEX_BUFFER
+                    | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                gpu_alloc::UsageFlags::DEVICE_ADDRESS | gpu_alloc::UsageFlags::HOST_ACCESS,
+            ),
+            scratch_pool: Default::default(),
+            blas_build_flags: vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE_KHR
+                | low_memory_flags,
+            pending_meshes: VecDeque::new(),
+            max_blas_builds_per_frame: settings.max_blas_builds_per_frame,
             bump,
+            frame: 0,
+            last_frame_start: None,
+            stats_log: None,
+            pending_screenshot: None,
+            recording: None,
+            readback: None,
+            screenshot_semaphore,
+            full_screen_exclusive,
             instance,
             _entry: entry,
+            destroyed: false,
         }
     }
 
-    pub fn load_models(&mut self, handle: &Handle<Mesh>, mesh: &Mesh) {
+    /// Returns the id assigned to `handle`, assigning the next free one if the renderer hasn't seen
+    /// this mesh yet. Ids are handed out sequentially starting at 0 and never reused.
+    pub fn geometry_id(&mut self, handle: &Handle<Mesh>) -> GeometryId {
+        self.geometry_ids.get_or_assign(handle)
+    }
+
+    /// Records the BLAS builds of `meshes` that aren't built yet and submits them all at once.
+    pub fn load_models(&mut self, meshes: &[(Handle<Mesh>, &Mesh)]) {
+        let meshes = meshes
+            .iter()
+            .map(|(handle, mesh)| (self.geometry_id(handle), *mesh))
+            .collect::<Vec<_>>();
+
+        let bump = self.bump.lock();
         let mut encoder = self.render_context.queue.create_enconder();
-        if let Entry::Vacant(entry) = self.blases.entry(handle.clone()) {
-            let bump = self.bump.lock();
-
-            let (blas, vertex, index, scratch) =
-                mesh.build_triangle_blas(&self.render_context, &mut encoder, &bump);
-            self.vertex_buffer.insert(handle.clone(), vertex);
-            self.index_buffer.insert(handle.clone(), index);
-            self.blas_scratch.insert(handle.clone(), scratch);
-            entry.insert(blas);
+        let mut recorded = 0;
+        for (id, mesh) in meshes {
+            if let Entry::Vacant(entry) = self.blases.entry(id) {
+                match mesh.build_triangle_blas(
+                    &self.render_context,
+                    &mut encoder,
+                    &bump,
+                    &mut self.geometry_arena,
+                    &mut self.scratch_pool,
+                    self.blas_build_flags,
+                ) {
+                    Ok((blas, buffers)) => {
+                        self.mesh_buffers.insert(id, buffers);
+                        entry.insert(blas);
+                        recorded += 1;
+                    }
+                    Err(err) => tracing::error!("skipping mesh {:?}: {}", id, err),
+                }
+            }
+        }
+
+        if recorded > 0 {
             self.render_context
                 .queue
                 .submit(encoder.finish(&self.render_context), &[], &[], None);
+            self.path_tracing_pipeline.invalidate_instances();
+        }
+    }
+
+    /// Loads a KTX2 texture, uploading its block-compressed mip levels without transcoding.
+    pub fn load_ktx2(&self, path: impl AsRef<Path>) -> Result<Image, Ktx2Error> {
+        let bytes = std::fs::read(path)?;
+        ktx2::load_ktx2(&self.render_context, &bytes)
+    }
+
+    /// Queues `handle` to have its BLAS built by [`Renderer::build_queued_meshes`].
+    pub fn queue_mesh(&mut self, handle: Handle<Mesh>) {
+        if !self.pending_meshes.contains(&handle) {
+            self.pending_meshes.push_back(handle);
+        }
+    }
+
+    /// Removes the BLAS and buffers of `handle` from the scene, waiting for the GPU to stop using
+    /// them first. The memory itself is only released when the device is destroyed.
+    pub fn unload_mesh(&mut self, handle: &Handle<Mesh>) {
+        self.unload_meshes(std::slice::from_ref(handle));
+    }
+
+    /// Like [`Renderer::unload_mesh`], but waits for the GPU only once for all of `handles`.
+    pub fn unload_meshes(&mut self, handles: &[Handle<Mesh>]) {
+        self.pending_meshes
+            .retain(|pending| !handles.contains(pending));
+
+        let ids = handles
+            .iter()
+            .filter_map(|handle| self.geometry_ids.get(handle))
+            .filter(|id| self.blases.contains_key(id))
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            return;
+        }
+
+        self.wait_idle();
+        for id in ids {
+            self.blases.remove(&id);
+            if let Some(buffers) = self.mesh_buffers.remove(&id) {
+                let regions = std::iter::once(&buffers.vertex_buffer)
+                    .chain(&buffers.index_buffer)
+                    .chain(&buffers.color_buffer)
+                    .chain(&buffers.shading_buffer);
+                for region in regions {
+                    self.geometry_arena.free(region);
+                }
+            }
+        }
+        self.path_tracing_pipeline.invalidate_instances();
+    }
+
+    /// Blocks until the GPU has finished all submitted work, after which resources in use by
+    /// earlier frames can be safely replaced.
+    pub fn wait_idle(&self) {
+        self.render_context.wait_idle();
+    }
+
+    /// Builds the BLASes of the queued meshes, at most `max_blas_builds_per_frame` of them.
+    pub fn build_queued_meshes(&mut self, meshes: &Assets<Mesh>) {
+        let budget = self.max_blas_builds_per_frame.unwrap_or(usize::MAX);
+
+        let mut batch = Vec::new();
+        while batch.len() < budget {
+            let handle = match self.pending_meshes.pop_front() {
+                Some(handle) => handle,
+                None => break,
+            };
+
+            if let Some(mesh) = meshes.get(&handle) {
+                batch.push((handle, mesh));
+            }
+        }
+
+        self.load_models(&batch);
+    }
+
+    /// Hides or shows the instances of `mesh`, takes effect on the next frame. Does nothing for
+    /// meshes the renderer hasn't seen.
+    pub fn set_mesh_visibility(&mut self, mesh: &Handle<Mesh>, visible: bool) {
+        let id = match self.geometry_ids.get(mesh) {
+            Some(id) => id,
+            None => return,
+        };
+        self.path_tracing_pipeline.set_visibility(id, visible);
+    }
+
+    /// Moves the instance of `mesh` drawn by `entity` to `transform`, including non-uniform scale.
+    /// Every entity drawing the same mesh gets its own instance.
+    pub fn set_mesh_transform(
+        &mut self,
+        entity: Entity,
+        mesh: &Handle<Mesh>,
+        transform: &GlobalTransform,
+    ) {
+        let id = self.geometry_id(mesh);
+        self.path_tracing_pipeline
+            .set_instance_transform(id, entity, transform.compute_matrix());
+    }
+
+    /// Removes the instance drawn by `entity`.
+    pub fn remove_mesh_instance(&mut self, entity: Entity) {
+        self.path_tracing_pipeline.remove_instance(entity);
+    }
+
+    /// Sets the custom index, visibility mask and hit group record of the instance of `mesh`.
+    pub fn set_instance_config(&mut self, mesh: &Handle<Mesh>, config: InstanceConfig) {
+        let id = self.geometry_id(mesh);
+        self.path_tracing_pipeline.set_instance_config(id, config);
+    }
+
+    /// Makes `mesh` a light with the emission of `material`, or a regular surface if it doesn't
+    /// emit.
+    pub fn set_mesh_material(&mut self, mesh: &Handle<Mesh>, material: &Material) {
+        let id = self.geometry_id(mesh);
+        self.path_tracing_pipeline
+            .set_emission(id, material.emission());
+    }
+
+    /// Traces `samples_per_pixel` primary rays per pixel every frame, converging faster at the
+    /// cost of frame time. Panics if it is 0.
+    pub fn set_samples_per_pixel(&mut self, samples_per_pixel: u32) {
+        self.path_tracing_pipeline
+            .set_samples_per_pixel(samples_per_pixel);
+    }
+
+    /// Switches to a ray tracing pipeline with `max_ray_recursion_depth` levels, waiting for the
+    /// GPU to stop using the current one. Bounces above the new limit are clamped.
+    pub fn set_max_ray_recursion_depth(&mut self, max_ray_recursion_depth: u32) {
+        self.wait_idle();
+        self.path_tracing_pipeline
+            .set_max_recursion_depth(&self.render_context, max_ray_recursion_depth);
+    }
+
+    /// Sets how many times a path may bounce after the primary hit. Values above the ray tracing
+    /// pipeline's recursion depth are clamped, see [`RenderSettings::max_ray_recursion_depth`].
+    pub fn set_max_bounces(&mut self, max_bounces: u32) {
+        self.path_tracing_pipeline.set_max_bounces(max_bounces);
+    }
+
+    /// Seeds the per-pixel random numbers of the path tracer. With a fixed seed and sample count
+    /// the same frame index renders the same image on every run.
+    pub fn set_seed(&mut self, seed: u32) {
+        self.path_tracing_pipeline.set_seed(seed);
+    }
+
+    /// Throws away the accumulated samples, e.g. after the scene changed in a way the temporal
+    /// reprojection can't follow.
+    pub fn reset_accumulation(&mut self) {
+        self.path_tracing_pipeline.reset_accumulation();
+    }
+
+    /// Changes the tonemap operator, exposure and white point applied to the path traced image.
+    pub fn set_tonemap_settings(&mut self, settings: TonemapSettings) {
+        self.path_tracing_pipeline.set_tonemap_settings(settings);
+    }
+
+    /// Runs the edge-avoiding denoiser on the path traced image, `None` turns it off.
+    pub fn set_denoise(&mut self, settings: Option<DenoiseSettings>) {
+        self.path_tracing_pipeline.set_denoise(settings);
+    }
+
+    /// Computes ray query shadows towards the directional light at `light_direction`, `None`
+    /// turns them off. Warns when the device doesn't support ray queries.
+    pub fn set_ray_query_shadows(&mut self, light_direction: Option<Vec3>) {
+        if !self
+            .path_tracing_pipeline
+            .set_ray_query_shadows(light_direction)
+        {
+            tracing::warn!(
+                "ray queries aren't supported by {}",
+                self.physical_device.info().name
+            );
+        }
+    }
+
+    /// Overlays the mesh edges in `edge_color` on the shaded image, `None` turns it off.
+    pub fn set_wireframe_overlay(&mut self, edge_color: Option<Vec4>) {
+        self.path_tracing_pipeline.set_wireframe_overlay(edge_color);
+    }
+
+    /// Changes the color the render passes clear their color attachments to.
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.path_tracing_pipeline.set_clear_color(clear_color);
+    }
+
+    /// Appends the stats of every following frame to the file at `path`.
+    pub fn set_stats_output(
+        &mut self,
+        path: impl AsRef<Path>,
+        format: StatsFormat,
+    ) -> io::Result<()> {
+        self.stats_log = Some(StatsLog::create(path, format)?);
+        Ok(())
+    }
+
+    pub fn disable_stats_output(&mut self) {
+        if let Some(mut stats_log) = self.stats_log.take() {
+            if let Err(err) = stats_log.flush() {
+                tracing::warn!("failed to flush frame stats: {}", err);
+            }
+        }
+    }
+
+    /// Recreates the swapchain for the current window size. While the window is minimized the
+    /// surface has no area and the swapchain is kept until it's restored.
+    pub fn resize(&mut self) {
+        self.physical_device
+            .update_surface_capabilities(&self.instance, self.swapchain.surface());
+
+        if self.physical_device.is_surface_empty() {
+            return;
+        }
+
+        self.swapchain
+            .configure(&self.render_context.device, self.physical_device.info());
+
+        // The passes rewrite the descriptor sets of every frame slot
+        self.wait_idle();
+        self.path_tracing_pipeline.resize(
+            &self.render_context,
+            self.physical_device
+                .info()
+                .surface_capabilities
+                .current_extent,
+        );
+    }
+
+    /// Takes exclusive control of the window's monitor for lower presentation latency, or gives it
+    /// back. Only supported on Windows with `VK_EXT_full_screen_exclusive`, elsewhere this warns
+    /// and does nothing.
+    pub fn set_fullscreen_exclusive(&mut self, enabled: bool) {
+        if enabled && self.full_screen_exclusive.is_none() {
+            tracing::warn!("exclusive fullscreen isn't supported");
+            return;
         }
+
+        self.swapchain
+            .set_full_screen_exclusive(self.full_screen_exclusive.filter(|_| enabled));
+        self.resize();
     }
 
     pub fn draw(&mut self, camera: &GlobalTransform) {
+        // Nothing is visible and a zero sized swapchain can't be created
+        if self.physical_device.is_surface_empty() {
+            return;
+        }
+
+        let frame_start = Instant::now();
+
         let swapchain_image = loop {
             if let Some(swapchain_image) = self
                 .swapchain
@@ -106,32 +505,323 @@ impl Renderer {
             {
                 break swapchain_image;
             }
-            self.swapchain
-                .configure(&self.render_context.device, self.physical_device.info());
+            // Out of date, usually the window was resized before the event got here
+            self.resize();
+            if self.physical_device.is_surface_empty() {
+                return;
+            }
+        };
+
+        // Textures created since the last frame, e.g. the UI font
+        self.render_context
+            .device
+            .flush_uploads(&mut self.render_context.queue);
+
+        let mut capture_paths = Vec::new();
+        if let Some(path) = self.pending_screenshot.take() {
+            capture_paths.push(path);
+        }
+        if let Some(recording) = &mut self.recording {
+            capture_paths.push(recording.next_frame_path());
+        }
+        // Requests are validated when made, this only fails if nothing was requested
+        let capture_order = self
+            .capture_order()
+            .ok()
+            .filter(|_| !capture_paths.is_empty());
+
+        let target_signal = match capture_order {
+            Some(_) => &self.screenshot_semaphore,
+            None => &swapchain_image.info().signal,
         };
 
         self.path_tracing_pipeline.draw(
             &mut self.render_context,
             swapchain_image.info().image.clone(),
             &swapchain_image.info().wait,
-            &swapchain_image.info().signal,
+            target_signal,
             &self.blases,
+            &self.mesh_buffers,
             &self.bump.lock(),
             camera,
         );
 
-        self.render_context.queue.present(swapchain_image);
+        if let Some(order) = capture_order {
+            self.capture(&swapchain_image, &capture_paths, order);
+        }
+
+        if self
+            .render_context
+            .queue
+            .present(swapchain_image)
+            .needs_reconfigure()
+        {
+            self.resize();
+        }
+
+        self.write_frame_stats(frame_start);
+        self.frame += 1;
+    }
+
+    /// Saves the next presented frame to `path` as a PNG. The file is written by the following
+    /// [`Renderer::draw`], which waits for the GPU to finish the frame; write errors are logged.
+    pub fn save_screenshot(&mut self, path: impl AsRef<Path>) -> Result<(), ScreenshotError> {
+        self.capture_order()?;
+        self.pending_screenshot = Some(path.as_ref().to_owned());
+        Ok(())
+    }
+
+    /// Saves the next `frame_count` presented frames to `dir` as `frame_00000.png`,
+    /// `frame_00001.png`, ... and calls `on_complete` with `dir` once the last one is written.
+    /// Replaces any recording in progress.
+    pub fn start_recording(
+        &mut self,
+        dir: impl Into<PathBuf>,
+        frame_count: u32,
+        on_complete: impl FnOnce(&Path) + Send + Sync + 'static,
+    ) -> Result<(), ScreenshotError> {
+        self.capture_order()?;
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let recording = Recording::new(dir, frame_count, on_complete);
+        if recording.is_complete() {
+            recording.complete();
+            self.recording = None;
+        } else {
+            self.recording = Some(recording);
+        }
+        Ok(())
+    }
+
+    /// Checks that presented frames can be copied out and how their channels are ordered.
+    fn capture_order(&self) -> Result<PixelOrder, ScreenshotError> {
+        let info = self.physical_device.info();
+        if !info
+            .surface_capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err(ScreenshotError::UnsupportedUsage);
+        }
+        PixelOrder::from_format(info.surface_format.format)
+    }
+
+    /// Copies the swapchain image out between the pipeline and the present, then writes it to
+    /// every path in `paths`.
+    fn capture(&mut self, swapchain_image: &SwapchainImage, paths: &[PathBuf], order: PixelOrder) {
+        let image = &swapchain_image.info().image;
+        let extent = image.info().extent;
+
+        // The readback buffer is kept across frames, so recordings allocate it once
+        let readback = match self.readback.take() {
+            Some(readback) if readback.matches(extent, order) => readback,
+            _ => Readback::new(&self.render_context, extent, order),
+        };
+        let readback = self.readback.get_or_insert(readback);
+        readback.copy(
+            &mut self.render_context,
+            image,
+            self.screenshot_semaphore.clone(),
+            &swapchain_image.info().signal,
+        );
+
+        for path in paths {
+            match readback.save(&self.render_context, path) {
+                Ok(()) => tracing::debug!("saved frame to {}", path.display()),
+                Err(err) => tracing::error!("failed to save frame to {}: {}", path.display(), err),
+            }
+        }
+
+        if self
+            .recording
+            .as_ref()
+            .map_or(false, Recording::is_complete)
+        {
+            if let Some(recording) = self.recording.take() {
+                recording.complete();
+            }
+        }
+    }
+
+    fn write_frame_stats(&mut self, frame_start: Instant) {
+        let frame_time = self
+            .last_frame_start
+            .replace(frame_start)
+            .map(|last_frame_start| frame_start - last_frame_start)
+            .unwrap_or_default();
+
+        if let Some(stats_log) = self.stats_log.as_mut() {
+            let stats = FrameStats {
+                frame: self.frame,
+                frame_time,
+                cpu_time: frame_start.elapsed(),
+                blas_count: self.blases.len(),
+                gpu_pass_times: self.path_tracing_pipeline.gpu_pass_times().to_vec(),
+            };
+
+            if let Err(err) = stats_log.write(&stats) {
+                tracing::warn!(
+                    "failed to write frame stats, disabling stats output: {}",
+                    err
+                );
+                self.stats_log = None;
+            }
+        }
+    }
+}
+
+impl Renderer {
+    /// Waits for the GPU and destroys every Vulkan object of the renderer. Dropping the renderer
+    /// does the same, this only makes the point of teardown explicit, e.g. before the window goes
+    /// away.
+    pub fn destroy(mut self) {
+        self.teardown();
+    }
+}
+
+/// The steps of destroying the renderer, each called once by [`Teardown::teardown`].
+trait Teardown {
+    /// Set once the teardown ran.
+    fn destroyed(&mut self) -> &mut bool;
+
+    /// Waits for the GPU and destroys the device and every object created from it.
+    fn destroy_device(&mut self);
+
+    /// Destroys the swapchain and its surface.
+    fn destroy_swapchain(&mut self);
+
+    fn destroy_debug_messenger(&mut self);
+
+    fn destroy_instance(&mut self);
+
+    /// Tears down in dependency order: device objects, swapchain and surface, debug messenger and
+    /// finally the instance. Runs at most once.
+    fn teardown(&mut self) {
+        let destroyed = self.destroyed();
+        if *destroyed {
+            return;
+        }
+        *destroyed = true;
+
+        self.destroy_device();
+        self.destroy_swapchain();
+        self.destroy_debug_messenger();
+        self.destroy_instance();
+    }
+}
+
+impl Teardown for Renderer {
+    fn destroyed(&mut self) -> &mut bool {
+        &mut self.destroyed
+    }
+
+    fn destroy_device(&mut self) {
+        self.render_context.destroy_context();
+    }
+
+    fn destroy_swapchain(&mut self) {
+        // Only called once, the swapchain isn't used after the teardown
+        unsafe { ManuallyDrop::drop(&mut self.swapchain) }
+    }
+
+    fn destroy_debug_messenger(&mut self) {
+        self.debug_messenger.destroy(&self.instance);
+    }
+
+    fn destroy_instance(&mut self) {
+        unsafe { self.instance.destroy_instance(None) }
     }
 }
 
 impl Drop for Renderer {
     fn drop(&mut self) {
-        unsafe {
-            self.render_context.destroy_context();
-            self.instance
-                .destroy_surface_khr(Some(self.surface.handle()), None);
-            self.debug_messenger.destroy(&self.instance);
-            self.instance.destroy_instance(None);
+        self.teardown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records the teardown steps, torn down on drop like the [`Renderer`].
+    #[derive(Default)]
+    struct StubRenderer {
+        steps: Rc<RefCell<Vec<&'static str>>>,
+        destroyed: bool,
+    }
+
+    impl StubRenderer {
+        fn destroy(mut self) {
+            self.teardown();
+        }
+    }
+
+    impl Teardown for StubRenderer {
+        fn destroyed(&mut self) -> &mut bool {
+            &mut self.destroyed
         }
+
+        fn destroy_device(&mut self) {
+            self.steps.borrow_mut().push("device");
+        }
+
+        fn destroy_swapchain(&mut self) {
+            self.steps.borrow_mut().push("swapchain");
+        }
+
+        fn destroy_debug_messenger(&mut self) {
+            self.steps.borrow_mut().push("debug_messenger");
+        }
+
+        fn destroy_instance(&mut self) {
+            self.steps.borrow_mut().push("instance");
+        }
+    }
+
+    impl Drop for StubRenderer {
+        fn drop(&mut self) {
+            self.teardown();
+        }
+    }
+
+    const TEARDOWN_ORDER: [&str; 4] = ["device", "swapchain", "debug_messenger", "instance"];
+
+    #[test]
+    fn dropping_without_destroy_tears_down() {
+        let renderer = StubRenderer::default();
+        let steps = renderer.steps.clone();
+
+        drop(renderer);
+
+        assert_eq!(*steps.borrow(), TEARDOWN_ORDER);
+    }
+
+    #[test]
+    fn destroy_tears_down_once() {
+        let renderer = StubRenderer::default();
+        let steps = renderer.steps.clone();
+
+        // Dropping at the end of `destroy` doesn't run the steps again
+        renderer.destroy();
+
+        assert_eq!(*steps.borrow(), TEARDOWN_ORDER);
+    }
+
+    #[test]
+    fn instance_is_destroyed_once_after_the_debug_messenger() {
+        let mut renderer = StubRenderer::default();
+        let steps = renderer.steps.clone();
+
+        renderer.teardown();
+        renderer.teardown();
+        renderer.destroy();
+
+        let steps = steps.borrow();
+        let count = |step| steps.iter().filter(|&&s| s == step).count();
+        assert_eq!(count("instance"), 1);
+        assert_eq!(count("debug_messenger"), 1);
+        assert_eq!(steps.last(), Some(&"instance"));
     }
 }
@@ -3,7 +3,9 @@ use crate::render::{
     buffer::{BufferInfo, DeviceAddress},
     descriptor::{DescriptorSetInfo, DescriptorSetLayoutInfo, DescriptorSizes},
     framebuffer::FramebufferInfo,
-    pipeline::{GraphicsPipelineInfo, PipelineLayoutInfo, RayTracingPipelineInfo},
+    pipeline::{
+        ComputePipelineInfo, GraphicsPipelineInfo, PipelineLayoutInfo, RayTracingPipelineInfo,
+    },
     render_pass::RenderPassInfo,
     shader::ShaderModuleInfo,
 };
@@ -87,6 +89,26 @@ impl Fence {
     }
 }
 
+#[derive(Clone)]
+pub struct QueryPool {
+    handle: vk::QueryPool,
+    count: u32,
+}
+
+impl QueryPool {
+    pub fn new(handle: vk::QueryPool, count: u32) -> Self {
+        QueryPool { handle, count }
+    }
+
+    pub fn handle(&self) -> vk::QueryPool {
+        self.handle
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
 #[derive(Clone)]
 pub struct Semaphore {
     handle: vk::Semaphore,
@@ -229,6 +251,10 @@ impl DescriptorSet {
     pub fn handle(&self) -> vk::DescriptorSet {
         self.handle
     }
+
+    pub fn pool(&self) -> vk::DescriptorPool {
+        self.pool
+    }
 }
 
 #[derive(Clone)]
@@ -273,6 +299,26 @@ impl GraphicsPipeline {
     }
 }
 
+#[derive(Clone)]
+pub struct ComputePipeline {
+    info: ComputePipelineInfo,
+    handle: vk::Pipeline,
+}
+
+impl ComputePipeline {
+    pub fn new(info: ComputePipelineInfo, handle: vk::Pipeline) -> Self {
+        ComputePipeline { info, handle }
+    }
+
+    pub fn info(&self) -> &ComputePipelineInfo {
+        &self.info
+    }
+
+    pub fn handle(&self) -> vk::Pipeline {
+        self.handle
+    }
+}
+
 #[derive(Clone)]
 pub struct AccelerationStructure {
     info: AccelerationStructureInfo,
@@ -337,4 +383,9 @@ impl RayTracingPipeline {
     pub fn group_handlers(&self) -> &[u8] {
         &*self.group_handlers
     }
+
+    /// The recursion depth the pipeline was created with, after clamping to the device limit.
+    pub fn max_recursion_depth(&self) -> u32 {
+        self.info.max_recursion_depth
+    }
 }
@@ -0,0 +1,87 @@
+use crate::render::{
+    buffer::{BufferInfo, DeviceAddress},
+    device::Device,
+    resources::Buffer,
+};
+use erupt::vk;
+
+/// Scratch memory shared by acceleration structure builds that run one after another, so loading
+/// many meshes only takes as much scratch as the largest build.
+///
+/// Every build using the pool overwrites the same memory, consecutive builds have to be separated
+/// by an `ACCELERATION_STRUCTURE_BUILD_KHR` barrier.
+#[derive(Default)]
+pub struct ScratchPool {
+    buffer: Option<Buffer>,
+}
+
+impl ScratchPool {
+    pub fn size(&self) -> u64 {
+        self.buffer.as_ref().map_or(0, |buffer| buffer.info().size)
+    }
+
+    /// Grows the pool to at least `size` bytes. A build already recorded with the previous buffer
+    /// keeps using it, buffers are only released with the device.
+    pub fn reserve(&mut self, device: &Device, size: u64) {
+        let size = match grown_size(self.size(), size) {
+            Some(size) => size,
+            None => return,
+        };
+
+        self.buffer = Some(device.create_buffer(BufferInfo {
+            align: device.scratch_align_mask(),
+            size,
+            usage_flags: vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::STORAGE_BUFFER,
+            allocation_flags: gpu_alloc::UsageFlags::DEVICE_ADDRESS,
+            name: Some("acceleration structure scratch"),
+        }));
+    }
+
+    /// Scratch for a build that needs `size` bytes.
+    pub fn scratch(&mut self, device: &Device, size: u64) -> DeviceAddress {
+        self.reserve(device, size);
+        self.buffer.as_ref().unwrap().device_address().unwrap()
+    }
+}
+
+/// Alignment mask for scratch buffers on a device reporting `min_scratch_alignment` as its
+/// `minAccelerationStructureScratchOffsetAlignment`, never below the usual 256 bytes.
+pub fn align_mask(min_scratch_alignment: u64) -> u64 {
+    255 | (min_scratch_alignment.max(1) - 1)
+}
+
+/// The size a pool of `pool_size` bytes has to grow to for a build needing `size` bytes, `None`
+/// if the build already fits.
+fn grown_size(pool_size: u64, size: u64) -> Option<u64> {
+    if size <= pool_size {
+        None
+    } else {
+        Some(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_of_different_sizes_fit_a_pool_sized_to_the_max() {
+        let builds = [4096, 65536];
+        let pool_size = grown_size(0, *builds.iter().max().unwrap()).unwrap();
+
+        assert_eq!(pool_size, 65536);
+        for &build in &builds {
+            assert_eq!(grown_size(pool_size, build), None);
+        }
+        assert_eq!(grown_size(pool_size, pool_size + 1), Some(pool_size + 1));
+    }
+
+    #[test]
+    fn align_mask_includes_the_device_scratch_alignment() {
+        assert_eq!(align_mask(0), 255);
+        assert_eq!(align_mask(128), 255);
+        assert_eq!(align_mask(256), 255);
+        assert_eq!(align_mask(1024), 1023);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::render::{
+    buffer::BufferInfo,
+    image::{Image, ImageMemoryBarrier},
+    render_context::RenderContext,
+    resources::{Buffer, Fence, Semaphore},
+};
+use erupt::vk;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ScreenshotError {
+    #[error("swapchain format {0:?} can't be saved as a PNG")]
+    UnsupportedFormat(vk::Format),
+    #[error("the surface doesn't allow copying out of swapchain images")]
+    UnsupportedUsage,
+    #[error("failed to write the screenshot: {0}")]
+    Image(#[from] ::image::ImageError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Channel order of the 8-bit swapchain formats screenshots can be taken from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelOrder {
+    Rgba,
+    Bgra,
+}
+
+impl PixelOrder {
+    pub fn from_format(format: vk::Format) -> Result<Self, ScreenshotError> {
+        match format {
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => Ok(PixelOrder::Rgba),
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => Ok(PixelOrder::Bgra),
+            _ => Err(ScreenshotError::UnsupportedFormat(format)),
+        }
+    }
+}
+
+/// Host visible copy of a presented image, converted to RGBA8 when saved.
+pub struct Readback {
+    buffer: Buffer,
+    fence: Fence,
+    extent: vk::Extent2D,
+    order: PixelOrder,
+}
+
+impl Readback {
+    pub fn new(render_context: &RenderContext, extent: vk::Extent2D, order: PixelOrder) -> Self {
+        let buffer = render_context.create_buffer(BufferInfo {
+            align: 0,
+            size: row_pitch(extent) * extent.height as u64,
+            usage_flags: vk::BufferUsageFlags::TRANSFER_DST,
+            allocation_flags: gpu_alloc::UsageFlags::HOST_ACCESS | gpu_alloc::UsageFlags::DOWNLOAD,
+            name: Some("screenshot readback"),
+        });
+
+        Readback {
+            buffer,
+            fence: render_context.create_fence(),
+            extent,
+            order,
+        }
+    }
+
+    /// Whether the buffer can hold a copy of an `extent` image with `order` channels.
+    pub fn matches(&self, extent: vk::Extent2D, order: PixelOrder) -> bool {
+        self.extent.width == extent.width
+            && self.extent.height == extent.height
+            && self.order == order
+    }
+
+    /// Copies `image`, which has to be in `PRESENT_SRC_KHR` layout, once `wait` is signaled and
+    /// signals `signal` when done. The image is left in `PRESENT_SRC_KHR`.
+    pub fn copy(
+        &mut self,
+        render_context: &mut RenderContext,
+        image: &Image,
+        wait: Semaphore,
+        signal: &Semaphore,
+    ) {
+        let extent = image.info().extent;
+        assert!(
+            extent.width == self.extent.width && extent.height == self.extent.height,
+            "the readback buffer doesn't match the image size"
+        );
+
+        let mut encoder = render_context.queue.create_enconder();
+        encoder.begin_debug_label("Screenshot", [0.6, 0.6, 0.6, 1.0]);
+
+        let to_transfer = [ImageMemoryBarrier::transition_whole(
+            image,
+            vk::ImageLayout::PRESENT_SRC_KHR..vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        )];
+        encoder.pipeline_barrier(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags::TRANSFER_READ,
+            &to_transfer,
+        );
+
+        encoder.copy_image_to_buffer(
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            &self.buffer,
+            0,
+        );
+
+        let to_present = [ImageMemoryBarrier::transition_whole(
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL..vk::ImageLayout::PRESENT_SRC_KHR,
+        )];
+        encoder.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::AccessFlags::TRANSFER_READ,
+            vk::AccessFlags::empty(),
+            &to_present,
+        );
+
+        encoder.end_debug_label();
+
+        let command_buffer = encoder.finish(&render_context.device);
+
+        render_context.reset_fences(&[&self.fence]);
+        render_context.queue.submit(
+            command_buffer,
+            &[(vk::PipelineStageFlags::TRANSFER, wait)],
+            std::slice::from_ref(signal),
+            Some(&self.fence),
+        );
+    }
+
+    /// Waits for the last [`Readback::copy`] and writes it to `path` as a PNG.
+    pub fn save(
+        &mut self,
+        render_context: &RenderContext,
+        path: &Path,
+    ) -> Result<(), ScreenshotError> {
+        render_context.wait_fences(&[&self.fence], true);
+
+        let pitch = row_pitch(self.extent) as usize;
+        let mut pixels = vec![0u8; pitch * self.extent.height as usize];
+        render_context.read_buffer_bytes(&mut self.buffer, 0, &mut pixels);
+
+        write_png(path, pixels, self.extent, self.order)
+    }
+}
+
+/// Writes tightly packed swapchain `pixels` in `order` to `path` as an opaque RGBA PNG.
+fn write_png(
+    path: &Path,
+    mut pixels: Vec<u8>,
+    extent: vk::Extent2D,
+    order: PixelOrder,
+) -> Result<(), ScreenshotError> {
+    for pixel in pixels.chunks_exact_mut(4) {
+        if order == PixelOrder::Bgra {
+            pixel.swap(0, 2);
+        }
+        // The swapchain is composited as opaque, whatever alpha ended up in it is meaningless
+        pixel[3] = u8::MAX;
+    }
+
+    ::image::save_buffer(
+        path,
+        &pixels,
+        extent.width,
+        extent.height,
+        ::image::ColorType::Rgba8,
+    )?;
+
+    Ok(())
+}
+
+/// Saves consecutive frames as numbered PNGs in a directory.
+pub struct Recording {
+    dir: PathBuf,
+    frame_count: u32,
+    next_frame: u32,
+    on_complete: Box<dyn FnOnce(&Path) + Send + Sync>,
+}
+
+impl Recording {
+    pub fn new(
+        dir: PathBuf,
+        frame_count: u32,
+        on_complete: impl FnOnce(&Path) + Send + Sync + 'static,
+    ) -> Self {
+        Recording {
+            dir,
+            frame_count,
+            next_frame: 0,
+            on_complete: Box::new(on_complete),
+        }
+    }
+
+    /// Path of the `index`th frame, zero padded so the files sort in frame order.
+    pub fn frame_path(&self, index: u32) -> PathBuf {
+        self.dir.join(format!("frame_{:05}.png", index))
+    }
+
+    /// Returns where to save the next frame and counts it as recorded.
+    pub fn next_frame_path(&mut self) -> PathBuf {
+        let path = self.frame_path(self.next_frame);
+        self.next_frame += 1;
+        path
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.next_frame >= self.frame_count
+    }
+
+    pub fn complete(self) {
+        (self.on_complete)(&self.dir);
+    }
+}
+
+/// Bytes between the starts of two rows in the readback buffer. The copy packs rows tightly.
+fn row_pitch(extent: vk::Extent2D) -> u64 {
+    extent.width as u64 * 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn recording_three_frames_writes_three_files() {
+        let dir = std::env::temp_dir().join("rdx_recording_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let completed = Arc::new(AtomicBool::new(false));
+        let mut recording = {
+            let completed = completed.clone();
+            Recording::new(dir.clone(), 3, move |_| {
+                completed.store(true, Ordering::SeqCst)
+            })
+        };
+
+        let extent = vk::Extent2D {
+            width: 2,
+            height: 2,
+        };
+        while !recording.is_complete() {
+            let pixels = vec![0u8; row_pitch(extent) as usize * extent.height as usize];
+            write_png(
+                &recording.next_frame_path(),
+                pixels,
+                extent,
+                PixelOrder::Bgra,
+            )
+            .unwrap();
+        }
+        recording.complete();
+
+        let mut files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            files,
+            ["frame_00000.png", "frame_00001.png", "frame_00002.png"]
+        );
+        assert!(completed.load(Ordering::SeqCst));
+    }
+}
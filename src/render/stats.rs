@@ -0,0 +1,146 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    pub frame: u64,
+    /// Time elapsed since the previous frame started.
+    pub frame_time: Duration,
+    /// Time spent recording and submitting this frame on the CPU.
+    pub cpu_time: Duration,
+    pub blas_count: usize,
+    /// GPU time of each pass, measured on the frame that last used this frame slot since the
+    /// results are only read back once it completed. Empty when the device can't write
+    /// timestamps or no frame used the slot yet.
+    pub gpu_pass_times: Vec<(&'static str, Duration)>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum StatsFormat {
+    Csv,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Appends per-frame [`FrameStats`] to a file so frame time traces can be diffed across runs.
+pub struct StatsLog {
+    format: StatsFormat,
+    writer: BufWriter<File>,
+}
+
+impl StatsLog {
+    /// `gpu_passes_ms` holds `name:ms` pairs separated by `;`.
+    const CSV_HEADER: &'static str = "frame,frame_time_ms,cpu_time_ms,blas_count,gpu_passes_ms";
+
+    pub fn create(path: impl AsRef<Path>, format: StatsFormat) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_empty = file.metadata()?.len() == 0;
+        let mut writer = BufWriter::new(file);
+
+        if is_empty {
+            if let StatsFormat::Csv = format {
+                writeln!(writer, "{}", Self::CSV_HEADER)?;
+            }
+        }
+
+        Ok(StatsLog { format, writer })
+    }
+
+    pub fn write(&mut self, stats: &FrameStats) -> io::Result<()> {
+        let frame_time_ms = stats.frame_time.as_secs_f64() * 1000.0;
+        let cpu_time_ms = stats.cpu_time.as_secs_f64() * 1000.0;
+
+        match self.format {
+            StatsFormat::Csv => writeln!(
+                self.writer,
+                "{},{:.4},{:.4},{},{}",
+                stats.frame,
+                frame_time_ms,
+                cpu_time_ms,
+                stats.blas_count,
+                Self::gpu_passes(stats, |name, ms| format!("{}:{:.4}", name, ms), ";")
+            ),
+            StatsFormat::Json => writeln!(
+                self.writer,
+                "{{\"frame\":{},\"frame_time_ms\":{:.4},\"cpu_time_ms\":{:.4},\"blas_count\":{},\"gpu_passes_ms\":{{{}}}}}",
+                stats.frame,
+                frame_time_ms,
+                cpu_time_ms,
+                stats.blas_count,
+                Self::gpu_passes(stats, |name, ms| format!("\"{}\":{:.4}", name, ms), ",")
+            ),
+        }
+    }
+
+    fn gpu_passes(
+        stats: &FrameStats,
+        format_pass: impl Fn(&str, f64) -> String,
+        separator: &str,
+    ) -> String {
+        stats
+            .gpu_pass_times
+            .iter()
+            .map(|(name, time)| format_pass(name, time.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_one(format: StatsFormat, name: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+
+        let mut stats_log = StatsLog::create(&path, format).unwrap();
+        stats_log
+            .write(&FrameStats {
+                frame: 3,
+                frame_time: Duration::from_millis(16),
+                cpu_time: Duration::from_millis(2),
+                blas_count: 1,
+                gpu_pass_times: vec![
+                    ("raytracing", Duration::from_micros(1500)),
+                    ("tonemap", Duration::from_micros(250)),
+                ],
+            })
+            .unwrap();
+        stats_log.flush().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        contents
+    }
+
+    #[test]
+    fn csv_lists_the_gpu_pass_times() {
+        let contents = write_one(StatsFormat::Csv, "rdx_stats_test.csv");
+
+        assert_eq!(
+            contents,
+            format!(
+                "{}\n3,16.0000,2.0000,1,raytracing:1.5000;tonemap:0.2500\n",
+                StatsLog::CSV_HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn json_lists_the_gpu_pass_times() {
+        let contents = write_one(StatsFormat::Json, "rdx_stats_test.json");
+
+        assert_eq!(
+            contents,
+            "{\"frame\":3,\"frame_time_ms\":16.0000,\"cpu_time_ms\":2.0000,\"blas_count\":1,\
+             \"gpu_passes_ms\":{\"raytracing\":1.5000,\"tonemap\":0.2500}}\n"
+        );
+    }
+}
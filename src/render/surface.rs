@@ -4,19 +4,29 @@ use std::sync::Arc;
 use winit::window::Window;
 
 struct SurfaceInner {
-    pub handle: vk::SurfaceKHR,
+    handle: vk::SurfaceKHR,
+    instance: Arc<InstanceLoader>,
 }
 
+impl Drop for SurfaceInner {
+    fn drop(&mut self) {
+        unsafe { self.instance.destroy_surface_khr(Some(self.handle), None) }
+    }
+}
+
+/// Shared handle to a window surface, destroyed when the last clone is dropped. Every swapchain
+/// created for it has to be destroyed first.
 #[derive(Clone)]
 pub struct Surface {
     inner: Arc<SurfaceInner>,
 }
 
 impl Surface {
-    pub fn new(instance: &InstanceLoader, window: &Window) -> Self {
+    pub fn new(instance: &Arc<InstanceLoader>, window: &Window) -> Self {
         Surface {
             inner: Arc::new(SurfaceInner {
                 handle: unsafe { surface::create_surface(instance, window, None).unwrap() },
+                instance: instance.clone(),
             }),
         }
     }
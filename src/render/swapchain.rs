@@ -5,7 +5,8 @@ use crate::render::{
     resources::Semaphore,
     surface::Surface,
 };
-use erupt::vk;
+use erupt::{vk, ExtendableFromConst};
+use winit::window::Window;
 
 pub struct SwapchainImage {
     info: SwapchainImageInfo,
@@ -27,6 +28,60 @@ impl SwapchainImage {
     }
 }
 
+/// How well the swapchain still matches the surface, reported by acquire and present.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentStatus {
+    Optimal,
+    /// Still usable, but no longer matches the surface exactly, for example after a resize.
+    Suboptimal,
+    /// Can't be presented to anymore and has to be reconfigured.
+    OutOfDate,
+}
+
+impl PresentStatus {
+    /// Panics on any error other than `ERROR_OUT_OF_DATE_KHR`.
+    pub fn from_result(result: vk::Result) -> Self {
+        match result {
+            vk::Result::SUCCESS => PresentStatus::Optimal,
+            vk::Result::SUBOPTIMAL_KHR => PresentStatus::Suboptimal,
+            // Reconfiguring acquires exclusive mode again
+            vk::Result::ERROR_OUT_OF_DATE_KHR
+            | vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT => PresentStatus::OutOfDate,
+            result => panic!("swapchain error: {:?}", result),
+        }
+    }
+
+    pub fn needs_reconfigure(self) -> bool {
+        self != PresentStatus::Optimal
+    }
+}
+
+/// Application controlled exclusive fullscreen through `VK_EXT_full_screen_exclusive`, which is
+/// only available on Windows.
+#[derive(Clone, Copy, Debug)]
+pub struct FullScreenExclusive {
+    /// `HMONITOR` of the monitor the window is on, kept as an integer so the swapchain stays
+    /// `Send`.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    monitor: usize,
+}
+
+impl FullScreenExclusive {
+    #[cfg(target_os = "windows")]
+    pub fn for_window(window: &Window) -> Option<Self> {
+        use winit::platform::windows::MonitorHandleExtWindows;
+
+        window.current_monitor().map(|monitor| FullScreenExclusive {
+            monitor: monitor.hmonitor() as usize,
+        })
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    pub fn for_window(_window: &Window) -> Option<Self> {
+        None
+    }
+}
+
 pub struct SwapchainImageInfo {
     pub image: Image,
     pub wait: Semaphore,
@@ -47,6 +102,8 @@ struct SwapchainInner {
     extent: vk::Extent2D,
     format: vk::Format,
     usage: vk::ImageUsageFlags,
+    /// Whether exclusive mode was acquired and has to be released before retiring.
+    exclusive: bool,
 }
 
 pub struct Swapchain {
@@ -55,6 +112,7 @@ pub struct Swapchain {
     retired_offset: u64,
     free_semaphore: Semaphore,
     surface: Surface,
+    full_screen_exclusive: Option<FullScreenExclusive>,
 }
 
 impl Swapchain {
@@ -65,51 +123,109 @@ impl Swapchain {
             retired_offset: 0,
             free_semaphore: device.create_semaphore(),
             surface: surface.clone(),
+            full_screen_exclusive: None,
         }
     }
 
+    /// Requests exclusive fullscreen from the next [`Swapchain::configure`] on, `None` goes back
+    /// to a regular swapchain. The device needs `VK_EXT_full_screen_exclusive` enabled.
+    pub fn set_full_screen_exclusive(
+        &mut self,
+        full_screen_exclusive: Option<FullScreenExclusive>,
+    ) {
+        self.full_screen_exclusive = full_screen_exclusive;
+    }
+
+    pub fn surface(&self) -> &Surface {
+        &self.surface
+    }
+
     pub fn configure(&mut self, device: &Device, info: &PhysicalDeviceInfo) {
         let old_swapchain = match self.inner.take() {
             None => vk::SwapchainKHR::null(),
             Some(inner) => {
                 let handle = inner.handle;
+                if inner.exclusive {
+                    let result = unsafe {
+                        device
+                            .handle()
+                            .release_full_screen_exclusive_mode_ext(handle)
+                    };
+                    if let Err(err) = result.result() {
+                        tracing::warn!("failed to release exclusive fullscreen: {:?}", err);
+                    }
+                }
                 self.retired.push(inner);
                 handle
             }
         };
 
+        // Screenshots copy out of the swapchain images, where the surface allows it
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::TRANSFER_DST
+            | (info.surface_capabilities.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC);
+
+        let mut exclusive_info = vk::SurfaceFullScreenExclusiveInfoEXTBuilder::new()
+            .full_screen_exclusive(vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED_EXT);
+        #[cfg(target_os = "windows")]
+        let mut exclusive_win32_info = vk::SurfaceFullScreenExclusiveWin32InfoEXTBuilder::new()
+            .hmonitor(
+                self.full_screen_exclusive
+                    .map_or(0, |full_screen_exclusive| full_screen_exclusive.monitor)
+                    as *mut std::ffi::c_void,
+            );
+
+        let queue_family_indices = [info.queue_index];
+        let mut create_info = vk::SwapchainCreateInfoKHRBuilder::new()
+            .surface(self.surface.handle())
+            .min_image_count(
+                3.min(info.surface_capabilities.max_image_count)
+                    .max(info.surface_capabilities.min_image_count),
+            )
+            .image_format(info.surface_format.format)
+            .image_color_space(info.surface_format.color_space)
+            .image_extent(info.surface_capabilities.current_extent)
+            .image_array_layers(1)
+            .image_usage(usage)
+            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .pre_transform(info.surface_capabilities.current_transform)
+            .composite_alpha(vk::CompositeAlphaFlagBitsKHR::OPAQUE_KHR)
+            .present_mode(info.present_mode)
+            .clipped(true)
+            .queue_family_indices(&queue_family_indices)
+            .old_swapchain(old_swapchain);
+        if self.full_screen_exclusive.is_some() {
+            create_info = create_info.extend_from(&mut exclusive_info);
+            #[cfg(target_os = "windows")]
+            {
+                create_info = create_info.extend_from(&mut exclusive_win32_info);
+            }
+        }
+
         let swapchain = unsafe {
             device
                 .handle()
-                .create_swapchain_khr(
-                    &vk::SwapchainCreateInfoKHRBuilder::new()
-                        .surface(self.surface.handle())
-                        .min_image_count(
-                            3.min(info.surface_capabilities.max_image_count)
-                                .max(info.surface_capabilities.min_image_count),
-                        )
-                        .image_format(info.surface_format.format)
-                        .image_color_space(info.surface_format.color_space)
-                        .image_extent(info.surface_capabilities.current_extent)
-                        .image_array_layers(1)
-                        .image_usage(
-                            vk::ImageUsageFlags::COLOR_ATTACHMENT
-                                | vk::ImageUsageFlags::TRANSFER_DST,
-                        )
-                        .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-                        .pre_transform(info.surface_capabilities.current_transform)
-                        .composite_alpha(vk::CompositeAlphaFlagBitsKHR::OPAQUE_KHR)
-                        .present_mode(info.present_mode)
-                        .clipped(true)
-                        .queue_family_indices(&[info.queue_index])
-                        .old_swapchain(old_swapchain),
-                    None,
-                )
+                .create_swapchain_khr(&create_info, None)
                 .unwrap()
         };
 
         device.swapchains().lock().insert(swapchain);
 
+        // Failing to go exclusive, e.g. while the window isn't focused, leaves a working swapchain
+        let exclusive = self.full_screen_exclusive.is_some()
+            && match unsafe {
+                device
+                    .handle()
+                    .acquire_full_screen_exclusive_mode_ext(swapchain)
+                    .result()
+            } {
+                Ok(()) => true,
+                Err(err) => {
+                    tracing::warn!("failed to acquire exclusive fullscreen: {:?}", err);
+                    false
+                }
+            };
+
         let images = unsafe {
             device
                 .handle()
@@ -145,7 +261,9 @@ impl Swapchain {
                         mip_levels: 1,
                         array_layers: 1,
                         samples: vk::SampleCountFlagBits::_1,
-                        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                        usage,
+                        flags: vk::ImageCreateFlags::empty(),
+                        name: None,
                     },
                     image,
                     None,
@@ -162,21 +280,30 @@ impl Swapchain {
             images,
             extent: info.surface_capabilities.current_extent.into(),
             format: info.surface_format.format,
-            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            usage,
+            exclusive,
         })
     }
 
+    /// Returns `None` when the swapchain has to be configured first, either because it never was
+    /// or because it's out of date. A suboptimal image is still returned, presenting it reports
+    /// the same status.
     pub fn acquire_next_image(&mut self, device: &Device) -> Option<SwapchainImage> {
         if let Some(inner) = self.inner.as_mut() {
             let wait = self.free_semaphore.clone();
 
-            let index = unsafe {
+            let result = unsafe {
                 device
                     .handle()
                     .acquire_next_image_khr(inner.handle, !0, Some(wait.handle()), None)
-                    .unwrap()
             };
 
+            // The semaphore isn't signaled when nothing was acquired, so it stays free
+            if PresentStatus::from_result(result.raw) == PresentStatus::OutOfDate {
+                return None;
+            }
+            let index = result.unwrap();
+
             let image_and_semaphores = &mut inner.images[index as usize];
 
             std::mem::swap(
@@ -70,3 +70,73 @@ pub trait ToErupt<T> {
 pub trait FromErupt<T> {
     fn from_erupt(value: T) -> Self;
 }
+
+/// Conversions from glam into the mint types used by `AsStd430` uniform structs.
+/// Matrices always go through `ColumnMatrix*`, matching glam's and GLSL's column-major layout.
+pub trait ToMint<T> {
+    fn to_mint(&self) -> T;
+}
+
+pub trait FromMint<T> {
+    fn from_mint(value: T) -> Self;
+}
+
+impl ToMint<mint::ColumnMatrix4<f32>> for glam::Mat4 {
+    fn to_mint(&self) -> mint::ColumnMatrix4<f32> {
+        (*self).into()
+    }
+}
+
+impl ToMint<mint::ColumnMatrix3<f32>> for glam::Mat3 {
+    fn to_mint(&self) -> mint::ColumnMatrix3<f32> {
+        (*self).into()
+    }
+}
+
+impl ToMint<mint::Vector4<f32>> for glam::Vec4 {
+    fn to_mint(&self) -> mint::Vector4<f32> {
+        (*self).into()
+    }
+}
+
+impl ToMint<mint::Vector3<f32>> for glam::Vec3 {
+    fn to_mint(&self) -> mint::Vector3<f32> {
+        (*self).into()
+    }
+}
+
+impl ToMint<mint::Vector2<f32>> for glam::Vec2 {
+    fn to_mint(&self) -> mint::Vector2<f32> {
+        (*self).into()
+    }
+}
+
+impl FromMint<mint::ColumnMatrix4<f32>> for glam::Mat4 {
+    fn from_mint(value: mint::ColumnMatrix4<f32>) -> Self {
+        value.into()
+    }
+}
+
+impl FromMint<mint::ColumnMatrix3<f32>> for glam::Mat3 {
+    fn from_mint(value: mint::ColumnMatrix3<f32>) -> Self {
+        value.into()
+    }
+}
+
+impl FromMint<mint::Vector4<f32>> for glam::Vec4 {
+    fn from_mint(value: mint::Vector4<f32>) -> Self {
+        value.into()
+    }
+}
+
+impl FromMint<mint::Vector3<f32>> for glam::Vec3 {
+    fn from_mint(value: mint::Vector3<f32>) -> Self {
+        value.into()
+    }
+}
+
+impl FromMint<mint::Vector2<f32>> for glam::Vec2 {
+    fn from_mint(value: mint::Vector2<f32>) -> Self {
+        value.into()
+    }
+}